@@ -0,0 +1,25 @@
+//! Compares checking a detector filled with a constant pattern byte by byte with checking it many bytes at a time.
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+
+use cosmic_ray_detection::simd;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const SIZE: usize = 64 << 20;
+
+fn verify(c: &mut Criterion) {
+    let buf = vec![0xFF; SIZE];
+    let mut group = c.benchmark_group("verify 64MiB of ones");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("byte by byte", |b| {
+        b.iter(|| simd::find_mismatch_scalar(black_box(&buf), 0xFF))
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| simd::find_mismatch(black_box(&buf), 0xFF))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, verify);
+criterion_main!(benches);
@@ -0,0 +1,177 @@
+//! Pinning the threads of the detector to CPU cores, so that the scheduler can not move them around during a check,
+//! which makes the time a check takes comparable between runs. This is supported on Linux and Windows,
+//! elsewhere pinning fails and the threads run wherever the scheduler puts them.
+
+use std::io;
+use std::str::FromStr;
+
+/// The CPU cores to pin the threads of the detector to, the first thread to the first core and so on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuSet(pub Vec<usize>);
+
+impl FromStr for CpuSet {
+    type Err = String;
+
+    /// Parses a list of cores and ranges of them like "0-3,8,10-11", as used by `taskset` and the kernel.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("'{cpu}' is not a CPU number"))
+        };
+        let mut cpus = Vec::new();
+        for item in s.split(',') {
+            match item.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("the range {item} of CPUs is empty"));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(parse(item)?),
+            }
+        }
+        Ok(CpuSet(cpus))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+    use std::mem;
+
+    pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU {cpu} is beyond the CPUs the kernel can pin a thread to"),
+            ));
+        }
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        if unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        usize::try_from(unsafe { libc::sched_getcpu() }).ok()
+    }
+
+    pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        if unsafe { libc::sched_getaffinity(0, mem::size_of_val(&set), &mut set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcessorNumber, GetCurrentThread, SetThreadAffinityMask,
+    };
+
+    pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+        // Without processor groups a thread can only be pinned to the first 64 cores.
+        if cpu >= usize::BITS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU {cpu} is beyond the CPUs of the processor group of the detector"),
+            ));
+        }
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << cpu) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        Some(unsafe { GetCurrentProcessorNumber() } as usize)
+    }
+
+    pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+        let cpus = std::thread::available_parallelism()?.get();
+        Ok((0..cpus.min(usize::BITS as usize)).collect())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod imp {
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pinning threads to CPUs is only supported on Linux and Windows",
+        )
+    }
+
+    pub fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn current_cpu() -> Option<usize> {
+        None
+    }
+
+    pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+        Err(unsupported())
+    }
+}
+
+/// Restricts the calling thread to the given CPU core.
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    imp::pin_current_thread(cpu)
+}
+
+/// Returns the CPU core the calling thread runs on right now, or `None` if the operating system can not tell.
+pub fn current_cpu() -> Option<usize> {
+    imp::current_cpu()
+}
+
+/// Returns the CPU cores this process may run on, in ascending order.
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    imp::allowed_cpus()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_cpu_sets() {
+        assert_eq!("0-3,8".parse(), Ok(CpuSet(vec![0, 1, 2, 3, 8])));
+        assert_eq!("5".parse(), Ok(CpuSet(vec![5])));
+        assert_eq!(
+            "3-1".parse::<CpuSet>(),
+            Err("the range 3-1 of CPUs is empty".to_owned())
+        );
+        assert_eq!(
+            "0,,1".parse::<CpuSet>(),
+            Err("'' is not a CPU number".to_owned())
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_pinning() {
+        let cpu = allowed_cpus().unwrap()[0];
+        // Pinning the test thread itself would slow down the tests that run on it later.
+        std::thread::spawn(move || {
+            pin_current_thread(cpu).unwrap();
+            assert_eq!(current_cpu(), Some(cpu));
+            assert_eq!(allowed_cpus().unwrap(), [cpu]);
+        })
+        .join()
+        .unwrap();
+    }
+}
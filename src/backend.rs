@@ -0,0 +1,210 @@
+//! The memory that a detector watches for flips, behind a trait so that memory the CPU can not address,
+//! like the VRAM of a GPU, can be monitored the same way as RAM. A backend only allocates, writes, reads back
+//! and frees its memory. Filling it with the pattern and finding the flips in what is read back is the same
+//! for every backend, and the offsets of the flips are offsets into the memory of the backend.
+//!
+//! The host backend is a [`Region`] of RAM, which the [`crate::Detector`] allocates its memory in and reads back
+//! in place, without a copy. With the `device` feature there is also a stub of a backend for device memory,
+//! which is where a CUDA or OpenCL backend is going to plug in.
+
+use std::io;
+use std::ops::Range;
+use std::ptr::write_volatile;
+
+use crate::config::WordSize;
+use crate::detector::{chunk_flips, classify_words, FlipEvent};
+use crate::pattern::{Contents, Pattern};
+use crate::region::{allocate_region, Region, RegionOptions};
+
+/// How many bytes are copied to or from a backend at once.
+const STAGING_LEN: usize = 1 << 20;
+
+/// Memory that the detector can fill and read back, see the [module documentation](self).
+pub trait MemoryBackend: Sized + Send + Sync {
+    /// How the memory is allocated.
+    type Options: Copy;
+
+    /// A short name of the kind of memory for the output, like "host".
+    const NAME: &'static str;
+
+    /// Allocates the given number of zeroed bytes.
+    fn allocate(size: usize, options: Self::Options) -> io::Result<Self>;
+
+    /// Returns the number of bytes that are allocated.
+    fn len(&self) -> usize;
+
+    /// Returns whether nothing is allocated.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the bytes to the memory from the given offset on.
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()>;
+
+    /// Reads back the bytes of the given range. Memory that the CPU can address is returned in place,
+    /// other memory is copied into `staging` first, which has to be at least as long as the range.
+    fn read_back<'a>(&'a self, range: Range<usize>, staging: &'a mut [u8]) -> io::Result<&'a [u8]>;
+
+    /// Frees the memory.
+    fn free(self) {
+        drop(self);
+    }
+}
+
+/// Fills all of the memory of the backend with the pattern.
+pub fn fill(backend: &mut impl MemoryBackend, pattern: Pattern) -> io::Result<()> {
+    let mut staging = vec![0; STAGING_LEN.min(backend.len())];
+    let mut offset = 0;
+    while offset < backend.len() {
+        let len = staging.len().min(backend.len() - offset);
+        pattern.fill(&mut staging[..len], offset);
+        backend.write(offset, &staging[..len])?;
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Reads back all of the memory of the backend and returns an event for every byte that does not match
+/// the pattern, ordered by offset and classified by the flipped bits of their word of the given size.
+pub fn check(
+    backend: &impl MemoryBackend,
+    pattern: Pattern,
+    word_size: WordSize,
+) -> io::Result<Vec<FlipEvent>> {
+    let contents = Contents {
+        pattern,
+        page_markers: false,
+    };
+    let mut staging = vec![0; STAGING_LEN.min(backend.len())];
+    let mut flips = Vec::new();
+    let mut offset = 0;
+    while offset < backend.len() {
+        let len = staging.len().min(backend.len() - offset);
+        let bytes = backend.read_back(offset..offset + len, &mut staging)?;
+        flips.extend(chunk_flips(contents, word_size, bytes, offset));
+        offset += len;
+    }
+    classify_words(&mut flips, word_size);
+    Ok(flips)
+}
+
+impl MemoryBackend for Region {
+    type Options = RegionOptions;
+
+    const NAME: &'static str = "host";
+
+    fn allocate(size: usize, options: RegionOptions) -> io::Result<Self> {
+        allocate_region(size, options).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!("could not allocate {size} bytes"),
+            )
+        })
+    }
+
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        let memory = self
+            .get_mut(offset..offset.saturating_add(bytes.len()))
+            .ok_or_else(|| out_of_bounds(offset, bytes.len()))?;
+        for (byte, &value) in memory.iter_mut().zip(bytes) {
+            unsafe { write_volatile(byte, value) };
+        }
+        Ok(())
+    }
+
+    fn read_back<'a>(
+        &'a self,
+        range: Range<usize>,
+        _staging: &'a mut [u8],
+    ) -> io::Result<&'a [u8]> {
+        let len = range.len();
+        self.get(range.clone())
+            .ok_or_else(|| out_of_bounds(range.start, len))
+    }
+}
+
+fn out_of_bounds(offset: usize, len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("the {len} bytes at offset {offset} are beyond the allocated memory"),
+    )
+}
+
+#[cfg(feature = "device")]
+/// The memory of a GPU or another device. This is a stub that can not allocate anything yet,
+/// a CUDA or OpenCL backend takes its place.
+#[derive(Debug)]
+pub struct DeviceMemory(());
+
+#[cfg(feature = "device")]
+impl MemoryBackend for DeviceMemory {
+    type Options = ();
+
+    const NAME: &'static str = "device";
+
+    fn allocate(_size: usize, _options: ()) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "monitoring device memory is not implemented yet",
+        ))
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        Err(out_of_bounds(offset, bytes.len()))
+    }
+
+    fn read_back<'a>(
+        &'a self,
+        range: Range<usize>,
+        _staging: &'a mut [u8],
+    ) -> io::Result<&'a [u8]> {
+        Err(out_of_bounds(range.start, range.len()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_host_backend() {
+        let len = STAGING_LEN + 1000;
+        let mut backend = Region::allocate(len, RegionOptions::default()).unwrap();
+        assert_eq!(MemoryBackend::len(&backend), len);
+        fill(&mut backend, Pattern::Random(9)).unwrap();
+        assert_eq!(
+            check(&backend, Pattern::Random(9), WordSize::U64).unwrap(),
+            []
+        );
+
+        // A flip just past the first copy is found at its offset into the backend memory.
+        let offset = STAGING_LEN + 3;
+        let byte = backend.read_back(offset..offset + 1, &mut []).unwrap()[0];
+        backend.write(offset, &[byte ^ 0x08]).unwrap();
+        assert_eq!(
+            check(&backend, Pattern::Random(9), WordSize::U64).unwrap(),
+            [FlipEvent::new(offset, byte, byte ^ 0x08)]
+        );
+
+        assert!(backend.write(len - 1, &[0, 0]).is_err());
+        assert!(backend.read_back(len - 1..len + 1, &mut []).is_err());
+        backend.free();
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn check_device_stub() {
+        assert_eq!(
+            DeviceMemory::allocate(1000, ()).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
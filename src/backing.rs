@@ -0,0 +1,317 @@
+//! Keeping the detector memory in a file given with `--backing-file`, so that it survives a restart of the detector.
+//! The file starts with a header that describes what the memory after it was filled with,
+//! which lets the next run carry on checking it instead of filling it anew.
+//!
+//! The file is laid out as
+//! - the header at the start, see [`Header::encode`],
+//! - the detector memory from 64 KiB on, and
+//! - right after the memory the parity of every block of [`BLOCK_SIZE`] bytes of it, as a little endian `u64` each.
+//!
+//! The parity of a block is the XOR of its little endian 64-bit words, the last block padded with zeros.
+//! It is written with the header once the memory has been filled, so that the next run can tell which blocks
+//! changed while no detector was watching them, see [`BackingFile::verify`].
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::pattern::Pattern;
+use crate::region::Region;
+
+const MAGIC: [u8; 8] = *b"COSMICRD";
+const VERSION: u32 = 2;
+/// The size of the encoded header.
+const HEADER_SIZE: usize = 40;
+/// The detector memory starts this far into the file, which is a whole number of pages for every page size in use.
+const DATA_OFFSET: u64 = 64 * 1024;
+/// The number of bytes of the detector memory covered by every parity word.
+pub const BLOCK_SIZE: usize = 4096;
+/// How many bytes of the detector memory are read from the file at once to compute their parity.
+const READ_LEN: usize = 256 * BLOCK_SIZE;
+
+/// Describes the detector memory in a backing file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub pattern: Pattern,
+    pub size: u64,
+    /// Whether the memory holds check bits for ECC emulation.
+    pub ecc: bool,
+}
+
+/// Returns a number that identifies the kind of the pattern and its parameter, if it has one.
+fn pattern_id(pattern: Pattern) -> (u8, u64) {
+    match pattern {
+        Pattern::Zeros => (0, 0),
+        Pattern::Ones => (1, 0),
+        Pattern::Alternating => (2, 0),
+        Pattern::WalkingOnes => (3, 0),
+        Pattern::WalkingZeros => (4, 0),
+        Pattern::Random(seed) => (5, seed),
+        Pattern::Byte(b) => (6, b.into()),
+    }
+}
+
+fn pattern_from_id(id: u8, parameter: u64) -> Option<Pattern> {
+    Some(match id {
+        0 => Pattern::Zeros,
+        1 => Pattern::Ones,
+        2 => Pattern::Alternating,
+        3 => Pattern::WalkingOnes,
+        4 => Pattern::WalkingZeros,
+        5 => Pattern::Random(parameter),
+        6 => Pattern::Byte(parameter.try_into().ok()?),
+        _ => return None,
+    })
+}
+
+impl Header {
+    /// Encodes the header as the magic number, the version, the flags, the pattern and the size, all little endian.
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let (id, parameter) = pattern_id(self.pattern);
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&MAGIC);
+        bytes[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&u32::from(self.ecc).to_le_bytes());
+        bytes[16] = id;
+        bytes[24..32].copy_from_slice(&parameter.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a header, or returns `None` if the bytes are not a header this version understands.
+    fn decode(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        if bytes[..8] != MAGIC || u32_at(8) != VERSION {
+            return None;
+        }
+        Some(Header {
+            ecc: u32_at(12) & 1 != 0,
+            pattern: pattern_from_id(bytes[16], u64_at(24))?,
+            size: u64_at(32),
+        })
+    }
+}
+
+/// A file that holds the detector memory.
+pub struct BackingFile {
+    file: File,
+    header: Header,
+    /// The header that was in the file when it was opened, if it had a valid one.
+    found: Option<Header>,
+}
+
+impl BackingFile {
+    /// Opens the file at the given path, creating it if it does not exist. If it does not describe the same
+    /// detector memory as the header, its header is cleared and it is resized to fit the detector memory,
+    /// which then has to be filled and [committed](Self::commit) before a later run can resume it.
+    pub fn open(path: &Path, header: Header) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = [0; HEADER_SIZE];
+        let mut found = match file.read_exact(&mut bytes) {
+            Ok(()) => Header::decode(&bytes),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+        let len = DATA_OFFSET + header.size + parity_len(header.size);
+        if file.metadata()?.len() < len {
+            // The memory itself is not all there.
+            found = None;
+        }
+        let mut backing = BackingFile {
+            file,
+            header,
+            found,
+        };
+        if !backing.resumed() {
+            // A run that is interrupted while filling the memory must not leave a valid header behind.
+            backing.write_header(&[0; HEADER_SIZE])?;
+            backing.file.set_len(len)?;
+        }
+        Ok(backing)
+    }
+
+    /// Returns whether the file already holds the detector memory that was asked for.
+    pub fn resumed(&self) -> bool {
+        self.found == Some(self.header)
+    }
+
+    /// Returns the header that was in the file when it was opened, if it had a valid one.
+    pub fn found(&self) -> Option<Header> {
+        self.found
+    }
+
+    /// Maps the detector memory in the file.
+    pub fn map(&self) -> io::Result<Region> {
+        let len = usize::try_from(self.header.size).map_err(io::Error::other)?;
+        #[cfg(unix)]
+        return Region::map_file(&self.file, DATA_OFFSET, len);
+        #[cfg(not(unix))]
+        {
+            let _ = len;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "backing files are only supported on Unix",
+            ))
+        }
+    }
+
+    /// Writes the parity of the detector memory and then the header to the file, after which a later run
+    /// can resume the detector memory in it. This should be called once the memory has been filled.
+    pub fn commit(&mut self) -> io::Result<()> {
+        // The parity is computed from what is in the file, so the mapped memory has to get there first.
+        self.file.sync_data()?;
+        let parity = self.parity()?;
+        let bytes: Vec<u8> = parity.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.file
+            .seek(SeekFrom::Start(DATA_OFFSET + self.header.size))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.write_header(&self.header.encode())?;
+        self.found = Some(self.header);
+        Ok(())
+    }
+
+    /// Returns the blocks of the detector memory whose parity no longer matches the one that was committed,
+    /// as ranges of offsets into the memory. Their bytes changed since, which for a resumed detector means
+    /// while no detector was running. A block in which the same bit of two words flipped is not found like this.
+    pub fn verify(&mut self) -> io::Result<Vec<Range<usize>>> {
+        let mut bytes =
+            vec![0; usize::try_from(parity_len(self.header.size)).map_err(io::Error::other)?];
+        self.file
+            .seek(SeekFrom::Start(DATA_OFFSET + self.header.size))?;
+        self.file.read_exact(&mut bytes)?;
+        let size = self.header.size as usize;
+        Ok(self
+            .parity()?
+            .into_iter()
+            .zip(bytes.chunks_exact(8))
+            .enumerate()
+            .filter(|(_, (parity, committed))| parity.to_le_bytes() != **committed)
+            .map(|(block, _)| block * BLOCK_SIZE..((block + 1) * BLOCK_SIZE).min(size))
+            .collect())
+    }
+
+    /// Computes the parity of every block of the detector memory as it is in the file.
+    fn parity(&mut self) -> io::Result<Vec<u64>> {
+        let size = usize::try_from(self.header.size).map_err(io::Error::other)?;
+        let mut parity = Vec::with_capacity(size.div_ceil(BLOCK_SIZE));
+        let mut buf = vec![0; READ_LEN.min(size)];
+        self.file.seek(SeekFrom::Start(DATA_OFFSET))?;
+        let mut offset = 0;
+        while offset < size {
+            let len = buf.len().min(size - offset);
+            self.file.read_exact(&mut buf[..len])?;
+            parity.extend(buf[..len].chunks(BLOCK_SIZE).map(block_parity));
+            offset += len;
+        }
+        Ok(parity)
+    }
+
+    fn write_header(&mut self, bytes: &[u8; HEADER_SIZE]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(bytes)?;
+        self.file.sync_data()
+    }
+}
+
+/// Returns the number of bytes the parity of detector memory of the given size takes up in the file.
+fn parity_len(size: u64) -> u64 {
+    size.div_ceil(BLOCK_SIZE as u64) * 8
+}
+
+/// Returns the XOR of the little endian 64-bit words of the block, padded with zeros to a whole word.
+fn block_parity(block: &[u8]) -> u64 {
+    block.chunks(8).fold(0, |parity, word| {
+        let mut bytes = [0; 8];
+        bytes[..word.len()].copy_from_slice(word);
+        parity ^ u64::from_le_bytes(bytes)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_header_encoding() {
+        for pattern in [
+            Pattern::Zeros,
+            Pattern::WalkingZeros,
+            Pattern::Random(u64::MAX),
+            Pattern::Byte(0x3C),
+        ] {
+            let header = Header {
+                pattern,
+                size: 1 << 40,
+                ecc: true,
+            };
+            assert_eq!(Header::decode(&header.encode()), Some(header));
+        }
+        assert_eq!(Header::decode(&[0; HEADER_SIZE]), None);
+    }
+
+    #[test]
+    fn check_block_parity() {
+        assert_eq!(block_parity(&[0xAA; BLOCK_SIZE]), 0);
+        assert_eq!(block_parity(&[1, 0, 0, 0, 0, 0, 0, 0, 3]), 2);
+        assert_eq!(block_parity(&[1, 2, 3]), 0x03_0201);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_resuming() {
+        let dir = TempDir::new("backing");
+        let path = dir.join("detector");
+        let header = Header {
+            pattern: Pattern::Ones,
+            size: 10000,
+            ecc: false,
+        };
+
+        let backing = BackingFile::open(&path, header).unwrap();
+        assert!(!backing.resumed());
+        let mut region = backing.map().unwrap();
+        region.fill(0xFF);
+        region[1234] = 0xFE;
+        drop(region);
+        // Without a commit the memory is not trusted.
+        let mut backing = BackingFile::open(&path, header).unwrap();
+        assert!(!backing.resumed());
+        backing.commit().unwrap();
+
+        let mut backing = BackingFile::open(&path, header).unwrap();
+        assert!(backing.resumed());
+        let mut region = backing.map().unwrap();
+        assert_eq!(region.len(), 10000);
+        assert_eq!(region[1234], 0xFE);
+        assert_eq!(region[1235], 0xFF);
+        assert_eq!(backing.verify().unwrap(), []);
+
+        // Flips while the detector is down show up in the parity of their blocks, also in the last partial one.
+        region[5000] ^= 0x10;
+        region[9999] ^= 0x01;
+        drop(region);
+        let mut backing = BackingFile::open(&path, header).unwrap();
+        assert!(backing.resumed());
+        assert_eq!(backing.verify().unwrap(), [4096..8192, 8192..10000]);
+
+        // A different pattern starts over.
+        let other = Header {
+            pattern: Pattern::Zeros,
+            ..header
+        };
+        let backing = BackingFile::open(&path, other).unwrap();
+        assert!(!backing.resumed());
+        assert_eq!(backing.found(), Some(header));
+        drop(backing);
+        assert!(!BackingFile::open(&path, header).unwrap().resumed());
+    }
+}
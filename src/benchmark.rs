@@ -0,0 +1,100 @@
+//! Timing integrity checks, to find out how long a check of the detector takes on this machine before choosing a delay.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use humantime::format_duration;
+use serde::Serialize;
+
+use crate::detector::Detector;
+use crate::output::serialize_seconds;
+
+/// The wall time of a number of integrity checks of the whole detector.
+#[derive(Serialize)]
+#[serde(tag = "event", rename = "benchmark")]
+pub struct Benchmark {
+    pub bytes: usize,
+    pub threads: usize,
+    pub passes: usize,
+    #[serde(rename = "min_seconds", serialize_with = "serialize_seconds")]
+    pub min: Duration,
+    #[serde(rename = "median_seconds", serialize_with = "serialize_seconds")]
+    pub median: Duration,
+    #[serde(rename = "max_seconds", serialize_with = "serialize_seconds")]
+    pub max: Duration,
+    /// Gigabytes (10^9 bytes) checked per second in the median pass.
+    pub gb_per_second: f64,
+}
+
+impl Benchmark {
+    /// Checks all of the detector the given number of times, the same way the detection loop does.
+    pub fn run(detector: &Detector, threads: usize, passes: usize) -> Self {
+        let times: Vec<Duration> = (0..passes.max(1))
+            .map(|_| {
+                let start = Instant::now();
+                if detector.uses_ecc_emulation() {
+                    detector.word_errors();
+                } else {
+                    detector.flips();
+                }
+                start.elapsed()
+            })
+            .collect();
+        Self::from_times(detector.capacity(), threads, times)
+    }
+
+    fn from_times(bytes: usize, threads: usize, mut times: Vec<Duration>) -> Self {
+        times.sort();
+        let median = times[times.len() / 2];
+        Benchmark {
+            bytes,
+            threads,
+            passes: times.len(),
+            min: times[0],
+            median,
+            max: times[times.len() - 1],
+            gb_per_second: bytes as f64 / 1e9 / median.as_secs_f64().max(f64::MIN_POSITIVE),
+        }
+    }
+
+    /// Writes the results as text, or as a single JSON object.
+    pub fn write(&self, mut w: impl Write, json: bool) -> io::Result<()> {
+        if json {
+            return writeln!(w, "{}", serde_json::to_string(self)?);
+        }
+        // Microseconds are plenty precise for a pass over the detector.
+        let round = |d: Duration| format_duration(Duration::from_micros(d.as_micros() as u64));
+        writeln!(
+            w,
+            "Checked {} bytes {} times on {} thread{}",
+            self.bytes,
+            self.passes,
+            self.threads,
+            if self.threads == 1 { "" } else { "s" }
+        )?;
+        writeln!(
+            w,
+            "Time per check: min {}, median {}, max {}",
+            round(self.min),
+            round(self.median),
+            round(self.max)
+        )?;
+        writeln!(w, "Throughput: {:.2} GB/s", self.gb_per_second)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_pass_statistics() {
+        let times = [30, 10, 20, 50, 40].map(Duration::from_millis).to_vec();
+        let benchmark = Benchmark::from_times(2_000_000_000, 1, times);
+        assert_eq!(benchmark.passes, 5);
+        assert_eq!(benchmark.min, Duration::from_millis(10));
+        assert_eq!(benchmark.median, Duration::from_millis(30));
+        assert_eq!(benchmark.max, Duration::from_millis(50));
+        assert!((benchmark.gb_per_second - 2.0 / 0.03).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,191 @@
+//! Canary pages with a known pattern that are allocated right before and after the detector memory, but are
+//! not part of it, so that a stray write of this or another program into the neighbourhood of the detector
+//! can be told apart from a bit flip: a write changes whole bytes of a canary, often many of them at once.
+//! On Unix every canary page sits between two guard pages that can not be accessed at all, so that a write
+//! that runs off the end of the detector memory faults right away instead of going unnoticed.
+
+use std::io;
+use std::ptr::read_volatile;
+#[cfg(unix)]
+use std::ptr::NonNull;
+
+use crate::pattern::Pattern;
+use crate::region::PAGE_SIZE;
+
+/// The pattern of the canary pages, which is unlike any pattern of the detector and of memory that was never written.
+const CANARY_PATTERN: Pattern = Pattern::Random(0xC0FF_EE00_CA9A_921E);
+
+/// A canary page of the detector that had been written to when it was checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanaryViolation {
+    /// The index of the canary among those of the detector, the one allocated before it is 0.
+    pub canary: usize,
+    /// How many bytes of the canary page no longer held its pattern.
+    pub changed_bytes: usize,
+    /// The index of the first of them in the canary page.
+    pub first: usize,
+    /// The value the first of them should have had.
+    pub expected: u8,
+    /// The value the first of them actually had.
+    pub found: u8,
+}
+
+/// A canary page, filled with its pattern.
+pub struct Canary {
+    #[cfg(unix)]
+    /// The mapping of the guard page, the canary page and the other guard page.
+    mapping: NonNull<u8>,
+    #[cfg(unix)]
+    page_size: usize,
+    #[cfg(not(unix))]
+    page: Vec<u8>,
+}
+
+// SAFETY: a canary owns its memory just like a Vec does, the pointer is never shared.
+unsafe impl Send for Canary {}
+unsafe impl Sync for Canary {}
+
+impl Canary {
+    /// Allocates a canary page between two guard pages and fills it with its pattern. Mappings that are made
+    /// one after the other usually end up next to each other, so this should be called right before and right after
+    /// the detector memory is allocated.
+    #[cfg(unix)]
+    pub fn allocate() -> io::Result<Self> {
+        let page_size =
+            usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).unwrap_or(PAGE_SIZE);
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                3 * page_size,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mapping = NonNull::new(ptr.cast()).expect("mmap does not return null on success");
+        let mut canary = Canary { mapping, page_size };
+        let page = unsafe { ptr.cast::<u8>().add(page_size) };
+        if unsafe { libc::mprotect(page.cast(), page_size, libc::PROT_READ | libc::PROT_WRITE) }
+            != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        canary.fill();
+        Ok(canary)
+    }
+
+    /// Allocates a canary page and fills it with its pattern. Without guard pages that fault
+    /// this should be called right before and right after the detector memory is allocated.
+    #[cfg(not(unix))]
+    pub fn allocate() -> io::Result<Self> {
+        let mut canary = Canary {
+            page: vec![0; PAGE_SIZE],
+        };
+        canary.fill();
+        Ok(canary)
+    }
+
+    fn page(&self) -> &[u8] {
+        #[cfg(unix)]
+        // SAFETY: the page between the guard pages is readable and writable, and only this canary refers to it.
+        return unsafe {
+            std::slice::from_raw_parts(self.mapping.as_ptr().add(self.page_size), self.page_size)
+        };
+        #[cfg(not(unix))]
+        return &self.page;
+    }
+
+    fn page_mut(&mut self) -> &mut [u8] {
+        #[cfg(unix)]
+        // SAFETY: as for `page`.
+        return unsafe {
+            std::slice::from_raw_parts_mut(
+                self.mapping.as_ptr().add(self.page_size),
+                self.page_size,
+            )
+        };
+        #[cfg(not(unix))]
+        return &mut self.page;
+    }
+
+    /// Writes the pattern to the canary page.
+    fn fill(&mut self) {
+        CANARY_PATTERN.fill(self.page_mut(), 0);
+    }
+
+    /// Checks the canary page, and if any byte of it changed reports them as the canary with the given index
+    /// and restores its pattern, so that the same write is reported only once.
+    pub fn check(&mut self, canary: usize) -> Option<CanaryViolation> {
+        let page = self.page();
+        let first = CANARY_PATTERN.verify(page, 0)?;
+        let changed_bytes = page[first..]
+            .iter()
+            .enumerate()
+            .filter(|&(i, r)| unsafe { read_volatile(r) } != CANARY_PATTERN.expected(first + i))
+            .count();
+        let violation = CanaryViolation {
+            canary,
+            changed_bytes,
+            first,
+            expected: CANARY_PATTERN.expected(first),
+            found: unsafe { read_volatile(&page[first]) },
+        };
+        self.fill();
+        Some(violation)
+    }
+
+    #[cfg(test)]
+    fn write(&mut self, index: usize, value: u8) {
+        unsafe { std::ptr::write_volatile(&mut self.page_mut()[index], value) };
+    }
+}
+
+impl Drop for Canary {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.mapping.as_ptr().cast(), 3 * self.page_size);
+        }
+    }
+}
+
+/// Checks all of the canaries and returns the ones that were written to, restoring them.
+pub fn check_canaries(canaries: &mut [Canary]) -> Vec<CanaryViolation> {
+    canaries
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, canary)| canary.check(i))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_canary_pages() {
+        let mut canaries = [Canary::allocate().unwrap(), Canary::allocate().unwrap()];
+        assert_eq!(check_canaries(&mut canaries), []);
+
+        let first = canaries[1].page()[10];
+        for i in 10..20 {
+            canaries[1].write(i, !CANARY_PATTERN.expected(i));
+        }
+        assert_eq!(
+            check_canaries(&mut canaries),
+            [CanaryViolation {
+                canary: 1,
+                changed_bytes: 10,
+                first: 10,
+                expected: first,
+                found: !first,
+            }]
+        );
+        // The canary was restored, so the write is only reported once.
+        assert_eq!(check_canaries(&mut canaries), []);
+    }
+}
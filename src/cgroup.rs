@@ -0,0 +1,196 @@
+//! Reading the memory limit of the control group this process runs in, which is what a container runtime
+//! enforces by killing the container, however much memory the host has. Both the unified hierarchy of cgroup v2
+//! and the memory controller of cgroup v1 are understood. Elsewhere than on Linux there are no limits to find.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+/// Limits of cgroup v1 at or above this are the "unlimited" of the kernel, which rounds `i64::MAX` down to a page.
+#[cfg(target_os = "linux")]
+const V1_UNLIMITED: u64 = 1 << 62;
+
+/// The memory limit of a cgroup and how much of it is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CgroupMemory {
+    /// The smallest limit of the cgroup and all of its ancestors, in bytes.
+    pub limit: u64,
+    /// The memory the cgroup uses, including the page cache, in bytes.
+    pub usage: u64,
+}
+
+impl CgroupMemory {
+    /// Returns how much more memory the cgroup can use before it hits its limit.
+    pub fn headroom(&self) -> u64 {
+        self.limit.saturating_sub(self.usage)
+    }
+}
+
+/// Returns the memory limit of the cgroup of this process, or `None` if it has none,
+/// or is not in a cgroup at all, or the cgroup file system is not mounted.
+pub fn memory() -> Option<CgroupMemory> {
+    #[cfg(target_os = "linux")]
+    {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+        read_memory(&mountinfo, &cgroups)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// The files of a cgroup version that hold the limit and the usage of memory.
+#[cfg(target_os = "linux")]
+struct Version {
+    limit: &'static str,
+    usage: &'static str,
+    unlimited: fn(&str) -> bool,
+}
+
+#[cfg(target_os = "linux")]
+const V2: Version = Version {
+    limit: "memory.max",
+    usage: "memory.current",
+    unlimited: |limit| limit == "max",
+};
+
+#[cfg(target_os = "linux")]
+const V1: Version = Version {
+    limit: "memory.limit_in_bytes",
+    usage: "memory.usage_in_bytes",
+    unlimited: |limit| limit.parse::<u64>().map_or(true, |l| l >= V1_UNLIMITED),
+};
+
+/// Finds the memory limit from the contents of `/proc/self/mountinfo` and `/proc/self/cgroup`,
+/// preferring cgroup v2 if the memory controller is available there.
+#[cfg(target_os = "linux")]
+fn read_memory(mountinfo: &str, cgroups: &str) -> Option<CgroupMemory> {
+    let v2 = cgroup_dir(
+        mountinfo,
+        cgroups,
+        |fs_type, _| fs_type == "cgroup2",
+        |controllers| controllers.is_empty(),
+    )
+    .filter(|dir| dir.join(V2.usage).exists());
+    match v2 {
+        Some(dir) => read_limit(&dir, &V2),
+        None => {
+            let dir = cgroup_dir(
+                mountinfo,
+                cgroups,
+                |fs_type, options| fs_type == "cgroup" && options.split(',').any(|o| o == "memory"),
+                |controllers| controllers.split(',').any(|c| c == "memory"),
+            )?;
+            read_limit(&dir, &V1)
+        }
+    }
+}
+
+/// Returns the directory of the cgroup of this process in the first mounted hierarchy that matches `mount`,
+/// which is given the file system type and the super block options, and whose line in `/proc/self/cgroup`
+/// matches `controllers`. The path of the cgroup is relative to the root of the hierarchy, of which
+/// a container usually only has its own part mounted.
+#[cfg(target_os = "linux")]
+fn cgroup_dir(
+    mountinfo: &str,
+    cgroups: &str,
+    mount: impl Fn(&str, &str) -> bool,
+    controllers: impl Fn(&str) -> bool,
+) -> Option<PathBuf> {
+    // Every line is like "36 35 98:0 /root /mnt/point rw,noatime shared:1 - cgroup2 cgroup2 rw",
+    // with any number of optional fields before the separator.
+    let (root, point) = mountinfo.lines().find_map(|line| {
+        let (mount_fields, fs_fields) = line.split_once(" - ")?;
+        let mut mount_fields = mount_fields.split(' ');
+        let root = mount_fields.nth(3)?;
+        let point = mount_fields.next()?;
+        let mut fs_fields = fs_fields.split(' ');
+        let fs_type = fs_fields.next()?;
+        let options = fs_fields.nth(1).unwrap_or("");
+        mount(fs_type, options).then_some((root, point))
+    })?;
+    // Every line is like "4:memory:/docker/123" for v1 and "0::/user.slice" for v2.
+    let path = cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let (_, names, path) = (fields.next()?, fields.next()?, fields.next()?);
+        controllers(names).then_some(path)
+    })?;
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    Some(Path::new(point).join(relative.trim_start_matches('/')))
+}
+
+/// Reads the usage of the cgroup in the directory and the smallest limit of it and its ancestors
+/// up to the root of the mounted hierarchy, or returns `None` if none of them has a limit.
+#[cfg(target_os = "linux")]
+fn read_limit(dir: &Path, version: &Version) -> Option<CgroupMemory> {
+    let usage = fs::read_to_string(dir.join(version.usage)).ok()?;
+    let limit = dir
+        .ancestors()
+        .map_while(|dir| fs::read_to_string(dir.join(version.limit)).ok())
+        .filter(|limit| !(version.unlimited)(limit.trim()))
+        .filter_map(|limit| limit.trim().parse::<u64>().ok())
+        .min()?;
+    Some(CgroupMemory {
+        limit,
+        usage: usage.trim().parse().ok()?,
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_cgroup_limits() {
+        let dir = TempDir::new("cgroup");
+        let write = |path: &str, contents: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+
+        // A container with its own part of the unified hierarchy mounted, inside a parent with a tighter limit.
+        write("v2/memory.max", "1000000\n");
+        write("v2/kubepods/memory.max", "max\n");
+        write("v2/kubepods/memory.current", "250000\n");
+        let mountinfo = format!(
+            "25 30 0:22 / /proc rw - proc proc rw\n\
+             31 25 0:26 /pod {} rw,nosuid shared:9 - cgroup2 cgroup2 rw\n",
+            dir.join("v2").display()
+        );
+        let memory = read_memory(&mountinfo, "0::/pod/kubepods\n").unwrap();
+        assert_eq!(
+            memory,
+            CgroupMemory {
+                limit: 1000000,
+                usage: 250000
+            }
+        );
+        assert_eq!(memory.headroom(), 750000);
+
+        // Without any limit there is nothing to respect.
+        write("v2/memory.max", "max\n");
+        assert_eq!(read_memory(&mountinfo, "0::/pod/kubepods\n"), None);
+
+        // cgroup v1, whose hierarchy is mounted as a whole.
+        write("v1/docker/abc/memory.limit_in_bytes", "2000000\n");
+        write("v1/docker/abc/memory.usage_in_bytes", "1000\n");
+        write("v1/memory.limit_in_bytes", "9223372036854771712\n");
+        let mountinfo = format!(
+            "40 30 0:35 / {} rw,relatime - cgroup cgroup rw,memory\n",
+            dir.join("v1").display()
+        );
+        let cgroups = "12:pids:/docker/abc\n4:memory:/docker/abc\n0::/\n";
+        assert_eq!(
+            read_memory(&mountinfo, cgroups),
+            Some(CgroupMemory {
+                limit: 2000000,
+                usage: 1000
+            })
+        );
+        assert_eq!(read_memory("", cgroups), None);
+    }
+}
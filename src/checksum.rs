@@ -0,0 +1,50 @@
+//! CRC32 checksums over fixed size blocks of the detector memory.
+
+use std::ptr::read_volatile;
+
+/// The number of bytes covered by every checksum.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// The lookup table of the reflected IEEE 802.3 polynomial, as used by zlib and friends.
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC32 of the buffer. Like everything else that reads the detector memory this uses volatile reads.
+pub fn crc32(buf: &[u8]) -> u32 {
+    !buf.iter().fold(!0, |crc, r| {
+        let b = unsafe { read_volatile(r) };
+        (crc >> 8) ^ TABLE[usize::from((crc as u8) ^ b)]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+}
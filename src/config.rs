@@ -1,68 +1,835 @@
-#[cfg(all(not(target_os = "windows"), not(target_os = "freebsd")))]
-use clap::ValueEnum;
-use clap::{ArgGroup, Parser};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
 use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::affinity::CpuSet;
+use crate::cgroup;
+use crate::duty_cycle::DutyCycle;
+use crate::pattern::{parse_pattern, Pattern};
+use crate::region::system_page_size;
+
 const DEFAULT_DELAY: &str = "30s";
+const DEFAULT_PATTERN: &str = "zeros";
 
-#[cfg(all(not(target_os = "windows"), not(target_os = "freebsd")))]
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum AllocationMode {
     Available,
     Free,
 }
 
+/// The order in which integrity checks read the detector memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ScanOrder {
+    /// From the first byte to the last.
+    #[default]
+    Sequential,
+    /// One cache line of every page at a time, so that every read has to go to the DRAM.
+    Strided,
+}
+
+/// The size of the words that integrity checks read and compare the detector memory in,
+/// and that multi-bit flips are grouped by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WordSize {
+    #[value(name = "1")]
+    U8,
+    #[value(name = "2")]
+    U16,
+    #[value(name = "4")]
+    U32,
+    #[default]
+    #[value(name = "8")]
+    U64,
+}
+
+impl WordSize {
+    /// Returns the number of bytes of a word.
+    pub fn bytes(self) -> usize {
+        match self {
+            WordSize::U8 => 1,
+            WordSize::U16 => 2,
+            WordSize::U32 => 4,
+            WordSize::U64 => 8,
+        }
+    }
+}
+
+/// The least severe messages that are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LogLevel {
+    /// Only errors.
+    Error,
+    /// Flips and warnings.
+    Warn,
+    /// Also the settings, heartbeats and pauses.
+    Info,
+    /// Also every integrity check.
+    Debug,
+    /// Everything.
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human readable text.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// When the text output is colored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColorChoice {
+    /// If the output is a terminal and NO_COLOR is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Returns whether to color output that goes to a writer which is a terminal or not.
+    pub(crate) fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// What to do instead of monitoring memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub(crate) enum Command {
+    /// Print the total, free and available memory, the cgroup memory limit, the page size, the huge pages,
+    /// the NUMA nodes and whether the memory has ECC as this program sees them, and exit.
+    /// Honours --format, so `info --format json` prints a single JSON object.
+    Info,
+    /// Print the JSON Schema of the events that --format json and the JSON log files write, and exit,
+    /// so that whatever reads them can validate them, and of the report that --report writes.
+    /// The webhook sends the flip events with the hostname added.
+    #[cfg(feature = "schema")]
+    Schema,
+}
+
 /// Monitors memory for bit-flips (won't work on ECC memory).
 /// The chance of detection scales with the physical size of your DRAM modules
 /// and the percentage of them you allocate to this program.
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+#[clap(
+    after_help = "Every option can also be set with the environment variable shown next to it, flags take \"true\" or \"false\". The command line takes precedence over the environment.\n\nThe exit status is 0 if no bit flips were detected, 10 if any were, 11 if --watchdog-abort gave up on a stalled check, 12 if --until-clean stopped before it checked a whole pass and 1 on errors.\n\nSending SIGUSR1 pauses checking, sending it again resumes it."
+)]
 #[clap(group(
     ArgGroup::new("detector memory size")
         .required(true)
-        .args(&["memory_to_monitor", "use_all"])
+        .args(&["memory_to_monitor", "use_all", "reference_file"])
+))]
+#[clap(group(
+    ArgGroup::new("check delay")
+        .args(&["delay_between_checks", "frequency"])
 ))]
 pub struct Cli {
-    #[arg(short, long, value_parser(parse_size_string))]
-    /// The size of the memory to monitor for bit flips, understands e.g. 200, 5kB, 2GB and 3Mb.
+    #[arg(long, value_name = "FILE", env = "COSMIC_CONFIG")]
+    /// Read settings from this TOML file. Its keys are the long names of the other flags, e.g. `memory-to-monitor = "4GB"`,
+    /// and anything given on the command line takes precedence over it.
+    pub(crate) config: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        value_parser(parse_memory_spec),
+        value_delimiter = ',',
+        env = "COSMIC_MEMORY_TO_MONITOR"
+    )]
+    /// The size of the memory to monitor for bit flips, understands e.g. 200, 5kB, 2GB, 3Mb, 8GiB and 1_500MB,
+    /// as well as a number of pages of this system like 1024p or of 64-byte cache lines like 512cl.
     /// If no suffix is given the program will assume that the given number is the number of bytes to monitor.
-    pub memory_to_monitor: Option<NonZeroUsize>,
+    /// A percentage, like 25%, is interpreted as that fraction of the total physical memory, or of the cgroup memory
+    /// limit of a container. "cgroup" takes 90% of the memory that the cgroup memory limit leaves.
+    /// A list of sizes like 1GB,2GB,4GB runs a staircase test, which runs the detector at every size in turn
+    /// for --duration or --max-checks each and reports every stage before the overall summary.
+    pub(crate) memory_to_monitor: Vec<MemorySpec>,
 
     // There is a difference between free and available memory,
     // and on most operating systems we can detect this difference.
     // This option lets the user specify which alternative they mean.
-    #[cfg(all(not(target_os = "windows"), not(target_os = "freebsd")))]
-    #[arg(long, value_enum, value_name = "ALLOCATION_MODE")]
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALLOCATION_MODE",
+        env = "COSMIC_USE_ALL"
+    )]
     /// Allocate as much memory as possible to the detector.
     /// If "free" is specified the program will allocate all currently unused memory,
     /// while if "available" is specified the program will also try to eject things that sit in memory
     /// but haven't been used in a while.
-    pub use_all: Option<AllocationMode>,
+    /// As that amount is only an estimate, whatever of it can not be committed is left out.
+    pub(crate) use_all: Option<AllocationMode>,
 
-    // On Windows and FreeBSD sysinfo has no way to differentiate free and available memory,
-    // so we just allocate as much as the OS gives us.
-    #[cfg(any(target_os = "windows", target_os = "freebsd"))]
-    #[arg(long)]
-    /// Allocate as much memory as possible to the detector.
-    pub use_all: bool,
+    #[arg(long, conflicts_with = "use_all", env = "COSMIC_FORCE")]
+    /// Allocate the detector even if it is larger than the available memory, instead of refusing to start.
+    pub(crate) force: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size_string, env = "COSMIC_RESERVE")]
+    /// The detector memory is allocated in chunks of 256MiB. Stop allocating before less than this much memory
+    /// would remain available to the rest of the system, e.g. "1GB". With --use-all this is left out from the start.
+    /// If not all of the memory could be allocated it is tried again with 90% of the size, down to half of the requested size,
+    /// below which the detector keeps whatever it could allocate.
+    pub(crate) reserve: Option<NonZeroUsize>,
+
+    #[arg(short, value_parser = parse_delay_string, default_value = DEFAULT_DELAY, env = "COSMIC_DELAY")]
+    /// The delay in between each integrity check, e.g. 30s, 5min or 1h 30min, or a plain number of seconds.
+    /// A delay of 0 runs the checks back to back.
+    pub(crate) delay_between_checks: Duration,
+
+    #[arg(long, value_name = "FREQUENCY", value_parser = parse_frequency, env = "COSMIC_FREQUENCY")]
+    /// Check the whole detector at this frequency instead of waiting a fixed delay between checks,
+    /// e.g. "2/min", "0.1Hz" or "30/h". The delay is chosen by timing a pass over the detector once it has been filled,
+    /// and is whatever the pass leaves of the period. If a pass takes longer than that the checks run back to back.
+    /// This is kept as the period, the time from the start of one pass to the next.
+    pub(crate) frequency: Option<Duration>,
+
+    #[arg(long, value_name = "ON/OFF", conflicts_with_all = ["delay_between_checks", "frequency", "jitter"], env = "COSMIC_DUTY_CYCLE")]
+    /// Check the detector in bursts, e.g. "1min/9min" to run the checks back to back for a minute and then
+    /// not at all for nine, over and over, to keep the heat and the power they cost down. A check that is still
+    /// running when a burst is over is finished first. Only the bursts count as monitored time,
+    /// which the GB-hours and the flip rate are based on.
+    pub(crate) duty_cycle: Option<DutyCycle>,
+
+    #[arg(long, value_name = "SLICES", num_args = 0..=1, default_missing_value = "64", env = "COSMIC_ROLLING")]
+    /// Check the detector memory one slice at a time instead of all of it at once, spreading the checks evenly
+    /// over the delay, so that every byte is checked once per delay wherever it is. The memory is split into
+    /// 64 slices unless given otherwise. Every check of a slice counts as an integrity check.
+    pub(crate) rolling: Option<NonZeroUsize>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_delay_string, conflicts_with_all = ["rolling", "frequency", "coverage", "snapshot"], env = "COSMIC_PASS_TIMEOUT")]
+    /// Stop every integrity check after this long, e.g. "30s", and carry on from where it stopped with the next one,
+    /// for detectors too large to be checked completely within a monitoring window. A check that runs out of time
+    /// reports the flips it found so far and how much of the detector it got through. The detector is checked
+    /// in slices of about 64MiB for that, so a check can run over by the time it takes to check one.
+    pub(crate) pass_timeout: Option<Duration>,
+
+    #[arg(long, value_name = "PERCENT", value_parser = parse_jitter, env = "COSMIC_JITTER")]
+    /// Randomize every delay between integrity checks within this percentage of it in either direction, e.g. "20%",
+    /// so that the checks do not run in step with other periodic activity on the machine. The delays still average
+    /// out to the configured one, also with --frequency and the checks of the slices of --rolling.
+    /// With --verbose every delay is logged.
+    pub(crate) jitter: Option<f64>,
+
+    #[arg(long, value_name = "LOAD", value_parser = parse_load, env = "COSMIC_THROTTLE")]
+    /// Slow the integrity checks down while the 1-minute load average of the machine is above this, e.g. "4",
+    /// so that the detector leaves the memory bandwidth to the rest of a busy machine. Every step doubles the time
+    /// from the start of one check to the next and halves the threads they run on, up to 16 times slower,
+    /// and once the load is below 80% of this the checks speed up again a step at a time. On Windows, which has
+    /// no load average, the share of the time the CPUs are busy times their number is used. Every step is logged
+    /// with -v.
+    pub(crate) throttle: Option<f64>,
+
+    #[arg(long, value_name = "PERCENT", value_parser = parse_coverage, conflicts_with_all = ["rolling", "ecc_emulate"], env = "COSMIC_COVERAGE")]
+    /// Only verify this percentage of the pages of the detector memory in every integrity check, e.g. "10%",
+    /// for detectors too large to be checked completely at the desired rate. The pages are picked pseudo-randomly
+    /// with the --seed, and every check moves on to pages that have not been verified yet, so that all of them have
+    /// been verified after 100 / PERCENT checks. A flip in a page that is not verified is found and scrubbed once
+    /// the page's turn comes, which may be several checks after it happened.
+    pub(crate) coverage: Option<f64>,
+
+    #[arg(long, value_parser = parse_delay_string, env = "COSMIC_DURATION")]
+    /// Stop after running for this long. The last integrity check is performed when the time is up,
+    /// so a duration of 0s performs exactly one check. Runs forever if neither this nor --max-checks is given.
+    /// In a staircase test this is the duration of every stage.
+    pub(crate) duration: Option<Duration>,
+
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), env = "COSMIC_MAX_CHECKS")]
+    /// Stop after this many integrity checks, or in a staircase test move on to the next stage.
+    pub(crate) max_checks: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["duration", "max_checks", "rolling"], env = "COSMIC_ONCE")]
+    /// Fill the detector, wait one --delay-between-checks, check all of it once, print the summary and exit,
+    /// for running from cron or as a check of a monitoring system like Nagios. Same as --max-checks 1.
+    /// The exit status tells whether any flips were detected, and a delay of 0s checks right after filling.
+    pub(crate) once: bool,
+
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_interval_string, env = "COSMIC_HEARTBEAT")]
+    /// Report the uptime and the number of checks and flips so far at this interval, e.g. "10min",
+    /// independently of the delay between checks. Off by default.
+    pub(crate) heartbeat: Option<Duration>,
+
+    #[arg(long, value_parser = parse_pattern, default_value = DEFAULT_PATTERN, env = "COSMIC_PATTERN")]
+    /// The pattern to fill the detector memory with.
+    /// One of "zeros", "ones", "alternating" (0xAA and 0x55), "walking-ones" (0x01, 0x02, 0x04 and so on),
+    /// "walking-zeros" (0xFE, 0xFD, 0xFB and so on), "random" or a hex byte like "0x3C".
+    pub(crate) pattern: Pattern,
+
+    #[arg(long, env = "COSMIC_SEED")]
+    /// The seed of the random pattern, and of the pages picked for --coverage. A new one is picked for every run
+    /// if this is not given, and printed at startup so that the run can be reproduced.
+    pub(crate) seed: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["pattern", "ecc_emulate", "anti_dedup", "backing_file", "benchmark"],
+        env = "COSMIC_REFERENCE_FILE"
+    )]
+    /// Fill the detector memory with the contents of this file instead of a pattern, to watch over that data.
+    /// The detector is as large as the file, and is checked by the CRC32 of every 4KiB block like with --checksum.
+    /// The file is only read again to find the flipped bytes of a block whose checksum changed, unlike
+    /// the --backing-file it is never written to, and it must not change while the detector runs.
+    pub(crate) reference_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "pattern", "reference_file", "checksum", "ecc_emulate", "anti_dedup", "mirror", "rolling",
+            "coverage", "refill_interval", "stuck_threshold", "backing_file", "benchmark",
+        ],
+        env = "COSMIC_SNAPSHOT"
+    )]
+    /// Watch whatever the detector memory holds when it is allocated instead of filling it with a pattern.
+    /// Before the first check the CRC32 of every 4KiB block is recorded, and every check reports the blocks
+    /// whose checksum changed since. Which bytes of a block changed is not known without a pattern.
+    /// Memory fresh from the operating system reads as zeros until it is written to.
+    pub(crate) snapshot: bool,
+
+    #[arg(long, env = "COSMIC_CHECKSUM")]
+    /// Check the detector memory by comparing the CRC32 of every 4KiB block with the one computed when it was filled,
+    /// which only takes 0.1% of extra memory. Only blocks whose checksum changed are compared to the pattern byte by byte.
+    pub(crate) checksum: bool,
+
+    #[arg(long, conflicts_with = "checksum", env = "COSMIC_ECC_EMULATE")]
+    /// Store SECDED Hamming check bits for every 64-bit word of the detector memory, like ECC memory does.
+    /// Single bit flips are then corrected when the detector is scrubbed and double bit flips are reported as uncorrectable.
+    /// The check bits take up one ninth of the detector memory.
+    pub(crate) ecc_emulate: bool,
+
+    #[arg(long, conflicts_with = "backing_file", env = "COSMIC_ANTI_DEDUP")]
+    /// Write the index of every page into its first eight bytes and check those too, so that no two pages
+    /// of the detector are the same. Meant for virtual machines and containers, where KSM or the hypervisor
+    /// could otherwise merge the identical pages into one and hide the flips in the RAM of the others.
+    pub(crate) anti_dedup: bool,
+
+    #[arg(long, value_name = "COPIES", value_parser = clap::value_parser!(u8).range(2..=3), conflicts_with_all = ["ecc_emulate", "reference_file"], env = "COSMIC_MIRROR")]
+    /// Divide the detector memory into 2 or 3 copies and compare the byte of every flip with the same byte of the other copies,
+    /// reporting which copy diverged. With 3 copies they vote on the value the byte should have, which corrects a flip
+    /// in any of them like ECC does. Only a half or a third of the detector then counts as separate memory.
+    pub(crate) mirror: Option<u8>,
+
+    #[arg(long, value_enum, default_value_t = ScanOrder::Sequential, env = "COSMIC_SCAN_ORDER")]
+    /// The order in which integrity checks read the detector memory. Reading it sequentially lets the hardware prefetcher
+    /// hide the DRAM latency, reading it strided makes sure that every check actually reads the DRAM rather than the cache.
+    /// Checksums are always computed sequentially.
+    pub(crate) scan_order: ScanOrder,
+
+    #[arg(long, value_enum, default_value_t = WordSize::U64, conflicts_with = "ecc_emulate", env = "COSMIC_WORD_SIZE")]
+    /// The number of bytes that integrity checks read and compare at a time, and that flips are classified by:
+    /// all the flipped bits of a word count towards whether it was a single, double or multi-bit flip.
+    /// Larger words are faster on 64-bit machines, a word size of 1 groups flips by byte.
+    /// Constant patterns are compared many bytes at a time whatever the word size, and ECC emulation always uses 8 byte words.
+    pub(crate) word_size: WordSize,
+
+    #[arg(long, env = "COSMIC_SKIP_SELF_TEST")]
+    /// Start monitoring right after filling the detector, instead of first reading all of it back to make sure
+    /// that it holds the pattern. A mismatch at that point is not a flip but a bug or failing memory,
+    /// and stops the program before it starts monitoring.
+    pub(crate) skip_self_test: bool,
+
+    #[arg(long, env = "COSMIC_NO_SCRUB")]
+    /// Leave flipped bytes as they are instead of rewriting them with the pattern after they have been reported.
+    /// This preserves the corrupted state for inspection, but every later check will report the same flips again.
+    pub(crate) no_scrub: bool,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_delay_string, env = "COSMIC_REFILL_INTERVAL")]
+    /// Rewrite all of the detector memory with the pattern this often, e.g. "24h", right after an integrity check
+    /// so that no flip is lost, instead of only rewriting the bytes that flipped. Every refill starts a fresh
+    /// detection window, and the time it takes does not count as monitored.
+    pub(crate) refill_interval: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "TIMES",
+        default_value_t = 1,
+        env = "COSMIC_REREADS"
+    )]
+    /// Read every mismatching byte again up to this many times, from the memory rather than the CPU cache where possible,
+    /// and report the flip as transient if it reads as expected at any of those times. A bit that flipped in the DRAM
+    /// stays flipped until it is rewritten, so transient flips point to a glitch elsewhere, like on the memory bus.
+    /// With 0 every flip is reported as persistent.
+    pub(crate) rereads: usize,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "no_scrub", env = "COSMIC_STUCK_THRESHOLD")]
+    /// Report a suspected stuck bit once the same byte has flipped more than N times, as a cosmic ray hardly ever
+    /// hits the same byte twice while failing memory flips the same bits again and again. The summary lists
+    /// the bytes that flipped most often either way.
+    pub(crate) stuck_threshold: Option<u64>,
+
+    #[arg(long, requires = "stuck_threshold", env = "COSMIC_EXCLUDE_STUCK")]
+    /// Stop monitoring the page of a suspected stuck bit, so that it does not report the same flips over and over.
+    /// The page is still read by every check, but its flips are ignored and left as they are, and it no longer
+    /// counts towards the monitored size of the summary and the flip rate.
+    pub(crate) exclude_stuck: bool,
+
+    #[arg(long, env = "COSMIC_CANARY")]
+    /// Allocate a canary page right before and right after the detector memory, fill them with a pattern of their own
+    /// and check them along with the detector. A canary that changed was written to by this or another program
+    /// rather than hit by a cosmic ray, which is reported separately from the flips. On Unix every canary page sits
+    /// between guard pages, so that a write that runs past it crashes right away.
+    pub(crate) canary: bool,
+
+    #[arg(long, env = "COSMIC_FAIL_ON_FLIP")]
+    /// Stop right after the first integrity check that detects a flip, instead of running to the end.
+    pub(crate) fail_on_flip: bool,
+
+    #[arg(
+        long,
+        value_name = "ATTEMPTS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["once", "coverage", "fail_on_flip", "no_scrub"],
+        env = "COSMIC_UNTIL_CLEAN"
+    )]
+    /// Stop as soon as a whole pass over the detector memory found no flips, after waiting the usual delay before it,
+    /// here at most this many passes, to verify that the memory is consistent. The flips of a pass are reported
+    /// and scrubbed as usual, so that every pass only finds what flipped since the one before it, and a single
+    /// flip in any check of a pass spoils it. The exit status is 10 if any pass found flips, also when a later one
+    /// was clean, 0 if the first pass was clean and 12 if the run stopped before a whole pass without finding any.
+    pub(crate) until_clean: Option<u64>,
+
+    #[arg(long, env = "COSMIC_WATCHDOG")]
+    /// Warn when an integrity check does not finish in time, e.g. because reading a failing page hangs.
+    /// A check may take a minute or four times as long as the slowest one so far, whichever is longer.
+    pub(crate) watchdog: bool,
+
+    #[arg(long, requires = "watchdog", env = "COSMIC_WATCHDOG_ABORT")]
+    /// Exit with the status 11 when the watchdog notices a stalled check, without a summary,
+    /// so that a supervisor like systemd can restart the detector.
+    pub(crate) watchdog_abort: bool,
+
+    #[arg(long, value_name = "PAGE_SIZE", num_args = 0..=1, default_missing_value = "2MiB", value_parser = parse_size_string, env = "COSMIC_HUGE_PAGES")]
+    /// Back the detector memory with huge pages (Linux only), which makes each integrity check of a large detector faster.
+    /// The page size defaults to 2MiB. Falls back to normal pages with a warning if no huge pages are available.
+    pub(crate) huge_pages: Option<NonZeroUsize>,
+
+    #[arg(long, env = "COSMIC_ALIGN_PAGES")]
+    /// Only monitor the whole pages of every allocation, so that the detector memory starts and ends on page boundaries,
+    /// leaving out the parts of pages at either end that it shares with the bookkeeping of the allocator.
+    /// How many bytes that trims off the allocations is printed at startup. Huge pages are always aligned.
+    pub(crate) align_pages: bool,
+
+    #[arg(long, env = "COSMIC_LOCK_MEMORY")]
+    /// Lock the detector memory into RAM so that it can not be swapped out.
+    /// This may require raising the limit on locked memory, e.g. with `ulimit -l`.
+    pub(crate) lock_memory: bool,
+
+    #[arg(long, env = "COSMIC_PHYSICAL_ADDRESSES")]
+    /// Report the physical address of every flipped byte, so that it can be traced back to a DIMM (Linux only).
+    /// This requires CAP_SYS_ADMIN, without it flips are reported by their index in the detector memory only.
+    pub(crate) physical_addresses: bool,
+
+    #[arg(long, requires = "physical_addresses", env = "COSMIC_DIMM_SLOTS")]
+    /// Read the inventory of memory modules from the SMBIOS tables of the firmware at startup (Linux only),
+    /// and name the DIMM slot that the physical address of every flip most likely belongs to.
+    /// This usually requires root, without it flips are reported by their physical address only.
+    pub(crate) dimm_slots: bool,
 
-    #[arg(short, value_parser = parse_delay_string, default_value = DEFAULT_DELAY)]
-    /// The delay in between each integrity check.
-    pub delay_between_checks: Duration,
+    #[arg(long, env = "COSMIC_VIRTUAL_ADDRESSES")]
+    /// Also report the address of every flipped byte in the address space of this process,
+    /// besides its index in the detector memory.
+    pub(crate) virtual_addresses: bool,
 
-    #[arg(long)]
-    /// Run the integrity check in parallel.
-    pub parallel: bool,
+    #[arg(long, env = "COSMIC_CORRELATE_MCE")]
+    /// Read the counters of corrected and uncorrected memory errors that the EDAC drivers keep (Linux only)
+    /// at every check, and mark the flips of a check during whose interval they went up as correlated
+    /// with a machine check exception. Without EDAC the flips are reported without it.
+    pub(crate) correlate_mce: bool,
 
-    #[arg(short, long)]
-    /// Print extra information.
-    pub verbose: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "huge_pages",
+        env = "COSMIC_BACKING_FILE"
+    )]
+    #[cfg_attr(feature = "numa", arg(conflicts_with = "numa_node"))]
+    /// Keep the detector memory in this file instead of anonymous memory (Unix only), so that it survives
+    /// a restart of the detector. If the file holds a detector of the same size, pattern and ECC emulation
+    /// from an earlier run, checking carries on where it stopped instead of filling the memory anew.
+    /// The flips that happened while no detector was running are then found by the parity the file keeps
+    /// of every 4 KiB block, and reported as flips during downtime rather than by the first check.
+    /// Pass the same --seed to carry on with the random pattern.
+    pub(crate) backing_file: Option<PathBuf>,
+
+    #[cfg(feature = "numa")]
+    #[arg(
+        long,
+        visible_alias = "numa",
+        value_name = "NODE",
+        env = "COSMIC_NUMA_NODE"
+    )]
+    /// Put the detector memory on this NUMA node and run the checks on its CPUs (Linux only),
+    /// or with "all" put an equal share of it on every node. The summary breaks the flips down by node.
+    pub(crate) numa_node: Option<crate::numa::NumaNodes>,
+
+    #[arg(long, env = "COSMIC_PARALLEL")]
+    /// Run the integrity check in parallel on all available cores. Same as --threads with the number of cores.
+    pub(crate) parallel: bool,
+
+    #[arg(long, conflicts_with = "parallel", env = "COSMIC_THREADS")]
+    /// Split the detector memory into about this many chunks and fill and check them on this many threads.
+    pub(crate) threads: Option<NonZeroUsize>,
+
+    #[arg(long, env = "COSMIC_PIN_THREADS")]
+    #[cfg_attr(feature = "numa", arg(conflicts_with = "numa_node"))]
+    /// Pin every thread that fills and checks the detector memory to a CPU core (Linux and Windows only),
+    /// one after the other, so that the scheduler does not move them around and the time a check takes
+    /// can be compared between runs. The core every thread landed on is printed at startup.
+    pub(crate) pin_threads: bool,
+
+    #[arg(long, value_name = "CPUS", env = "COSMIC_CPU_SET")]
+    #[cfg_attr(feature = "numa", arg(conflicts_with = "numa_node"))]
+    /// The CPU cores to pin the threads to, like 0-3 or 0,2,4-7, instead of all of the cores this process may run on.
+    /// Implies --pin-threads.
+    pub(crate) cpu_set: Option<CpuSet>,
+
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Count,
+        value_parser = parse_verbosity,
+        conflicts_with = "log_level",
+        env = "COSMIC_VERBOSE"
+    )]
+    /// Print extra information, given once the same as --log-level info and twice the same as --log-level debug.
+    /// The environment variable takes "true" or "false" like the other flags, or the number of times, e.g. COSMIC_VERBOSE=2.
+    pub(crate) verbose: u8,
+
+    #[arg(long, value_enum, value_name = "LEVEL", env = "COSMIC_LOG_LEVEL")]
+    /// Print the messages of this level and the more severe ones.
+    /// Flips are warnings, the settings and heartbeats are info and every integrity check is debug. Defaults to warn.
+    pub(crate) log_level: Option<LogLevel>,
+
+    #[arg(long, env = "COSMIC_DRY_RUN")]
+    /// Print how much memory would be allocated and with which settings, then exit without allocating anything.
+    pub(crate) dry_run: bool,
+
+    #[arg(
+        long,
+        value_name = "CHECKS",
+        num_args = 0..=1,
+        default_missing_value = "10",
+        conflicts_with = "dry_run",
+        value_parser = clap::value_parser!(u64).range(1..),
+        env = "COSMIC_BENCHMARK"
+    )]
+    /// Allocate and fill the detector, time this many integrity checks of all of it back to back (10 if no number
+    /// is given) and print how long they took and how many GB/s they checked, then exit.
+    /// Helps choosing a delay that is long compared to a check, together with --threads, --checksum and --scan-order.
+    pub(crate) benchmark: Option<u64>,
+
+    #[arg(long, env = "COSMIC_ALLOW_ECC")]
+    /// Don't warn when the machine appears to have ECC memory.
+    pub(crate) allow_ecc: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true, env = "COSMIC_FORMAT")]
+    /// The format of the output.
+    pub(crate) format: OutputFormat,
+
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, env = "COSMIC_COLOR")]
+    /// Whether to highlight bit flips and headers of the text output with colors.
+    /// By default they are when the output is a terminal, unless the NO_COLOR environment variable is set.
+    pub(crate) color: ColorChoice,
+
+    #[arg(long, value_name = "N", env = "COSMIC_MAX_EVENTS_PER_CHECK")]
+    /// Report at most this many flips of an integrity check one by one, and only how many more there were
+    /// beyond that, so that failing memory with millions of flips does not flood the output.
+    /// The summary, the report and the metrics still count all of them.
+    pub(crate) max_events_per_check: Option<NonZeroUsize>,
+
+    #[arg(long, value_name = "NAME", env = "COSMIC_TAG")]
+    /// A name for this instance of the detector, which is part of every JSON event, the metrics, the syslog messages,
+    /// the webhook requests and the report, so that the events of several instances can be told apart where they
+    /// are collected. Defaults to the host name.
+    pub(crate) tag: Option<String>,
+
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "daemonize", env = "COSMIC_TUI")]
+    /// Show a live dashboard that takes over the terminal, with the uptime, the checks and flips so far,
+    /// the flips of the recent checks and the latest events. Press q or Ctrl-C to stop, after which
+    /// the summary is printed as usual. Warnings still go to stderr, which is best redirected to a file.
+    pub(crate) tui: bool,
+
+    #[arg(long, value_name = "[FORMAT:]PATH", value_parser = parse_log_file, env = "COSMIC_LOG_FILE")]
+    /// Also append every event, with a timestamp, to this file. Can be given several times to write several files.
+    /// The file is written in the --format, unless the path is prefixed with text: or json:,
+    /// e.g. --log-file json:events.ndjson for one JSON object per line next to text on the terminal.
+    /// Every event is synced to disk as soon as it has been written.
+    pub(crate) log_file: Vec<LogFileSpec>,
+
+    #[arg(long, value_name = "SIZE", requires = "log_file", value_parser = parse_size_string, env = "COSMIC_LOG_ROTATE_SIZE")]
+    /// Once the log file has grown to this size it is renamed to <PATH>.1, <PATH>.2 and so on, and a new one is started.
+    pub(crate) log_rotate_size: Option<NonZeroUsize>,
+
+    #[cfg(feature = "compression")]
+    #[arg(long, requires = "log_file", env = "COSMIC_LOG_COMPRESS")]
+    /// Compress the log file with zstd. Every rotated segment is a complete zstd stream of its own,
+    /// and the current one can be read up to its last event after a crash, e.g. with `zstdcat`.
+    /// The rotation size is that of the compressed file.
+    pub(crate) log_compress: bool,
+
+    #[arg(long, value_name = "PATH", env = "COSMIC_CSV")]
+    /// Also append a row for every integrity check to this CSV file, with the columns timestamp (RFC 3339),
+    /// check_number, flips_this_check, cumulative_flips (since the start of this run) and monitored_bytes.
+    /// The header row is only written to a new or empty file.
+    pub(crate) csv: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", env = "COSMIC_REPORT")]
+    /// Write a JSON report of the run to this file when the detector stops, also after Ctrl-C or SIGTERM: when it
+    /// started and stopped, the arguments and COSMIC_* environment variables it ran with and the statistics of the summary.
+    /// The report replaces the file at once, so that it never holds half of one.
+    pub(crate) report: Option<PathBuf>,
+
+    #[arg(long, requires = "log_file", conflicts_with_all = ["dry_run", "benchmark"], env = "COSMIC_DAEMONIZE")]
+    /// Fork into the background and detach from the terminal (Unix only). The events then only go to the --log-file,
+    /// and the warnings and errors to the --stderr-file, if there is one. Stop the daemon with SIGTERM, which it answers like Ctrl-C.
+    /// On Windows run the detector as a service with a service wrapper instead.
+    pub(crate) daemonize: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "daemonize",
+        env = "COSMIC_STDERR_FILE"
+    )]
+    /// Append the warnings and errors of the daemon to this file, which is left alone by --log-rotate-size.
+    /// Without it they are discarded once the detector runs in the background.
+    pub(crate) stderr_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", env = "COSMIC_PID_FILE")]
+    /// Write the process ID to this file, and remove it again on exit.
+    /// Refuses to start if the file holds the ID of a process that is still running.
+    pub(crate) pid_file: Option<PathBuf>,
+
+    #[arg(long, env = "COSMIC_DROP_PRIVS")]
+    /// Drop all capabilities once the detector memory has been set up, before the checks start (Linux only),
+    /// so that a detector started as root for --lock-memory, --physical-addresses or --dimm-slots does not
+    /// go on checking it with the privileges of root. A staircase run with --lock-memory keeps CAP_IPC_LOCK
+    /// to lock the memory of the later stages.
+    pub(crate) drop_privs: bool,
+
+    #[cfg(feature = "notify")]
+    #[arg(long, env = "COSMIC_NOTIFY")]
+    /// Show a desktop notification when a flip is detected. Flips within a minute of the last notification
+    /// are summed up in a single notification.
+    pub(crate) notify: bool,
+
+    #[cfg(all(unix, feature = "syslog"))]
+    #[arg(long, env = "COSMIC_SYSLOG")]
+    /// Also send startup, shutdown and every flip to the local syslog daemon. Flips are logged
+    /// as warnings, and as critical if they are part of a multi-bit flip of their word.
+    pub(crate) syslog: bool,
+
+    #[cfg(feature = "webhook")]
+    #[arg(long, value_name = "URL", env = "COSMIC_WEBHOOK_URL")]
+    /// Send an HTTP POST with a JSON description of every detected flip to this URL.
+    pub(crate) webhook_url: Option<String>,
+
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "ADDRESS", env = "COSMIC_METRICS_ADDR")]
+    /// Serve Prometheus metrics at http://<ADDRESS>/metrics, e.g. 127.0.0.1:9184.
+    pub(crate) metrics_addr: Option<SocketAddr>,
+
+    #[cfg(feature = "metrics")]
+    #[arg(long, requires = "metrics_addr", env = "COSMIC_METRICS_EXEMPLARS")]
+    /// Attach the offset and direction of the latest flip, and its physical address if known, to the flip counter
+    /// as an exemplar, so that a tracing backend can jump from a spike of the counter to the byte that flipped.
+    /// Exemplars are part of the OpenMetrics format, which is served to scrapers that ask for it
+    /// like Prometheus with exemplar storage enabled. The others get the plain format without them.
+    pub(crate) metrics_exemplars: bool,
+
+    #[cfg(feature = "statsd")]
+    #[arg(long, value_name = "ADDRESS", env = "COSMIC_STATSD_ADDR")]
+    /// Push StatsD counters and gauges of the flips, checks and monitored bytes over UDP to this address,
+    /// e.g. localhost:8125, at the --heartbeat interval or every 10s without one.
+    pub(crate) statsd_addr: Option<String>,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    #[arg(skip)]
+    /// The arguments the detector was started with after the first, including the ones read from the config file.
+    pub(crate) arguments: Vec<String>,
+
+    #[arg(skip)]
+    /// The `COSMIC_*` environment variables that set an option, with their values, which are not among the arguments.
+    pub(crate) environment: Vec<(String, String)>,
 }
 
+impl Cli {
+    /// Returns the least severe level of the messages to print, from --log-level or the number of -v flags.
+    pub(crate) fn log_level(&self) -> LevelFilter {
+        match (self.log_level, self.verbose) {
+            (Some(level), _) => level.into(),
+            (None, 0) => LevelFilter::Warn,
+            (None, 1) => LevelFilter::Info,
+            (None, 2) => LevelFilter::Debug,
+            (None, _) => LevelFilter::Trace,
+        }
+    }
+}
+
+/// A file that events are appended to, and the format to write them in if it differs from --format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LogFileSpec {
+    pub format: Option<OutputFormat>,
+    pub path: PathBuf,
+}
+
+/// Parses a path to a log file, optionally prefixed with the format of the file, like "json:events.ndjson".
+/// Anything before a colon that is not a format, like the drive of a Windows path, is part of the path.
+fn parse_log_file(s: &str) -> Result<LogFileSpec, String> {
+    let (format, path) = match s.split_once(':') {
+        Some((format, path)) => match OutputFormat::from_str(format, false) {
+            Ok(format) => (Some(format), path),
+            Err(_) => (None, s),
+        },
+        None => (None, s),
+    };
+    if path.is_empty() {
+        return Err("the path of the log file is empty".to_owned());
+    }
+    Ok(LogFileSpec {
+        format,
+        path: PathBuf::from(path),
+    })
+}
+
+/// A requested detector size, either as an exact number of bytes, as a percentage of the total physical memory
+/// or as a share of the memory limit of the cgroup of this process, the latter two of which are resolved at startup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum MemorySpec {
+    Bytes(NonZeroUsize),
+    Percent(f64),
+    Cgroup,
+}
+
+/// The share of what the cgroup memory limit leaves that a detector sized by it takes,
+/// leaving the rest to the page cache and whatever else runs in the container.
+const CGROUP_SHARE: f64 = 0.9;
+
+impl MemorySpec {
+    /// Returns the number of bytes this spec corresponds to on a system with the given amount of total memory.
+    pub(crate) fn resolve(self, total_memory: u64) -> Result<NonZeroUsize, String> {
+        match self {
+            MemorySpec::Bytes(b) => Ok(b),
+            MemorySpec::Percent(p) => {
+                let num_bytes = checked_byte_count(total_memory as f64 * p / 100.0, usize::MAX)
+                    .map_err(|e| format!("{p}% of the total memory is {e}"))?;
+                NonZeroUsize::new(num_bytes)
+                    .ok_or_else(|| format!("{p}% of the total memory is less than one byte"))
+            }
+            MemorySpec::Cgroup => {
+                let memory = cgroup::memory().ok_or(
+                    "this program does not run in a cgroup with a memory limit, please specify the detector size in bytes",
+                )?;
+                let num_bytes =
+                    checked_byte_count(memory.headroom() as f64 * CGROUP_SHARE, usize::MAX)
+                        .map_err(|e| format!("the share of the cgroup memory limit is {e}"))?;
+                NonZeroUsize::new(num_bytes).ok_or_else(|| {
+                    format!(
+                        "the cgroup memory limit of {} bytes is used up already",
+                        memory.limit
+                    )
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MemorySpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemorySpec::Bytes(b) => write!(f, "{b} bytes"),
+            MemorySpec::Percent(p) => write!(f, "{p}% of the total memory"),
+            MemorySpec::Cgroup => write!(
+                f,
+                "{}% of what the cgroup memory limit leaves",
+                CGROUP_SHARE * 100.0
+            ),
+        }
+    }
+}
+
+/// Parses a string describing the size of the detector.
+/// A trailing '%' means a percentage of the total memory and 'cgroup' a share of the cgroup memory limit,
+/// anything else is given to [`parse_size_string`].
+pub(crate) fn parse_memory_spec(s: &str) -> Result<MemorySpec, String> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("cgroup") {
+        return Ok(MemorySpec::Cgroup);
+    }
+    match s.strip_suffix('%') {
+        Some(number) => {
+            let percent: f64 = number
+                .trim_end()
+                .parse()
+                .map_err(|_| format!("could not interpret '{number}' as a number"))?;
+            if !percent.is_finite() {
+                Err(format!(
+                    "the percentage must be a finite number, not {percent}"
+                ))
+            } else if percent <= 0.0 || percent > 100.0 {
+                Err(format!(
+                    "the percentage must be above 0 and at most 100, not {percent}"
+                ))
+            } else {
+                Ok(MemorySpec::Percent(percent))
+            }
+        }
+        None => parse_size_string(s).map(MemorySpec::Bytes),
+    }
+}
+
+/// The number of bytes of a cache line, which sizes with the suffix 'cl' count in.
+const CACHE_LINE_SIZE: usize = 64;
+
 /// Parses a string describing a number of bytes into an integer.
-/// The string can use common SI prefixes as well, like '4GB' or '30kB'.
-pub fn parse_size_string(size_string: &str) -> Result<NonZeroUsize, String> {
+/// The string can use common SI prefixes as well, like '4GB' or '30kB',
+/// or binary prefixes, like '8GiB' or '512KiB'. It can also count pages of this system, like '1024p',
+/// or cache lines of 64 bytes, like '512cl'. Fractions of a byte are dropped, and a size that comes to
+/// less than one byte, like '0.6B' or '0.0001kB', is an error that tells how many bytes it came to.
+pub(crate) fn parse_size_string(size_string: &str) -> Result<NonZeroUsize, String> {
+    parse_size_with_page_size(size_string, system_page_size())
+}
+
+/// Like [`parse_size_string`], with the size of the pages that the suffix 'p' counts, or `None` if it is unknown.
+fn parse_size_with_page_size(
+    size_string: &str,
+    page_size: Option<usize>,
+) -> Result<NonZeroUsize, String> {
+    let size_string = size_string.trim();
+    let input = size_string;
+    let number_end = size_string
+        .find(|c: char| !c.is_ascii_digit() && c != '.' && c != '_')
+        .unwrap_or(size_string.len());
+    let (number, suffix) = size_string.split_at(number_end);
+    let size_string = format!("{}{suffix}", strip_separators(number)?);
+    let size_string = size_string.as_str();
     match size_string.parse() {
         // The input was a number, interpret it as the number of bytes if nonzero.
         Ok(t) => NonZeroUsize::new(t).ok_or_else(|| "zero is not a valid value".to_owned()),
@@ -74,47 +841,159 @@ pub fn parse_size_string(size_string: &str) -> Result<NonZeroUsize, String> {
                 .position(|c| !c.is_ascii_digit() && c != '.')
             {
                 Some(index) => Ok(size_string.split_at(index)),
+                // An integer that does not fit in a usize, e.g. more than 4GB on a 32-bit platform.
+                None if size_string.chars().all(|c| c.is_ascii_digit()) => Err(format!(
+                    "{size_string} bytes is more than this platform can address"
+                )),
                 None => Err("you need to specify a suffix to use non-integer numbers".to_owned()),
             }?;
+            // People tend to put a space between the number and the unit, as in "1.5 GB".
+            let suffix = suffix.trim_start();
 
             // Parse the number part
-            let mut num_bytes: f64 = number
+            let num_bytes: f64 = number
                 .parse()
                 .map_err(|_| format!("could not interpret '{number}' as a number"))?;
-
-            if suffix.len() > 2 {
-                return Err("the suffix can be at most two letters long".to_owned());
+            let multiplier = match suffix {
+                "p" => page_size.ok_or_else(|| {
+                    "the page size of this system is unknown, so the size can not be given in pages"
+                        .to_owned()
+                })? as f64,
+                "cl" => CACHE_LINE_SIZE as f64,
+                _ => suffix_multiplier(suffix).map_err(|e| match correct_case(suffix) {
+                    // Guessing wrong would be off by a factor of a thousand or more, so the fix is only suggested.
+                    Some(corrected) => format!(
+                        "{e}, did you mean {}{corrected}?",
+                        &input[..input.len() - suffix.len()]
+                    ),
+                    None => e,
+                })?,
+            };
+            let num_bytes = num_bytes * multiplier;
+            // Rounding a fraction of a byte up to a whole one would make up a size nobody asked for.
+            if num_bytes < 1.0 {
+                return Err(format!(
+                    "{input} is only {num_bytes} bytes, the size must be at least one byte"
+                ));
             }
 
-            let mut chars = suffix.chars().rev();
-
-            if let Some(ending) = chars.next() {
-                if ending == 'B' {
-                    if let Some(si_prefix) = chars.next() {
-                        num_bytes *= parse_si_prefix(si_prefix)?;
-                    }
-                } else if ending == 'b' {
-                    let si_prefix = chars.next().ok_or_else(|| {
-                        "if the suffix ends with 'b' it must be two characters long".to_owned()
-                    })?;
-
-                    num_bytes *= parse_si_prefix(si_prefix)? / 8.0;
-                } else {
-                    return Err(format!(
-                        "the suffix must end with either 'B' or 'b', not '{ending}'"
-                    ));
-                }
-            }
+            Ok(
+                NonZeroUsize::new(checked_byte_count(num_bytes, usize::MAX)?)
+                    .expect("the size is at least one byte"),
+            )
+        }
+    }
+}
+
+/// Returns the number of bytes per unit of a size suffix like "kB", "Mib" or "GiB", or 1 for no suffix.
+/// Every way a suffix can be wrong gets its own message that names the offending letter.
+fn suffix_multiplier(suffix: &str) -> Result<f64, String> {
+    let chars: Vec<char> = suffix.chars().collect();
+    let Some((&ending, prefix)) = chars.split_last() else {
+        return Ok(1.0);
+    };
+    if chars.iter().any(|c| c.is_whitespace()) {
+        return Err(format!("the suffix '{suffix}' can not contain spaces"));
+    }
+    if chars.len() > 3 {
+        return Err(format!(
+            "the suffix '{suffix}' is too long, it can be at most three letters like 'GiB'"
+        ));
+    }
+
+    let is_prefix = |c: char| parse_si_prefix(c).is_ok() || parse_iec_prefix(c).is_ok();
+    if ending != 'B' && ending != 'b' {
+        return Err(
+            if ending == 'i' || (prefix.is_empty() && is_prefix(ending)) {
+                format!("the suffix '{suffix}' is missing the unit, write '{suffix}B' for bytes or '{suffix}b' for bits")
+            } else if is_prefix(ending) {
+                let prefix: String = prefix.iter().collect();
+                format!("the suffix '{suffix}' ends with the prefix '{ending}' instead of a unit, did you mean '{prefix}B'?")
+            } else {
+                format!("the suffix '{suffix}' must end with 'B' for bytes or 'b' for bits, not '{ending}'")
+            },
+        );
+    }
+
+    let multiplier = match *prefix {
+        [] if ending == 'b' => {
+            return Err(
+                "'b' means bits, which only add up to whole bytes with a prefix like 'kb', write 'B' for bytes"
+                    .to_owned(),
+            )
+        }
+        [] => 1.0,
+        [unit @ ('B' | 'b')] | [_, unit @ ('B' | 'b')] | [unit @ ('B' | 'b'), _] => {
+            return Err(format!(
+                "the suffix '{suffix}' has the unit '{unit}' where a prefix like 'k' or 'Mi' belongs"
+            ))
+        }
+        [si_prefix] => parse_si_prefix(si_prefix)?,
+        [iec_prefix, 'i'] => parse_iec_prefix(iec_prefix)?,
+        [_, second] => {
+            return Err(format!(
+                "a three letter suffix must be a binary prefix like 'Ki' followed by 'B' or 'b', but the second letter of '{suffix}' is '{second}' instead of 'i'"
+            ))
+        }
+        _ => unreachable!("the suffix is at most three letters long"),
+    };
+    Ok(if ending == 'b' {
+        multiplier / 8.0
+    } else {
+        multiplier
+    })
+}
 
-            NonZeroUsize::new(num_bytes as usize)
-                .ok_or_else(|| "the size must be at least one byte".to_owned())
+/// Returns the suffix with the prefix in the case that was most likely meant, if it is one of the prefixes
+/// that are easily typed in the wrong case, like the 'm' of milli in "500mB", and the result is a valid suffix.
+fn correct_case(suffix: &str) -> Option<String> {
+    let corrected = match suffix.chars().next()? {
+        'm' => 'M',
+        'g' => 'G',
+        't' => 'T',
+        // The binary prefix is the only one with an upper case 'K'.
+        'k' if suffix.starts_with("ki") => 'K',
+        _ => return None,
+    };
+    let corrected = format!("{corrected}{}", &suffix[1..]);
+    suffix_multiplier(&corrected).is_ok().then_some(corrected)
+}
+
+/// Removes the underscores that separate groups of digits, as in 1_000_000. Commas would be ambiguous
+/// in a list of sizes, so they are not accepted. An underscore has to have a digit on both sides.
+fn strip_separators(number: &str) -> Result<String, String> {
+    let chars: Vec<char> = number.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let digit_at = |j: Option<usize>| {
+            j.and_then(|j| chars.get(j))
+                .is_some_and(char::is_ascii_digit)
+        };
+        if c == '_' && !(digit_at(i.checked_sub(1)) && digit_at(Some(i + 1))) {
+            return Err(format!(
+                "'{number}' has an underscore that does not separate two digits"
+            ));
         }
     }
+    Ok(number.replace('_', ""))
+}
+
+/// Turns a computed number of bytes into a `usize`, or fails if it is more than `max`,
+/// where a plain cast would silently turn it into `max` instead.
+fn checked_byte_count(num_bytes: f64, max: usize) -> Result<usize, String> {
+    if num_bytes > max as f64 {
+        Err(format!(
+            "{num_bytes} bytes, which is more than this platform can address"
+        ))
+    } else {
+        Ok(num_bytes as usize)
+    }
 }
 
 fn parse_si_prefix(c: char) -> Result<f64, String> {
     if c == 'k' {
         Ok(1e3)
+    } else if c == 'm' {
+        Err("'m' is the SI prefix milli, a thousandth, which makes no sense for bytes".to_owned())
     } else if c == 'M' {
         Ok(1e6)
     } else if c == 'G' {
@@ -135,16 +1014,127 @@ fn parse_si_prefix(c: char) -> Result<f64, String> {
     }
 }
 
+/// Parses the first letter of a binary (IEC) prefix, e.g. the 'G' in "GiB".
+fn parse_iec_prefix(c: char) -> Result<f64, String> {
+    if c == 'K' {
+        Ok(1024.0)
+    } else if c == 'M' {
+        Ok(1024.0_f64.powi(2))
+    } else if c == 'G' {
+        Ok(1024.0_f64.powi(3))
+    } else if c == 'T' {
+        Ok(1024.0_f64.powi(4))
+    } else if c == 'P' {
+        // Values higher than this one should not be needed, but are included for completeness.
+        Ok(1024.0_f64.powi(5))
+    } else if c == 'E' {
+        Ok(1024.0_f64.powi(6))
+    } else if c == 'Z' {
+        Ok(1024.0_f64.powi(7))
+    } else if c == 'Y' {
+        Ok(1024.0_f64.powi(8))
+    } else {
+        Err(format!("'{c}i' is not a supported binary prefix"))
+    }
+}
+
+/// Parses a delay like '30s' or '1h 5min'. A bare number like '90' or '2.5' is a number of seconds,
+/// so a bare '0' means no delay at all.
 fn parse_delay_string(s: &str) -> Result<Duration, String> {
-    match s.parse::<humantime::Duration>() {
-        Ok(d) => Ok(d.into()),
-        Err(e) => Err(e.to_string()),
+    let s = s.trim();
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        if let Ok(seconds) = s.parse::<f64>() {
+            return Duration::try_from_secs_f64(seconds)
+                .map_err(|_| format!("the delay of {s} seconds is too long"));
+        }
+    }
+    s.parse::<humantime::Duration>().map(Into::into).map_err(|e| {
+        format!("'{s}' is not a duration ({e}), it takes e.g. 30s, 5min, 2h, 1h 30min or 1day 12h, or a number of seconds")
+    })
+}
+
+/// Parses a percentage like '10%' or '12.5', which has to be more than 0 and at most 100.
+fn parse_coverage(s: &str) -> Result<f64, String> {
+    let number = s.trim().strip_suffix('%').unwrap_or(s).trim();
+    match number.parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        Ok(_) => Err(format!(
+            "the coverage must be more than 0% and at most 100%, not {s}"
+        )),
+        Err(_) => Err(format!("'{s}' is not a percentage")),
+    }
+}
+
+/// Parses a percentage like '20%' or '5', which has to be at least 0 and less than 100.
+fn parse_jitter(s: &str) -> Result<f64, String> {
+    let number = s.trim().strip_suffix('%').unwrap_or(s).trim();
+    match number.parse::<f64>() {
+        Ok(percent) if (0.0..100.0).contains(&percent) => Ok(percent),
+        Ok(_) => Err(format!(
+            "the jitter must be at least 0% and less than 100%, not {s}"
+        )),
+        Err(_) => Err(format!("'{s}' is not a percentage")),
+    }
+}
+
+/// Parses a load average like '4' or '0.5', which has to be more than 0.
+fn parse_load(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(load) if load > 0.0 && load.is_finite() => Ok(load),
+        Ok(_) => Err(format!("the load must be more than 0, not {s}")),
+        Err(_) => Err(format!("'{s}' is not a number")),
+    }
+}
+
+/// Parses how verbose to be from the environment, either the number of times -v is given or whether it is.
+fn parse_verbosity(s: &str) -> Result<u8, String> {
+    match s.trim() {
+        "true" => Ok(1),
+        "false" => Ok(0),
+        count => count
+            .parse()
+            .map_err(|_| format!("'{count}' is neither true, false nor a number of times")),
+    }
+}
+
+/// Parses a frequency like '2/min', '0.5Hz' or '30/h' into its period.
+fn parse_frequency(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit_seconds) = if let Some(number) = s
+        .strip_suffix("Hz")
+        .or_else(|| s.strip_suffix("hz"))
+        .or_else(|| s.strip_suffix("/s"))
+    {
+        (number, 1.0)
+    } else if let Some(number) = s.strip_suffix("/min") {
+        (number, 60.0)
+    } else if let Some(number) = s.strip_suffix("/h") {
+        (number, 3600.0)
+    } else {
+        return Err(format!(
+            "'{s}' is not a frequency, give it in Hz or per second, minute or hour like 0.5Hz, 2/min or 30/h"
+        ));
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Duration::try_from_secs_f64(unit_seconds / n)
+            .map_err(|_| format!("the frequency {s} is too low")),
+        Ok(_) => Err("the frequency must be more than zero".to_owned()),
+        Err(_) => Err(format!("'{}' is not a number", number.trim())),
+    }
+}
+
+/// Parses an interval like '10min', which unlike a delay can not be zero.
+fn parse_interval_string(s: &str) -> Result<Duration, String> {
+    match parse_delay_string(s)? {
+        d if d.is_zero() => Err("the interval must be longer than zero".to_owned()),
+        d => Ok(d),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::test_util::{read_environment, Environment};
 
     #[test]
     fn verify_cli() {
@@ -152,6 +1142,248 @@ mod test {
         Cli::command().debug_assert()
     }
 
+    #[test]
+    fn check_coverage_parsing() {
+        assert_eq!(parse_coverage("10%"), Ok(10.0));
+        assert_eq!(parse_coverage("12.5"), Ok(12.5));
+        assert_eq!(parse_coverage(" 100 % "), Ok(100.0));
+        assert!(parse_coverage("0%").is_err());
+        assert!(parse_coverage("150%").is_err());
+        assert!(parse_coverage("NaN").is_err());
+        assert!(parse_coverage("ten").is_err());
+    }
+
+    #[test]
+    fn check_jitter_parsing() {
+        assert_eq!(parse_jitter("20%"), Ok(20.0));
+        assert_eq!(parse_jitter("0"), Ok(0.0));
+        assert!(parse_jitter("100%").is_err());
+        assert!(parse_jitter("-5").is_err());
+        assert!(parse_jitter("NaN").is_err());
+    }
+
+    #[test]
+    fn check_delay_parsing() {
+        assert_eq!(parse_delay_string("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_delay_string("0s").unwrap(), Duration::ZERO);
+        assert_eq!(parse_delay_string("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(
+            parse_delay_string("1m 5s").unwrap(),
+            Duration::from_secs(65)
+        );
+        assert_eq!(parse_delay_string("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(
+            parse_delay_string(" 2.5 ").unwrap(),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(
+            parse_delay_string("1day 12h").unwrap(),
+            Duration::from_secs(36 * 3600)
+        );
+        assert_eq!(
+            parse_delay_string("1d12h").unwrap(),
+            Duration::from_secs(36 * 3600)
+        );
+        assert_eq!(
+            parse_delay_string("5min").unwrap(),
+            Duration::from_secs(300)
+        );
+        let error = parse_delay_string("5 parsecs").unwrap_err();
+        assert!(
+            error.starts_with("'5 parsecs' is not a duration ("),
+            "{error}"
+        );
+        assert!(error.ends_with(
+            "it takes e.g. 30s, 5min, 2h, 1h 30min or 1day 12h, or a number of seconds"
+        ));
+        assert!(parse_delay_string("-5").is_err());
+        assert!(parse_delay_string("1.2.3").is_err());
+        assert!(parse_delay_string("").is_err());
+
+        assert_eq!(
+            parse_interval_string("10min").unwrap(),
+            Duration::from_secs(600)
+        );
+        assert!(parse_interval_string("0").is_err());
+        assert!(parse_interval_string("0s").is_err());
+    }
+
+    #[test]
+    fn check_frequency_parsing() {
+        let _env = read_environment();
+        assert_eq!(parse_frequency("2/min"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_frequency("0.5Hz"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_frequency("4 hz"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_frequency("1/s"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_frequency("30/h"), Ok(Duration::from_secs(120)));
+        assert!(parse_frequency("0/min").is_err());
+        assert!(parse_frequency("2").is_err());
+        assert!(parse_frequency("fast/min").is_err());
+
+        // It replaces the delay, so they can not be given together.
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["cosmic_ray_detection", "-m", "1GB"].iter().chain(args))
+        };
+        assert_eq!(
+            parse(&["--frequency", "2/min"]).unwrap().frequency,
+            Some(Duration::from_secs(30))
+        );
+        assert!(parse(&["--frequency", "2/min", "-d", "10s"]).is_err());
+    }
+
+    #[test]
+    fn check_info_command() {
+        let _env = read_environment();
+        // Nothing is allocated, so no detector size is needed.
+        let cli = Cli::try_parse_from(["cosmic_ray_detection", "info"]).unwrap();
+        assert_eq!(cli.command, Some(Command::Info));
+        let cli =
+            Cli::try_parse_from(["cosmic_ray_detection", "info", "--format", "json"]).unwrap();
+        assert_eq!(cli.format, OutputFormat::Json);
+        #[cfg(feature = "schema")]
+        {
+            let cli = Cli::try_parse_from(["cosmic_ray_detection", "schema"]).unwrap();
+            assert_eq!(cli.command, Some(Command::Schema));
+        }
+        assert_eq!(
+            Cli::try_parse_from(["cosmic_ray_detection"])
+                .unwrap_err()
+                .kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn check_environment_variables() {
+        let mut env = Environment::lock();
+        env.set("COSMIC_PATTERN", "ones");
+        env.set("COSMIC_LOCK_MEMORY", "true");
+        let cli = Cli::try_parse_from(["cosmic_ray_detection", "-m", "1"]).unwrap();
+        assert_eq!(cli.pattern, Pattern::Ones);
+        assert!(cli.lock_memory);
+
+        let cli =
+            Cli::try_parse_from(["cosmic_ray_detection", "-m", "1", "--pattern", "0x3C"]).unwrap();
+        assert_eq!(cli.pattern, Pattern::Byte(0x3C));
+
+        // -v counts, but its variable takes true and false like the other flags as well.
+        for (value, level) in [
+            ("true", LevelFilter::Info),
+            ("false", LevelFilter::Warn),
+            ("2", LevelFilter::Debug),
+        ] {
+            env.set("COSMIC_VERBOSE", value);
+            let cli = Cli::try_parse_from(["cosmic_ray_detection", "-m", "1"]).unwrap();
+            assert_eq!(cli.log_level(), level);
+        }
+        env.set("COSMIC_VERBOSE", "often");
+        assert!(Cli::try_parse_from(["cosmic_ray_detection", "-m", "1"]).is_err());
+    }
+
+    #[test]
+    fn check_log_level() {
+        let _env = read_environment();
+        let level = |args: &[&str]| {
+            Cli::try_parse_from(["cosmic_ray_detection", "-m", "1"].iter().chain(args))
+                .map(|cli| cli.log_level())
+        };
+        assert_eq!(level(&[]).unwrap(), LevelFilter::Warn);
+        assert_eq!(level(&["-v"]).unwrap(), LevelFilter::Info);
+        assert_eq!(level(&["-vv"]).unwrap(), LevelFilter::Debug);
+        assert_eq!(level(&["-v", "-v", "-v"]).unwrap(), LevelFilter::Trace);
+        assert_eq!(
+            level(&["--log-level", "error"]).unwrap(),
+            LevelFilter::Error
+        );
+        assert!(level(&["-v", "--log-level", "debug"]).is_err());
+    }
+
+    #[test]
+    fn check_color() {
+        let _env = read_environment();
+        let color = |args: &[&str]| {
+            Cli::try_parse_from(["cosmic_ray_detection", "-m", "1"].iter().chain(args))
+                .unwrap()
+                .color
+        };
+        assert_eq!(color(&[]), ColorChoice::Auto);
+        assert!(!color(&[]).enabled(false));
+        assert!(color(&["--color", "always"]).enabled(false));
+        assert!(!color(&["--color", "never"]).enabled(true));
+    }
+
+    #[test]
+    fn check_log_files() {
+        let _env = read_environment();
+        let cli = Cli::try_parse_from([
+            "cosmic_ray_detection",
+            "-m",
+            "1",
+            "--log-file",
+            "events.log",
+            "--log-file",
+            "json:events.ndjson",
+            "--log-file",
+            r"C:\logs\events.log",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.log_file,
+            [
+                LogFileSpec {
+                    format: None,
+                    path: PathBuf::from("events.log")
+                },
+                LogFileSpec {
+                    format: Some(OutputFormat::Json),
+                    path: PathBuf::from("events.ndjson")
+                },
+                LogFileSpec {
+                    format: None,
+                    path: PathBuf::from(r"C:\logs\events.log")
+                },
+            ]
+        );
+        assert_eq!(
+            parse_log_file("text:").unwrap_err(),
+            "the path of the log file is empty"
+        );
+    }
+
+    #[test]
+    fn check_staircase_parsing() {
+        let _env = read_environment();
+        let cli = Cli::try_parse_from(["cosmic_ray_detection", "-m", "1kB,2kB,10%"]).unwrap();
+        assert_eq!(
+            cli.memory_to_monitor,
+            [
+                MemorySpec::Bytes(NonZeroUsize::new(1000).unwrap()),
+                MemorySpec::Bytes(NonZeroUsize::new(2000).unwrap()),
+                MemorySpec::Percent(10.0),
+            ]
+        );
+        assert!(Cli::try_parse_from(["cosmic_ray_detection", "-m", "1kB,"]).is_err());
+    }
+
+    #[test]
+    fn check_once() {
+        let _env = read_environment();
+        let cli = Cli::try_parse_from(["cosmic_ray_detection", "-m", "1", "--once"]).unwrap();
+        assert!(cli.once);
+        for other in [
+            ["--max-checks", "2"],
+            ["--duration", "1h"],
+            ["--rolling", "4"],
+        ] {
+            assert!(Cli::try_parse_from(
+                ["cosmic_ray_detection", "-m", "1", "--once"]
+                    .iter()
+                    .chain(&other)
+            )
+            .is_err());
+        }
+    }
+
     #[test]
     fn check_memory_parsing() {
         for s in (0..10).map(|i| 2_usize.pow(i)) {
@@ -178,4 +1410,266 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn check_size_overflow() {
+        // As on a 32-bit platform.
+        let max = u32::MAX as usize;
+        assert_eq!(checked_byte_count(4e9, max), Ok(4_000_000_000));
+        assert!(checked_byte_count(5e9, max).is_err());
+        assert_eq!(checked_byte_count(max as f64, max), Ok(max));
+
+        assert!(parse_size_string("100000000000000000000")
+            .unwrap_err()
+            .contains("more than this platform can address"));
+        assert!(parse_size_string("100000EB")
+            .unwrap_err()
+            .contains("more than this platform can address"));
+        assert!(MemorySpec::Percent(50.0).resolve(usize::MAX as u64).is_ok());
+    }
+
+    #[test]
+    fn check_digit_separators() {
+        assert_eq!(parse_size_string("1_000kB").unwrap().get(), 1_000_000);
+        assert_eq!(parse_size_string("2_500MB").unwrap().get(), 2_500_000_000);
+        assert_eq!(
+            parse_size_string("1_000_000_000").unwrap().get(),
+            1_000_000_000
+        );
+        assert_eq!(parse_size_string("1_0.2_5 kB").unwrap().get(), 10_250);
+        for misplaced in [
+            "_1000", "1000_", "1__000", "1_.5GB", "1._5GB", "1_000_kB", "_",
+        ] {
+            assert!(
+                parse_size_string(misplaced)
+                    .unwrap_err()
+                    .contains("underscore"),
+                "{misplaced}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_whitespace_in_sizes() {
+        assert_eq!(parse_size_string("1.5 GB").unwrap().get(), 1_500_000_000);
+        assert_eq!(parse_size_string("512 kB").unwrap().get(), 512_000);
+        assert_eq!(parse_size_string("2  MB").unwrap().get(), 2_000_000);
+        assert_eq!(parse_size_string(" 4 KiB ").unwrap().get(), 4096);
+        assert_eq!(
+            parse_size_string("1.5").unwrap_err(),
+            "you need to specify a suffix to use non-integer numbers"
+        );
+        assert_eq!(
+            parse_size_string("1.5 ").unwrap_err(),
+            "you need to specify a suffix to use non-integer numbers"
+        );
+        assert!(parse_size_string("1 G B").is_err());
+        assert_eq!(parse_memory_spec("25 %"), Ok(MemorySpec::Percent(25.0)));
+    }
+
+    #[test]
+    fn check_sub_byte_sizes() {
+        for (size, bytes) in [
+            ("0.4B", "0.4"),
+            ("0.5B", "0.5"),
+            ("0.9B", "0.9"),
+            ("0.0B", "0"),
+        ] {
+            assert_eq!(
+                parse_size_string(size).unwrap_err(),
+                format!("{size} is only {bytes} bytes, the size must be at least one byte")
+            );
+        }
+        assert!(parse_size_string("0.0001kB")
+            .unwrap_err()
+            .contains("is only 0.1 bytes"));
+        assert_eq!(parse_size_string("1.0B").unwrap().get(), 1);
+        // Fractions are dropped from sizes of a byte or more.
+        assert_eq!(parse_size_string("1.9B").unwrap().get(), 1);
+        assert_eq!(parse_size_string("0.012kb").unwrap().get(), 1);
+    }
+
+    #[test]
+    fn check_page_and_cache_line_parsing() {
+        let parse = |s: &str, page_size| parse_size_with_page_size(s, page_size).map(|n| n.get());
+        assert_eq!(parse("1024p", Some(4096)), Ok(4 << 20));
+        assert_eq!(parse("3 p", Some(16384)), Ok(3 * 16384));
+        assert_eq!(parse("1_000p", Some(65536)), Ok(65_536_000));
+        assert_eq!(parse("512cl", None), Ok(512 * 64));
+        assert_eq!(parse("1.5cl", Some(4096)), Ok(96));
+        assert_eq!(
+            parse("1024p", None).unwrap_err(),
+            "the page size of this system is unknown, so the size can not be given in pages"
+        );
+        assert!(parse("0p", Some(4096)).is_err());
+        assert!(parse("1P", Some(4096)).is_err());
+        assert!(parse("2pB", Some(4096)).is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            parse_size_string("2p").unwrap().get(),
+            2 * system_page_size().unwrap()
+        );
+    }
+
+    #[test]
+    fn check_binary_memory_parsing() {
+        for s in (0..10).map(|i| 2_usize.pow(i)) {
+            assert_eq!(
+                parse_size_string(&format!("{s}KiB")).unwrap().get(),
+                s * 1024
+            );
+            assert_eq!(
+                parse_size_string(&format!("{s}MiB")).unwrap().get(),
+                s * 1024 * 1024
+            );
+            assert_eq!(
+                parse_size_string(&format!("{s}GiB")).unwrap().get(),
+                s * 1024 * 1024 * 1024
+            );
+            assert_eq!(
+                parse_size_string(&format!("{s}TiB")).unwrap().get(),
+                s * 1024 * 1024 * 1024 * 1024
+            );
+            assert_eq!(
+                parse_size_string(&format!("{s}PiB")).unwrap().get(),
+                s * 1024 * 1024 * 1024 * 1024 * 1024
+            );
+        }
+        assert_eq!(
+            parse_size_string("8GiB").unwrap().get(),
+            8 * 1024 * 1024 * 1024
+        );
+        assert_eq!(parse_size_string("8Kib").unwrap().get(), 1024);
+        assert!(parse_size_string("8kiB").is_err());
+        assert!(parse_size_string("8GxB").is_err());
+    }
+
+    #[test]
+    fn check_miscased_prefixes() {
+        let error = |s| parse_size_string(s).unwrap_err();
+        assert_eq!(
+            error("500mB"),
+            "'m' is the SI prefix milli, a thousandth, which makes no sense for bytes, did you mean 500MB?"
+        );
+        assert_eq!(
+            error("500 mb"),
+            "'m' is the SI prefix milli, a thousandth, which makes no sense for bytes, did you mean 500 Mb?"
+        );
+        assert_eq!(
+            error("4gB"),
+            "'g' is not a supported SI prefix, did you mean 4GB?"
+        );
+        assert_eq!(
+            error("1.5tB"),
+            "'t' is not a supported SI prefix, did you mean 1.5TB?"
+        );
+        assert_eq!(
+            error("8giB"),
+            "'gi' is not a supported binary prefix, did you mean 8GiB?"
+        );
+        assert_eq!(
+            error("1_000kiB"),
+            "'ki' is not a supported binary prefix, did you mean 1_000KiB?"
+        );
+        assert_eq!(
+            error("2miB"),
+            "'mi' is not a supported binary prefix, did you mean 2MiB?"
+        );
+        // There is nothing to suggest if the suffix is wrong in other ways too.
+        assert_eq!(
+            error("5gX"),
+            "the suffix 'gX' must end with 'B' for bytes or 'b' for bits, not 'X'"
+        );
+        assert_eq!(error("5xB"), "'x' is not a supported SI prefix");
+        // The correct case is still accepted.
+        assert_eq!(parse_size_string("500MB").unwrap().get(), 500_000_000);
+        assert_eq!(parse_size_string("500kB").unwrap().get(), 500_000);
+    }
+
+    #[test]
+    fn check_malformed_suffixes() {
+        let error = |s| parse_size_string(s).unwrap_err();
+        assert_eq!(
+            error("5GG"),
+            "the suffix 'GG' ends with the prefix 'G' instead of a unit, did you mean 'GB'?"
+        );
+        assert_eq!(
+            error("5kk"),
+            "the suffix 'kk' ends with the prefix 'k' instead of a unit, did you mean 'kB'?"
+        );
+        assert_eq!(
+            error("5GiG"),
+            "the suffix 'GiG' ends with the prefix 'G' instead of a unit, did you mean 'GiB'?"
+        );
+        assert_eq!(
+            error("5G"),
+            "the suffix 'G' is missing the unit, write 'GB' for bytes or 'Gb' for bits"
+        );
+        assert_eq!(
+            error("5Ki"),
+            "the suffix 'Ki' is missing the unit, write 'KiB' for bytes or 'Kib' for bits"
+        );
+        assert_eq!(
+            error("5GX"),
+            "the suffix 'GX' must end with 'B' for bytes or 'b' for bits, not 'X'"
+        );
+        assert_eq!(
+            error("5Bb"),
+            "the suffix 'Bb' has the unit 'B' where a prefix like 'k' or 'Mi' belongs"
+        );
+        assert_eq!(
+            error("5BB"),
+            "the suffix 'BB' has the unit 'B' where a prefix like 'k' or 'Mi' belongs"
+        );
+        assert_eq!(
+            error("5bB"),
+            "the suffix 'bB' has the unit 'b' where a prefix like 'k' or 'Mi' belongs"
+        );
+        assert_eq!(
+            error("5Bib"),
+            "the suffix 'Bib' has the unit 'B' where a prefix like 'k' or 'Mi' belongs"
+        );
+        assert_eq!(
+            error("5b"),
+            "'b' means bits, which only add up to whole bytes with a prefix like 'kb', write 'B' for bytes"
+        );
+        assert_eq!(error("5xB"), "'x' is not a supported SI prefix");
+        assert_eq!(error("5xiB"), "'xi' is not a supported binary prefix");
+        assert_eq!(
+            error("8GxB"),
+            "a three letter suffix must be a binary prefix like 'Ki' followed by 'B' or 'b', but the second letter of 'GxB' is 'x' instead of 'i'"
+        );
+        assert_eq!(
+            error("5GiBB"),
+            "the suffix 'GiBB' is too long, it can be at most three letters like 'GiB'"
+        );
+        assert_eq!(error("1 G B"), "the suffix 'G B' can not contain spaces");
+        assert_eq!(parse_size_string("8 Gb").unwrap().get(), 1_000_000_000);
+    }
+
+    #[test]
+    fn check_percentage_parsing() {
+        assert_eq!(parse_memory_spec("25%").unwrap(), MemorySpec::Percent(25.0));
+        assert_eq!(
+            parse_memory_spec("100%").unwrap(),
+            MemorySpec::Percent(100.0)
+        );
+        assert_eq!(
+            parse_memory_spec("2kB").unwrap(),
+            MemorySpec::Bytes(NonZeroUsize::new(2000).unwrap())
+        );
+        assert!(parse_memory_spec("0%").is_err());
+        assert!(parse_memory_spec("-5%").is_err());
+        assert!(parse_memory_spec("100.1%").is_err());
+        assert!(parse_memory_spec("%").is_err());
+        assert!(parse_memory_spec("NaN%").is_err());
+        assert!(parse_memory_spec("inf%").is_err());
+        assert_eq!(parse_memory_spec(" cgroup").unwrap(), MemorySpec::Cgroup);
+
+        assert_eq!(
+            MemorySpec::Percent(25.0).resolve(1 << 30).unwrap().get(),
+            1 << 28
+        );
+        assert!(MemorySpec::Percent(1.0).resolve(10).is_err());
+    }
 }
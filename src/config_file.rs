@@ -0,0 +1,370 @@
+//! Settings read from a TOML file given with `--config`, so that they don't have to be repeated on every command line.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{
+    error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgMatches, Command, CommandFactory,
+    FromArgMatches,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Cli;
+
+/// The contents of a config file. Every key is the long name of the corresponding command line flag,
+/// e.g. `memory-to-monitor = "4GB"` or `parallel = true`, and is parsed the same way.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct ConfigFile {
+    memory_to_monitor: Option<String>,
+    use_all: Option<String>,
+    force: Option<bool>,
+    reserve: Option<String>,
+    delay_between_checks: Option<String>,
+    frequency: Option<String>,
+    duty_cycle: Option<String>,
+    rolling: Option<usize>,
+    pass_timeout: Option<String>,
+    jitter: Option<String>,
+    throttle: Option<f64>,
+    coverage: Option<String>,
+    duration: Option<String>,
+    max_checks: Option<u64>,
+    once: Option<bool>,
+    heartbeat: Option<String>,
+    pattern: Option<String>,
+    seed: Option<u64>,
+    reference_file: Option<PathBuf>,
+    snapshot: Option<bool>,
+    checksum: Option<bool>,
+    ecc_emulate: Option<bool>,
+    anti_dedup: Option<bool>,
+    mirror: Option<u8>,
+    scan_order: Option<String>,
+    word_size: Option<String>,
+    skip_self_test: Option<bool>,
+    no_scrub: Option<bool>,
+    refill_interval: Option<String>,
+    rereads: Option<usize>,
+    stuck_threshold: Option<u64>,
+    exclude_stuck: Option<bool>,
+    canary: Option<bool>,
+    fail_on_flip: Option<bool>,
+    until_clean: Option<u64>,
+    watchdog: Option<bool>,
+    watchdog_abort: Option<bool>,
+    huge_pages: Option<String>,
+    align_pages: Option<bool>,
+    lock_memory: Option<bool>,
+    physical_addresses: Option<bool>,
+    dimm_slots: Option<bool>,
+    virtual_addresses: Option<bool>,
+    correlate_mce: Option<bool>,
+    backing_file: Option<PathBuf>,
+    #[cfg(feature = "numa")]
+    numa_node: Option<String>,
+    parallel: Option<bool>,
+    threads: Option<usize>,
+    pin_threads: Option<bool>,
+    cpu_set: Option<String>,
+    verbose: Option<Verbosity>,
+    log_level: Option<String>,
+    dry_run: Option<bool>,
+    benchmark: Option<u64>,
+    allow_ecc: Option<bool>,
+    format: Option<String>,
+    color: Option<String>,
+    max_events_per_check: Option<usize>,
+    tag: Option<String>,
+    #[cfg(feature = "tui")]
+    tui: Option<bool>,
+    log_file: Option<OneOrMany<String>>,
+    log_rotate_size: Option<String>,
+    #[cfg(feature = "compression")]
+    log_compress: Option<bool>,
+    csv: Option<PathBuf>,
+    report: Option<PathBuf>,
+    daemonize: Option<bool>,
+    stderr_file: Option<PathBuf>,
+    pid_file: Option<PathBuf>,
+    drop_privs: Option<bool>,
+    #[cfg(feature = "notify")]
+    notify: Option<bool>,
+    #[cfg(all(unix, feature = "syslog"))]
+    syslog: Option<bool>,
+    #[cfg(feature = "webhook")]
+    webhook_url: Option<String>,
+    #[cfg(feature = "metrics")]
+    metrics_addr: Option<String>,
+    #[cfg(feature = "metrics")]
+    metrics_exemplars: Option<bool>,
+    #[cfg(feature = "statsd")]
+    statsd_addr: Option<String>,
+}
+
+/// A setting of a flag that can be given several times, which can be a single value or a list of them.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// The setting of `-v`, either whether it is given like any other flag or how many times, like `verbose = 2`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Verbosity {
+    Flag(bool),
+    Count(u8),
+}
+
+impl ConfigFile {
+    /// Reads and parses the config file at the given path.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read the config file {}: {e}", path.display()))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("could not parse the config file {}: {e}", path.display()))
+    }
+
+    /// Turns the settings in the file into command line arguments, leaving out the ones that
+    /// were given on the actual command line or in the environment, or that conflict with something given there.
+    fn to_args(&self, cmd: &Command, matches: &ArgMatches) -> Vec<OsString> {
+        let Value::Object(fields) =
+            serde_json::to_value(self).expect("the config file consists of plain values")
+        else {
+            unreachable!("the config file is a struct")
+        };
+
+        let explicit: Vec<&Arg> = cmd
+            .get_arguments()
+            .filter(|arg| {
+                matches!(
+                    matches.value_source(arg.get_id().as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .collect();
+
+        let mut args = Vec::new();
+        for (key, value) in fields {
+            let id = key.replace('-', "_");
+            let arg = cmd
+                .get_arguments()
+                .find(|arg| arg.get_id() == id.as_str())
+                .expect("every key of the config file is a command line flag");
+            if explicit
+                .iter()
+                .any(|other| other.get_id() == arg.get_id() || conflicting(cmd, arg, other))
+            {
+                continue;
+            }
+
+            let flag = match arg.get_long() {
+                Some(long) => format!("--{long}"),
+                None => format!("-{}", arg.get_short().expect("every flag has a name")),
+            };
+            match value {
+                Value::Null | Value::Bool(false) => {}
+                Value::Bool(true) => args.push(flag.into()),
+                // A flag that counts is given as many times as the number says.
+                Value::Number(n) if matches!(arg.get_action(), ArgAction::Count) => {
+                    let times = n.as_u64().expect("a count is a small number");
+                    args.extend((0..times).map(|_| OsString::from(&flag)));
+                }
+                Value::String(s) => args.extend([flag, s].map(OsString::from)),
+                Value::Array(values) => {
+                    for value in values {
+                        let value = match value {
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        };
+                        args.extend([flag.clone(), value].map(OsString::from));
+                    }
+                }
+                other => args.extend([flag, other.to_string()].map(OsString::from)),
+            }
+        }
+        args
+    }
+}
+
+/// Returns whether the two arguments can not be given together.
+fn conflicting(cmd: &Command, a: &Arg, b: &Arg) -> bool {
+    let conflicts_with = |x: &Arg, y: &Arg| {
+        cmd.get_arg_conflicts_with(x)
+            .iter()
+            .any(|c| c.get_id() == y.get_id())
+    };
+    conflicts_with(a, b)
+        || conflicts_with(b, a)
+        || cmd.get_groups().any(|group| {
+            !group.clone().is_multiple()
+                && group.get_args().any(|id| id == a.get_id())
+                && group.get_args().any(|id| id == b.get_id())
+        })
+}
+
+/// Parses the command line, filling in anything it does not set from the config file given with `--config`.
+/// Exits with a usage message if the arguments are invalid.
+pub fn parse_cli() -> Cli {
+    parse_cli_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+fn parse_cli_from(args: impl IntoIterator<Item = OsString>) -> Result<Cli, clap::Error> {
+    let args: Vec<OsString> = args.into_iter().collect();
+
+    // The detector size may come from the file, so nothing can be required before it has been read.
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return parse_with_arguments(args);
+    };
+    let file = ConfigFile::load(path).map_err(|e| Cli::command().error(ErrorKind::Io, e))?;
+
+    let (bin, rest) = args
+        .split_first()
+        .map_or((None, &[][..]), |(b, r)| (Some(b), r));
+    let merged = bin
+        .cloned()
+        .into_iter()
+        .chain(file.to_args(&Cli::command(), &matches))
+        .chain(rest.iter().cloned());
+    // Parsing everything again checks the combined arguments, including that a detector size was given somewhere.
+    parse_with_arguments(merged.collect())
+}
+
+/// Parses the arguments and keeps them in [`Cli::arguments`], and the environment variables that set an option
+/// in [`Cli::environment`].
+fn parse_with_arguments(args: Vec<OsString>) -> Result<Cli, clap::Error> {
+    let command = Cli::command();
+    let matches = command.clone().try_get_matches_from(&args)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    cli.arguments = args
+        .iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    cli.environment = command
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::EnvVariable))
+        .filter_map(|arg| {
+            let name = arg.get_env()?;
+            let value = std::env::var_os(name)?;
+            Some((
+                name.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            ))
+        })
+        .collect();
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{MemorySpec, OutputFormat};
+    use crate::test_util::{Environment, TempDir};
+    use log::LevelFilter;
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn check_config_file_merging() {
+        let mut env = Environment::lock();
+        let dir = TempDir::new("config");
+        let path = dir.join("config.toml");
+        let parse = |args: &[&str]| {
+            parse_cli_from(
+                ["cosmic_ray_detection", "--config", path.to_str().unwrap()]
+                    .iter()
+                    .chain(args)
+                    .map(OsString::from),
+            )
+        };
+
+        fs::write(
+            &path,
+            "memory-to-monitor = \"1GB\"\ndelay-between-checks = \"10s\"\nparallel = true\nmax-checks = 3\n",
+        )
+        .unwrap();
+        let cli = parse(&[]).unwrap();
+        assert_eq!(
+            cli.memory_to_monitor,
+            [MemorySpec::Bytes(NonZeroUsize::new(1_000_000_000).unwrap())]
+        );
+        assert_eq!(cli.delay_between_checks, Duration::from_secs(10));
+        assert!(cli.parallel);
+        assert_eq!(cli.max_checks, Some(3));
+        assert!(cli.arguments.contains(&"--max-checks".to_owned()));
+
+        // The command line takes precedence, also over settings it conflicts with.
+        let cli = parse(&["-m", "2GB", "--threads", "3"]).unwrap();
+        assert_eq!(
+            cli.memory_to_monitor,
+            [MemorySpec::Bytes(NonZeroUsize::new(2_000_000_000).unwrap())]
+        );
+        assert_eq!(cli.threads, NonZeroUsize::new(3));
+        assert!(!cli.parallel);
+        assert_eq!(cli.delay_between_checks, Duration::from_secs(10));
+
+        // The detector size must still be given somewhere.
+        fs::write(&path, "parallel = true\n").unwrap();
+        assert_eq!(
+            parse(&[]).unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert!(parse(&["-m", "1GB"]).is_ok());
+
+        // The environment takes precedence over the file, and can provide the detector size too.
+        env.set("COSMIC_MEMORY_TO_MONITOR", "3GB");
+        let cli = parse(&[]).unwrap();
+        assert_eq!(
+            cli.memory_to_monitor,
+            [MemorySpec::Bytes(NonZeroUsize::new(3_000_000_000).unwrap())]
+        );
+        // It is not among the arguments, so it is kept apart for the report.
+        assert_eq!(
+            cli.environment,
+            [("COSMIC_MEMORY_TO_MONITOR".to_owned(), "3GB".to_owned())]
+        );
+        env.remove("COSMIC_MEMORY_TO_MONITOR");
+        assert!(parse(&["-m", "1GB"]).unwrap().environment.is_empty());
+
+        // Flags that can be given several times take a list.
+        fs::write(
+            &path,
+            "memory-to-monitor = \"1GB\"\nlog-file = [\"events.log\", \"json:events.ndjson\"]\n",
+        )
+        .unwrap();
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.log_file.len(), 2);
+        assert_eq!(cli.log_file[1].format, Some(OutputFormat::Json));
+        fs::write(
+            &path,
+            "memory-to-monitor = \"1GB\"\nlog-file = \"events.log\"\n",
+        )
+        .unwrap();
+        assert_eq!(parse(&[]).unwrap().log_file.len(), 1);
+
+        // -v takes a number of times as well as whether it is given.
+        for (verbose, level) in [
+            ("true", LevelFilter::Info),
+            ("false", LevelFilter::Warn),
+            ("2", LevelFilter::Debug),
+        ] {
+            fs::write(
+                &path,
+                format!("memory-to-monitor = \"1GB\"\nverbose = {verbose}\n"),
+            )
+            .unwrap();
+            assert_eq!(parse(&[]).unwrap().log_level(), level);
+        }
+
+        fs::write(&path, "memory-to-monitor = \"1GB\"\nsize = 3\n").unwrap();
+        assert!(parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Sparse integrity checks that only verify a pseudo-random share of the pages of the detector memory,
+//! for detectors too large to be checked completely at the desired rate.
+
+use crate::pattern::splitmix64;
+
+/// How many Feistel rounds the page permutation runs through. Four make it pseudo-random for any round function.
+const ROUNDS: u64 = 4;
+
+/// Picks the pages that every sparse check verifies. The pages are put into a pseudo-random order that is
+/// determined by the seed, and every check takes the next share of them in that order, starting over
+/// at the beginning once all have been checked. Every page is thereby checked once in every
+/// `100 / percent` checks, while the ones checked together are scattered over the whole detector.
+pub struct PageSelector {
+    pages: u64,
+    per_check: u64,
+    seed: u64,
+    /// Half the number of bits of the page indices the Feistel network permutes, which cover at least all the pages.
+    half_bits: u32,
+    /// How many pages have been selected so far.
+    selected: u64,
+}
+
+/// How much of the detector memory the sparse checks verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage {
+    pub pages: usize,
+    /// The number of pages that were verified by the last check.
+    pub checked: usize,
+    /// The number of pages that were verified by at least one check so far.
+    pub covered: usize,
+}
+
+impl Coverage {
+    /// Returns the percentage of the pages that the last check verified.
+    pub fn check_percent(&self) -> f64 {
+        100.0 * self.checked as f64 / self.pages as f64
+    }
+
+    /// Returns the percentage of the pages that were verified at least once.
+    pub fn cumulative_percent(&self) -> f64 {
+        100.0 * self.covered as f64 / self.pages as f64
+    }
+}
+
+impl PageSelector {
+    /// Creates a selector that picks the given percentage of the given number of pages for every check,
+    /// rounded up to a whole page.
+    /// # Panic
+    /// Panics if there are no pages.
+    pub fn new(pages: usize, percent: f64, seed: u64) -> Self {
+        assert!(pages > 0, "there are no pages to select from");
+        let pages = pages as u64;
+        let per_check = ((pages as f64 * percent / 100.0).ceil() as u64).clamp(1, pages);
+        let bits = u64::BITS - (pages - 1).leading_zeros();
+        PageSelector {
+            pages,
+            per_check,
+            seed,
+            half_bits: bits.div_ceil(2).max(1),
+            selected: 0,
+        }
+    }
+
+    /// Returns the `position`th page in the pseudo-random order.
+    fn page(&self, position: u64) -> u64 {
+        // The network permutes all numbers of its width, walking the cycle until one is a page
+        // makes it a permutation of the pages. The width is less than twice that of the pages,
+        // so that this takes fewer than four steps on average.
+        let mut page = position;
+        loop {
+            page = self.feistel(page);
+            if page < self.pages {
+                return page;
+            }
+        }
+    }
+
+    fn feistel(&self, x: u64) -> u64 {
+        let mask = (1 << self.half_bits) - 1;
+        let (mut left, mut right) = (x >> self.half_bits, x & mask);
+        for round in 0..ROUNDS {
+            let f = splitmix64(self.seed, right * ROUNDS + round) & mask;
+            (left, right) = (right, left ^ f);
+        }
+        (left << self.half_bits) | right
+    }
+
+    /// Returns the pages the next check verifies, in ascending order.
+    pub fn next_check(&mut self) -> Vec<usize> {
+        let mut pages: Vec<usize> = (self.selected..self.selected + self.per_check)
+            .map(|position| self.page(position % self.pages) as usize)
+            .collect();
+        self.selected += self.per_check;
+        // Reading them in order is faster, and they are far enough apart to defeat the prefetcher anyway.
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Returns the coverage up to and including the last check.
+    pub fn coverage(&self) -> Coverage {
+        Coverage {
+            pages: self.pages as usize,
+            checked: self.per_check.min(self.selected) as usize,
+            covered: self.selected.min(self.pages) as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_page_selection() {
+        for pages in [1, 2, 7, 1000, 4096] {
+            let mut selector = PageSelector::new(pages, 10.0, 42);
+            let per_check = pages.div_ceil(10);
+            let mut seen = vec![0; pages];
+            // Every page is checked exactly once in every round of ten checks.
+            for check in 1..=20 {
+                let selected = selector.next_check();
+                assert!(selected.windows(2).all(|w| w[0] < w[1]));
+                for page in selected {
+                    seen[page] += 1;
+                }
+                let coverage = selector.coverage();
+                assert_eq!(coverage.checked, per_check);
+                assert_eq!(coverage.covered, (check * per_check).min(pages));
+            }
+            if (20 * per_check) % pages == 0 {
+                assert!(seen.iter().all(|&n| n == 20 * per_check / pages));
+            } else {
+                assert!(seen.iter().all(|&n| n > 0));
+            }
+        }
+
+        // The order depends on the seed, and is the same for the same one.
+        let first = |seed| PageSelector::new(1000, 10.0, seed).next_check();
+        assert_eq!(first(1), first(1));
+        assert_ne!(first(1), first(2));
+        // The pages of a check are spread over the whole detector.
+        assert!(first(1).iter().any(|&page| page >= 500));
+        assert!(first(1).iter().any(|&page| page < 500));
+
+        let coverage = PageSelector::new(1000, 25.0, 0).coverage();
+        assert_eq!((coverage.checked, coverage.covered), (0, 0));
+        let mut selector = PageSelector::new(1000, 25.0, 0);
+        selector.next_check();
+        assert_eq!(selector.coverage().check_percent(), 25.0);
+        assert_eq!(selector.coverage().cumulative_percent(), 25.0);
+    }
+}
@@ -0,0 +1,110 @@
+//! A time series of the integrity checks, one row per check, for plotting in a spreadsheet.
+
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use humantime::format_rfc3339;
+
+use crate::output::{Output, Startup};
+use crate::summary::Stage;
+
+/// The header row, see `--csv` for what the columns mean.
+const HEADER: &str = "timestamp,check_number,flips_this_check,cumulative_flips,monitored_bytes";
+
+/// Writes a row for every integrity check and flushes it right away. Everything else is left out.
+pub struct CsvOutput<W: Write> {
+    writer: W,
+    /// Whether the header row still has to be written, which is only the case for a new file.
+    header: bool,
+    cumulative_flips: u64,
+    monitored_bytes: usize,
+}
+
+impl<W: Write> CsvOutput<W> {
+    /// Writes the header row before the first row if `header` is true, which it should not be
+    /// when appending to a file that already has one.
+    pub fn new(writer: W, header: bool) -> Self {
+        CsvOutput {
+            writer,
+            header,
+            cumulative_flips: 0,
+            monitored_bytes: 0,
+        }
+    }
+}
+
+impl<W: Write> Output for CsvOutput<W> {
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.monitored_bytes = startup.allocated_bytes;
+        Ok(())
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        if std::mem::take(&mut self.header) {
+            writeln!(self.writer, "{HEADER}")?;
+        }
+        self.cumulative_flips += flips as u64;
+        writeln!(
+            self.writer,
+            "{},{check},{flips},{},{}",
+            format_rfc3339(SystemTime::now()),
+            self.cumulative_flips,
+            self.monitored_bytes
+        )?;
+        self.writer.flush()
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        if let Some(bytes) = stage.next_bytes {
+            self.monitored_bytes = bytes;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pattern::Pattern;
+    use std::time::Duration;
+
+    #[test]
+    fn check_csv_rows() {
+        let rows = |header| {
+            let mut output = CsvOutput::new(Vec::new(), header);
+            output
+                .startup(&Startup {
+                    allocated_bytes: 1000,
+                    allocation_mode: None,
+                    pattern: Pattern::Zeros,
+                    reference_file: None,
+                    snapshot: false,
+                    delay: Duration::from_secs(30),
+                    threads: 1,
+                    lock_memory: false,
+                    huge_pages: false,
+                    checksum_block_size: None,
+                    ecc_data_bytes: None,
+                    coverage: None,
+                    resumed: false,
+                    downtime_flips: 0,
+                })
+                .unwrap();
+            output.check(1, 0).unwrap();
+            output.check(2, 2).unwrap();
+            output.check(3, 1).unwrap();
+            String::from_utf8(output.writer).unwrap()
+        };
+
+        let text = rows(true);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], HEADER);
+        let columns: Vec<&str> = lines[3].split(',').collect();
+        assert!(humantime::parse_rfc3339(columns[0]).is_ok());
+        assert_eq!(columns[1..], ["3", "1", "3", "1000"]);
+
+        // Appending to a file that already has a header.
+        assert_eq!(rows(false).lines().count(), 3);
+    }
+}
@@ -0,0 +1,146 @@
+//! Running the detector in the background as a traditional Unix daemon, and the PID file that tells others its process ID.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Forks into the background and detaches from the terminal, with the classic double fork: the first child starts
+/// a new session without a controlling terminal, and its child, which is not a session leader and can therefore
+/// never acquire one again, goes on as the daemon. The parents exit right away. Standard input and output are
+/// redirected to `/dev/null` and standard error is appended to the given file, or also sent to `/dev/null`.
+/// That file must not be one of the log files, whose formats and rotation know nothing of what else is written to it.
+/// This has to happen before any threads are started, as only the calling thread survives a fork.
+#[cfg(unix)]
+pub fn daemonize(stderr: Option<&Path>) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    fn fork_and_exit_parent() -> io::Result<()> {
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(()),
+            // Skip the destructors and exit handlers, the child takes over from here.
+            _ => unsafe { libc::_exit(0) },
+        }
+    }
+
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    fork_and_exit_parent()?;
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let error_file = match stderr {
+        Some(path) => OpenOptions::new().append(true).create(true).open(path)?,
+        None => null.try_clone()?,
+    };
+    for (file, fd) in [
+        (&null, libc::STDIN_FILENO),
+        (&null, libc::STDOUT_FILENO),
+        (&error_file, libc::STDERR_FILENO),
+    ] {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_stderr: Option<&Path>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--daemonize is only supported on Unix, on Windows run the detector as a service with a wrapper like WinSW or NSSM instead",
+    ))
+}
+
+/// Returns whether a process with the given ID is running. Signal 0 checks for it without sending anything.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // A process that we may not signal is running all the same.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// A file that holds the ID of this process for as long as it runs, and is removed when it is dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the ID of this process to the file at the given path. A file that is left over from a process
+    /// that is no longer running is replaced, but if that process is still running this fails.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Ok(contents) = fs::read_to_string(path) {
+            if let Ok(pid) = contents.trim().parse() {
+                if pid != std::process::id() && is_running(pid) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("the process {pid} that wrote it is still running"),
+                    ));
+                }
+            }
+        }
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(PidFile {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_pid_file() {
+        let dir = TempDir::new("daemon");
+        let path = dir.join("detector.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // A PID file of a process that is gone is taken over.
+        fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        drop(PidFile::create(&path).unwrap());
+        assert!(!path.exists());
+
+        #[cfg(unix)]
+        {
+            // The parent of the test runner is still running.
+            fs::write(&path, format!("{}\n", std::os::unix::process::parent_id())).unwrap();
+            assert_eq!(
+                PidFile::create(&path).err().map(|e| e.kind()),
+                Some(io::ErrorKind::AlreadyExists)
+            );
+        }
+    }
+}
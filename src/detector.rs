@@ -1,124 +1,2296 @@
+use std::fmt;
+use std::io;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::ptr::{read_volatile, write_volatile};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-#[cfg(all(not(target_os = "windows"), not(target_os = "freebsd")))]
-use crate::config::AllocationMode;
-
-use rayon::prelude::{
-    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+use crate::affinity;
+use crate::backend::MemoryBackend;
+use crate::cgroup;
+use crate::checksum::{crc32, BLOCK_SIZE};
+use crate::config::{AllocationMode, ScanOrder, WordSize};
+use crate::hamming::{self, WordStatus};
+use crate::memlock;
+use crate::pattern::{Contents, Pattern};
+use crate::reference::Reference;
+use crate::region::{
+    allocate_regions_probing, allocate_regions_retrying, Region, RegionOptions, PAGE_SIZE,
 };
+
 use sysinfo::{RefreshKind, System, SystemExt};
 
+/// A byte in the detector memory that did not match the pattern during an integrity check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlipEvent {
+    /// The index of the byte in the detector memory.
+    pub offset: usize,
+    /// The value the byte should have had according to the pattern.
+    pub expected: u8,
+    /// The value the byte actually had.
+    pub found: u8,
+    /// How many bits flipped in the word of `--word-size` the byte belongs to, counting all of its bytes.
+    pub class: FlipClass,
+    /// The address of the byte in the address space of this process, if it was asked for.
+    pub virtual_address: Option<usize>,
+    /// The physical address of the byte, if it was looked up.
+    pub physical_address: Option<u64>,
+    /// The DIMM slot that the byte is most likely in, if it was looked up.
+    pub dimm: Option<String>,
+    /// Whether the byte was still wrong when it was read again, see [`Detector::reread`].
+    pub persistence: Persistence,
+    /// How the copies of a mirrored detector voted on the byte, see [`Detector::vote_mirrors`].
+    pub mirror: Option<MirrorVote>,
+    /// Whether the memory controllers counted an error in the same interval between checks, see `--correlate-mce`.
+    pub correlated_mce: bool,
+    /// Every bit that flipped in the word of the byte if that was a multi-bit flip, in the order of their addresses.
+    /// Only the first flipped byte of the word carries them, for the others this is empty.
+    pub cluster: Vec<BitFlip>,
+}
+
+/// A single bit that flipped, narrowed down from a flipped byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitFlip {
+    /// The index of the byte of the bit in the detector memory.
+    pub offset: usize,
+    /// The bit within the byte, 0 being the least significant one.
+    pub bit: u8,
+    pub direction: BitDirection,
+}
+
+/// Which way a bit flipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDirection {
+    /// From 0 to 1.
+    Set,
+    /// From 1 to 0.
+    Cleared,
+}
+
+impl fmt::Display for BitDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BitDirection::Set => "0 -> 1",
+            BitDirection::Cleared => "1 -> 0",
+        })
+    }
+}
+
+/// Which copy of a mirrored detector a flipped byte is in, and what the same byte in the other copies says about it.
+/// The copies are compared by how far they are off the pattern, so they need not hold the same bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MirrorVote {
+    /// The copy the byte is in, counting from 0.
+    pub copy: usize,
+    /// How many copies there are.
+    pub copies: usize,
+    /// How many of the other copies hold the pattern at the same place, which tells that it is this copy that diverged.
+    pub agreeing: usize,
+    /// The value that more than half of the copies vote this byte should have, if they do.
+    /// With three copies this corrects a flip in any one of them without knowing the pattern.
+    pub majority: Option<u8>,
+}
+
+/// How many bits of the word of `--word-size` the byte belongs to flipped. A single particle strike can flip several adjacent bits at once,
+/// while bits that flip independently of each other rarely share a word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlipClass {
+    Single,
+    Double,
+    Multi,
+}
+
+impl FlipClass {
+    /// Returns the class of a word in which the given number of bits flipped.
+    pub fn from_flipped_bits(bits: u32) -> Self {
+        match bits {
+            0 | 1 => FlipClass::Single,
+            2 => FlipClass::Double,
+            _ => FlipClass::Multi,
+        }
+    }
+}
+
+impl fmt::Display for FlipClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FlipClass::Single => "single-bit",
+            FlipClass::Double => "double-bit",
+            FlipClass::Multi => "multi-bit",
+        })
+    }
+}
+
+/// Whether a flip was still there when the byte was read again. A bit that flipped in the DRAM cell stays flipped
+/// until it is rewritten, while a glitch on the way from the memory to the CPU is usually gone on the next read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persistence {
+    Persistent,
+    Transient,
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Persistence::Persistent => "persistent",
+            Persistence::Transient => "transient",
+        })
+    }
+}
+
+impl FlipEvent {
+    /// Creates a flip event that is classified by the bits that flipped in this byte alone,
+    /// until [`classify_words`] looks at the rest of its word.
+    pub fn new(offset: usize, expected: u8, found: u8) -> Self {
+        FlipEvent {
+            offset,
+            expected,
+            found,
+            class: FlipClass::from_flipped_bits((expected ^ found).count_ones()),
+            virtual_address: None,
+            physical_address: None,
+            dimm: None,
+            persistence: Persistence::Persistent,
+            mirror: None,
+            correlated_mce: false,
+            cluster: Vec::new(),
+        }
+    }
+
+    /// Returns every bit that flipped in this byte, from the least significant one up.
+    pub fn bits(&self) -> impl Iterator<Item = BitFlip> + '_ {
+        (0..8)
+            .filter(|bit| self.changed_bits() & (1 << bit) != 0)
+            .map(|bit| BitFlip {
+                offset: self.offset,
+                bit,
+                direction: if self.set_bits() & (1 << bit) != 0 {
+                    BitDirection::Set
+                } else {
+                    BitDirection::Cleared
+                },
+            })
+    }
+
+    /// Returns a mask of the bits that differ between the expected and the found value.
+    pub fn changed_bits(&self) -> u8 {
+        self.expected ^ self.found
+    }
+
+    /// Returns a mask of the bits that flipped from 0 to 1.
+    pub fn set_bits(&self) -> u8 {
+        self.changed_bits() & self.found
+    }
+
+    /// Returns a mask of the bits that flipped from 1 to 0.
+    pub fn cleared_bits(&self) -> u8 {
+        self.changed_bits() & self.expected
+    }
+}
+
+/// Returns the size of a detector that fills up as much memory as possible in the specified way,
+/// but no more than the memory limit of the cgroup of this process leaves, if it has one.
+/// # Panic
+/// Panics if this function is called on an operating system that is not supported by [sysinfo](https://crates.io/crates/sysinfo).
+pub fn maximum_size_in_mode(mode: AllocationMode) -> usize {
+    let size = host_maximum_size_in_mode(mode);
+    match cgroup::memory() {
+        Some(memory) => size.min(usize::try_from(memory.headroom()).unwrap_or(usize::MAX)),
+        None => size,
+    }
+}
+
+fn host_maximum_size_in_mode(mode: AllocationMode) -> usize {
+    #[cfg(windows)]
+    if let Some(bytes) = windows_memory(mode) {
+        return usize::try_from(bytes)
+            .expect("number of bytes of available memory fits in a usize");
+    }
+    #[cfg(target_os = "freebsd")]
+    if let Some(bytes) = freebsd_memory(mode) {
+        return usize::try_from(bytes)
+            .expect("number of bytes of available memory fits in a usize");
+    }
+
+    if !<System as SystemExt>::IS_SUPPORTED {
+        panic!("{} is not supported by the mechanism this program uses to determine available memory, please specify it manually", std::env::consts::OS);
+    }
+
+    let s = System::new_with_specifics(RefreshKind::new().with_memory());
+    usize::try_from(match mode {
+        AllocationMode::Available => s.available_memory(),
+        AllocationMode::Free => s.free_memory(),
+    })
+    .expect("number of bytes of available memory fits in a usize")
+}
+
+/// Checks that a detector of the given size fits into the memory of the machine, so that a typo in the size
+/// is reported right away instead of making the system swap or run out of memory once the detector is filled.
+/// Any size is let through if the operating system can not tell how much memory there is.
+pub fn check_fits_in_memory(size: usize) -> Result<(), String> {
+    if let Some(memory) = cgroup::memory() {
+        if size as u64 > memory.limit {
+            return Err(format!(
+                "the detector of {size} bytes is larger than the memory limit of {} bytes of the cgroup this program runs in, filling it would get the program killed",
+                memory.limit
+            ));
+        }
+    }
+    if !<System as SystemExt>::IS_SUPPORTED {
+        return Ok(());
+    }
+    let s = System::new_with_specifics(RefreshKind::new().with_memory());
+    fits_in_memory(
+        size,
+        s.total_memory(),
+        maximum_size_in_mode(AllocationMode::Available) as u64,
+    )
+}
+
+fn fits_in_memory(size: usize, total: u64, available: u64) -> Result<(), String> {
+    if size as u64 > total {
+        Err(format!(
+            "the detector of {size} bytes is larger than all {total} bytes of memory of this machine"
+        ))
+    } else if size as u64 > available {
+        Err(format!("the detector of {size} bytes is larger than the {available} bytes of memory that are available, filling it would make the system swap or run out of memory"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+/// Asks Windows how much memory is free or available. Available memory is the `ullAvailPhys` of `GlobalMemoryStatusEx`,
+/// which includes the standby list of cached pages that can be repurposed. Free memory leaves out the standby list,
+/// and is read from the "Free & Zero Page List Bytes" performance counter.
+/// Returns `None` if the query fails, in which case we fall back to what sysinfo reports.
+fn windows_memory(mode: AllocationMode) -> Option<u64> {
+    use windows_sys::Win32::System::Performance::{
+        PdhAddEnglishCounterW, PdhCloseQuery, PdhCollectQueryData, PdhGetFormattedCounterValue,
+        PdhOpenQueryW, PDH_FMT_COUNTERVALUE, PDH_FMT_LARGE,
+    };
+    use windows_sys::Win32::System::SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX};
+
+    match mode {
+        AllocationMode::Available => {
+            let mut status: MEMORYSTATUSEX = unsafe { std::mem::zeroed() };
+            status.dwLength = std::mem::size_of::<MEMORYSTATUSEX>() as u32;
+            (unsafe { GlobalMemoryStatusEx(&mut status) } != 0).then_some(status.ullAvailPhys)
+        }
+        AllocationMode::Free => {
+            let path: Vec<u16> = "\\Memory\\Free & Zero Page List Bytes\0"
+                .encode_utf16()
+                .collect();
+            let mut query = 0;
+            if unsafe { PdhOpenQueryW(std::ptr::null(), 0, &mut query) } != 0 {
+                return None;
+            }
+            let mut counter = 0;
+            let mut value: PDH_FMT_COUNTERVALUE = unsafe { std::mem::zeroed() };
+            // The counter is a snapshot rather than a rate, so a single sample is enough.
+            let ok = unsafe {
+                PdhAddEnglishCounterW(query, path.as_ptr(), 0, &mut counter) == 0
+                    && PdhCollectQueryData(query) == 0
+                    && PdhGetFormattedCounterValue(
+                        counter,
+                        PDH_FMT_LARGE,
+                        std::ptr::null_mut(),
+                        &mut value,
+                    ) == 0
+            };
+            unsafe { PdhCloseQuery(query) };
+            ok.then_some(unsafe { value.Anonymous.largeValue } as u64)
+        }
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+/// Asks FreeBSD how much memory is free or available by counting pages with the `vm.stats.vm` sysctls.
+/// Free memory is `v_free_count` pages, available memory adds the `v_inactive_count` pages that can be reclaimed
+/// and, on releases that still have it, the `v_cache_count` pages. Pages are `vm.stats.vm.v_page_size` bytes.
+/// Returns `None` if the sysctls can not be read, in which case we fall back to what sysinfo reports.
+fn freebsd_memory(mode: AllocationMode) -> Option<u64> {
+    fn sysctl_u32(name: &std::ffi::CStr) -> Option<u32> {
+        let mut value: u32 = 0;
+        let mut len = std::mem::size_of::<u32>();
+        let result = unsafe {
+            libc::sysctlbyname(
+                name.as_ptr(),
+                (&mut value as *mut u32).cast(),
+                &mut len,
+                std::ptr::null(),
+                0,
+            )
+        };
+        (result == 0 && len == std::mem::size_of::<u32>()).then_some(value)
+    }
+
+    let page_size = u64::from(sysctl_u32(c"vm.stats.vm.v_page_size")?);
+    let mut pages = u64::from(sysctl_u32(c"vm.stats.vm.v_free_count")?);
+    if let AllocationMode::Available = mode {
+        pages += u64::from(sysctl_u32(c"vm.stats.vm.v_inactive_count")?);
+        // The cache queue was removed in FreeBSD 12.
+        pages += u64::from(sysctl_u32(c"vm.stats.vm.v_cache_count").unwrap_or(0));
+    }
+    Some(pages * page_size)
+}
+
+/// A page of the detector memory that read as all zeros in an integrity check although the pattern is not zero
+/// there. This is what a page looks like that the operating system reclaimed and mapped anew, e.g. under memory
+/// pressure, so its bytes are reported as one page rather than as thousands of flips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReclaimedPage {
+    /// The index of the first byte of the page in the detector memory.
+    pub offset: usize,
+    /// How many bytes of the detector memory are in the page, less than a page at the ends of a region that is not aligned.
+    pub len: usize,
+    /// The bytes of the page that no longer held the pattern, in the order they were found.
+    pub flips: Vec<FlipEvent>,
+}
+
+impl ReclaimedPage {
+    /// Returns whether the byte at the given index of the detector memory is in this page.
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.offset..self.offset + self.len).contains(&offset)
+    }
+}
+
+/// A block of the detector memory whose contents differ from the snapshot taken with [`Detector::use_snapshot`].
+/// Only the checksum of the block is known from the snapshot, so which of its bytes changed is not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangedBlock {
+    /// The index of the first byte of the block in the detector memory.
+    pub offset: usize,
+    /// How many bytes are in the block, less than a block at the end of a region.
+    pub len: usize,
+    /// The CRC32 of the block as it is now.
+    pub checksum: u32,
+}
+
+/// A word whose check bits did not match during an integrity check with ECC emulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WordError {
+    /// The index of the word among the words of the detector memory.
+    pub word: usize,
+    /// Whether the flips could be corrected.
+    pub status: WordStatus,
+    /// The flipped bytes of the word and its check byte. For corrected words they are
+    /// found by the error correction, for uncorrectable ones by comparing with the pattern.
+    pub flips: Vec<FlipEvent>,
+}
+
+/// Reads eight bytes of the detector memory as a little endian word.
+fn read_word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(std::array::from_fn(|i| unsafe { read_volatile(&bytes[i]) }))
+}
+
+/// Classifies flips that are ordered by index by the total number of flipped bits of the word they belong to.
+pub(crate) fn classify_words(flips: &mut [FlipEvent], word_size: WordSize) {
+    let len = word_size.bytes();
+    for word in flips.chunk_by_mut(|a, b| a.offset / len == b.offset / len) {
+        let bits = word
+            .iter()
+            .map(|flip| flip.changed_bits().count_ones())
+            .sum();
+        let class = FlipClass::from_flipped_bits(bits);
+        word.iter_mut().for_each(|flip| flip.class = class);
+        narrow_cluster(word);
+    }
+}
+
+/// Gives the first of the flipped bytes of a word with a multi-bit flip every flipped bit of the word,
+/// so that the whole cluster is reported with its addresses rather than as a count of bits.
+fn narrow_cluster(word: &mut [FlipEvent]) {
+    let Some(first) = word.first() else {
+        return;
+    };
+    if first.class == FlipClass::Multi {
+        let cluster = word.iter().flat_map(FlipEvent::bits).collect();
+        word[0].cluster = cluster;
+    }
+}
+
+/// Returns an event for every byte of a word and its check byte that differs from the expected values,
+/// classified by the flipped bits of both together.
+fn word_flips(
+    offset: usize,
+    check_offset: usize,
+    (found, found_check): (u64, u8),
+    (expected, expected_check): (u64, u8),
+) -> Vec<FlipEvent> {
+    let mut flips: Vec<FlipEvent> = (0..8)
+        .map(|i| {
+            FlipEvent::new(
+                offset + i,
+                (expected >> (8 * i)) as u8,
+                (found >> (8 * i)) as u8,
+            )
+        })
+        .filter(|flip| flip.expected != flip.found)
+        .collect();
+    if found_check != expected_check {
+        flips.push(FlipEvent::new(check_offset, expected_check, found_check));
+    }
+    let bits = (found ^ expected).count_ones() + (found_check ^ expected_check).count_ones();
+    let class = FlipClass::from_flipped_bits(bits);
+    flips.iter_mut().for_each(|flip| flip.class = class);
+    narrow_cluster(&mut flips);
+    flips
+}
+
+/// Reads the byte from the memory rather than from the cache of the CPU, where the CPU allows that.
+fn read_uncached(byte: &u8) -> u8 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        // SSE2 is part of x86_64, so these are always available.
+        std::arch::x86_64::_mm_clflush(byte);
+        std::arch::x86_64::_mm_mfence();
+    }
+    unsafe { read_volatile(byte) }
+}
+
+/// Returns an event for every element of the chunk that does not match the pattern,
+/// where `offset` is the index of the first element of the chunk in the detector memory.
+pub(crate) fn chunk_flips(
+    contents: Contents,
+    word_size: WordSize,
+    chunk: &[u8],
+    offset: usize,
+) -> Vec<FlipEvent> {
+    // Most checks find nothing, so first look for any mismatch at all
+    // and only collect the events from the first mismatching element onwards.
+    match contents.verify(chunk, offset, word_size) {
+        Some(first) => chunk[first..]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| {
+                let offset = offset + first + i;
+                let expected = contents.expected(offset);
+                let found = unsafe { read_volatile(r) };
+                (found != expected).then(|| FlipEvent::new(offset, expected, found))
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Like [`chunk_flips`], but only compares the blocks whose CRC32 no longer matches the given checksums to the pattern.
+/// The chunk has to start at a block boundary of the detector memory.
+fn block_flips(
+    contents: Contents,
+    word_size: WordSize,
+    checksums: &[u32],
+    chunk: &[u8],
+    offset: usize,
+) -> Vec<FlipEvent> {
+    let first_block = offset / BLOCK_SIZE;
+    chunk
+        .chunks(BLOCK_SIZE)
+        .enumerate()
+        .filter(|&(i, block)| crc32(block) != checksums[first_block + i])
+        // If the flip hit the checksum itself no byte of the block differs from the pattern.
+        .flat_map(|(i, block)| chunk_flips(contents, word_size, block, offset + i * BLOCK_SIZE))
+        .collect()
+}
+
+/// Like [`block_flips`], but compares the blocks whose CRC32 no longer matches to the reference file instead of a pattern.
+/// # Panic
+/// Panics if the reference file can not be read anymore.
+fn reference_block_flips(
+    reference: &Reference,
+    checksums: &[u32],
+    chunk: &[u8],
+    offset: usize,
+) -> Vec<FlipEvent> {
+    let first_block = offset / BLOCK_SIZE;
+    let mut expected = [0; BLOCK_SIZE];
+    let mut flips = Vec::new();
+    for (i, block) in chunk.chunks(BLOCK_SIZE).enumerate() {
+        if crc32(block) == checksums[first_block + i] {
+            continue;
+        }
+        let offset = offset + i * BLOCK_SIZE;
+        let expected = &mut expected[..block.len()];
+        reference
+            .read(offset, expected)
+            .expect("could not read the reference file");
+        flips.extend(block.iter().zip(expected.iter()).enumerate().filter_map(
+            |(j, (r, &expected))| {
+                let found = unsafe { read_volatile(r) };
+                (found != expected).then(|| FlipEvent::new(offset + j, expected, found))
+            },
+        ));
+    }
+    flips
+}
+
+/// The size of a cache line on practically every CPU the detector runs on.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Returns the cache lines of a buffer of the given length in the order of a strided scan:
+/// the first line of every page, then the second line of every page and so on.
+///
+/// Reading the memory sequentially measures the cache more than the DRAM. The prefetcher recognizes the
+/// sequential reads and fetches the lines ahead of them, and whatever part of the detector memory fits
+/// into the cache may still be there from the previous check, so that bits that flipped in the DRAM cells
+/// are not seen until the line is evicted. Successive reads of a strided scan are a page apart,
+/// and prefetchers don't cross page boundaries. By the time the scan returns to a page all the other pages
+/// have been read in between, so unless the whole buffer fits into the cache its lines have been evicted
+/// and every read goes to the DRAM.
+fn strided_lines(len: usize) -> impl Iterator<Item = Range<usize>> {
+    (0..PAGE_SIZE)
+        .step_by(CACHE_LINE_SIZE)
+        .flat_map(move |line| {
+            (line..len)
+                .step_by(PAGE_SIZE)
+                .map(move |start| start..(start + CACHE_LINE_SIZE).min(len))
+        })
+}
+
+/// Like [`chunk_flips`], but reads the chunk in the given order. The events are still ordered by index.
+fn chunk_flips_in_order(
+    order: ScanOrder,
+    word_size: WordSize,
+    contents: Contents,
+    chunk: &[u8],
+    offset: usize,
+) -> Vec<FlipEvent> {
+    match order {
+        ScanOrder::Sequential => chunk_flips(contents, word_size, chunk, offset),
+        ScanOrder::Strided => {
+            let mut flips: Vec<FlipEvent> = strided_lines(chunk.len())
+                .flat_map(|line| {
+                    chunk_flips(
+                        contents,
+                        word_size,
+                        &chunk[line.clone()],
+                        offset + line.start,
+                    )
+                })
+                .collect();
+            flips.sort_unstable_by_key(|flip| flip.offset);
+            flips
+        }
+    }
+}
+
+/// Returns the indices of the words of a chunk of the given number of words in the order they are checked.
+fn word_indices(order: ScanOrder, words: usize) -> Box<dyn Iterator<Item = usize>> {
+    match order {
+        ScanOrder::Sequential => Box::new(0..words),
+        // Cache lines and pages are made up of whole words.
+        ScanOrder::Strided => {
+            Box::new(strided_lines(8 * words).flat_map(|line| line.start / 8..line.end / 8))
+        }
+    }
+}
+
+/// Returns how many bytes at the start of a region of the given length are filled with the pattern,
+/// which is all of them unless some are used for the check bits of ECC emulation.
+fn data_len(region_len: usize, ecc: bool) -> usize {
+    if ecc {
+        region_len / 9 * 8
+    } else {
+        region_len
+    }
+}
+
+/// Reads back the first `len` bytes of a region through the host backend, which returns them in place.
+fn read_back(region: &Region, len: usize) -> &[u8] {
+    region
+        .read_back(0..len, &mut [])
+        .expect("a region is read back within its length")
+}
+
+/// Runs `f` on every item on the given number of threads, and returns the results in the order of the items.
+/// Every thread takes the next item as soon as it is done with the previous one. If `cpus` is not empty
+/// the threads are pinned to its cores one after the other, see [`Detector::pin_threads`].
+fn run_parallel<I: Send, T: Send>(
+    threads: usize,
+    cpus: &[usize],
+    items: Vec<I>,
+    f: impl Fn(I) -> T + Sync,
+) -> Vec<T> {
+    if threads == 1 || items.len() <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let workers = threads.min(items.len());
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (queue, f) = (&queue, &f);
+    let mut results: Vec<(usize, T)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                s.spawn(move || {
+                    // Pinning was tried out when the cores were chosen, so it only fails if they went offline since.
+                    if !cpus.is_empty() {
+                        let _ = affinity::pin_current_thread(cpus[worker % cpus.len()]);
+                    }
+                    let mut results = Vec::new();
+                    loop {
+                        // Only hold the lock while taking an item, not while working on it.
+                        let next = queue.lock().expect("a detector thread panicked").next();
+                        let Some((i, item)) = next else {
+                            break;
+                        };
+                        results.push((i, f(item)));
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("a detector thread panicked"))
+            .collect()
+    });
+    results.sort_unstable_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The largest chunk of the detector memory a thread fills or checks at once, so that the threads
+/// get through large detectors in steps that a progress indicator can follow, see [`Detector::progress`].
+const MAX_CHUNK_LEN: usize = 64 << 20;
+
+/// A chunk of the words of a region with ECC emulation, together with their check bytes.
+struct EccChunk<'a, C> {
+    data: &'a [u8],
+    check: C,
+    /// The index of the first byte of the chunk in the detector memory.
+    offset: usize,
+    /// The index of the first check byte in the detector memory.
+    check_offset: usize,
+    /// The index of the first word of the chunk among the words of the detector memory.
+    first_word: usize,
+}
+
 /// In order to prevent the optimizer from removing the reads of the memory that make up the detector
 /// this struct will only use volatile reads and writes to its memory.
 pub struct Detector {
-    parallel: bool,
-    default: u8,
-    detector_mass: Vec<u8>,
+    threads: usize,
+    /// How many of the threads fill and check the memory at the moment, see [`Detector::set_active_threads`].
+    active_threads: usize,
+    contents: Contents,
+    locked: bool,
+    /// The CRC32 of every block of the detector memory, if it is checked by checksum.
+    checksums: Option<Vec<u32>>,
+    /// The file the detector memory is filled with instead of the pattern, see [`Detector::use_reference`].
+    reference: Option<Reference>,
+    /// The CPU cores the threads are pinned to, see [`Detector::pin_threads`].
+    cpus: Vec<usize>,
+    /// Whether the end of every region holds check bits for the words at its start.
+    ecc: bool,
+    scan_order: ScanOrder,
+    word_size: WordSize,
+    /// The number of slices the chunks of the detector memory are divided into, see [`Detector::use_slices`].
+    slices: usize,
+    /// The number of copies the detector memory is divided into, see [`Detector::use_mirrors`].
+    mirrors: usize,
+    /// The regions that make up the detector memory, one after the other.
+    detector_mass: Vec<Region>,
+    /// How many bytes of the detector memory have been filled or checked so far.
+    progress: Arc<AtomicUsize>,
 }
 
 impl Detector {
-    pub fn new(parallel: bool, default: u8, capacity_bytes: usize) -> Self {
-        Detector {
-            parallel,
-            default,
-            detector_mass: vec![default; capacity_bytes],
+    /// Returns a builder for a detector of up to the given size in bytes,
+    /// filled with zeros and checked on a single thread unless configured otherwise.
+    pub fn builder(capacity_bytes: usize) -> DetectorBuilder {
+        DetectorBuilder {
+            capacity_bytes,
+            pattern: Pattern::Zeros,
+            threads: NonZeroUsize::MIN,
+            options: RegionOptions::default(),
         }
     }
 
-    #[cfg(any(target_os = "windows", target_os = "freebsd"))]
-    /// Creates a new detector that fills up as much memory as possible.
-    pub fn new_with_maximum_size(parallel: bool, default: u8) -> Self {
-        // Know this is supported on windows.
-        let s = System::new_with_specifics(RefreshKind::new().with_memory());
-        let capacity_bytes = usize::try_from(s.available_memory())
-            .expect("number of bytes of available memory fits in a usize");
+    /// Creates a new detector of up to the given size, whose memory is split into chunks
+    /// that are filled and checked on the given number of threads.
+    /// It ends up smaller if not all of the memory could be allocated, see [`allocate_regions_retrying`],
+    /// or [`allocate_regions_probing`] if the options ask for probing.
+    /// # Panic
+    /// Panics if `threads` is zero.
+    pub fn new(
+        threads: usize,
+        pattern: Pattern,
+        capacity_bytes: usize,
+        options: RegionOptions,
+    ) -> Self {
+        let mut detector = Self::unfilled(threads, pattern, capacity_bytes, options);
+        detector.fill();
+        detector
+    }
+
+    /// Like [`Detector::new`], but leaves the memory as the operating system handed it out,
+    /// e.g. to take a [snapshot](Detector::use_snapshot) of it.
+    /// # Panic
+    /// Panics if `threads` is zero.
+    pub fn unfilled(
+        threads: usize,
+        pattern: Pattern,
+        capacity_bytes: usize,
+        options: RegionOptions,
+    ) -> Self {
+        let regions = if options.probe {
+            allocate_regions_probing(capacity_bytes, options)
+        } else {
+            allocate_regions_retrying(capacity_bytes, options)
+        };
+        Self::resume(threads, pattern, regions, false)
+    }
+
+    /// Creates a new detector out of the given memory and fills it with the pattern.
+    /// # Panic
+    /// Panics if `threads` is zero.
+    pub fn with_regions(threads: usize, pattern: Pattern, regions: Vec<Region>) -> Self {
+        let mut detector = Self::resume(threads, pattern, regions, false);
+        detector.fill();
+        detector
+    }
 
+    /// Creates a new detector out of memory that was already filled with the pattern, e.g. by an earlier run,
+    /// and that holds the check bits of ECC emulation if `ecc` is set. Unlike [`Detector::with_regions`]
+    /// this leaves the memory as it is, so that the first integrity check reports whatever changed in the meantime.
+    /// # Panic
+    /// Panics if `threads` is zero.
+    pub fn resume(threads: usize, pattern: Pattern, regions: Vec<Region>, ecc: bool) -> Self {
+        assert!(threads > 0, "the detector needs at least one thread");
         Detector {
-            parallel,
-            default,
-            detector_mass: vec![default; capacity_bytes],
+            threads,
+            active_threads: threads,
+            contents: Contents {
+                pattern,
+                page_markers: false,
+            },
+            locked: false,
+            checksums: None,
+            reference: None,
+            cpus: Vec::new(),
+            ecc,
+            scan_order: ScanOrder::Sequential,
+            word_size: WordSize::U64,
+            slices: 1,
+            mirrors: 1,
+            detector_mass: regions,
+            progress: Arc::default(),
+        }
+    }
+
+    /// Returns the allocated memory size of the detector in bytes.
+    pub fn capacity(&self) -> usize {
+        self.detector_mass.iter().map(|region| region.len()).sum()
+    }
+
+    /// Returns how many bytes of the memory allocated for the detector were left out to keep it to whole pages.
+    pub fn trimmed_bytes(&self) -> usize {
+        self.detector_mass.iter().map(Region::trimmed_bytes).sum()
+    }
+
+    /// Returns a counter of the bytes of the detector memory that its threads have filled, written or checked so far,
+    /// which goes up by a chunk at a time while they are at it, for a progress indicator.
+    pub fn progress(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.progress)
+    }
+
+    /// Fills and checks the detector memory on only the given number of its threads, at least one and at most
+    /// all of them, without changing how the memory is split into chunks between them.
+    pub fn set_active_threads(&mut self, threads: usize) {
+        self.active_threads = threads.clamp(1, self.threads);
+    }
+
+    /// Returns whether the detector memory is backed by huge pages.
+    pub fn uses_huge_pages(&self) -> bool {
+        !self.detector_mass.is_empty() && self.detector_mass.iter().all(Region::is_huge_pages)
+    }
+
+    /// Returns the byte at the given index of the detector memory, or `None` if it is beyond the end.
+    pub fn byte(&self, offset: usize) -> Option<&u8> {
+        let (region, start) = self
+            .regions()
+            .take_while(|(_, start)| *start <= offset)
+            .last()?;
+        region.get(offset - start)
+    }
+
+    #[cfg(feature = "numa")]
+    /// Returns the NUMA node that the byte at the given index of the detector memory is on, if the kernel can tell.
+    pub fn numa_node(&self, offset: usize) -> Option<u32> {
+        self.byte(offset).and_then(crate::numa::node_of)
+    }
+
+    #[cfg(feature = "numa")]
+    /// Returns the NUMA nodes that the regions of the detector memory are on, in ascending order.
+    pub fn numa_nodes(&self) -> Vec<u32> {
+        let mut nodes: Vec<u32> = self
+            .regions()
+            .filter_map(|(_, start)| self.numa_node(start))
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes
+    }
+
+    /// Locks the detector memory into RAM, so that it can not be swapped out.
+    /// It is unlocked again when the detector is dropped.
+    pub fn lock(&mut self) -> io::Result<()> {
+        // Unlock whatever was locked on drop, even if locking some region fails.
+        self.locked = true;
+        for region in &self.detector_mass {
+            memlock::lock(region)?;
         }
+        Ok(())
     }
 
-    #[cfg(all(not(target_os = "windows"), not(target_os = "freebsd")))]
-    /// Creates a new detector that fills up as much memory as possible in the specified way.
+    /// Pins the threads that fill and check the detector memory to the given CPU cores, the first thread
+    /// to the first core and so on, starting over with the first core if there are more threads than cores.
+    /// The calling thread is pinned to the first core, as the detector does its work on it when there is only one
+    /// thread, and the threads it spawns afterwards start out on that core too.
+    /// Returns the core every thread ended up on, if the operating system can tell.
     /// # Panic
-    /// Panics if this function is called on an operating system that is not supported by [sysinfo](https://crates.io/crates/sysinfo).
-    pub fn new_with_maximum_size_in_mode(
-        parallel: bool,
-        default: u8,
-        mode: AllocationMode,
-    ) -> Self {
-        if !<System as SystemExt>::IS_SUPPORTED {
-            panic!("{} is not supported by the mechanism this program uses to determine available memory, please specify it manually", std::env::consts::OS);
+    /// Panics if `cpus` is empty.
+    pub fn pin_threads(&mut self, cpus: Vec<usize>) -> io::Result<Vec<Option<usize>>> {
+        assert!(!cpus.is_empty(), "the threads need a core to be pinned to");
+        let landed = thread::scope(|s| {
+            let handles: Vec<_> = (0..self.threads)
+                .map(|thread| {
+                    let cpu = cpus[thread % cpus.len()];
+                    s.spawn(move || {
+                        affinity::pin_current_thread(cpu)?;
+                        Ok(affinity::current_cpu())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("a detector thread panicked"))
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+        // Only once all of the cores turned out to work, so that a failure leaves everything to the scheduler.
+        affinity::pin_current_thread(cpus[0])?;
+        self.cpus = cpus;
+        Ok(landed)
+    }
+
+    /// Makes integrity checks compare a CRC32 of every block of the detector memory with the one computed
+    /// when it was filled, and only compare the bytes of blocks whose checksum changed to the pattern.
+    pub fn use_checksums(&mut self) {
+        self.checksums = Some(self.compute_checksums());
+    }
+
+    /// Fills the detector memory with the contents of the reference file instead of the pattern, and checks it
+    /// by checksum. Only the blocks whose checksum changed are compared to the file, which is read again for that,
+    /// and [`Detector::reset`] reads the file into the memory again too. The file has to be as large as the memory.
+    /// # Panic
+    /// Panics if ECC emulation or page markers are used.
+    pub(crate) fn use_reference(&mut self, reference: Reference) -> io::Result<()> {
+        assert!(
+            !self.ecc && !self.contents.page_markers,
+            "the detector can not hold check bits or page markers next to the reference file"
+        );
+        if reference.size() != self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the file has {} bytes, but the detector memory {}",
+                    reference.size(),
+                    self.capacity()
+                ),
+            ));
         }
+        // Reading the whole file once up front gives any error a chance to show up now rather than in a check.
+        let mut block = [0; BLOCK_SIZE];
+        for offset in (0..reference.size()).step_by(BLOCK_SIZE) {
+            let len = BLOCK_SIZE.min(reference.size() - offset);
+            reference.read(offset, &mut block[..len])?;
+        }
+        self.reference = Some(reference);
+        self.checksums = Some(Vec::new());
+        self.fill();
+        Ok(())
+    }
+
+    /// Takes whatever the detector memory holds now as what it should hold, instead of the pattern, by keeping
+    /// a CRC32 of every block of it. Integrity checks then look for [changed blocks](Detector::changed_blocks)
+    /// rather than flips, and the memory must not be filled or reset anymore.
+    /// # Panic
+    /// Panics if ECC emulation is used.
+    pub fn use_snapshot(&mut self) {
+        assert!(!self.ecc, "a snapshot can not be taken with ECC emulation");
+        // The chunks only consist of whole blocks once there are checksums.
+        self.checksums = Some(Vec::new());
+        let checksums = self
+            .map_chunks(|chunk, _| chunk.chunks(BLOCK_SIZE).map(crc32).collect::<Vec<_>>())
+            .into_iter()
+            .flatten()
+            .collect();
+        self.checksums = Some(checksums);
+    }
 
-        let s = System::new_with_specifics(RefreshKind::new().with_memory());
-        let capacity_bytes = usize::try_from(match mode {
-            AllocationMode::Available => s.available_memory(),
-            AllocationMode::Free => s.free_memory(),
+    /// Returns every block of the detector memory whose checksum differs from the snapshot, ordered by index.
+    /// # Panic
+    /// Panics if no snapshot was taken with [`Detector::use_snapshot`].
+    pub fn changed_blocks(&self) -> Vec<ChangedBlock> {
+        let checksums = self
+            .checksums
+            .as_deref()
+            .expect("the detector has no snapshot");
+        self.map_chunks(|chunk, offset| {
+            let first_block = offset / BLOCK_SIZE;
+            chunk
+                .chunks(BLOCK_SIZE)
+                .enumerate()
+                .filter_map(|(i, block)| {
+                    let checksum = crc32(block);
+                    (checksum != checksums[first_block + i]).then_some(ChangedBlock {
+                        offset: offset + i * BLOCK_SIZE,
+                        len: block.len(),
+                        checksum,
+                    })
+                })
+                .collect::<Vec<_>>()
         })
-        .expect("number of bytes of available memory fits in a usize");
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 
-        Detector {
-            parallel,
-            default,
-            detector_mass: vec![default; capacity_bytes],
+    /// Takes the current contents of the given blocks into the snapshot, so that later checks
+    /// only report blocks that changed again. This is what scrubbing is for flips.
+    pub fn update_snapshot(&mut self, blocks: &[ChangedBlock]) {
+        let checksums = self
+            .checksums
+            .as_mut()
+            .expect("the detector has no snapshot");
+        for block in blocks {
+            checksums[block.offset / BLOCK_SIZE] = block.checksum;
         }
     }
 
-    /// Returns the allocated memory size of the detector in bytes.
-    pub fn capacity(&self) -> usize {
-        self.detector_mass.capacity()
+    /// Returns whether integrity checks compare checksums of blocks.
+    pub fn uses_checksums(&self) -> bool {
+        self.checksums.is_some()
+    }
+
+    /// Splits every region of the detector memory into 64-bit words filled with the pattern, followed by a byte of
+    /// SECDED Hamming check bits for every word. Integrity checks then decode every word, so that
+    /// single bit flips can be corrected and double bit flips detected without knowing the pattern.
+    pub fn use_ecc_emulation(&mut self) {
+        self.ecc = true;
+        self.fill();
+    }
+
+    /// Returns whether the detector memory holds emulated ECC check bits.
+    pub fn uses_ecc_emulation(&self) -> bool {
+        self.ecc
+    }
+
+    /// Overwrites the first bytes of every page of the detector memory with the index of the page, and checks them
+    /// like the rest of the pattern. Otherwise the pages of most patterns are all the same, and the kernel (KSM)
+    /// or a hypervisor that deduplicates memory can merge them into a single copy, so that flips in the RAM
+    /// that used to hold the others go unnoticed. This is only worth it in virtual machines and on hosts that do that.
+    pub fn use_page_markers(&mut self) {
+        self.contents.page_markers = true;
+        self.fill();
+    }
+
+    /// Makes integrity checks read the detector memory in the given order.
+    pub fn use_scan_order(&mut self, order: ScanOrder) {
+        self.scan_order = order;
+    }
+
+    /// Makes integrity checks compare the detector memory in words of the given size, and classify flips by them.
+    /// Words of eight bytes are used by default, and always with ECC emulation.
+    pub fn use_word_size(&mut self, word_size: WordSize) {
+        self.word_size = word_size;
+    }
+
+    /// Divides the detector memory into the given number of slices of about the same size,
+    /// that can be checked one at a time with [`Detector::slice_flips`] and [`Detector::slice_word_errors`].
+    /// Slices are made up of whole words, so the flips of a word are always reported together.
+    pub fn use_slices(&mut self, slices: NonZeroUsize) {
+        self.slices = slices.get();
+    }
+
+    /// Divides the detector memory into the given number of copies of the same size, of whole pages,
+    /// whose bytes vote on each other's flips with [`Detector::vote_mirrors`]. Any pages after the last whole
+    /// copy are still checked, but belong to no copy. This doesn't work with ECC emulation or a reference file.
+    pub fn use_mirrors(&mut self, copies: NonZeroUsize) {
+        self.mirrors = copies.get();
+    }
+
+    /// Returns the size of every copy of a mirrored detector.
+    pub fn mirror_len(&self) -> usize {
+        self.capacity() / self.mirrors / PAGE_SIZE * PAGE_SIZE
+    }
+
+    /// Compares the bytes of the given flips of a mirrored detector with the same bytes of the other copies,
+    /// and records which copy every flip is in and how the copies vote on it. Flips that are in no copy are left as they are.
+    pub fn vote_mirrors(&self, flips: &mut [FlipEvent]) {
+        let len = self.mirror_len();
+        if self.mirrors < 2 || len == 0 {
+            return;
+        }
+        for flip in flips {
+            let copy = flip.offset / len;
+            if copy >= self.mirrors {
+                continue;
+            }
+            let position = flip.offset % len;
+            // Every copy votes for the expected value of this byte, changed the way its own byte is off its pattern.
+            let votes: Vec<u8> = (0..self.mirrors)
+                .map(|other| {
+                    if other == copy {
+                        return flip.found;
+                    }
+                    let offset = other * len + position;
+                    let byte = self
+                        .byte(offset)
+                        .expect("the copies are in the detector memory");
+                    let found = unsafe { read_volatile(byte) };
+                    flip.expected ^ found ^ self.contents.expected(offset)
+                })
+                .collect();
+            flip.mirror = Some(MirrorVote {
+                copy,
+                copies: self.mirrors,
+                agreeing: votes.iter().filter(|&&vote| vote == flip.expected).count(),
+                majority: votes.iter().copied().find(|vote| {
+                    votes.iter().filter(|&other| other == vote).count() * 2 > votes.len()
+                }),
+            });
+        }
+    }
+
+    /// Takes the flips out of the given ones that are in pages of the detector memory that read as all zeros,
+    /// and returns those pages. The pages are the pages of memory the operating system maps, so at the ends
+    /// of a region that is not aligned to them only part of a page belongs to the detector.
+    pub fn take_reclaimed_pages(&self, flips: &mut Vec<FlipEvent>) -> Vec<ReclaimedPage> {
+        let mut pages: Vec<ReclaimedPage> = Vec::new();
+        // The last page that was looked at and still holds something other than zeros.
+        let mut intact = 0..0;
+        for flip in std::mem::take(flips) {
+            if flip.found != 0 || intact.contains(&flip.offset) {
+                flips.push(flip);
+                continue;
+            }
+            if let Some(page) = pages.last_mut().filter(|page| page.contains(flip.offset)) {
+                page.flips.push(flip);
+                continue;
+            }
+            let (bytes, range) = self.page_around(flip.offset);
+            if bytes.iter().all(|byte| unsafe { read_volatile(byte) } == 0) {
+                pages.push(ReclaimedPage {
+                    offset: range.start,
+                    len: range.len(),
+                    flips: vec![flip],
+                });
+            } else {
+                intact = range;
+                flips.push(flip);
+            }
+        }
+        pages
+    }
+
+    /// Returns the bytes of the detector memory that are in the same page of memory as the byte at the given index,
+    /// together with their indices.
+    fn page_around(&self, offset: usize) -> (&[u8], Range<usize>) {
+        let (region, start) = self
+            .regions()
+            .take_while(|(_, start)| *start <= offset)
+            .last()
+            .expect("flips are in the detector memory");
+        let base = region.as_ptr() as usize;
+        let page = (base + offset - start) / PAGE_SIZE * PAGE_SIZE;
+        let first = page.max(base) - base;
+        let end = (page + PAGE_SIZE).min(base + region.len()) - base;
+        (&region[first..end], start + first..start + end)
+    }
+
+    /// Returns the number of bytes of the detector memory that are filled with the pattern.
+    /// This is all of it, unless some is used for the check bits of ECC emulation.
+    pub fn data_capacity(&self) -> usize {
+        self.detector_mass
+            .iter()
+            .map(|region| data_len(region.len(), self.ecc))
+            .sum()
+    }
+
+    /// Returns the regions of the detector memory together with the index of their first byte.
+    fn regions(&self) -> impl Iterator<Item = (&Region, usize)> {
+        self.detector_mass.iter().scan(0, |start, region| {
+            let offset = *start;
+            *start += region.len();
+            Some((region, offset))
+        })
+    }
+
+    /// Returns the regions of the detector memory together with the index of their first byte.
+    fn regions_mut(&mut self) -> impl Iterator<Item = (&mut Region, usize)> {
+        self.detector_mass.iter_mut().scan(0, |start, region| {
+            let offset = *start;
+            *start += region.len();
+            Some((region, offset))
+        })
+    }
+
+    /// Returns the byte at the given index of the detector memory.
+    /// # Panic
+    /// Panics if the index is beyond the end of the detector memory.
+    fn byte_mut(&mut self, offset: usize) -> &mut u8 {
+        let (region, start) = self
+            .regions_mut()
+            .take_while(|(_, start)| *start <= offset)
+            .last()
+            .expect("the detector memory is not empty");
+        &mut region[offset - start]
+    }
+
+    /// Writes the check bits of every word of the detector memory.
+    fn encode_check_bytes(&mut self) {
+        let cpus = self.cpus.clone();
+        run_parallel(self.active_threads, &cpus, self.ecc_chunks_mut(), |chunk| {
+            for (word, c) in chunk.data.chunks_exact(8).zip(chunk.check) {
+                unsafe { write_volatile(c, hamming::encode(read_word(word))) };
+            }
+        });
+    }
+
+    /// Returns the CRC32 of every block of the detector memory as it should be. They are computed from the pattern
+    /// or the reference file rather than read from the memory, so that they are also right for memory
+    /// that was filled by an earlier run.
+    fn compute_checksums(&self) -> Vec<u32> {
+        let (contents, reference) = (self.contents, self.reference.as_ref());
+        self.map_chunks(|chunk, offset| {
+            let mut block = [0; BLOCK_SIZE];
+            (0..chunk.len())
+                .step_by(BLOCK_SIZE)
+                .map(|start| {
+                    let block = &mut block[..BLOCK_SIZE.min(chunk.len() - start)];
+                    match reference {
+                        Some(reference) => reference
+                            .read(offset + start, block)
+                            .expect("could not read the reference file"),
+                        None => contents.fill(block, offset + start),
+                    }
+                    crc32(block)
+                })
+                .collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Returns the length of the chunks the pattern filled part of every region is split into,
+    /// so that there is about one chunk per thread in every slice. When checksums are used every chunk is made up
+    /// of whole blocks, and with ECC emulation or slices of whole words. As regions are made up of whole pages,
+    /// which are whole blocks, every chunk then starts at a block boundary of the detector memory.
+    fn chunk_len(&self) -> usize {
+        let len = self
+            .data_capacity()
+            .div_ceil(self.threads * self.slices)
+            .clamp(1, MAX_CHUNK_LEN);
+        if self.checksums.is_some() {
+            len.next_multiple_of(BLOCK_SIZE)
+        } else if self.ecc || self.slices > 1 {
+            len.next_multiple_of(8)
+        } else {
+            len
+        }
+    }
+
+    /// Returns the chunks of the pattern filled part of every region together with the index of their first byte.
+    fn chunks(&self) -> Vec<(&[u8], usize)> {
+        let chunk_len = self.chunk_len();
+        self.regions()
+            .flat_map(|(region, start)| {
+                read_back(region, data_len(region.len(), self.ecc))
+                    .chunks(chunk_len)
+                    .enumerate()
+                    .map(move |(i, chunk)| (chunk, start + i * chunk_len))
+            })
+            .collect()
+    }
+
+    /// Returns the chunks of the pattern filled part of every region together with the index of their first byte.
+    fn chunks_mut(&mut self) -> Vec<(&mut [u8], usize)> {
+        let chunk_len = self.chunk_len();
+        let ecc = self.ecc;
+        self.regions_mut()
+            .flat_map(|(region, start)| {
+                let len = data_len(region.len(), ecc);
+                region[..len]
+                    .chunks_mut(chunk_len)
+                    .enumerate()
+                    .map(move |(i, chunk)| (chunk, start + i * chunk_len))
+            })
+            .collect()
+    }
+
+    /// Returns the chunks of words of every region with ECC emulation, together with their check bytes.
+    fn ecc_chunks(&self) -> Vec<EccChunk<'_, &[u8]>> {
+        let chunk_len = self.chunk_len();
+        let mut first_word = 0;
+        let mut chunks = Vec::new();
+        for (region, start) in self.regions() {
+            let data_len = data_len(region.len(), true);
+            let (data, check) = read_back(region, region.len()).split_at(data_len);
+            let check = &check[..data_len / 8];
+            for (i, (data, check)) in data
+                .chunks(chunk_len)
+                .zip(check.chunks(chunk_len / 8))
+                .enumerate()
+            {
+                chunks.push(EccChunk {
+                    data,
+                    check,
+                    offset: start + i * chunk_len,
+                    check_offset: start + data_len + i * chunk_len / 8,
+                    first_word: first_word + i * chunk_len / 8,
+                });
+            }
+            first_word += data_len / 8;
+        }
+        chunks
+    }
+
+    /// Returns the chunks of words of every region with ECC emulation, together with their check bytes.
+    fn ecc_chunks_mut(&mut self) -> Vec<EccChunk<'_, &mut [u8]>> {
+        let chunk_len = self.chunk_len();
+        let mut first_word = 0;
+        let mut chunks = Vec::new();
+        for (region, start) in self.regions_mut() {
+            let data_len = data_len(region.len(), true);
+            let (data, check) = region.split_at_mut(data_len);
+            let check = &mut check[..data_len / 8];
+            for (i, (data, check)) in data
+                .chunks(chunk_len)
+                .zip(check.chunks_mut(chunk_len / 8))
+                .enumerate()
+            {
+                chunks.push(EccChunk {
+                    data,
+                    check,
+                    offset: start + i * chunk_len,
+                    check_offset: start + data_len + i * chunk_len / 8,
+                    first_word: first_word + i * chunk_len / 8,
+                });
+            }
+            first_word += data_len / 8;
+        }
+        chunks
+    }
+
+    /// Runs `f` on every chunk of the detector memory together with the index of its first byte,
+    /// spread over the threads of the detector.
+    fn for_each_chunk_mut(&mut self, f: impl Fn(&mut [u8], usize) + Sync) {
+        let cpus = self.cpus.clone();
+        let progress = Arc::clone(&self.progress);
+        run_parallel(
+            self.active_threads,
+            &cpus,
+            self.chunks_mut(),
+            |(chunk, offset)| {
+                let len = chunk.len();
+                f(chunk, offset);
+                progress.fetch_add(len, Ordering::Relaxed);
+            },
+        );
+    }
+
+    /// Runs `f` on every chunk of the detector memory together with the index of its first byte,
+    /// spread over the threads of the detector, and returns the results in the order of the chunks.
+    fn map_chunks<T: Send>(&self, f: impl Fn(&[u8], usize) -> T + Sync) -> Vec<T> {
+        self.map_slice_chunks(None, f)
+    }
+
+    /// Like [`Detector::map_chunks`], but only for the chunks of the given slice if there is one.
+    fn map_slice_chunks<T: Send>(
+        &self,
+        slice: Option<usize>,
+        f: impl Fn(&[u8], usize) -> T + Sync,
+    ) -> Vec<T> {
+        run_parallel(
+            self.active_threads,
+            &self.cpus,
+            self.select(slice, self.chunks()),
+            |(chunk, offset)| {
+                let result = f(chunk, offset);
+                self.progress.fetch_add(chunk.len(), Ordering::Relaxed);
+                result
+            },
+        )
     }
 
-    /// Checks if every element of the detector memory is equal to the default value.
-    pub fn is_intact(&self) -> bool {
-        self.position_of_changed_element().is_none()
+    /// Returns the chunks that belong to the given slice, or all of them for `None`.
+    /// Every slice gets the same share of the chunks, give or take one.
+    fn select<T>(&self, slice: Option<usize>, mut chunks: Vec<T>) -> Vec<T> {
+        if let Some(slice) = slice {
+            let len = chunks.len();
+            chunks.truncate((slice + 1) * len / self.slices);
+            chunks.drain(..slice * len / self.slices);
+        }
+        chunks
     }
 
     /// Writes the given value to every element of the detector memory.
     pub fn write(&mut self, value: u8) {
-        if self.parallel {
-            self.detector_mass
-                .par_iter_mut()
-                .for_each(|n| unsafe { write_volatile(n, value) });
-        } else {
-            self.detector_mass
+        let write = |chunk: &mut [u8], _| {
+            chunk
                 .iter_mut()
-                .for_each(|n| unsafe { write_volatile(n, value) });
+                .for_each(|n| unsafe { write_volatile(n, value) })
+        };
+        self.for_each_chunk_mut(write);
+        let ecc = self.ecc;
+        for (region, start) in self.regions_mut() {
+            let len = data_len(region.len(), ecc);
+            write(&mut region[len..], start + len);
+        }
+    }
+
+    /// Writes the pattern, or the contents of the reference file, to the detector memory.
+    /// # Panic
+    /// Panics if the reference file can not be read anymore.
+    pub fn fill(&mut self) {
+        let contents = self.contents;
+        match self.reference.take() {
+            Some(reference) => {
+                self.for_each_chunk_mut(|chunk, offset| {
+                    let mut block = [0; BLOCK_SIZE];
+                    for (i, bytes) in chunk.chunks_mut(BLOCK_SIZE).enumerate() {
+                        let block = &mut block[..bytes.len()];
+                        reference
+                            .read(offset + i * BLOCK_SIZE, block)
+                            .expect("could not read the reference file");
+                        for (n, &b) in bytes.iter_mut().zip(block.iter()) {
+                            unsafe { write_volatile(n, b) };
+                        }
+                    }
+                });
+                self.reference = Some(reference);
+            }
+            None => self.for_each_chunk_mut(|chunk, offset| contents.fill(chunk, offset)),
+        }
+        if self.checksums.is_some() {
+            self.checksums = Some(self.compute_checksums());
+        }
+        if self.ecc {
+            self.encode_check_bytes();
         }
     }
 
-    /// If an element in the detector does not match its default value, return it's index.
-    pub fn position_of_changed_element(&self) -> Option<usize> {
-        if self.parallel {
-            self.detector_mass
-                .par_iter()
-                .position_any(|r| unsafe { read_volatile(r) != self.default })
+    /// Decodes every word of the detector memory with ECC emulation and returns the ones whose check bits did not match.
+    /// # Panic
+    /// Panics if ECC emulation is not used.
+    pub(crate) fn word_errors(&self) -> Vec<WordError> {
+        self.word_errors_in(None)
+    }
+
+    /// Like [`Detector::word_errors`], but only for the words of the given slice.
+    /// # Panic
+    /// Panics if ECC emulation is not used, or if there is no such slice.
+    pub(crate) fn slice_word_errors(&self, slice: usize) -> Vec<WordError> {
+        assert!(slice < self.slices, "there is no slice {slice}");
+        self.word_errors_in(Some(slice))
+    }
+
+    fn word_errors_in(&self, slice: Option<usize>) -> Vec<WordError> {
+        assert!(self.ecc, "the detector does not emulate ECC");
+        let (contents, order) = (self.contents, self.scan_order);
+        let chunks = self.select(slice, self.ecc_chunks());
+        let mut errors: Vec<WordError> =
+            run_parallel(self.active_threads, &self.cpus, chunks, |chunk| {
+                self.progress.fetch_add(chunk.data.len(), Ordering::Relaxed);
+                word_indices(order, chunk.check.len())
+                    .filter_map(|i| {
+                        let (bytes, check) = (&chunk.data[8 * i..8 * i + 8], &chunk.check[i]);
+                        let offset = chunk.offset + 8 * i;
+                        let found = (read_word(bytes), unsafe { read_volatile(check) });
+                        let (status, data, check) = hamming::decode(found.0, found.1);
+                        let expected = match status {
+                            WordStatus::Clean => return None,
+                            WordStatus::Corrected => (data, check),
+                            // Only the pattern can tell what the word should have been.
+                            WordStatus::Uncorrectable => {
+                                let data = u64::from_le_bytes(std::array::from_fn(|b| {
+                                    contents.expected(offset + b)
+                                }));
+                                (data, hamming::encode(data))
+                            }
+                        };
+                        Some(WordError {
+                            word: chunk.first_word + i,
+                            status,
+                            flips: word_flips(offset, chunk.check_offset + i, found, expected),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        errors.sort_by_key(|error| error.word);
+        errors
+    }
+
+    /// Returns an event for every element in the detector that does not match the pattern, ordered by index
+    /// and classified by the flipped bits of their word. With ECC emulation this also includes the check bytes.
+    pub fn flips(&self) -> Vec<FlipEvent> {
+        self.flips_in(None)
+    }
+
+    /// Like [`Detector::flips`], but only for the bytes of the given slice.
+    /// # Panic
+    /// Panics if there is no such slice.
+    pub fn slice_flips(&self, slice: usize) -> Vec<FlipEvent> {
+        assert!(slice < self.slices, "there is no slice {slice}");
+        self.flips_in(Some(slice))
+    }
+
+    fn flips_in(&self, slice: Option<usize>) -> Vec<FlipEvent> {
+        if self.ecc {
+            let mut flips: Vec<FlipEvent> = self
+                .word_errors_in(slice)
+                .into_iter()
+                .flat_map(|error| error.flips)
+                .collect();
+            flips.sort_by_key(|flip| flip.offset);
+            return flips;
+        }
+
+        let (contents, order, word_size) = (self.contents, self.scan_order, self.word_size);
+        let mut flips: Vec<FlipEvent> = match (&self.checksums, &self.reference) {
+            // Chunks are made up of whole blocks.
+            (Some(checksums), Some(reference)) => self.map_slice_chunks(slice, |chunk, offset| {
+                reference_block_flips(reference, checksums, chunk, offset)
+            }),
+            (Some(checksums), None) => self.map_slice_chunks(slice, |chunk, offset| {
+                block_flips(contents, word_size, checksums, chunk, offset)
+            }),
+            (None, _) => self.map_slice_chunks(slice, |chunk, offset| {
+                chunk_flips_in_order(order, word_size, contents, chunk, offset)
+            }),
+        }
+        .into_iter()
+        .flatten()
+        .collect();
+        // A word can be split between two chunks, so this has to wait until all of them are in.
+        classify_words(&mut flips, word_size);
+        flips
+    }
+
+    /// Like [`Detector::flips`], but only for the bytes of the given pages of the detector memory,
+    /// which have to be in ascending order. The pages are checked in parallel, in the order they are given.
+    /// # Panic
+    /// Panics if ECC emulation is used.
+    pub fn page_flips(&self, pages: &[usize]) -> Vec<FlipEvent> {
+        assert!(
+            !self.ecc,
+            "pages can not be checked on their own with ECC emulation"
+        );
+        let mut ranges: Vec<(&[u8], usize)> = Vec::with_capacity(pages.len());
+        let mut regions = self.regions().peekable();
+        for &page in pages {
+            let page = page * PAGE_SIZE..(page + 1) * PAGE_SIZE;
+            // A page that is split between two regions is checked in two parts.
+            while let Some(&(region, start)) = regions.peek() {
+                let end = start + region.len();
+                if start < page.end && page.start < end {
+                    let part = page.start.max(start)..page.end.min(end);
+                    ranges.push((&region[part.start - start..part.end - start], part.start));
+                }
+                if end > page.end {
+                    break;
+                }
+                regions.next();
+            }
+        }
+
+        let (contents, checksums, word_size) =
+            (self.contents, self.checksums.as_deref(), self.word_size);
+        let reference = self.reference.as_ref();
+        // Taking the pages one at a time would spend more time on the queue than on the pages.
+        let batch_len = ranges.len().div_ceil(4 * self.threads).max(1);
+        let batches: Vec<_> = ranges.chunks(batch_len).collect();
+        let mut flips: Vec<FlipEvent> =
+            run_parallel(self.active_threads, &self.cpus, batches, |batch| {
+                batch
+                    .iter()
+                    .flat_map(|&(bytes, offset)| match (checksums, reference) {
+                        // Pages are made up of whole blocks, and so are regions.
+                        (Some(checksums), Some(reference)) => {
+                            reference_block_flips(reference, checksums, bytes, offset)
+                        }
+                        (Some(checksums), None) => {
+                            block_flips(contents, word_size, checksums, bytes, offset)
+                        }
+                        (None, _) => chunk_flips(contents, word_size, bytes, offset),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        classify_words(&mut flips, word_size);
+        flips
+    }
+
+    /// Reads the bytes of the given flips again, up to the given number of times each, and marks the flips
+    /// of the bytes that read as expected at any of those times as transient. The cache line of the byte is
+    /// flushed before every read where the CPU allows that, so that it is read from the memory again.
+    pub fn reread(&self, flips: &mut [FlipEvent], times: usize) {
+        for flip in flips {
+            let byte = self
+                .byte(flip.offset)
+                .expect("flips are in the detector memory");
+            if (0..times).any(|_| read_uncached(byte) == flip.expected) {
+                flip.persistence = Persistence::Transient;
+            }
+        }
+    }
+
+    /// Writes the expected values back to the bytes of the given flips,
+    /// so that later checks only report new flips.
+    pub fn scrub(&mut self, flips: &[FlipEvent]) {
+        for flip in flips {
+            unsafe { write_volatile(self.byte_mut(flip.offset), flip.expected) };
+        }
+    }
+
+    /// Checks the whole detector memory and returns every byte that no longer holds the pattern.
+    /// With ECC emulation these are the flips of the words with errors, including those in check bytes.
+    pub fn check(&self) -> Vec<FlipEvent> {
+        if self.ecc {
+            self.word_errors()
+                .into_iter()
+                .flat_map(|error| error.flips)
+                .collect()
         } else {
-            self.detector_mass
-                .iter()
-                .position(|r| unsafe { read_volatile(r) != self.default })
+            self.flips()
         }
     }
 
-    /// Resets the detector to its default value.
+    /// Resets the detector memory to the pattern, or the contents of the reference file.
     pub fn reset(&mut self) {
-        if self.default == 0 {
+        if self.contents.pattern.is_zero() && self.reference.is_none() {
             // If some memory pages have been moved to swap due to inactivity
             // just writing zero to them might not prompt the OS to give them back.
-            std::hint::black_box(self.write(42));
+            self.write(42);
+            std::hint::black_box(&mut self.detector_mass);
         }
-        self.write(self.default);
+        self.fill();
     }
+}
 
-    /// Returns the value of the element at the given index, if it exists.
-    pub fn get(&self, index: usize) -> Option<u8> {
-        if index < self.detector_mass.len() {
-            Some(unsafe { read_volatile(&self.detector_mass[index]) })
-        } else {
-            None
+impl Drop for Detector {
+    fn drop(&mut self) {
+        if self.locked {
+            // The memory is about to be freed anyway, so there is nothing to do if this fails.
+            for region in &self.detector_mass {
+                let _ = memlock::unlock(region);
+            }
         }
     }
 }
+
+/// Configures a [`Detector`] before its memory is allocated, see [`Detector::builder`].
+#[derive(Debug, Clone, Copy)]
+pub struct DetectorBuilder {
+    capacity_bytes: usize,
+    pattern: Pattern,
+    threads: NonZeroUsize,
+    options: RegionOptions,
+}
+
+impl DetectorBuilder {
+    /// Sets the pattern the detector memory is filled with.
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Sets the number of threads that fill and check the detector memory.
+    pub fn threads(mut self, threads: NonZeroUsize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how the detector memory is allocated.
+    pub fn region_options(mut self, options: RegionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Allocates the detector memory and fills it with the pattern. The detector ends up smaller
+    /// than requested if not all of the memory could be allocated, see [`Detector::new`].
+    pub fn build(self) -> Detector {
+        Detector::new(
+            self.threads.get(),
+            self.pattern,
+            self.capacity_bytes,
+            self.options,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_memory_validation() {
+        assert!(fits_in_memory(1000, 4000, 2000).is_ok());
+        assert!(fits_in_memory(2000, 4000, 2000).is_ok());
+        assert!(fits_in_memory(3000, 4000, 2000)
+            .unwrap_err()
+            .contains("2000 bytes of memory that are available"));
+        assert!(fits_in_memory(5000, 4000, 2000)
+            .unwrap_err()
+            .contains("all 4000 bytes"));
+    }
+
+    #[test]
+    fn check_flip_reporting() {
+        // The flips are placed at the very end of a chunk for two and three threads.
+        for threads in [1, 2, 3, 4, 100] {
+            let mut detector =
+                Detector::new(threads, Pattern::Alternating, 64, RegionOptions::default());
+            assert!(detector.flips().is_empty());
+
+            *detector.byte_mut(3) ^= 0b1000_0001;
+            *detector.byte_mut(21) ^= 0b0000_0100;
+            *detector.byte_mut(31) ^= 0b0100_0000;
+
+            let flips = detector.flips();
+            assert_eq!(
+                flips,
+                vec![
+                    FlipEvent {
+                        offset: 3,
+                        expected: 0x55,
+                        found: 0xD4,
+                        class: FlipClass::Double,
+                        virtual_address: None,
+                        physical_address: None,
+                        dimm: None,
+                        persistence: Persistence::Persistent,
+                        mirror: None,
+                        correlated_mce: false,
+                        cluster: Vec::new(),
+                    },
+                    FlipEvent {
+                        offset: 21,
+                        expected: 0x55,
+                        found: 0x51,
+                        class: FlipClass::Single,
+                        virtual_address: None,
+                        physical_address: None,
+                        dimm: None,
+                        persistence: Persistence::Persistent,
+                        mirror: None,
+                        correlated_mce: false,
+                        cluster: Vec::new(),
+                    },
+                    FlipEvent {
+                        offset: 31,
+                        expected: 0x55,
+                        found: 0x15,
+                        class: FlipClass::Single,
+                        virtual_address: None,
+                        physical_address: None,
+                        dimm: None,
+                        persistence: Persistence::Persistent,
+                        mirror: None,
+                        correlated_mce: false,
+                        cluster: Vec::new(),
+                    },
+                ]
+            );
+            assert_eq!(flips[0].changed_bits(), 0b1000_0001);
+            assert_eq!(flips[0].set_bits(), 0b1000_0000);
+            assert_eq!(flips[0].cleared_bits(), 0b0000_0001);
+            assert_eq!(flips[1].set_bits(), 0);
+            assert_eq!(flips[1].cleared_bits(), 0b0000_0100);
+
+            detector.scrub(&flips[..1]);
+            assert_eq!(detector.flips(), flips[1..]);
+        }
+    }
+
+    #[test]
+    fn check_builder() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Detector>();
+
+        let mut detector = Detector::builder(64)
+            .pattern(Pattern::Ones)
+            .threads(NonZeroUsize::new(3).unwrap())
+            .build();
+        assert_eq!(detector.capacity(), 64);
+        assert_eq!(detector.byte(40), Some(&0xFF));
+        assert!(detector.check().is_empty());
+
+        *detector.byte_mut(40) = 0xF7;
+        let flips = detector.check();
+        assert_eq!(flips, [FlipEvent::new(40, 0xFF, 0xF7)]);
+        detector.scrub(&flips);
+        assert!(detector.check().is_empty());
+
+        // With ECC emulation the flips come from the words with errors.
+        let mut detector = Detector::builder(72).build();
+        detector.use_ecc_emulation();
+        *detector.byte_mut(9) = 0x01;
+        assert_eq!(detector.check(), [FlipEvent::new(9, 0x00, 0x01)]);
+    }
+
+    #[test]
+    fn check_word_sizes() {
+        let word_sizes = [WordSize::U8, WordSize::U16, WordSize::U32, WordSize::U64];
+        for pattern in [Pattern::Random(3), Pattern::Zeros] {
+            let mut detector = Detector::new(3, pattern, 100, RegionOptions::default());
+            // Two bits of one byte, at an offset that no word size aligns to.
+            let expected = *detector.byte(61).unwrap();
+            *detector.byte_mut(61) ^= 0b0001_0100;
+            for &word_size in &word_sizes {
+                detector.use_word_size(word_size);
+                let flips = detector.flips();
+                assert_eq!(flips.len(), 1, "{word_size:?}");
+                assert_eq!(
+                    (flips[0].offset, flips[0].expected, flips[0].class),
+                    (61, expected, FlipClass::Double),
+                    "{word_size:?}"
+                );
+            }
+
+            // Single bit flips in neighbouring bytes only add up in words that hold both.
+            *detector.byte_mut(61) ^= 0b0001_0000;
+            *detector.byte_mut(62) ^= 0b0000_0001;
+            let mut classes = |word_size| {
+                detector.use_word_size(word_size);
+                detector
+                    .flips()
+                    .iter()
+                    .map(|flip| flip.class)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(classes(WordSize::U8), [FlipClass::Single; 2]);
+            assert_eq!(classes(WordSize::U16), [FlipClass::Single; 2]);
+            assert_eq!(classes(WordSize::U32), [FlipClass::Double; 2]);
+            assert_eq!(classes(WordSize::U64), [FlipClass::Double; 2]);
+        }
+    }
+
+    #[test]
+    fn check_checksum_narrowing() {
+        // Three and a half blocks, so the last block is shorter than the others.
+        let len = 3 * BLOCK_SIZE + BLOCK_SIZE / 2;
+        for threads in [1, 2, 3, 8] {
+            let mut detector =
+                Detector::new(threads, Pattern::Random(3), len, RegionOptions::default());
+            detector.use_checksums();
+            assert!(detector.flips().is_empty());
+
+            let offsets = [BLOCK_SIZE - 1, BLOCK_SIZE, len - 1];
+            for offset in offsets {
+                *detector.byte_mut(offset) ^= 0b0010_0000;
+            }
+            let flips = detector.flips();
+            assert_eq!(flips.iter().map(|f| f.offset).collect::<Vec<_>>(), offsets);
+            assert!(flips.iter().all(|f| f.changed_bits() == 0b0010_0000));
+
+            detector.scrub(&flips);
+            assert!(detector.flips().is_empty());
+
+            // A different checksum without a differing byte means that the checksum itself was hit.
+            detector.checksums.as_mut().unwrap()[2] ^= 1;
+            assert!(detector.flips().is_empty());
+        }
+    }
+
+    #[test]
+    fn check_multi_bit_clusters() {
+        let mut detector = Detector::new(2, Pattern::Byte(0x0F), 4096, RegionOptions::default());
+        // Three bits over two bytes of one word, and a single bit in another word.
+        *detector.byte_mut(16) ^= 0b0001_0001;
+        *detector.byte_mut(19) ^= 0b1000_0000;
+        *detector.byte_mut(40) ^= 0b0000_0100;
+        let flips = detector.flips();
+        assert_eq!(flips.len(), 3);
+        assert!(flips[..2].iter().all(|flip| flip.class == FlipClass::Multi));
+        let bit = |offset, bit, direction| BitFlip {
+            offset,
+            bit,
+            direction,
+        };
+        assert_eq!(
+            flips[0].cluster,
+            [
+                bit(16, 0, BitDirection::Cleared),
+                bit(16, 4, BitDirection::Set),
+                bit(19, 7, BitDirection::Set),
+            ]
+        );
+        assert!(flips[1].cluster.is_empty());
+        assert_eq!(flips[2].class, FlipClass::Single);
+        assert!(flips[2].cluster.is_empty());
+        assert_eq!(
+            flips[2].bits().collect::<Vec<_>>(),
+            [bit(40, 2, BitDirection::Cleared)]
+        );
+
+        // The uncorrectable words of ECC emulation are narrowed down the same way.
+        let mut detector = Detector::new(1, Pattern::Zeros, 4096, RegionOptions::default());
+        detector.use_ecc_emulation();
+        *detector.byte_mut(8) ^= 0b0000_1111;
+        let flips = &detector.word_errors()[0].flips;
+        assert_eq!(flips[0].cluster.len(), 4);
+        assert!(flips[0]
+            .cluster
+            .iter()
+            .all(|flip| flip.offset == 8 && flip.direction == BitDirection::Set));
+    }
+
+    #[test]
+    fn check_snapshot() {
+        let len = 3 * BLOCK_SIZE + BLOCK_SIZE / 2;
+        for threads in [1, 3] {
+            let mut detector =
+                Detector::new(threads, Pattern::Random(5), len, RegionOptions::default());
+            detector.use_snapshot();
+            assert!(detector.uses_checksums());
+            assert_eq!(detector.changed_blocks(), []);
+
+            *detector.byte_mut(BLOCK_SIZE + 7) ^= 0x01;
+            *detector.byte_mut(len - 1) ^= 0x80;
+            let changed = detector.changed_blocks();
+            assert_eq!(
+                changed
+                    .iter()
+                    .map(|b| (b.offset, b.len))
+                    .collect::<Vec<_>>(),
+                [(BLOCK_SIZE, BLOCK_SIZE), (3 * BLOCK_SIZE, BLOCK_SIZE / 2)]
+            );
+            // Once the changes are part of the snapshot only new ones are reported.
+            detector.update_snapshot(&changed);
+            assert_eq!(detector.changed_blocks(), []);
+            *detector.byte_mut(0) ^= 0x10;
+            assert_eq!(detector.changed_blocks().len(), 1);
+        }
+    }
+
+    #[test]
+    fn check_reference_contents() {
+        let len = 2 * BLOCK_SIZE + 100;
+        let contents: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+        let dir = TempDir::new("reference_detector");
+        let path = dir.join("reference");
+        std::fs::write(&path, &contents).unwrap();
+        for threads in [1, 3] {
+            let mut detector =
+                Detector::new(threads, Pattern::Zeros, len, RegionOptions::default());
+            detector
+                .use_reference(Reference::open(&path).unwrap())
+                .unwrap();
+            assert!(detector.uses_checksums());
+            assert!((0..len).all(|i| *detector.byte(i).unwrap() == contents[i]));
+            assert!(detector.flips().is_empty());
+
+            let offsets = [5, BLOCK_SIZE + 1, len - 1];
+            for offset in offsets {
+                *detector.byte_mut(offset) ^= 0b0000_1000;
+            }
+            let flips = detector.flips();
+            assert_eq!(flips.iter().map(|f| f.offset).collect::<Vec<_>>(), offsets);
+            assert!(flips.iter().all(|f| f.expected == contents[f.offset]));
+            assert_eq!(detector.page_flips(&[1, 2]), flips[1..]);
+
+            detector.scrub(&flips);
+            assert!(detector.flips().is_empty());
+            detector.write(0);
+            detector.reset();
+            assert!(detector.flips().is_empty());
+        }
+
+        let mut detector = Detector::new(1, Pattern::Zeros, len - 1, RegionOptions::default());
+        assert!(detector
+            .use_reference(Reference::open(&path).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn check_ecc_emulation() {
+        // 40 words and their check bytes, plus a few bytes that are not used.
+        for threads in [1, 3, 8] {
+            let mut detector =
+                Detector::new(threads, Pattern::Random(5), 365, RegionOptions::default());
+            detector.use_ecc_emulation();
+            assert_eq!(detector.data_capacity(), 320);
+            assert!(detector.word_errors().is_empty());
+
+            // A single flip in a word, a single flip in the check byte of another
+            // and two flips in a third word.
+            let found = *detector.byte_mut(17) ^ 0b0100_0000;
+            *detector.byte_mut(17) = found;
+            *detector.byte_mut(320 + 30) ^= 0b0000_0001;
+            *detector.byte_mut(100) ^= 0b0000_0011;
+
+            let errors = detector.word_errors();
+            assert_eq!(
+                errors
+                    .iter()
+                    .map(|e| (e.word, e.status))
+                    .collect::<Vec<_>>(),
+                [
+                    (2, WordStatus::Corrected),
+                    (12, WordStatus::Uncorrectable),
+                    (30, WordStatus::Corrected)
+                ]
+            );
+            assert_eq!(
+                errors[0].flips,
+                [FlipEvent {
+                    offset: 17,
+                    expected: found ^ 0b0100_0000,
+                    found,
+                    class: FlipClass::Single,
+                    virtual_address: None,
+                    physical_address: None,
+                    dimm: None,
+                    persistence: Persistence::Persistent,
+                    mirror: None,
+                    correlated_mce: false,
+                    cluster: Vec::new(),
+                }]
+            );
+            assert_eq!(errors[1].flips[0].offset, 100);
+            assert_eq!(errors[1].flips[0].changed_bits(), 0b0000_0011);
+            assert_eq!(errors[2].flips[0].offset, 350);
+
+            let flips = detector.flips();
+            assert_eq!(
+                flips.iter().map(|f| f.offset).collect::<Vec<_>>(),
+                [17, 100, 350]
+            );
+            detector.scrub(&flips);
+            assert!(detector.word_errors().is_empty());
+        }
+    }
+
+    #[test]
+    fn check_offset_patterns_across_chunks() {
+        // 100 bytes split over 3 or 7 threads gives chunks that don't start at a multiple of 8.
+        for threads in [1, 3, 7] {
+            let mut detector =
+                Detector::new(threads, Pattern::WalkingOnes, 100, RegionOptions::default());
+            assert!(detector.flips().is_empty());
+            for (offset, &b) in detector
+                .detector_mass
+                .iter()
+                .flat_map(|r| r.iter())
+                .enumerate()
+            {
+                assert_eq!(b, 1 << (offset % 8));
+            }
+
+            *detector.byte_mut(34) = 0;
+            assert_eq!(detector.flips(), vec![FlipEvent::new(34, 0x04, 0)]);
+        }
+    }
+
+    #[test]
+    fn check_detector_across_regions() {
+        // Three regions of a page each and half a page at the end.
+        let len = 3 * PAGE_SIZE + PAGE_SIZE / 2;
+        let options = RegionOptions {
+            region_size: NonZeroUsize::new(PAGE_SIZE),
+            ..Default::default()
+        };
+        let offsets = [PAGE_SIZE - 1, PAGE_SIZE, 2 * PAGE_SIZE + 9, len - 1];
+        for threads in [1, 2, 5] {
+            for checksum in [false, true] {
+                let mut detector = Detector::new(threads, Pattern::Random(7), len, options);
+                assert_eq!(detector.detector_mass.len(), 4);
+                assert_eq!(detector.capacity(), len);
+                if checksum {
+                    detector.use_checksums();
+                }
+                assert!(detector.flips().is_empty());
+
+                for offset in offsets {
+                    *detector.byte_mut(offset) ^= 0b0001_0000;
+                }
+                let flips = detector.flips();
+                assert_eq!(flips.iter().map(|f| f.offset).collect::<Vec<_>>(), offsets);
+                detector.scrub(&flips);
+                assert!(detector.flips().is_empty());
+            }
+
+            // With ECC emulation every region holds the check bytes of its own words.
+            let mut detector = Detector::new(threads, Pattern::Random(7), len, options);
+            detector.use_ecc_emulation();
+            let region_data = PAGE_SIZE / 9 * 8;
+            assert_eq!(
+                detector.data_capacity(),
+                3 * region_data + PAGE_SIZE / 2 / 9 * 8
+            );
+            *detector.byte_mut(PAGE_SIZE + 17) ^= 0b0000_0010;
+            *detector.byte_mut(2 * PAGE_SIZE + region_data + 3) ^= 0b1000_0000;
+            let errors = detector.word_errors();
+            assert_eq!(
+                errors
+                    .iter()
+                    .map(|e| (e.word, e.status, e.flips[0].offset))
+                    .collect::<Vec<_>>(),
+                [
+                    (region_data / 8 + 2, WordStatus::Corrected, PAGE_SIZE + 17),
+                    (
+                        2 * region_data / 8 + 3,
+                        WordStatus::Corrected,
+                        2 * PAGE_SIZE + region_data + 3
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn check_flip_classification() {
+        let mut detector = Detector::new(3, Pattern::Zeros, 64, RegionOptions::default());
+        // A single bit in word 0, one bit in each of two bytes of word 2,
+        // and three bits of word 5, which is split between the second and third of three chunks.
+        *detector.byte_mut(5) ^= 0b0000_1000;
+        *detector.byte_mut(16) ^= 0b0000_0001;
+        *detector.byte_mut(23) ^= 0b1000_0000;
+        *detector.byte_mut(43) ^= 0b0000_0011;
+        *detector.byte_mut(44) ^= 0b0000_0001;
+        assert_eq!(
+            detector
+                .flips()
+                .iter()
+                .map(|f| (f.offset, f.class))
+                .collect::<Vec<_>>(),
+            [
+                (5, FlipClass::Single),
+                (16, FlipClass::Double),
+                (23, FlipClass::Double),
+                (43, FlipClass::Multi),
+                (44, FlipClass::Multi),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_page_markers() {
+        let len = 4 * PAGE_SIZE;
+        for (checksum, ecc) in [(false, false), (true, false), (false, true)] {
+            let mut detector = Detector::new(2, Pattern::Ones, len, RegionOptions::default());
+            if checksum {
+                detector.use_checksums();
+            }
+            if ecc {
+                detector.use_ecc_emulation();
+            }
+            detector.use_page_markers();
+            assert!(detector.flips().is_empty());
+            // The marker of the second page, the index 1, and a byte of its pattern.
+            assert_eq!(*detector.byte_mut(PAGE_SIZE), 1);
+            assert_eq!(*detector.byte_mut(PAGE_SIZE + 1), 0);
+            *detector.byte_mut(PAGE_SIZE) ^= 0b10;
+            *detector.byte_mut(PAGE_SIZE + 9) ^= 0b10;
+            let flips = detector.flips();
+            assert_eq!(
+                flips
+                    .iter()
+                    .map(|flip| (flip.offset, flip.expected))
+                    .collect::<Vec<_>>(),
+                [(PAGE_SIZE, 1), (PAGE_SIZE + 9, 0xFF)]
+            );
+            detector.scrub(&flips);
+            assert!(detector.flips().is_empty());
+        }
+    }
+
+    #[test]
+    fn check_strided_scan() {
+        // Every byte is visited exactly once, also when the last page is partial.
+        let len = 2 * PAGE_SIZE + 100;
+        let mut visited = vec![0; len];
+        for line in strided_lines(len) {
+            visited[line].iter_mut().for_each(|v| *v += 1);
+        }
+        assert!(visited.iter().all(|&v| v == 1));
+        let starts: Vec<usize> = strided_lines(len).take(4).map(|line| line.start).collect();
+        assert_eq!(starts, [0, PAGE_SIZE, 2 * PAGE_SIZE, CACHE_LINE_SIZE]);
+
+        for ecc in [false, true] {
+            let mut detector = Detector::new(2, Pattern::Random(7), len, RegionOptions::default());
+            detector.use_scan_order(ScanOrder::Strided);
+            if ecc {
+                detector.use_ecc_emulation();
+            }
+            assert!(detector.flips().is_empty());
+            let offsets = [3, PAGE_SIZE + 70, detector.data_capacity() - 1];
+            for offset in offsets {
+                *detector.byte_mut(offset) ^= 0b0001_0000;
+            }
+            let strided = detector.flips();
+            detector.use_scan_order(ScanOrder::Sequential);
+            assert_eq!(strided, detector.flips());
+            assert_eq!(strided.len(), offsets.len());
+        }
+    }
+
+    #[test]
+    fn check_slices() {
+        let len = 10 * PAGE_SIZE + 5;
+        for (checksum, ecc) in [(false, false), (true, false), (false, true)] {
+            let mut detector =
+                Detector::new(3, Pattern::Alternating, len, RegionOptions::default());
+            if checksum {
+                detector.use_checksums();
+            }
+            if ecc {
+                detector.use_ecc_emulation();
+            }
+            detector.use_slices(NonZeroUsize::new(4).unwrap());
+            let offsets = [0, 3 * PAGE_SIZE + 7, detector.data_capacity() - 1];
+            for offset in offsets {
+                *detector.byte_mut(offset) ^= 0b0000_0001;
+            }
+
+            // Every flip is found in exactly one slice, and the slices together find all of them.
+            let sliced: Vec<FlipEvent> = (0..4).flat_map(|s| detector.slice_flips(s)).collect();
+            assert_eq!(sliced, detector.flips());
+            assert_eq!(sliced.iter().map(|f| f.offset).collect::<Vec<_>>(), offsets);
+            assert_eq!(detector.slice_flips(0).len(), 1);
+        }
+    }
+
+    #[test]
+    fn check_page_flips() {
+        let len = 5 * PAGE_SIZE + 100;
+        let options = RegionOptions {
+            region_size: NonZeroUsize::new(2 * PAGE_SIZE),
+            ..RegionOptions::default()
+        };
+        for checksum in [false, true] {
+            let mut detector = Detector::new(2, Pattern::Alternating, len, options);
+            assert!(detector.detector_mass.len() > 1);
+            if checksum {
+                detector.use_checksums();
+            }
+            let offsets = [7, PAGE_SIZE + 7, 3 * PAGE_SIZE, len - 1];
+            for offset in offsets {
+                *detector.byte_mut(offset) ^= 0b0000_0100;
+            }
+
+            // Only the flips in the given pages are found, including the partial page at the end.
+            let found = |pages: &[usize]| -> Vec<usize> {
+                detector
+                    .page_flips(pages)
+                    .iter()
+                    .map(|f| f.offset)
+                    .collect()
+            };
+            assert_eq!(found(&[1, 5]), [PAGE_SIZE + 7, len - 1]);
+            assert!(found(&[2, 4]).is_empty());
+            assert_eq!(found(&[0, 1, 2, 3, 4, 5]), offsets);
+            assert_eq!(detector.page_flips(&[0, 1, 2, 3, 4, 5]), detector.flips());
+        }
+    }
+
+    #[test]
+    fn check_mirror_votes() {
+        let mut detector = Detector::new(
+            1,
+            Pattern::Random(3),
+            3 * PAGE_SIZE + 100,
+            RegionOptions::default(),
+        );
+        detector.use_mirrors(NonZeroUsize::new(3).unwrap());
+        *detector.byte_mut(PAGE_SIZE + 7) ^= 0x10;
+        *detector.byte_mut(3 * PAGE_SIZE + 5) ^= 0x01;
+        let mut flips = detector.flips();
+        detector.vote_mirrors(&mut flips);
+        assert_eq!(
+            flips[0].mirror,
+            Some(MirrorVote {
+                copy: 1,
+                copies: 3,
+                agreeing: 2,
+                majority: Some(flips[0].expected),
+            })
+        );
+        // The bytes after the last copy are in none.
+        assert_eq!(flips[1].mirror, None);
+
+        // Two copies can tell which of them diverged, but not vote.
+        detector.scrub(&flips);
+        detector.use_mirrors(NonZeroUsize::new(2).unwrap());
+        *detector.byte_mut(2) ^= 0x80;
+        let mut flips = detector.flips();
+        detector.vote_mirrors(&mut flips);
+        let vote = flips[0].mirror.unwrap();
+        assert_eq!((vote.copy, vote.agreeing, vote.majority), (0, 1, None));
+    }
+
+    #[test]
+    fn check_reclaimed_pages() {
+        let options = RegionOptions {
+            align_pages: true,
+            ..RegionOptions::default()
+        };
+        let mut detector = Detector::new(1, Pattern::Random(5), 4 * PAGE_SIZE, options);
+        for offset in PAGE_SIZE..2 * PAGE_SIZE {
+            *detector.byte_mut(offset) = 0;
+        }
+        // A scattered flip to zero in another page is still a flip.
+        let scattered = (0..PAGE_SIZE)
+            .map(|i| 3 * PAGE_SIZE + i)
+            .find(|&offset| detector.contents.expected(offset) != 0)
+            .unwrap();
+        *detector.byte_mut(scattered) = 0;
+
+        let mut flips = detector.flips();
+        let zeroed = flips.len() - 1;
+        let pages = detector.take_reclaimed_pages(&mut flips);
+        assert_eq!(pages.len(), 1);
+        assert_eq!((pages[0].offset, pages[0].len), (PAGE_SIZE, PAGE_SIZE));
+        assert_eq!(pages[0].flips.len(), zeroed);
+        assert!(pages[0]
+            .flips
+            .iter()
+            .all(|flip| pages[0].contains(flip.offset)));
+        assert_eq!(
+            flips.iter().map(|flip| flip.offset).collect::<Vec<_>>(),
+            [scattered]
+        );
+
+        detector.scrub(&pages[0].flips);
+        detector.scrub(&flips);
+        assert!(detector.flips().is_empty());
+    }
+
+    #[test]
+    fn check_rereads() {
+        let mut detector = Detector::new(1, Pattern::Ones, 2 * PAGE_SIZE, RegionOptions::default());
+        *detector.byte_mut(5) = 0xFE;
+        *detector.byte_mut(PAGE_SIZE) = 0x7F;
+        let mut flips = detector.flips();
+        // The second byte is fine again by the time it is read again, like after a glitch on the bus.
+        *detector.byte_mut(PAGE_SIZE) = 0xFF;
+        detector.reread(&mut flips, 0);
+        assert!(flips
+            .iter()
+            .all(|f| f.persistence == Persistence::Persistent));
+        detector.reread(&mut flips, 2);
+        assert_eq!(
+            flips.iter().map(|f| f.persistence).collect::<Vec<_>>(),
+            [Persistence::Persistent, Persistence::Transient]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Reading the inventory of memory modules from the SMBIOS tables of the firmware, to tell which DIMM slot
+//! a physical address is most likely in. Linux exposes the tables in `/sys/firmware/dmi/entries/`,
+//! which are usually only readable by root. Elsewhere the inventory is not available.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+#[cfg(target_os = "linux")]
+use std::{fs, path::Path};
+
+/// The SMBIOS structure type that describes a memory device, i.e. a DIMM slot.
+const MEMORY_DEVICE: u8 = 17;
+/// The SMBIOS structure type that maps a range of physical addresses to a memory device.
+const MEMORY_DEVICE_MAPPED_ADDRESS: u8 = 20;
+
+/// A structure of a raw SMBIOS table.
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+pub struct Structure<'a> {
+    pub kind: u8,
+    /// The formatted part of the structure, including its type and length at the start.
+    pub formatted: &'a [u8],
+    /// The strings that follow the formatted part, each of them terminated by a zero byte.
+    strings: &'a [u8],
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+impl<'a> Structure<'a> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.formatted.get(offset..offset + N)?.try_into().ok()
+    }
+
+    fn word(&self, offset: usize) -> Option<u16> {
+        self.bytes(offset).map(u16::from_le_bytes)
+    }
+
+    fn dword(&self, offset: usize) -> Option<u32> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    fn qword(&self, offset: usize) -> Option<u64> {
+        self.bytes(offset).map(u64::from_le_bytes)
+    }
+
+    /// Returns the string that the byte at the given offset refers to, if it refers to one that is not blank.
+    fn string(&self, offset: usize) -> Option<&'a str> {
+        let index = usize::from(*self.formatted.get(offset)?).checked_sub(1)?;
+        let s = self.strings.split(|&b| b == 0).nth(index)?;
+        Some(std::str::from_utf8(s).ok()?.trim()).filter(|s| !s.is_empty())
+    }
+}
+
+/// Splits a sequence of raw SMBIOS structures into the structures, up to the first one that is cut off.
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+pub fn structures(table: &[u8]) -> impl Iterator<Item = Structure<'_>> {
+    let mut rest = table;
+    std::iter::from_fn(move || {
+        // Every structure starts with its type and the length of its formatted part,
+        // and is followed by a set of strings that ends with two zero bytes.
+        if rest.len() < 4 {
+            return None;
+        }
+        let (kind, length) = (rest[0], usize::from(rest[1]));
+        if length < 4 || length > rest.len() {
+            return None;
+        }
+        let (formatted, after) = rest.split_at(length);
+        let (strings, next) = match after.windows(2).position(|w| w == [0, 0]) {
+            Some(end) => (&after[..end], &after[end + 2..]),
+            None => (after, &[][..]),
+        };
+        rest = next;
+        Some(Structure {
+            kind,
+            formatted,
+            strings,
+        })
+    })
+}
+
+#[cfg(target_os = "linux")]
+/// Concatenates the raw SMBIOS structures of the given types that the kernel exposes in the directory.
+pub fn read_entries(entries: &Path, kinds: &[u8]) -> io::Result<Vec<u8>> {
+    let mut table = Vec::new();
+    for entry in fs::read_dir(entries)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let kind = name.to_string_lossy().split('-').next().map(str::parse);
+        if matches!(kind, Some(Ok(kind)) if kinds.contains(&kind)) {
+            table.extend(fs::read(entry.path().join("raw"))?);
+        }
+    }
+    Ok(table)
+}
+
+/// A populated DIMM slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dimm {
+    handle: u16,
+    /// The label of the slot on the board, like "DIMM_A1".
+    pub locator: Option<String>,
+    /// The label of the bank or channel the slot belongs to, like "BANK 0".
+    pub bank: Option<String>,
+    /// The size of the module, if the firmware knows it.
+    pub size: Option<u64>,
+}
+
+impl fmt::Display for Dimm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.locator, &self.bank) {
+            (Some(locator), Some(bank)) => write!(f, "{locator} ({bank})"),
+            (Some(locator), None) => f.write_str(locator),
+            (None, Some(bank)) => write!(f, "memory device {:#06x} ({bank})", self.handle),
+            (None, None) => write!(f, "memory device {:#06x}", self.handle),
+        }
+    }
+}
+
+/// The DIMMs of the machine, and the ranges of physical addresses the firmware says they hold.
+#[derive(Debug, Default)]
+pub struct Inventory {
+    dimms: Vec<Dimm>,
+    mappings: Vec<(Range<u64>, u16)>,
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+impl Inventory {
+    /// Reads the inventory from the firmware tables.
+    /// Fails if they can not be read, which usually takes root, or if they describe no DIMMs at all.
+    pub fn read() -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let table = read_entries(
+                Path::new("/sys/firmware/dmi/entries"),
+                &[MEMORY_DEVICE, MEMORY_DEVICE_MAPPED_ADDRESS],
+            )?;
+            let inventory = Inventory::parse(&table);
+            if inventory.dimms.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the firmware tables describe no memory modules",
+                ));
+            }
+            Ok(inventory)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the DIMM inventory is only available on Linux",
+            ))
+        }
+    }
+
+    /// Collects the populated memory devices and the address mappings from a sequence of raw SMBIOS structures.
+    fn parse(table: &[u8]) -> Self {
+        let mut inventory = Inventory::default();
+        for structure in structures(table) {
+            let Some(handle) = structure.word(0x02) else {
+                continue;
+            };
+            match structure.kind {
+                MEMORY_DEVICE => {
+                    let size = match structure.word(0x0C) {
+                        // The slot is empty.
+                        Some(0) | None => continue,
+                        Some(0xFFFF) => None,
+                        // The size is given in the extended size field, in MiB.
+                        Some(0x7FFF) => structure
+                            .dword(0x1C)
+                            .map(|size| u64::from(size & 0x7FFF_FFFF) << 20),
+                        // The size is given in KiB if the top bit is set, and in MiB otherwise.
+                        Some(size) if size & 0x8000 != 0 => Some(u64::from(size & 0x7FFF) << 10),
+                        Some(size) => Some(u64::from(size) << 20),
+                    };
+                    inventory.dimms.push(Dimm {
+                        handle,
+                        locator: structure.string(0x10).map(str::to_owned),
+                        bank: structure.string(0x11).map(str::to_owned),
+                        size,
+                    });
+                }
+                MEMORY_DEVICE_MAPPED_ADDRESS => {
+                    let (Some(start), Some(end), Some(device)) = (
+                        structure.dword(0x04),
+                        structure.dword(0x08),
+                        structure.word(0x0C),
+                    ) else {
+                        continue;
+                    };
+                    // The addresses are those of the first and last KiB, unless they are too large for that
+                    // and the extended fields hold the addresses of the first and last byte.
+                    let range = if start == u32::MAX {
+                        match (structure.qword(0x13), structure.qword(0x1B)) {
+                            (Some(start), Some(end)) => start..end.saturating_add(1),
+                            _ => continue,
+                        }
+                    } else {
+                        u64::from(start) << 10..(u64::from(end) + 1) << 10
+                    };
+                    inventory.mappings.push((range, device));
+                }
+                _ => {}
+            }
+        }
+        inventory
+    }
+
+    /// Returns the populated DIMM slots.
+    pub fn dimms(&self) -> &[Dimm] {
+        &self.dimms
+    }
+
+    /// Makes a best guess at the DIMM slot that holds the given physical address.
+    /// With interleaved memory the firmware maps the same range to several modules, and all of them are named.
+    pub fn locate(&self, physical_address: u64) -> Option<String> {
+        let mut candidates: Vec<&Dimm> = Vec::new();
+        for (range, device) in &self.mappings {
+            if !range.contains(&physical_address) {
+                continue;
+            }
+            if let Some(dimm) = self.dimms.iter().find(|dimm| dimm.handle == *device) {
+                if !candidates.contains(&dimm) {
+                    candidates.push(dimm);
+                }
+            }
+        }
+        // Without any mappings, a single module must hold everything.
+        if self.mappings.is_empty() && self.dimms.len() == 1 {
+            candidates.push(&self.dimms[0]);
+        }
+
+        match candidates[..] {
+            [] => None,
+            [dimm] => Some(dimm.to_string()),
+            _ => Some(format!(
+                "one of the interleaved {}",
+                candidates
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a structure with the given formatted part after the type, length and handle, and strings.
+    fn structure(kind: u8, handle: u16, fields: &[u8], strings: &[&str]) -> Vec<u8> {
+        let mut structure = vec![kind, 4 + fields.len() as u8];
+        structure.extend(handle.to_le_bytes());
+        structure.extend(fields);
+        for s in strings {
+            structure.extend(s.as_bytes());
+            structure.push(0);
+        }
+        if strings.is_empty() {
+            structure.push(0);
+        }
+        structure.push(0);
+        structure
+    }
+
+    /// Builds a memory device with the given size field, and its locators as the first two strings.
+    fn memory_device(handle: u16, size: u16, strings: &[&str]) -> Vec<u8> {
+        let mut fields = vec![0; 0x18];
+        fields[0x0C - 4..0x0E - 4].copy_from_slice(&size.to_le_bytes());
+        fields[0x10 - 4] = 1;
+        fields[0x11 - 4] = 2;
+        structure(MEMORY_DEVICE, handle, &fields, strings)
+    }
+
+    /// Builds a mapping of the KiB with the given numbers to a memory device.
+    fn mapping(handle: u16, kib: Range<u32>, device: u16) -> Vec<u8> {
+        let mut fields = Vec::new();
+        fields.extend(kib.start.to_le_bytes());
+        fields.extend((kib.end - 1).to_le_bytes());
+        fields.extend(device.to_le_bytes());
+        fields.resize(0x13 - 4, 0);
+        structure(MEMORY_DEVICE_MAPPED_ADDRESS, handle, &fields, &[])
+    }
+
+    #[test]
+    fn check_inventory_parsing() {
+        let mut table = structure(0, 0, &[], &["vendor"]);
+        table.extend(memory_device(0x10, 8192, &["DIMM_A1", "BANK 0"]));
+        table.extend(memory_device(0x11, 0, &["DIMM_A2", "BANK 1"]));
+        table.extend(memory_device(0x12, 0x8000 | 512, &["DIMM_B1", " "]));
+        let inventory = Inventory::parse(&table);
+        assert_eq!(
+            inventory
+                .dimms()
+                .iter()
+                .map(|dimm| (dimm.to_string(), dimm.size))
+                .collect::<Vec<_>>(),
+            [
+                ("DIMM_A1 (BANK 0)".to_owned(), Some(8 << 30)),
+                ("DIMM_B1".to_owned(), Some(512 << 10)),
+            ]
+        );
+        // Without mappings there is no telling which module an address is in.
+        assert_eq!(inventory.locate(0), None);
+
+        table.extend(mapping(0x20, 0..1024, 0x10));
+        table.extend(mapping(0x21, 1024..2048, 0x10));
+        table.extend(mapping(0x22, 1024..2048, 0x12));
+        let inventory = Inventory::parse(&table);
+        assert_eq!(
+            inventory.locate(1024 * 1024 - 1).as_deref(),
+            Some("DIMM_A1 (BANK 0)")
+        );
+        assert_eq!(
+            inventory.locate(1024 * 1024).as_deref(),
+            Some("one of the interleaved DIMM_A1 (BANK 0), DIMM_B1")
+        );
+        assert_eq!(inventory.locate(2048 * 1024), None);
+
+        let single = Inventory::parse(&memory_device(0x10, 0xFFFF, &[]));
+        assert_eq!(
+            single.locate(1 << 40).as_deref(),
+            Some("memory device 0x0010")
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! The bursts of `--duty-cycle`, which check the detector back to back for a while and then leave it alone
+//! for a while, to keep the heat and the power the checks cost down on average.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How long the detector checks and then idles, over and over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycle {
+    pub on: Duration,
+    pub off: Duration,
+}
+
+impl FromStr for DutyCycle {
+    type Err = String;
+
+    /// Parses two durations like "1min/9min", how long to check and how long to idle afterwards.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (on, off) = s
+            .split_once('/')
+            .ok_or_else(|| format!("'{s}' is not two durations like 1min/9min"))?;
+        let parse = |duration: &str| {
+            let duration: Duration = duration
+                .trim()
+                .parse::<humantime::Duration>()
+                .map_err(|e| format!("'{}' is not a duration ({e})", duration.trim()))?
+                .into();
+            if duration.is_zero() {
+                return Err(format!("the durations of {s} must be more than 0"));
+            }
+            Ok(duration)
+        };
+        Ok(DutyCycle {
+            on: parse(on)?,
+            off: parse(off)?,
+        })
+    }
+}
+
+/// Keeps track of when the current burst ends.
+#[derive(Debug)]
+pub struct Bursts {
+    cycle: DutyCycle,
+    burst_end: Instant,
+}
+
+impl Bursts {
+    /// Begins the first burst at the given time.
+    pub fn start(cycle: DutyCycle, now: Instant) -> Self {
+        Bursts {
+            cycle,
+            burst_end: now + cycle.on,
+        }
+    }
+
+    /// Returns `None` while the burst goes on at the given time, or when the next one begins once it is over.
+    /// A check that runs past the end of a burst shortens the next one rather than the idle window.
+    pub fn idle_until(&mut self, now: Instant) -> Option<Instant> {
+        if now < self.burst_end {
+            return None;
+        }
+        let next = now + self.cycle.off;
+        self.burst_end = (self.burst_end + self.cycle.off + self.cycle.on).max(next);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_bursts() {
+        let minute = Duration::from_secs(60);
+        let cycle: DutyCycle = "1min/9min".parse().unwrap();
+        assert_eq!(
+            cycle,
+            DutyCycle {
+                on: minute,
+                off: minute * 9
+            }
+        );
+        assert_eq!(
+            " 30s / 1h 30min ".parse::<DutyCycle>().unwrap().off,
+            minute * 90
+        );
+        assert!("1min".parse::<DutyCycle>().is_err());
+        assert!("0s/1min".parse::<DutyCycle>().is_err());
+        assert!("1min/soon".parse::<DutyCycle>().is_err());
+
+        let start = Instant::now();
+        let mut bursts = Bursts::start(cycle, start);
+        assert_eq!(bursts.idle_until(start), None);
+        assert_eq!(bursts.idle_until(start + minute / 2), None);
+        // A check that ended 10 seconds late still idles the whole 9 minutes, and the next burst is 10 seconds shorter.
+        let late = start + minute + Duration::from_secs(10);
+        assert_eq!(bursts.idle_until(late), Some(late + minute * 9));
+        assert_eq!(bursts.idle_until(start + minute * 10), None);
+        assert_eq!(
+            bursts.idle_until(start + minute * 11),
+            Some(start + minute * 20)
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Detection of ECC memory, which corrects bit flips before this program can see them.
+
+#[cfg(target_os = "linux")]
+use std::{fs, path::Path};
+
+#[cfg(any(target_os = "linux", windows))]
+use crate::dmi;
+
+/// Whether the memory of this machine uses error correcting codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+pub enum EccStatus {
+    Present,
+    Absent,
+    Unknown,
+}
+
+/// Tries to determine whether this machine has ECC memory.
+///
+/// On Linux the EDAC (error detection and correction) subsystem in `/sys/devices/system/edac/mc/` is
+/// consulted first, and if that is inconclusive the SMBIOS memory array entries in `/sys/firmware/dmi/entries/`,
+/// which are usually only readable by root. On Windows the SMBIOS tables are read from the firmware.
+pub fn detect_ecc() -> EccStatus {
+    #[cfg(target_os = "linux")]
+    {
+        match detect_edac(Path::new("/sys/devices/system/edac/mc")) {
+            EccStatus::Unknown => detect_dmi(Path::new("/sys/firmware/dmi/entries")),
+            status => status,
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        windows_smbios_table()
+            .map(|table| smbios_error_correction(&table))
+            .unwrap_or(EccStatus::Unknown)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        EccStatus::Unknown
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Looks at the memory controllers registered with EDAC, which are only registered for controllers that can do ECC.
+/// Their DIMMs (or chip select rows on older kernels) report which error correction mode they use.
+fn detect_edac(mc: &Path) -> EccStatus {
+    let Ok(controllers) = fs::read_dir(mc) else {
+        return EccStatus::Unknown;
+    };
+
+    let mut any_controller = false;
+    let mut modes = Vec::new();
+    for controller in controllers.flatten() {
+        if !controller.file_name().to_string_lossy().starts_with("mc") {
+            continue;
+        }
+        any_controller = true;
+
+        for entry in fs::read_dir(controller.path())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let path = entry.path();
+            for mode_file in ["dimm_edac_mode", "edac_mode"] {
+                if let Ok(mode) = fs::read_to_string(path.join(mode_file)) {
+                    modes.push(mode.trim().to_owned());
+                }
+            }
+        }
+    }
+
+    if !any_controller {
+        EccStatus::Unknown
+    } else if modes.is_empty() || modes.iter().any(|m| m != "None" && m != "Unknown") {
+        EccStatus::Present
+    } else {
+        EccStatus::Absent
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Reads the raw SMBIOS type 16 (physical memory array) entries exposed by the kernel.
+fn detect_dmi(entries: &Path) -> EccStatus {
+    match dmi::read_entries(entries, &[PHYSICAL_MEMORY_ARRAY]) {
+        Ok(table) => smbios_error_correction(&table),
+        Err(_) => EccStatus::Unknown,
+    }
+}
+
+#[cfg(any(target_os = "linux", windows))]
+/// The SMBIOS structure type that describes a physical memory array.
+const PHYSICAL_MEMORY_ARRAY: u8 = 16;
+#[cfg(any(target_os = "linux", windows))]
+/// The offset of the "memory error correction" field in a physical memory array structure.
+const ERROR_CORRECTION_OFFSET: usize = 0x06;
+
+#[cfg(any(target_os = "linux", windows))]
+/// Determines the error correction status from a sequence of raw SMBIOS structures
+/// by looking at the memory error correction field of all physical memory arrays.
+fn smbios_error_correction(table: &[u8]) -> EccStatus {
+    let mut status = EccStatus::Unknown;
+    for structure in dmi::structures(table) {
+        if structure.kind != PHYSICAL_MEMORY_ARRAY {
+            continue;
+        }
+        match structure.formatted.get(ERROR_CORRECTION_OFFSET) {
+            // Single-bit ECC, multi-bit ECC and CRC.
+            Some(5..=7) => return EccStatus::Present,
+            // None and parity.
+            Some(3 | 4) => status = EccStatus::Absent,
+            // Other and unknown.
+            _ => {}
+        }
+    }
+    status
+}
+
+#[cfg(windows)]
+/// Reads the raw SMBIOS table from the firmware, without the header Windows puts in front of it.
+fn windows_smbios_table() -> Option<Vec<u8>> {
+    use windows_sys::Win32::System::SystemInformation::GetSystemFirmwareTable;
+
+    const RSMB: u32 = u32::from_be_bytes(*b"RSMB");
+    // The table is preceded by the version of the calling method, the SMBIOS version, the DMI revision and its length.
+    const HEADER_LENGTH: usize = 8;
+
+    let size = unsafe { GetSystemFirmwareTable(RSMB, 0, std::ptr::null_mut(), 0) };
+    if size == 0 {
+        return None;
+    }
+    let mut buf = vec![0; size as usize];
+    let written = unsafe { GetSystemFirmwareTable(RSMB, 0, buf.as_mut_ptr().cast(), size) };
+    if written == 0 || written > size || (written as usize) < HEADER_LENGTH {
+        return None;
+    }
+    buf.truncate(written as usize);
+    Some(buf.split_off(HEADER_LENGTH))
+}
+
+#[cfg(all(test, any(target_os = "linux", windows)))]
+mod test {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use crate::test_util::TempDir;
+
+    /// Builds a physical memory array structure with the given error correction type and no strings.
+    fn memory_array(error_correction: u8) -> Vec<u8> {
+        let mut structure = vec![PHYSICAL_MEMORY_ARRAY, 0x17, 0x00, 0x10, 0x03, 0x03];
+        structure.push(error_correction);
+        structure.resize(0x17, 0);
+        structure.extend([0, 0]);
+        structure
+    }
+
+    #[test]
+    fn check_smbios_parsing() {
+        // A BIOS information structure with a string, which should be skipped.
+        let mut table = vec![0, 0x04, 0x00, 0x00];
+        table.extend(b"vendor\0\0");
+
+        assert_eq!(smbios_error_correction(&table), EccStatus::Unknown);
+
+        let mut absent = table.clone();
+        absent.extend(memory_array(3));
+        assert_eq!(smbios_error_correction(&absent), EccStatus::Absent);
+
+        let mut present = absent.clone();
+        present.extend(memory_array(6));
+        assert_eq!(smbios_error_correction(&present), EccStatus::Present);
+
+        assert_eq!(
+            smbios_error_correction(&memory_array(2)),
+            EccStatus::Unknown
+        );
+        assert_eq!(smbios_error_correction(&[16, 0x17, 0]), EccStatus::Unknown);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_edac_parsing() {
+        let dir = TempDir::new("edac");
+        let mc = dir.join("mc");
+        assert_eq!(detect_edac(&mc), EccStatus::Unknown);
+
+        fs::create_dir_all(mc.join("mc0/dimm0")).unwrap();
+        assert_eq!(detect_edac(&mc), EccStatus::Present);
+
+        fs::write(mc.join("mc0/dimm0/dimm_edac_mode"), "None\n").unwrap();
+        assert_eq!(detect_edac(&mc), EccStatus::Absent);
+
+        fs::create_dir_all(mc.join("mc0/dimm1")).unwrap();
+        fs::write(mc.join("mc0/dimm1/dimm_edac_mode"), "SECDED\n").unwrap();
+        assert_eq!(detect_edac(&mc), EccStatus::Present);
+    }
+}
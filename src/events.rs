@@ -0,0 +1,517 @@
+//! The events of a run as structured data, which every output that writes them as JSON builds from,
+//! so that a flip looks the same in the JSON stream, the log files and the requests of the webhook.
+//! With the `schema` feature, the `schema` subcommand prints their JSON Schema for consumers to validate against.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use humantime::format_rfc3339;
+#[cfg(feature = "schema")]
+use schemars::{generate::SchemaSettings, JsonSchema};
+use serde::Serialize;
+
+use crate::canary::CanaryViolation;
+use crate::config::AllocationMode;
+use crate::coverage::Coverage;
+use crate::detector::{ChangedBlock, FlipEvent, ReclaimedPage, WordError};
+use crate::hamming::WordStatus;
+use crate::output::{Startup, StuckBit, REPEATED_FLIPS_LISTED};
+#[cfg(feature = "schema")]
+use crate::report::Report;
+use crate::summary::{Stage, Summary};
+
+/// An event of the detector, named by its `event` field. Every event has the time it happened at
+/// as an RFC 3339 `timestamp`.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The detector memory has been allocated and filled, and the checks begin.
+    Startup {
+        timestamp: String,
+        allocated_bytes: usize,
+        /// How the size was chosen with --use-all, "available" or "free".
+        #[serde(skip_serializing_if = "Option::is_none")]
+        allocation_mode: Option<&'static str>,
+        pattern: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seed: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reference_file: Option<PathBuf>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        snapshot: bool,
+        delay_seconds: f64,
+        threads: usize,
+        lock_memory: bool,
+        huge_pages: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        checksum_block_size: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ecc_data_bytes: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        coverage_percent: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        coverage_seed: Option<u64>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        resumed: bool,
+        /// The flips the parity of a resumed backing file showed to have happened while no detector was running.
+        #[serde(skip_serializing_if = "is_zero")]
+        downtime_flips: u64,
+    },
+    /// An integrity check is done, and the events of what it found follow.
+    Check {
+        timestamp: String,
+        /// The number of the check, counting from 1.
+        check: u64,
+        flips: usize,
+    },
+    /// How much of the detector the checks verify with --coverage or --pass-timeout.
+    Coverage {
+        timestamp: String,
+        pages: usize,
+        /// The pages the last check verified.
+        checked_pages: usize,
+        /// The pages verified since the checks last started over.
+        covered_pages: usize,
+    },
+    /// A byte of the detector memory did not hold what it should.
+    Flip {
+        timestamp: String,
+        /// The index of the byte in the detector memory.
+        offset: usize,
+        expected: u8,
+        found: u8,
+        /// How many bits of the word flipped: "single-bit", "double-bit" or "multi-bit".
+        class: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        virtual_address: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        physical_address: Option<u64>,
+        /// The memory module the byte is on, with --dimm-slots.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dimm: Option<String>,
+        /// Whether the flip was still there when the byte was read again: "persistent" or "transient".
+        persistence: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mirror: Option<MirrorVote>,
+        /// Whether the machine reported a memory error in the same interval.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        correlated_mce: bool,
+        /// The bits that flipped in the word, for flips of more than one bit.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        cluster: Vec<ClusterBit>,
+    },
+    /// More flips were found than --max-events-per-check lets through.
+    OmittedFlips { timestamp: String, flips: usize },
+    /// A word that the emulated ECC found an error in.
+    WordError {
+        timestamp: String,
+        word: usize,
+        corrected: bool,
+    },
+    /// A page of the detector memory that the operating system reclaimed and that reads as zeros.
+    Reclaimed {
+        timestamp: String,
+        offset: usize,
+        len: usize,
+        zeroed_bytes: usize,
+    },
+    /// A block of the detector memory changed since the snapshot.
+    ChangedBlock {
+        timestamp: String,
+        offset: usize,
+        len: usize,
+    },
+    /// A byte flipped more often than --stuck-threshold.
+    Stuck {
+        timestamp: String,
+        offset: usize,
+        flips: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        physical_address: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dimm: Option<String>,
+        /// Whether the page of the byte is no longer monitored from now on.
+        excluded: bool,
+    },
+    /// A canary page next to the detector memory changed.
+    Canary {
+        timestamp: String,
+        canary: usize,
+        changed_bytes: usize,
+        first: usize,
+        expected: u8,
+        found: u8,
+    },
+    /// Checking was paused.
+    Paused { timestamp: String },
+    /// Checking was resumed.
+    Resumed { timestamp: String },
+    /// The detector is still running, sent at the --heartbeat interval.
+    Heartbeat {
+        timestamp: String,
+        uptime_seconds: f64,
+        checks: u64,
+        flips: u64,
+        byte_seconds: f64,
+    },
+    /// A stage of a staircase run is done.
+    Stage {
+        timestamp: String,
+        stage: usize,
+        stages: usize,
+        monitored_bytes: usize,
+        monitored_seconds: f64,
+        checks: u64,
+        flips: u64,
+        flips_per_gb_hour: Option<f64>,
+        /// The size of the detector of the next stage, which the checks go on with.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_bytes: Option<usize>,
+    },
+    /// The detector stopped, with the statistics of the whole run.
+    Shutdown {
+        timestamp: String,
+        #[serde(flatten)]
+        totals: Totals,
+    },
+}
+
+/// How the copies of a mirrored detector voted on a flip.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct MirrorVote {
+    copy: usize,
+    copies: usize,
+    agreeing: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    majority: Option<u8>,
+}
+
+/// One of the bits of a word with a multi-bit flip.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct ClusterBit {
+    offset: usize,
+    bit: u8,
+    /// "0 -> 1" or "1 -> 0".
+    direction: String,
+}
+
+/// A byte that flipped more than once, in the summary.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct RepeatedFlips {
+    offset: usize,
+    flips: u64,
+}
+
+/// The statistics of a whole run, which the shutdown event and the report of `--report` both have.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Totals {
+    runtime_seconds: f64,
+    paused_seconds: f64,
+    idle_seconds: f64,
+    monitored_bytes: usize,
+    byte_seconds: f64,
+    checks: u64,
+    flips: u64,
+    single_bit_flips: u64,
+    double_bit_flips: u64,
+    multi_bit_flips: u64,
+    persistent_flips: u64,
+    transient_flips: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    stuck_bits: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    flips_per_numa_node: BTreeMap<u32, u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    repeated_flips: Vec<RepeatedFlips>,
+    #[serde(skip_serializing_if = "is_zero")]
+    excluded_pages: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    canary_violations: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    reclaimed_pages: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    downtime_flips: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    changed_blocks: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verified_clean: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verification_passes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revisit_interval_seconds: Option<f64>,
+    flips_per_gb_hour: Option<f64>,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn now() -> String {
+    format_rfc3339(SystemTime::now()).to_string()
+}
+
+impl Event {
+    pub fn startup(startup: &Startup) -> Self {
+        Event::Startup {
+            timestamp: now(),
+            allocated_bytes: startup.allocated_bytes,
+            allocation_mode: startup.allocation_mode.map(|mode| match mode {
+                AllocationMode::Available => "available",
+                AllocationMode::Free => "free",
+            }),
+            pattern: startup.pattern.to_string(),
+            seed: startup.pattern.seed(),
+            reference_file: startup.reference_file.clone(),
+            snapshot: startup.snapshot,
+            delay_seconds: startup.delay.as_secs_f64(),
+            threads: startup.threads,
+            lock_memory: startup.lock_memory,
+            huge_pages: startup.huge_pages,
+            checksum_block_size: startup.checksum_block_size,
+            ecc_data_bytes: startup.ecc_data_bytes,
+            coverage_percent: startup.coverage.map(|(percent, _)| percent),
+            coverage_seed: startup.coverage.map(|(_, seed)| seed),
+            resumed: startup.resumed,
+            downtime_flips: startup.downtime_flips as u64,
+        }
+    }
+
+    pub fn check(check: u64, flips: usize) -> Self {
+        Event::Check {
+            timestamp: now(),
+            check,
+            flips,
+        }
+    }
+
+    pub fn coverage(coverage: &Coverage) -> Self {
+        Event::Coverage {
+            timestamp: now(),
+            pages: coverage.pages,
+            checked_pages: coverage.checked,
+            covered_pages: coverage.covered,
+        }
+    }
+
+    pub fn flip(flip: &FlipEvent) -> Self {
+        Event::Flip {
+            timestamp: now(),
+            offset: flip.offset,
+            expected: flip.expected,
+            found: flip.found,
+            class: flip.class.to_string(),
+            virtual_address: flip.virtual_address,
+            physical_address: flip.physical_address,
+            dimm: flip.dimm.clone(),
+            persistence: flip.persistence.to_string(),
+            mirror: flip.mirror.map(|mirror| MirrorVote {
+                copy: mirror.copy,
+                copies: mirror.copies,
+                agreeing: mirror.agreeing,
+                majority: mirror.majority,
+            }),
+            correlated_mce: flip.correlated_mce,
+            cluster: flip
+                .cluster
+                .iter()
+                .map(|bit| ClusterBit {
+                    offset: bit.offset,
+                    bit: bit.bit,
+                    direction: bit.direction.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn omitted_flips(omitted: usize) -> Self {
+        Event::OmittedFlips {
+            timestamp: now(),
+            flips: omitted,
+        }
+    }
+
+    pub fn word_error(error: &WordError) -> Self {
+        Event::WordError {
+            timestamp: now(),
+            word: error.word,
+            corrected: error.status == WordStatus::Corrected,
+        }
+    }
+
+    pub fn reclaimed(page: &ReclaimedPage) -> Self {
+        Event::Reclaimed {
+            timestamp: now(),
+            offset: page.offset,
+            len: page.len,
+            zeroed_bytes: page.flips.len(),
+        }
+    }
+
+    pub fn changed_block(block: &ChangedBlock) -> Self {
+        Event::ChangedBlock {
+            timestamp: now(),
+            offset: block.offset,
+            len: block.len,
+        }
+    }
+
+    pub fn stuck(stuck: &StuckBit) -> Self {
+        Event::Stuck {
+            timestamp: now(),
+            offset: stuck.offset,
+            flips: stuck.flips,
+            physical_address: stuck.physical_address,
+            dimm: stuck.dimm.clone(),
+            excluded: stuck.excluded,
+        }
+    }
+
+    pub fn canary(violation: &CanaryViolation) -> Self {
+        Event::Canary {
+            timestamp: now(),
+            canary: violation.canary,
+            changed_bytes: violation.changed_bytes,
+            first: violation.first,
+            expected: violation.expected,
+            found: violation.found,
+        }
+    }
+
+    /// Returns the event of checking being paused, or resumed.
+    pub fn paused(paused: bool) -> Self {
+        let timestamp = now();
+        if paused {
+            Event::Paused { timestamp }
+        } else {
+            Event::Resumed { timestamp }
+        }
+    }
+
+    pub fn heartbeat(summary: &Summary) -> Self {
+        Event::Heartbeat {
+            timestamp: now(),
+            uptime_seconds: summary.runtime().as_secs_f64(),
+            checks: summary.checks,
+            flips: summary.flips,
+            byte_seconds: summary.byte_seconds(),
+        }
+    }
+
+    pub fn stage(stage: &Stage) -> Self {
+        Event::Stage {
+            timestamp: now(),
+            stage: stage.number,
+            stages: stage.stages,
+            monitored_bytes: stage.monitored_bytes,
+            monitored_seconds: stage.monitored_time.as_secs_f64(),
+            checks: stage.checks,
+            flips: stage.flips,
+            flips_per_gb_hour: stage.flips_per_gb_hour(),
+            next_bytes: stage.next_bytes,
+        }
+    }
+
+    pub fn shutdown(summary: &Summary) -> Self {
+        Event::Shutdown {
+            timestamp: now(),
+            totals: Totals::new(summary),
+        }
+    }
+}
+
+impl Totals {
+    pub fn new(summary: &Summary) -> Self {
+        Totals {
+            runtime_seconds: summary.runtime().as_secs_f64(),
+            paused_seconds: summary.paused_time().as_secs_f64(),
+            idle_seconds: summary.idle_time().as_secs_f64(),
+            monitored_bytes: summary.monitored_bytes,
+            byte_seconds: summary.byte_seconds(),
+            checks: summary.checks,
+            flips: summary.flips,
+            single_bit_flips: summary.single_bit_flips,
+            double_bit_flips: summary.double_bit_flips,
+            multi_bit_flips: summary.multi_bit_flips,
+            persistent_flips: summary.persistent_flips,
+            transient_flips: summary.transient_flips,
+            stuck_bits: summary.stuck_bits,
+            flips_per_numa_node: summary.flips_per_node.clone(),
+            repeated_flips: summary
+                .flip_counts
+                .repeated(REPEATED_FLIPS_LISTED)
+                .into_iter()
+                .map(|(offset, flips)| RepeatedFlips { offset, flips })
+                .collect(),
+            excluded_pages: summary.excluded_pages,
+            canary_violations: summary.canary_violations,
+            reclaimed_pages: summary.reclaimed_pages,
+            downtime_flips: summary.downtime_flips,
+            changed_blocks: summary.changed_blocks,
+            verified_clean: summary.verification.map(|v| v.clean),
+            verification_passes: summary.verification.map(|v| v.passes),
+            revisit_interval_seconds: summary.revisit_interval().map(|i| i.as_secs_f64()),
+            flips_per_gb_hour: summary.flips_per_gb_hour(),
+        }
+    }
+}
+
+/// An event together with the tag of the instance of the detector it is from, see `--tag`.
+/// This is what every line of the JSON output is.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct TaggedEvent<'a> {
+    #[serde(flatten)]
+    pub event: &'a Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<&'a str>,
+}
+
+/// Returns the JSON Schema of the lines of the JSON output, see [`TaggedEvent`]. It also defines the report
+/// that `--report` writes, which a consumer validates against `#/$defs/Report`.
+#[cfg(feature = "schema")]
+pub fn schema() -> String {
+    // The fields that are left out when they are empty are only optional in the schema of what is written.
+    let mut generator = SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator();
+    generator.subschema_for::<Report>();
+    let schema = generator.into_root_schema_for::<TaggedEvent<'static>>();
+    serde_json::to_string_pretty(&schema).expect("a schema can be serialized")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn check_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&schema()).unwrap();
+        let text = schema.to_string();
+        for event in ["startup", "check", "flip", "heartbeat", "shutdown"] {
+            assert!(text.contains(&format!("\"const\":\"{event}\"")), "{event}");
+        }
+        assert!(text.contains("\"tag\""));
+        assert!(text.contains("\"physical_address\""));
+        let report = &schema["$defs"]["Report"]["properties"];
+        for field in ["started", "arguments", "stuck_bits", "repeated_flips"] {
+            assert!(report.get(field).is_some(), "{field}");
+        }
+    }
+
+    #[test]
+    fn check_flip_event() {
+        // The events the outputs write have the fields the schema promises.
+        let flip = serde_json::to_value(Event::flip(&FlipEvent::new(7, 0x00, 0x10))).unwrap();
+        assert_eq!(flip["event"], "flip");
+        assert_eq!(flip["offset"], 7);
+        assert_eq!(flip["class"], "single-bit");
+        assert!(flip.get("cluster").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+//! A SECDED (single error correcting, double error detecting) Hamming code over 64-bit words,
+//! with eight check bits per word like the ECC memory it emulates.
+//!
+//! The word and its check bits form a 72 bit codeword. Bit 0 holds the parity of the whole codeword,
+//! the bits at the powers of two 1, 2, 4, ..., 64 hold the Hamming parity bits and the
+//! 64 remaining positions hold the bits of the word. The check byte stores the Hamming parity bits
+//! in its seven lowest bits and the overall parity in its highest bit.
+
+/// The result of checking a word against its check bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordStatus {
+    /// The word and its check bits are intact.
+    Clean,
+    /// A single bit of the word or its check bits flipped, and can be corrected.
+    Corrected,
+    /// At least two bits flipped, which can be detected but not corrected.
+    Uncorrectable,
+}
+
+/// The number of positions in a codeword.
+const CODEWORD_BITS: u32 = 72;
+
+/// The position in the codeword of every bit of the word.
+const DATA_POSITIONS: [u32; 64] = {
+    let mut positions = [0; 64];
+    let mut position: u32 = 1;
+    let mut bit = 0;
+    while bit < 64 {
+        if !position.is_power_of_two() {
+            positions[bit] = position;
+            bit += 1;
+        }
+        position += 1;
+    }
+    positions
+};
+
+/// Returns the exclusive or of the positions of all set bits of the word,
+/// which is what the Hamming parity bits must cancel out.
+fn data_syndrome(data: u64) -> u32 {
+    (0..64)
+        .filter(|bit| data & (1 << bit) != 0)
+        .fold(0, |syndrome, bit| syndrome ^ DATA_POSITIONS[bit])
+}
+
+/// Returns the check bits of a word.
+pub fn encode(data: u64) -> u8 {
+    let hamming = data_syndrome(data) as u8;
+    let parity = (data.count_ones() + hamming.count_ones()) % 2;
+    hamming | (parity as u8) << 7
+}
+
+/// Checks a word against its check bits, and returns the status of the word
+/// together with the corrected word and check bits. Uncorrectable words are returned as they are.
+pub fn decode(data: u64, check: u8) -> (WordStatus, u64, u8) {
+    let hamming = check & 0x7F;
+    // The position of a single flipped bit, since the Hamming parity bits sit at the powers of two.
+    let syndrome = data_syndrome(data) ^ u32::from(hamming);
+    let parity_error = (data.count_ones() + check.count_ones()) % 2 == 1;
+
+    match (syndrome, parity_error) {
+        (0, false) => (WordStatus::Clean, data, check),
+        // Only the overall parity bit flipped.
+        (0, true) => (WordStatus::Corrected, data, check ^ 0x80),
+        (position, true) if position.is_power_of_two() => (
+            WordStatus::Corrected,
+            data,
+            check ^ (1 << position.trailing_zeros()),
+        ),
+        (position, true) if position < CODEWORD_BITS => {
+            let bit = DATA_POSITIONS
+                .iter()
+                .position(|&p| p == position)
+                .expect("every position that is not a power of two holds a bit of the word");
+            (WordStatus::Corrected, data ^ (1 << bit), check)
+        }
+        // An even number of flips leaves the overall parity intact, and an odd number
+        // larger than one can point outside of the codeword.
+        _ => (WordStatus::Uncorrectable, data, check),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_single_bit_correction() {
+        for data in [0, u64::MAX, 0x0123_4567_89AB_CDEF, 0xAAAA_AAAA_AAAA_AAAA] {
+            let check = encode(data);
+            assert_eq!(decode(data, check), (WordStatus::Clean, data, check));
+
+            for bit in 0..64 {
+                assert_eq!(
+                    decode(data ^ (1 << bit), check),
+                    (WordStatus::Corrected, data, check)
+                );
+            }
+            for bit in 0..8 {
+                assert_eq!(
+                    decode(data, check ^ (1 << bit)),
+                    (WordStatus::Corrected, data, check)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn check_double_bit_detection() {
+        let data = 0x0123_4567_89AB_CDEF;
+        let check = encode(data);
+        for a in 0..72 {
+            for b in a + 1..72 {
+                // Flip two bits anywhere in the word or its check bits.
+                let flip = |bit: u32| {
+                    if bit < 64 {
+                        (1 << bit, 0)
+                    } else {
+                        (0, 1 << (bit - 64))
+                    }
+                };
+                let ((da, ca), (db, cb)) = (flip(a), flip(b));
+                let (status, _, _) = decode(data ^ da ^ db, check ^ ca ^ cb);
+                assert_eq!(status, WordStatus::Uncorrectable, "bits {a} and {b}");
+            }
+        }
+    }
+}
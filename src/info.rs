@@ -0,0 +1,210 @@
+//! What this program finds out about the memory of the machine it runs on, printed by the `info` subcommand
+//! so that the sizes and flags of a run can be chosen with the same numbers the detector would use.
+
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use sysinfo::{RefreshKind, System, SystemExt};
+
+use crate::cgroup;
+use crate::config::AllocationMode;
+use crate::detector::maximum_size_in_mode;
+use crate::ecc::{detect_ecc, EccStatus};
+#[cfg(feature = "numa")]
+use crate::numa;
+use crate::region::{system_page_size, PAGE_SIZE};
+
+/// A pool of huge pages of one size that the kernel has set aside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HugePagePool {
+    /// The size of the pages in bytes.
+    pub page_size: usize,
+    /// How many pages the pool has.
+    pub pages: u64,
+    /// How many of them are not in use.
+    pub free_pages: u64,
+}
+
+/// The memory of this machine as the detector sees it.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename = "info")]
+pub struct SystemInfo {
+    /// The physical memory of the machine, if the operating system can tell.
+    pub total_bytes: Option<u64>,
+    /// What `--use-all free` would allocate.
+    pub free_bytes: Option<usize>,
+    /// What `--use-all available` would allocate.
+    pub available_bytes: Option<usize>,
+    /// The memory limit of the cgroup this program runs in, if it has one.
+    pub cgroup_limit_bytes: Option<u64>,
+    /// How much of the cgroup memory limit is in use.
+    pub cgroup_usage_bytes: Option<u64>,
+    pub page_size: usize,
+    pub huge_pages: Vec<HugePagePool>,
+    /// The NUMA nodes, or `None` if they could not be found out.
+    pub numa_nodes: Option<Vec<u32>>,
+    /// Whether the machine has ECC memory: "present", "absent" or "unknown".
+    pub ecc: &'static str,
+}
+
+impl SystemInfo {
+    /// Queries the memory of this machine.
+    pub fn query() -> Self {
+        let total_bytes = <System as SystemExt>::IS_SUPPORTED
+            .then(|| System::new_with_specifics(RefreshKind::new().with_memory()).total_memory());
+        // Without sysinfo asking for the free memory panics, except where it is looked up without it.
+        let supported =
+            <System as SystemExt>::IS_SUPPORTED || cfg!(any(windows, target_os = "freebsd"));
+        let cgroup = cgroup::memory();
+        SystemInfo {
+            total_bytes,
+            free_bytes: supported.then(|| maximum_size_in_mode(AllocationMode::Free)),
+            available_bytes: supported.then(|| maximum_size_in_mode(AllocationMode::Available)),
+            cgroup_limit_bytes: cgroup.map(|memory| memory.limit),
+            cgroup_usage_bytes: cgroup.map(|memory| memory.usage),
+            page_size: page_size(),
+            huge_pages: huge_page_pools(),
+            #[cfg(feature = "numa")]
+            numa_nodes: numa::nodes().ok(),
+            #[cfg(not(feature = "numa"))]
+            numa_nodes: None,
+            ecc: match detect_ecc() {
+                EccStatus::Present => "present",
+                EccStatus::Absent => "absent",
+                EccStatus::Unknown => "unknown",
+            },
+        }
+    }
+
+    /// Writes the information as text, or as a single JSON object.
+    pub fn write(&self, mut w: impl Write, json: bool) -> io::Result<()> {
+        if json {
+            return writeln!(w, "{}", serde_json::to_string(self)?);
+        }
+        let bytes =
+            |bytes: Option<u64>| bytes.map_or("unknown".to_owned(), |b| format!("{b} bytes"));
+        writeln!(w, "Total memory: {}", bytes(self.total_bytes))?;
+        writeln!(
+            w,
+            "Free memory: {} (what --use-all free allocates)",
+            bytes(self.free_bytes.map(|b| b as u64))
+        )?;
+        writeln!(
+            w,
+            "Available memory: {} (what --use-all available allocates)",
+            bytes(self.available_bytes.map(|b| b as u64))
+        )?;
+        if let (Some(limit), Some(usage)) = (self.cgroup_limit_bytes, self.cgroup_usage_bytes) {
+            writeln!(
+                w,
+                "Cgroup memory limit: {limit} bytes, {usage} bytes in use"
+            )?;
+        }
+        writeln!(w, "Page size: {} bytes", self.page_size)?;
+        if self.huge_pages.is_empty() {
+            writeln!(w, "Huge pages: none")?;
+        }
+        for pool in &self.huge_pages {
+            writeln!(
+                w,
+                "Huge pages of {} bytes: {} of {} free",
+                pool.page_size, pool.free_pages, pool.pages
+            )?;
+        }
+        match &self.numa_nodes {
+            Some(nodes) => {
+                let nodes: Vec<String> = nodes.iter().map(u32::to_string).collect();
+                writeln!(w, "NUMA nodes: {}", nodes.join(", "))?;
+            }
+            None => writeln!(w, "NUMA nodes: unknown")?,
+        }
+        writeln!(w, "ECC memory: {}", self.ecc)
+    }
+}
+
+/// Returns the size of the pages the operating system maps memory in.
+fn page_size() -> usize {
+    system_page_size().unwrap_or(PAGE_SIZE)
+}
+
+/// Returns the pools of huge pages the kernel has, which are only known on Linux.
+fn huge_page_pools() -> Vec<HugePagePool> {
+    #[cfg(target_os = "linux")]
+    return read_huge_page_pools(Path::new("/sys/kernel/mm/hugepages"));
+    #[cfg(not(target_os = "linux"))]
+    return Vec::new();
+}
+
+#[cfg(target_os = "linux")]
+/// Reads the pools of huge pages from their directories like `hugepages-2048kB`, ordered by page size.
+fn read_huge_page_pools(dir: &Path) -> Vec<HugePagePool> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let read = |path: &Path, name: &str| -> Option<u64> {
+        fs::read_to_string(path.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let mut pools: Vec<HugePagePool> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let kilobytes: usize = name
+                .to_str()?
+                .strip_prefix("hugepages-")?
+                .strip_suffix("kB")?
+                .parse()
+                .ok()?;
+            let path = entry.path();
+            Some(HugePagePool {
+                page_size: kilobytes * 1024,
+                pages: read(&path, "nr_hugepages")?,
+                free_pages: read(&path, "free_hugepages")?,
+            })
+        })
+        .collect();
+    pools.sort_by_key(|pool| pool.page_size);
+    pools
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_huge_page_pools() {
+        let dir = TempDir::new("hugepages");
+        for (name, pages, free) in [
+            ("hugepages-1048576kB", "0", "0"),
+            ("hugepages-2048kB", "16", "12"),
+        ] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            fs::write(dir.join(name).join("nr_hugepages"), format!("{pages}\n")).unwrap();
+            fs::write(dir.join(name).join("free_hugepages"), format!("{free}\n")).unwrap();
+        }
+        fs::create_dir_all(dir.join("unrelated")).unwrap();
+        assert_eq!(
+            read_huge_page_pools(dir.path()),
+            [
+                HugePagePool {
+                    page_size: 2 << 20,
+                    pages: 16,
+                    free_pages: 12,
+                },
+                HugePagePool {
+                    page_size: 1 << 30,
+                    pages: 0,
+                    free_pages: 0,
+                },
+            ]
+        );
+        fs::remove_dir_all(dir.path()).unwrap();
+        assert!(read_huge_page_pools(dir.path()).is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+//! Randomized delays between integrity checks, so that the checks do not keep running in step with something else
+//! that happens periodically on the machine, like a cron job or a backup, and always see the memory in the same state.
+
+use std::time::Duration;
+
+use crate::pattern::splitmix64;
+
+/// The shortest delay a jittered delay that was not zero to begin with is cut down to, however short it was.
+const MIN_DELAY: Duration = Duration::from_micros(1);
+
+/// Draws every delay uniformly from within a percentage of the delay it is given, so that the delays
+/// still average out to the given one.
+pub struct Jitter {
+    percent: f64,
+    seed: u64,
+    /// How many delays have been drawn so far.
+    draws: u64,
+}
+
+impl Jitter {
+    /// Creates a jitter of up to the given percentage, which is less than 100, in either direction.
+    pub fn new(percent: f64, seed: u64) -> Self {
+        Jitter {
+            percent,
+            seed,
+            draws: 0,
+        }
+    }
+
+    /// Returns the next delay in place of the given one. A delay of zero stays zero, checks that run
+    /// back to back have no phase to vary.
+    pub fn apply(&mut self, delay: Duration) -> Duration {
+        if delay.is_zero() {
+            return delay;
+        }
+        let random = splitmix64(self.seed, self.draws);
+        self.draws += 1;
+        // The top 53 bits make a uniform float in [0, 1), which is stretched to [-1, 1).
+        let unit = (random >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+        delay
+            .mul_f64(1.0 + unit * self.percent / 100.0)
+            .max(MIN_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_jitter() {
+        let delay = Duration::from_secs(30);
+        let mut jitter = Jitter::new(10.0, 7);
+        let delays: Vec<Duration> = (0..1000).map(|_| jitter.apply(delay)).collect();
+        assert!(delays
+            .iter()
+            .all(|d| (Duration::from_secs(27)..=Duration::from_secs(33)).contains(d)));
+        assert!(delays.windows(2).any(|pair| pair[0] != pair[1]));
+        let average = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!(
+            average.abs_diff(delay) < Duration::from_millis(500),
+            "{average:?}"
+        );
+
+        assert_eq!(jitter.apply(Duration::ZERO), Duration::ZERO);
+        let mut jitter = Jitter::new(99.999_999, 7);
+        assert!((0..1000).all(|_| jitter.apply(Duration::from_micros(10)) > Duration::ZERO));
+        assert_eq!(Jitter::new(0.0, 7).apply(delay), delay);
+    }
+}
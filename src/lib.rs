@@ -0,0 +1,97 @@
+//! Detecting bit flips in memory, e.g. by cosmic rays, by filling memory with a known pattern
+//! and checking regularly that it still holds it.
+//!
+//! The command line program only hands its arguments to [`run`], and the library
+//! can also be used to monitor memory from within another program. A [`Detector`] owns its memory, which is
+//! freed when it is dropped, and is `Send` and `Sync`, so that it can be checked from a background thread,
+//! or from an async task with [`monitor::monitor`] if the `tokio` feature is enabled:
+//!
+//! ```no_run
+//! use std::num::NonZeroUsize;
+//! use cosmic_ray_detection::{Detector, Pattern};
+//!
+//! let mut detector = Detector::builder(1 << 30)
+//!     .pattern(Pattern::Alternating)
+//!     .threads(NonZeroUsize::new(4).unwrap())
+//!     .build();
+//! loop {
+//!     std::thread::sleep(std::time::Duration::from_secs(30));
+//!     let flips = detector.check();
+//!     for flip in &flips {
+//!         eprintln!("byte {} flipped from {:#010b} to {:#010b}", flip.offset, flip.expected, flip.found);
+//!     }
+//!     detector.scrub(&flips);
+//! }
+//! ```
+
+pub(crate) mod affinity;
+pub mod backend;
+pub(crate) mod backing;
+pub(crate) mod benchmark;
+pub(crate) mod canary;
+pub(crate) mod cgroup;
+pub(crate) mod checksum;
+pub(crate) mod config;
+pub(crate) mod config_file;
+pub(crate) mod coverage;
+pub(crate) mod csv;
+pub(crate) mod daemon;
+pub mod detector;
+pub(crate) mod dmi;
+pub(crate) mod duty_cycle;
+pub(crate) mod ecc;
+pub(crate) mod events;
+pub(crate) mod hamming;
+pub(crate) mod info;
+pub(crate) mod jitter;
+pub(crate) mod logfile;
+pub(crate) mod logging;
+pub(crate) mod mce;
+pub(crate) mod memlock;
+#[cfg(any(feature = "metrics", feature = "statsd"))]
+pub(crate) mod metrics;
+pub mod monitor;
+#[cfg(feature = "notify")]
+pub(crate) mod notify;
+#[cfg(feature = "numa")]
+pub(crate) mod numa;
+pub(crate) mod output;
+pub(crate) mod pagemap;
+pub mod pattern;
+pub(crate) mod privileges;
+pub(crate) mod progress;
+pub(crate) mod reference;
+pub mod region;
+pub(crate) mod report;
+pub(crate) mod signal;
+// Only public for the benchmarks.
+#[doc(hidden)]
+pub mod simd;
+pub(crate) mod slices;
+#[cfg(feature = "statsd")]
+pub(crate) mod statsd;
+pub mod summary;
+#[cfg(all(unix, feature = "syslog"))]
+pub(crate) mod syslog;
+#[cfg(test)]
+mod test_util;
+pub(crate) mod throttle;
+#[cfg(feature = "tui")]
+pub(crate) mod tui;
+pub(crate) mod watchdog;
+#[cfg(feature = "webhook")]
+pub(crate) mod webhook;
+
+use std::error::Error;
+use std::process::ExitCode;
+
+pub use config::{AllocationMode, ScanOrder, WordSize};
+pub use detector::{Detector, DetectorBuilder, FlipEvent};
+pub use pattern::Pattern;
+
+/// Runs the command line program with the arguments and the configuration file of this process.
+// Only public for the binary.
+#[doc(hidden)]
+pub fn run() -> Result<ExitCode, Box<dyn Error>> {
+    monitor::run(&config_file::parse_cli())
+}
@@ -0,0 +1,424 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use humantime::format_rfc3339;
+
+use crate::canary::CanaryViolation;
+use crate::coverage::Coverage;
+use crate::detector::{ChangedBlock, FlipEvent, Persistence, ReclaimedPage, WordError};
+use crate::hamming::WordStatus;
+use crate::output::{Output, Startup, StuckBit, REPEATED_FLIPS_LISTED};
+use crate::summary::{Stage, Summary};
+
+/// A file that is only ever appended to and that is synced to disk on every flush,
+/// so that an abrupt power loss loses as little as possible.
+/// If a rotation size is given the file is renamed to `<path>.1`, `<path>.2` and so on
+/// whenever a flush finds it to have grown beyond that size, and a new file is started.
+pub struct LogFile {
+    path: PathBuf,
+    sink: Sink,
+    size: u64,
+    rotate_size: Option<u64>,
+}
+
+/// Where a [`LogFile`] writes to.
+enum Sink {
+    Plain(File),
+    /// A zstd frame that is written to the file block by block, and ended when the file is rotated or closed.
+    /// Every flush ends a block, so that a decoder can read everything up to the last flush of a file whose frame
+    /// was never ended. Every rotated segment is a complete frame of its own.
+    #[cfg(feature = "compression")]
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Sink {
+    fn file(&self) -> &File {
+        match self {
+            Sink::Plain(file) => file,
+            #[cfg(feature = "compression")]
+            Sink::Zstd(encoder) => encoder.get_ref(),
+        }
+    }
+}
+
+/// The compression level of compressed log files. The events are small and few, so speed hardly matters.
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 19;
+
+impl LogFile {
+    pub fn open(path: impl AsRef<Path>, rotate_size: Option<u64>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path,
+            sink: Sink::Plain(file),
+            size,
+            rotate_size,
+        })
+    }
+
+    /// Like [`LogFile::open`], but compresses everything written to the file with zstd.
+    /// The rotation size is that of the compressed file. A file that is not empty is rotated right away,
+    /// as whatever it ends with may be a frame that an earlier run never got to end.
+    #[cfg(feature = "compression")]
+    pub fn open_compressed(path: impl AsRef<Path>, rotate_size: Option<u64>) -> io::Result<Self> {
+        let mut log_file = Self::open(path, rotate_size)?;
+        if !log_file.is_empty() {
+            log_file.rotate()?;
+        }
+        log_file.compress()?;
+        Ok(log_file)
+    }
+
+    #[cfg(feature = "compression")]
+    fn compress(&mut self) -> io::Result<()> {
+        let file = self.sink.file().try_clone()?;
+        self.sink = Sink::Zstd(zstd::Encoder::new(file, COMPRESSION_LEVEL)?);
+        Ok(())
+    }
+
+    /// Ends the zstd frame of a compressed file, and syncs the file to disk.
+    fn finish(&mut self) -> io::Result<()> {
+        #[cfg(feature = "compression")]
+        if let Sink::Zstd(encoder) = &mut self.sink {
+            encoder.do_finish()?;
+        }
+        self.sink.file().sync_data()
+    }
+
+    /// Returns whether nothing has been written to the file yet.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the path of the given rotated segment of the log file.
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{segment}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.finish()?;
+        let segment = (1..)
+            .find(|&segment| !self.segment_path(segment).exists())
+            .expect("there is an unused segment number");
+        fs::rename(&self.path, self.segment_path(segment))?;
+        let compressed = !matches!(self.sink, Sink::Plain(_));
+        *self = Self::open(&self.path, self.rotate_size)?;
+        #[cfg(feature = "compression")]
+        if compressed {
+            self.compress()?;
+        }
+        #[cfg(not(feature = "compression"))]
+        let _ = compressed;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.sink {
+            Sink::Plain(file) => {
+                let written = file.write(buf)?;
+                self.size += written as u64;
+                Ok(written)
+            }
+            #[cfg(feature = "compression")]
+            Sink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        #[cfg(feature = "compression")]
+        if let Sink::Zstd(encoder) = &mut self.sink {
+            encoder.flush()?;
+            self.size = encoder.get_ref().metadata()?.len();
+        }
+        self.sink.file().sync_data()?;
+        // Every event ends with a flush, so rotating here never splits an event between two files.
+        if self.rotate_size.is_some_and(|max| self.size >= max) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Plain text output meant for log files, with one timestamped line per event.
+pub struct LogOutput<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LogOutput<W> {
+    pub fn new(writer: W) -> Self {
+        LogOutput { writer }
+    }
+
+    fn line(&mut self, message: std::fmt::Arguments) -> io::Result<()> {
+        let line = format!("{} {message}\n", format_rfc3339(SystemTime::now()));
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Output for LogOutput<W> {
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.line(format_args!("startup: monitoring {}", startup.describe()))
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        self.line(format_args!("check {check}: {flips} flips"))
+    }
+
+    fn coverage(&mut self, coverage: &Coverage) -> io::Result<()> {
+        self.line(format_args!(
+            "coverage: {} of {} pages checked, {} covered so far",
+            coverage.checked, coverage.pages, coverage.covered
+        ))
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        let virtual_address = flip
+            .virtual_address
+            .map_or(String::new(), |address| format!(", address {address:#x}"));
+        let address = flip.physical_address.map_or(String::new(), |address| {
+            format!(", physical address {address:#x}")
+        });
+        let dimm = flip
+            .dimm
+            .as_ref()
+            .map_or(String::new(), |dimm| format!(" in {dimm}"));
+        let mirror = flip.mirror.map_or(String::new(), |mirror| {
+            let majority = mirror.majority.map_or(String::new(), |majority| {
+                format!(", majority {majority:#04X}")
+            });
+            format!(
+                ", copy {} of {} with {} agreeing{majority}",
+                mirror.copy + 1,
+                mirror.copies,
+                mirror.agreeing
+            )
+        });
+        let transient = match flip.persistence {
+            Persistence::Persistent => "",
+            Persistence::Transient => ", transient",
+        };
+        let mce = if flip.correlated_mce {
+            ", correlated MCE"
+        } else {
+            ""
+        };
+        let cluster: String = flip
+            .cluster
+            .iter()
+            .map(|bit| format!(", bit {} of byte {} {}", bit.bit, bit.offset, bit.direction))
+            .collect();
+        self.line(format_args!(
+            "flip at offset {}: expected {:#04X}, found {:#04X}, {}{virtual_address}{address}{dimm}{mirror}{transient}{mce}{cluster}",
+            flip.offset, flip.expected, flip.found, flip.class
+        ))
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        self.line(format_args!("...and {omitted} more flips this check"))
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        let status = match error.status {
+            WordStatus::Corrected => "corrected",
+            _ => "uncorrectable",
+        };
+        self.line(format_args!("word {}: {status}", error.word))
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.line(format_args!(
+            "page reclaimed at offset {}: {} of its {} bytes lost the pattern",
+            page.offset,
+            page.flips.len(),
+            page.len
+        ))
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.line(format_args!(
+            "block changed since the snapshot at offset {}: {} bytes",
+            block.offset, block.len
+        ))
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        let address = stuck.physical_address.map_or(String::new(), |address| {
+            format!(", physical address {address:#x}")
+        });
+        let dimm = stuck
+            .dimm
+            .as_ref()
+            .map_or(String::new(), |dimm| format!(" in {dimm}"));
+        let excluded = if stuck.excluded { ", excluded" } else { "" };
+        self.line(format_args!(
+            "stuck bit suspected at offset {}: {} flips{address}{dimm}{excluded}",
+            stuck.offset, stuck.flips
+        ))
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        self.line(format_args!(
+            "canary page {} written to: {} bytes changed, first at index {}: expected {:#010b}, found {:#010b}",
+            violation.canary, violation.changed_bytes, violation.first, violation.expected, violation.found
+        ))
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        self.line(format_args!(
+            "{}",
+            if paused { "paused" } else { "resumed" }
+        ))
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        self.line(format_args!(
+            "heartbeat after {:.3}s: {} checks, {} flips, {:.6} GB-hours",
+            summary.runtime().as_secs_f64(),
+            summary.checks,
+            summary.flips,
+            summary.gb_hours()
+        ))
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        let rate = match stage.flips_per_gb_hour() {
+            Some(rate) => format!("{rate:.4}"),
+            None => "n/a".to_owned(),
+        };
+        self.line(format_args!(
+            "stage {} of {} with {} bytes after {:.3}s: {} checks, {} flips, {rate} flips per GB-hour",
+            stage.number,
+            stage.stages,
+            stage.monitored_bytes,
+            stage.monitored_time.as_secs_f64(),
+            stage.checks,
+            stage.flips
+        ))?;
+        match stage.next_bytes {
+            Some(bytes) => self.line(format_args!(
+                "stage {}: monitoring {bytes} bytes",
+                stage.number + 1
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        let rate = match summary.flips_per_gb_hour() {
+            Some(rate) => format!("{rate:.4}"),
+            None => "n/a".to_owned(),
+        };
+        let nodes: String = summary
+            .flips_per_node
+            .iter()
+            .map(|(node, flips)| format!(", {flips} on NUMA node {node}"))
+            .collect();
+        let repeated: String = summary
+            .flip_counts
+            .repeated(REPEATED_FLIPS_LISTED)
+            .iter()
+            .map(|(offset, flips)| format!(", offset {offset} flipped {flips} times"))
+            .collect();
+        self.line(format_args!(
+            "shutdown after {:.3}s: {} checks, {} flips ({} single-bit, {} double-bit, {} multi-bit, {} transient{nodes}), {:.6} GB-hours, {rate} flips per GB-hour{repeated}",
+            summary.runtime().as_secs_f64(),
+            summary.checks,
+            summary.flips,
+            summary.single_bit_flips,
+            summary.double_bit_flips,
+            summary.multi_bit_flips,
+            summary.transient_flips,
+            summary.gb_hours()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_log_rotation() {
+        let dir = TempDir::new("logfile");
+        let path = dir.join("detector.log");
+
+        let mut output = LogOutput::new(LogFile::open(&path, Some(90)).unwrap());
+        for check in 1..=9 {
+            output.check(check, 0).unwrap();
+        }
+
+        // Every line is 48 bytes long, so every other line starts a new file.
+        for segment in 1..=4 {
+            let text = fs::read_to_string(dir.join(format!("detector.log.{segment}"))).unwrap();
+            assert_eq!(text.lines().count(), 2);
+            assert!(text.ends_with(&format!(" check {}: 0 flips\n", 2 * segment)));
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.ends_with(" check 9: 0 flips\n"));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn check_log_compression() {
+        use std::io::Read;
+
+        let dir = TempDir::new("zstd");
+        let path = dir.join("detector.log");
+        // An existing plain log is moved out of the way.
+        fs::write(&path, "earlier run\n").unwrap();
+
+        let mut output = LogOutput::new(LogFile::open_compressed(&path, Some(200)).unwrap());
+        for check in 1..=20 {
+            output.check(check, 0).unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(dir.join("detector.log.1")).unwrap(),
+            "earlier run\n"
+        );
+
+        // The frame of the current segment has not been ended, but everything flushed can be read all the same.
+        let mut partial = Vec::new();
+        let mut decoder = zstd::Decoder::new(File::open(&path).unwrap()).unwrap();
+        let _ = decoder.read_to_end(&mut partial);
+        assert!(String::from_utf8(partial)
+            .unwrap()
+            .ends_with(" check 20: 0 flips\n"));
+        drop(output);
+
+        // The rotated segments are complete streams that hold all lines in order.
+        let mut lines = Vec::new();
+        for segment in 2.. {
+            let Ok(file) = File::open(dir.join(format!("detector.log.{segment}"))) else {
+                break;
+            };
+            let text = String::from_utf8(zstd::decode_all(file).unwrap()).unwrap();
+            lines.extend(text.lines().map(str::to_owned));
+        }
+        assert!(!lines.is_empty() && lines.len() < 20);
+        let text =
+            String::from_utf8(zstd::decode_all(File::open(&path).unwrap()).unwrap()).unwrap();
+        lines.extend(text.lines().map(str::to_owned));
+        assert_eq!(lines.len(), 20);
+        for (check, line) in (1..).zip(&lines) {
+            assert!(line.ends_with(&format!(" check {check}: 0 flips")));
+        }
+    }
+}
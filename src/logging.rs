@@ -0,0 +1,23 @@
+//! Printing warnings and diagnostics to stderr through the `log` crate, at the level chosen with `--log-level` or `-v`.
+
+use std::io::Write;
+
+use log::{Level, LevelFilter};
+
+/// Prints the messages of this program of the given level and the more severe ones to stderr,
+/// warnings prefixed with "Warning:" and errors with "Error:".
+/// Dependencies are left at warnings, so that their debug output does not drown out that of the detector.
+pub fn init(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module(env!("CARGO_CRATE_NAME"), level)
+        .format(|buf, record| {
+            let prefix = match record.level() {
+                Level::Error => "Error: ",
+                Level::Warn => "Warning: ",
+                Level::Info | Level::Debug | Level::Trace => "",
+            };
+            writeln!(buf, "{prefix}{}", record.args())
+        })
+        .init();
+}
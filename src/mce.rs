@@ -0,0 +1,119 @@
+//! Correlating flips with the memory errors that the hardware reported itself, which makes a flip that the memory
+//! controller saw as well easier to trust. The EDAC drivers of Linux count the corrected and uncorrected errors
+//! of every memory controller in `/sys/devices/system/edac/mc/`, which unlike `/dev/mcelog` or the MCE tracepoints
+//! can be read without any privileges. Without EDAC, or on other systems, there is nothing to correlate.
+
+use std::io;
+#[cfg(target_os = "linux")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The error counters of the memory controllers, as of the last time they were read.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct MceCounters {
+    #[cfg(target_os = "linux")]
+    dir: PathBuf,
+    /// The corrected and uncorrected errors of all the memory controllers together.
+    errors: u64,
+}
+
+impl MceCounters {
+    /// Reads the error counters of the memory controllers for the first time.
+    /// Fails if there are no memory controllers registered with EDAC or their counters can not be read.
+    pub fn open() -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        return Self::open_in(Path::new("/sys/devices/system/edac/mc"));
+        #[cfg(not(target_os = "linux"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory error counters are only read on Linux",
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_in(dir: &Path) -> io::Result<Self> {
+        Ok(MceCounters {
+            errors: read_errors(dir)?,
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Reads the counters again and returns whether they went up since they were read last.
+    /// Counters that can not be read anymore, or that were reset, count as no new errors.
+    pub fn poll(&mut self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Ok(errors) = read_errors(&self.dir) {
+            let increased = errors > self.errors;
+            self.errors = errors;
+            return increased;
+        }
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Adds up the `ce_count` and `ue_count` of every memory controller in the directory.
+fn read_errors(dir: &Path) -> io::Result<u64> {
+    let mut errors = 0;
+    let mut controllers = 0;
+    for controller in fs::read_dir(dir)? {
+        let controller = controller?;
+        if !controller.file_name().to_string_lossy().starts_with("mc") {
+            continue;
+        }
+        for counter in ["ce_count", "ue_count"] {
+            let count = fs::read_to_string(controller.path().join(counter))?;
+            errors += count
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        controllers += 1;
+    }
+    if controllers == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no memory controllers are registered with EDAC",
+        ));
+    }
+    Ok(errors)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_error_counters() {
+        let dir = TempDir::new("mce");
+        assert_eq!(
+            MceCounters::open_in(dir.path()).err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+        let write = |controller: &str, ce: u64, ue: u64| {
+            fs::create_dir_all(dir.join(controller)).unwrap();
+            fs::write(dir.join(controller).join("ce_count"), format!("{ce}\n")).unwrap();
+            fs::write(dir.join(controller).join("ue_count"), format!("{ue}\n")).unwrap();
+        };
+        write("mc0", 3, 0);
+        write("mc1", 0, 1);
+        fs::create_dir_all(dir.join("power")).unwrap();
+
+        let mut counters = MceCounters::open_in(dir.path()).unwrap();
+        assert_eq!(counters.errors, 4);
+        assert!(!counters.poll());
+        write("mc1", 1, 1);
+        assert!(counters.poll());
+        assert!(!counters.poll());
+        // Resetting the counters is not an error.
+        write("mc0", 0, 0);
+        assert!(!counters.poll());
+
+        // Counters that vanish are not correlated with anything.
+        fs::remove_dir_all(dir.path()).unwrap();
+        assert!(!counters.poll());
+    }
+}
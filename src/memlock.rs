@@ -0,0 +1,93 @@
+//! Locking memory into RAM, so that the operating system can not swap it out.
+//! A detector that has been swapped to disk and back measures the disk rather than the DRAM.
+
+use std::io;
+
+#[cfg(unix)]
+/// Locks the memory of the buffer into RAM.
+pub fn lock(buf: &[u8]) -> io::Result<()> {
+    if unsafe { libc::mlock(buf.as_ptr().cast(), buf.len()) } == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // rlim_t is signed on some platforms.
+    #[allow(clippy::unnecessary_cast)]
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } == 0
+        && limit.rlim_cur != libc::RLIM_INFINITY
+        && buf.len() as u64 > limit.rlim_cur as u64
+    {
+        Err(io::Error::new(
+            err.kind(),
+            format!(
+                "could not lock {} bytes of memory since RLIMIT_MEMLOCK only allows {} bytes ({err}), try raising the limit with e.g. `ulimit -l`",
+                buf.len(),
+                limit.rlim_cur,
+            ),
+        ))
+    } else {
+        Err(io::Error::new(
+            err.kind(),
+            format!("could not lock {} bytes of memory: {err}", buf.len()),
+        ))
+    }
+}
+
+#[cfg(unix)]
+/// Unlocks memory that was previously locked with [`lock`].
+pub fn unlock(buf: &[u8]) -> io::Result<()> {
+    if unsafe { libc::munlock(buf.as_ptr().cast(), buf.len()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+/// Locks the memory of the buffer into RAM.
+pub fn lock(buf: &[u8]) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualLock;
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetProcessWorkingSetSize};
+
+    // A process can only lock as much memory as fits in its minimum working set,
+    // which is very small by default, so we first grow it to make room for the buffer.
+    // Some headroom is added for the rest of the process.
+    const HEADROOM: usize = 64 * 1024 * 1024;
+    let working_set = buf.len().saturating_add(HEADROOM);
+    if unsafe { SetProcessWorkingSetSize(GetCurrentProcess(), working_set, working_set) } == 0 {
+        let err = io::Error::last_os_error();
+        return Err(io::Error::new(
+            err.kind(),
+            format!(
+                "could not grow the working set to fit {} bytes of locked memory: {err}",
+                buf.len()
+            ),
+        ));
+    }
+
+    if unsafe { VirtualLock(buf.as_ptr().cast(), buf.len()) } == 0 {
+        let err = io::Error::last_os_error();
+        Err(io::Error::new(
+            err.kind(),
+            format!("could not lock {} bytes of memory: {err}", buf.len()),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+/// Unlocks memory that was previously locked with [`lock`].
+pub fn unlock(buf: &[u8]) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualUnlock;
+
+    if unsafe { VirtualUnlock(buf.as_ptr().cast(), buf.len()) } == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
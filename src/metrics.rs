@@ -0,0 +1,353 @@
+#[cfg(feature = "metrics")]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "metrics")]
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "metrics")]
+use std::thread;
+use std::time::Instant;
+#[cfg(feature = "metrics")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "metrics")]
+use crate::detector::FlipEvent;
+
+/// How long we wait for a scraper to send its request before giving up on the connection.
+#[cfg(feature = "metrics")]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The flip counter, which is the one metric that carries an exemplar.
+#[cfg(feature = "metrics")]
+const FLIPS: &str = "cosmic_ray_flips_total";
+
+/// Whether a metric only ever grows, or can go both ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// The current value of a metric, with what both the Prometheus exporter and the StatsD pusher need to know about it.
+pub struct Sample {
+    pub name: &'static str,
+    pub kind: Kind,
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub help: &'static str,
+    pub value: f64,
+}
+
+/// The latest flip, which the flip counter points to in the OpenMetrics format, see `--metrics-exemplars`.
+/// Only one is kept, so that the flips do not turn into a series per offset.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, PartialEq)]
+struct Exemplar {
+    offset: usize,
+    /// "set" if the bits of the byte flipped from 0 to 1, "cleared" if from 1 to 0, "mixed" if both.
+    direction: &'static str,
+    physical_address: Option<u64>,
+    /// When the flip was found, in seconds since the Unix epoch.
+    timestamp: f64,
+}
+
+/// Counters that are updated by the detection loop and served to Prometheus or pushed to StatsD.
+pub struct Metrics {
+    /// The tag of this instance of the detector, see `--tag`.
+    tag: Option<String>,
+    start: Instant,
+    monitored_bytes: AtomicU64,
+    checks: AtomicU64,
+    flips: AtomicU64,
+    /// Whether scrapers that ask for OpenMetrics get an exemplar with the flip counter.
+    #[cfg(feature = "metrics")]
+    exemplars: bool,
+    #[cfg(feature = "metrics")]
+    exemplar: Mutex<Option<Exemplar>>,
+}
+
+impl Metrics {
+    pub fn new(monitored_bytes: usize) -> Self {
+        Metrics {
+            tag: None,
+            start: Instant::now(),
+            monitored_bytes: AtomicU64::new(monitored_bytes as u64),
+            checks: AtomicU64::new(0),
+            flips: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            exemplars: false,
+            #[cfg(feature = "metrics")]
+            exemplar: Mutex::new(None),
+        }
+    }
+
+    /// Serves the metrics in the OpenMetrics format to scrapers that ask for it, with the latest flip
+    /// as the exemplar of the flip counter, see `--metrics-exemplars`.
+    #[cfg(feature = "metrics")]
+    pub fn with_exemplars(mut self, exemplars: bool) -> Self {
+        self.exemplars = exemplars;
+        self
+    }
+
+    /// Labels the metrics with the given tag, see `--tag`.
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    /// Returns the tag the metrics are labelled with.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Updates the size of the detector memory, when a staircase test moves on to its next stage.
+    pub fn set_monitored_bytes(&self, monitored_bytes: usize) {
+        self.monitored_bytes
+            .store(monitored_bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records the result of an integrity check.
+    pub fn record_check(&self, flips: usize) {
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        self.flips.fetch_add(flips as u64, Ordering::Relaxed);
+    }
+
+    /// Keeps the flip as the exemplar of the flip counter, in place of the one before, if exemplars are served.
+    #[cfg(feature = "metrics")]
+    pub fn record_exemplar(&self, flip: &FlipEvent) {
+        if !self.exemplars {
+            return;
+        }
+        let direction = match (flip.set_bits() != 0, flip.cleared_bits() != 0) {
+            (true, false) => "set",
+            (false, true) => "cleared",
+            _ => "mixed",
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        *self.exemplar.lock().expect("the metrics server panicked") = Some(Exemplar {
+            offset: flip.offset,
+            direction,
+            physical_address: flip.physical_address,
+            timestamp,
+        });
+    }
+
+    /// Returns the current values of all metrics.
+    pub fn samples(&self) -> [Sample; 4] {
+        [
+            Sample {
+                name: "cosmic_ray_flips_total",
+                kind: Kind::Counter,
+                help: "Number of flipped bytes detected.",
+                value: self.flips.load(Ordering::Relaxed) as f64,
+            },
+            Sample {
+                name: "cosmic_ray_checks_total",
+                kind: Kind::Counter,
+                help: "Number of integrity checks performed.",
+                value: self.checks.load(Ordering::Relaxed) as f64,
+            },
+            Sample {
+                name: "cosmic_ray_monitored_bytes",
+                kind: Kind::Gauge,
+                help: "Size of the detector memory in bytes.",
+                value: self.monitored_bytes.load(Ordering::Relaxed) as f64,
+            },
+            Sample {
+                name: "cosmic_ray_uptime_seconds",
+                kind: Kind::Gauge,
+                help: "Time since the detector started.",
+                value: self.start.elapsed().as_secs_f64(),
+            },
+        ]
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format, or in the OpenMetrics format
+    /// with the exemplar of the flip counter.
+    #[cfg(feature = "metrics")]
+    fn render(&self, openmetrics: bool) -> String {
+        let mut text = String::new();
+        // Label values are quoted, with backslashes, quotes and newlines escaped.
+        let labels = self.tag().map_or(String::new(), |tag| {
+            format!(
+                "{{tag=\"{}\"}}",
+                tag.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        });
+        let exemplar = self
+            .exemplar
+            .lock()
+            .expect("the detector panicked")
+            .clone()
+            .filter(|_| openmetrics);
+        for sample in self.samples() {
+            let kind = match sample.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+            };
+            // OpenMetrics names a counter without the suffix of its sample.
+            let family = match (openmetrics, sample.kind) {
+                (true, Kind::Counter) => sample.name.trim_end_matches("_total"),
+                _ => sample.name,
+            };
+            text.push_str(&format!(
+                "# HELP {family} {}\n# TYPE {family} {kind}\n{}{labels} {}",
+                sample.help, sample.name, sample.value
+            ));
+            if let Some(exemplar) = exemplar.as_ref().filter(|_| sample.name == FLIPS) {
+                text.push_str(&format!(
+                    " # {{offset=\"{}\",direction=\"{}\"",
+                    exemplar.offset, exemplar.direction
+                ));
+                if let Some(address) = exemplar.physical_address {
+                    text.push_str(&format!(",physical_address=\"{address:#x}\""));
+                }
+                text.push_str(&format!("}} 1 {:.3}", exemplar.timestamp));
+            }
+            text.push('\n');
+        }
+        if openmetrics {
+            text.push_str("# EOF\n");
+        }
+        text
+    }
+}
+
+#[cfg(feature = "metrics")]
+/// Starts serving the metrics over HTTP at `/metrics` on a background thread.
+/// The address is bound before this function returns, so that a bad address is reported immediately.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A broken connection is the scraper's problem, not ours.
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "metrics")]
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Only the Accept header matters, for whether the scraper understands exemplars.
+    let mut header = String::new();
+    let mut openmetrics = false;
+    while reader.read_line(&mut header)? > 2 {
+        let lowercase = header.to_ascii_lowercase();
+        openmetrics |=
+            lowercase.starts_with("accept:") && lowercase.contains("application/openmetrics-text");
+        header.clear();
+    }
+    let openmetrics = openmetrics && metrics.exemplars;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render(openmetrics)),
+        _ => ("404 Not Found", "not found, try /metrics\n".to_owned()),
+    };
+    let content_type = if openmetrics {
+        "application/openmetrics-text; version=1.0.0; charset=utf-8"
+    } else {
+        "text/plain; version=0.0.4"
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_metrics_endpoint() {
+        use std::io::Read;
+
+        let metrics = Arc::new(Metrics::new(1000));
+        metrics.record_check(0);
+        metrics.record_check(2);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        serve(addr, Arc::clone(&metrics)).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\ncosmic_ray_flips_total 2\n"));
+        assert!(response.contains("\ncosmic_ray_checks_total 2\n"));
+        assert!(response.contains("\ncosmic_ray_monitored_bytes 1000\n"));
+        assert!(response.contains("\ncosmic_ray_uptime_seconds "));
+
+        let metrics = Metrics::new(1000).with_tag(Some("rack \"3\"".to_owned()));
+        assert!(metrics
+            .render(false)
+            .contains("\ncosmic_ray_monitored_bytes{tag=\"rack \\\"3\\\"\"} 1000\n"));
+    }
+
+    #[test]
+    fn check_exemplars() {
+        use std::io::Read;
+
+        let metrics = Arc::new(Metrics::new(1000).with_exemplars(true));
+        metrics.record_check(2);
+        metrics.record_exemplar(&FlipEvent::new(3, 0x00, 0x01));
+        let mut flip = FlipEvent::new(7, 0xFF, 0xFB);
+        flip.physical_address = Some(0x1234);
+        metrics.record_exemplar(&flip);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        serve(addr, Arc::clone(&metrics)).unwrap();
+        let scrape = |accept: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET /metrics HTTP/1.1\r\n{accept}\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        // Only the latest flip is attached to the counter, and only for scrapers that understand OpenMetrics.
+        let response =
+            scrape("Accept: application/openmetrics-text;version=1.0.0,text/plain;q=0.5\r\n");
+        assert!(response.contains("Content-Type: application/openmetrics-text; version=1.0.0"));
+        assert!(response.contains("\n# TYPE cosmic_ray_flips counter\n"));
+        assert!(response.contains(
+            "\ncosmic_ray_flips_total 2 # {offset=\"7\",direction=\"cleared\",physical_address=\"0x1234\"} 1 "
+        ));
+        assert_eq!(response.matches(" # {").count(), 1);
+        assert!(response.ends_with("\n# EOF\n"));
+
+        let response = scrape("");
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("\ncosmic_ray_flips_total 2\n"));
+        assert!(!response.contains("# EOF"));
+
+        // Without --metrics-exemplars no exemplar is kept at all.
+        let metrics = Metrics::new(1000);
+        metrics.record_exemplar(&flip);
+        assert!(metrics.exemplar.lock().unwrap().is_none());
+    }
+}
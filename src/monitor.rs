@@ -0,0 +1,1577 @@
+//! Monitoring memory the way the command line asks for, which is all that the command line program does,
+//! and with the `tokio` feature monitoring a detector from an async task, for programs built on tokio.
+//! The task waits between the integrity checks without blocking a thread, and the checks themselves, which read
+//! all of the detector memory, run on the blocking thread pool so that they don't stall the other tasks of the runtime.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, stdout, IsTerminal};
+use std::num::NonZeroUsize;
+#[cfg(feature = "tokio")]
+use std::panic;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use humantime::format_duration;
+use log::{info, trace, warn, LevelFilter};
+use sysinfo::{RefreshKind, System, SystemExt};
+#[cfg(feature = "tokio")]
+use tokio::sync::mpsc::Sender;
+#[cfg(feature = "tokio")]
+use tokio::task;
+#[cfg(feature = "tokio")]
+use tokio_util::sync::CancellationToken;
+
+use crate::affinity::{self, CpuSet};
+use crate::backing::{BackingFile, Header};
+use crate::benchmark::Benchmark;
+use crate::canary::{check_canaries, Canary};
+use crate::cgroup;
+use crate::checksum;
+use crate::config::{AllocationMode, Cli, Command, MemorySpec, OutputFormat};
+use crate::coverage::PageSelector;
+use crate::csv::CsvOutput;
+use crate::daemon::{self, PidFile};
+use crate::detector::{
+    check_fits_in_memory, maximum_size_in_mode, ChangedBlock, Detector, FlipEvent, ReclaimedPage,
+    WordError,
+};
+use crate::dmi;
+use crate::duty_cycle::Bursts;
+use crate::ecc::{detect_ecc, EccStatus};
+#[cfg(feature = "schema")]
+use crate::events;
+use crate::info::SystemInfo;
+use crate::jitter::Jitter;
+use crate::logfile::{LogFile, LogOutput};
+use crate::logging;
+use crate::mce::MceCounters;
+#[cfg(any(feature = "metrics", feature = "statsd"))]
+use crate::metrics;
+#[cfg(feature = "notify")]
+use crate::notify;
+#[cfg(feature = "numa")]
+use crate::numa;
+use crate::output::{
+    JsonOutput, MultiOutput, Output, Plan, Settings, Startup, StuckBit, TextOutput,
+};
+use crate::pagemap::Pagemap;
+use crate::pattern::{random_seed, Pattern};
+use crate::privileges::{self, Capability};
+use crate::progress::{self, ProgressBar};
+use crate::reference::Reference;
+use crate::region::{RegionOptions, PAGE_SIZE};
+use crate::report::Report;
+use crate::signal::{pause_flag, poll_time, sleep_unless_stopped, stop_flag};
+use crate::slices::SliceCursor;
+#[cfg(feature = "statsd")]
+use crate::statsd;
+use crate::summary::{Stage, Summary, Verification};
+#[cfg(all(unix, feature = "syslog"))]
+use crate::syslog;
+use crate::throttle::{LoadMonitor, Throttle};
+#[cfg(feature = "tui")]
+use crate::tui;
+use crate::watchdog::Watchdog;
+#[cfg(feature = "webhook")]
+use crate::webhook;
+
+/// About how many bytes of the detector a pass with --pass-timeout checks at a time, between which it can stop.
+const PASS_SLICE_BYTES: usize = 64 << 20;
+
+/// Runs the subcommand of the given command line, or monitors memory for bit flips the way it asks for until
+/// the detector is stopped, which is all that the command line program does. Returns the exit status of the program.
+pub(crate) fn run(conf: &Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let log_level = conf.log_level();
+    logging::init(log_level);
+
+    if let Some(Command::Info) = conf.command {
+        SystemInfo::query().write(stdout(), matches!(conf.format, OutputFormat::Json))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    #[cfg(feature = "schema")]
+    if let Some(Command::Schema) = conf.command {
+        println!("{}", events::schema());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // With --frequency this is the period of the checks, until a timed pass tells how much of it is left to wait.
+    // The bursts of --duty-cycle run the checks back to back.
+    let sleep_duration = match conf.duty_cycle {
+        Some(_) => Duration::ZERO,
+        None => conf.frequency.unwrap_or(conf.delay_between_checks),
+    };
+    let pattern = match conf.pattern {
+        Pattern::Random(_) => Pattern::Random(conf.seed.unwrap_or_else(random_seed)),
+        pattern => {
+            if conf.seed.is_some() && conf.coverage.is_none() {
+                warn!("--seed only has an effect with --pattern random or --coverage");
+            }
+            pattern
+        }
+    };
+    // The random pattern and the pages of sparse checks share the seed, so that there is only one to reproduce a run with.
+    let coverage_seed = conf.seed.or(pattern.seed()).unwrap_or_else(random_seed);
+
+    if !conf.allow_ecc && detect_ecc() == EccStatus::Present {
+        warn!("this machine appears to have ECC memory. ECC memory corrects bit flips before this program can see them, so not detecting any flips does not mean that none happened. Pass --allow-ecc to silence this warning.");
+    }
+
+    let available_parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = match conf.threads {
+        Some(threads) => {
+            if threads.get() > available_parallelism {
+                warn!("using {threads} threads, but only {available_parallelism} can run in parallel on this machine");
+            }
+            threads.get()
+        }
+        None if conf.parallel => available_parallelism,
+        None => 1,
+    };
+    // The cores are looked up before any thread is pinned to one of them, which would leave only that one.
+    let cpus = match &conf.cpu_set {
+        Some(CpuSet(cpus)) => Some(cpus.clone()),
+        None if conf.pin_threads => affinity::allowed_cpus()
+            .map_err(|e| warn!("could not find the CPUs to pin the detector threads to, leaving them to the scheduler: {e}"))
+            .ok(),
+        None => None,
+    };
+
+    // A percentage of the total memory can only be turned into a number of bytes once we know how much there is.
+    let total_memory = if conf
+        .memory_to_monitor
+        .iter()
+        .any(|spec| matches!(spec, MemorySpec::Percent(_)))
+    {
+        if !<System as SystemExt>::IS_SUPPORTED {
+            return Err(format!("{} is not supported by the mechanism this program uses to determine the total memory, please specify the detector size in bytes", std::env::consts::OS).into());
+        }
+        let total = System::new_with_specifics(RefreshKind::new().with_memory()).total_memory();
+        // Inside a container all of its memory is what its cgroup may use.
+        cgroup::memory().map_or(total, |memory| total.min(memory.limit))
+    } else {
+        0
+    };
+    let memory_to_monitor = conf
+        .memory_to_monitor
+        .iter()
+        .map(|spec| spec.resolve(total_memory))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut reference =
+        match &conf.reference_file {
+            Some(path) => Some(Reference::open(path).map_err(|e| {
+                format!("could not open the reference file {}: {e}", path.display())
+            })?),
+            None => None,
+        };
+
+    // Every size of a staircase test is a stage of its own, any other run has a single one.
+    let stage_sizes: Vec<usize> = if let Some(reference) = &reference {
+        vec![reference.size()]
+    } else if memory_to_monitor.is_empty() {
+        // Leave the reserve to the rest of the system from the start, rather than only finding out while allocating.
+        vec![maximum_size_in_mode(conf.use_all.expect("this only happens if neither -m nor --reference-file was specified, and one of them or --use-all must be specified at the CLI level"))
+            .saturating_sub(conf.reserve.map_or(0, NonZeroUsize::get))]
+    } else {
+        memory_to_monitor.iter().map(|s| s.get()).collect()
+    };
+    let stages = stage_sizes.len();
+    if !conf.force {
+        // The largest stage is the one that has to fit.
+        let largest = memory_to_monitor
+            .iter()
+            .map(|size| size.get())
+            .chain(reference.as_ref().map(Reference::size))
+            .max();
+        if let Some(largest) = largest {
+            check_fits_in_memory(largest).map_err(|e| {
+                format!("{e}. Use --use-all available to monitor as much memory as possible, or pass --force to allocate it anyway")
+            })?;
+        }
+    }
+    // A single check is the same as a run of one check.
+    let max_checks = conf.max_checks.or(conf.once.then_some(1));
+    if stages > 1 && conf.benchmark.is_none() && conf.duration.is_none() && max_checks.is_none() {
+        return Err("a staircase test with several detector sizes needs --duration or --max-checks to know when to move on to the next size".into());
+    }
+    if stages > 1 && conf.until_clean.is_some() {
+        return Err(
+            "--until-clean verifies a detector of a single size, so it can not be used for a staircase test"
+                .into(),
+        );
+    }
+    if stages > 1 && conf.backing_file.is_some() {
+        return Err(
+            "a backing file holds a detector of a single size, so it can not be used for a staircase test"
+                .into(),
+        );
+    }
+
+    if conf.dry_run {
+        Plan {
+            bytes: stage_sizes[0],
+            later_bytes: stage_sizes[1..].to_vec(),
+            pattern,
+            threads,
+            delay: sleep_duration,
+            frequency: conf.frequency.is_some(),
+        }
+        .write(stdout(), matches!(conf.format, OutputFormat::Json))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let region_options = RegionOptions {
+        huge_page_size: conf.huge_pages,
+        reserve: conf.reserve.map(NonZeroUsize::get),
+        #[cfg(feature = "numa")]
+        numa: conf.numa_node,
+        // The size of all free or available memory is only an estimate of what can be committed.
+        probe: conf.use_all.is_some(),
+        align_pages: conf.align_pages,
+        ..Default::default()
+    };
+
+    #[cfg(feature = "numa")]
+    if let Some(numa::NumaNodes::Node(node)) = conf.numa_node {
+        // The detector threads are spawned from this one, so they end up on the node too.
+        if let Err(e) = numa::bind_thread(node) {
+            warn!("could not run the detector on the CPUs of NUMA node {node}: {e}");
+        }
+    }
+
+    if let Some(passes) = conf.benchmark {
+        let json = matches!(conf.format, OutputFormat::Json);
+        for &capacity_bytes in &stage_sizes {
+            let mut detector = Detector::new(threads, pattern, capacity_bytes, region_options);
+            if detector.capacity() == 0 {
+                return Err("could not allocate any memory for the detector".into());
+            }
+            if conf.checksum {
+                detector.use_checksums();
+            }
+            if conf.ecc_emulate {
+                detector.use_ecc_emulation();
+            }
+            if conf.anti_dedup {
+                detector.use_page_markers();
+            }
+            detector.use_scan_order(conf.scan_order);
+            detector.use_word_size(conf.word_size);
+            if let Some(cpus) = &cpus {
+                pin_threads(&mut detector, cpus);
+            }
+            Benchmark::run(&detector, threads, passes as usize).write(stdout(), json)?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Capabilities belong to threads, so the ones the setup does not need are dropped before there are any others.
+    if conf.drop_privs {
+        let setup = [
+            (conf.lock_memory, Capability::IpcLock),
+            (conf.physical_addresses, Capability::SysAdmin),
+            (conf.dimm_slots, Capability::DacReadSearch),
+        ]
+        .into_iter()
+        .filter_map(|(needed, cap)| needed.then_some(cap))
+        .collect::<Vec<_>>();
+        privileges::restrict(&setup).map_err(|e| format!("could not drop privileges: {e}"))?;
+    }
+
+    // Only the forking thread carries on in the daemon, so this has to come before anything starts a thread.
+    if conf.daemonize {
+        if let Some(path) = conf
+            .stderr_file
+            .as_ref()
+            .filter(|&path| conf.log_file.iter().any(|log_file| &log_file.path == path))
+        {
+            return Err(format!(
+                "the warnings of the daemon can not go to the log file {} as well, give --stderr-file a file of its own",
+                path.display()
+            )
+            .into());
+        }
+        daemon::daemonize(conf.stderr_file.as_deref())
+            .map_err(|e| format!("could not daemonize: {e}"))?;
+    }
+    let pid_file = match &conf.pid_file {
+        Some(path) => Some(
+            PidFile::create(path)
+                .map_err(|e| format!("could not write the PID file {}: {e}", path.display()))?,
+        ),
+        None => None,
+    };
+    let stop = stop_flag()?;
+
+    // Tell the events of several instances apart by host, unless they are given names of their own.
+    let tag = conf
+        .tag
+        .clone()
+        .or_else(|| System::new().host_name().filter(|name| !name.is_empty()));
+    let terminal = stdout().is_terminal();
+    // The progress bar of the passes before the first check has no place between JSON events, and is left out
+    // when only errors are printed or when the text output already tells about the passes itself.
+    let show_progress = terminal
+        && conf.format == OutputFormat::Text
+        && (LevelFilter::Warn..=LevelFilter::Info).contains(&log_level);
+    #[cfg(feature = "tui")]
+    let show_progress = show_progress && !conf.tui;
+    let setup = Setup {
+        conf,
+        pattern,
+        threads,
+        cpus,
+        region_options,
+        coverage_seed,
+        show_progress,
+    };
+    let mut output = open_outputs(conf, log_level, terminal, &tag, &stop)?;
+
+    // With rolling checks the delay is spread over the slices, so that every one is checked once per delay.
+    let slices = conf.rolling.map_or(1, NonZeroUsize::get);
+    let mut check_interval = sleep_duration / slices as u32;
+    let mut jitter = conf
+        .jitter
+        .map(|percent| Jitter::new(percent, random_seed()));
+    let mut throttle = conf.throttle.map(Throttle::new);
+    let mut load_monitor = LoadMonitor::default();
+    let mut physical_addresses = conf.physical_addresses;
+    let dimms = if conf.dimm_slots {
+        match dmi::Inventory::read() {
+            Ok(inventory) => {
+                for dimm in inventory.dimms() {
+                    match dimm.size {
+                        Some(size) => info!("Found the memory module {dimm} with {size} bytes"),
+                        None => info!("Found the memory module {dimm}"),
+                    }
+                }
+                Some(inventory)
+            }
+            Err(e) => {
+                warn!("could not read the inventory of memory modules, flips are reported by their physical address only: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let pause = pause_flag()?;
+    let mut paused = false;
+    let mut summary: Option<Summary> = None;
+    let mut next_heartbeat = conf.heartbeat.map(|interval| Instant::now() + interval);
+
+    // The same metrics are served to Prometheus and pushed to StatsD.
+    #[cfg(any(feature = "metrics", feature = "statsd"))]
+    let metrics = metrics::Metrics::new(stage_sizes[0]).with_tag(tag.clone());
+    #[cfg(feature = "metrics")]
+    let metrics = metrics.with_exemplars(conf.metrics_exemplars);
+    #[cfg(any(feature = "metrics", feature = "statsd"))]
+    let metrics = Arc::new(metrics);
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = conf.metrics_addr {
+        metrics::serve(addr, Arc::clone(&metrics))?;
+    }
+    #[cfg(feature = "statsd")]
+    if let Some(addr) = &conf.statsd_addr {
+        statsd::push(
+            addr,
+            conf.heartbeat.unwrap_or(statsd::DEFAULT_INTERVAL),
+            Arc::clone(&metrics),
+        )
+        .map_err(|e| format!("could not send metrics to the StatsD server {addr}: {e}"))?;
+    }
+
+    let mut watchdog = conf.watchdog.then(|| Watchdog::start(conf.watchdog_abort));
+    if let Some(watchdog) = &watchdog {
+        if let Some(path) = pid_file.as_ref().map(|pid_file| pid_file.path().to_owned()) {
+            watchdog.on_abort(move || {
+                let _ = std::fs::remove_file(path);
+            });
+        }
+        #[cfg(feature = "tui")]
+        if conf.tui {
+            watchdog.on_abort(tui::restore_terminal);
+        }
+    }
+
+    // Opened once for all stages, since the kernel only reveals physical addresses to whoever opened the file
+    // with CAP_SYS_ADMIN, which --drop-privs does not keep.
+    let mut pagemap = None;
+    // A stage that is not the last ends once the detector of the next one is allocated, which it announces.
+    let mut finished_stage: Option<Stage> = None;
+    for (stage, &capacity_bytes) in stage_sizes.iter().enumerate() {
+        let mut notes = Vec::new();
+        if let Some(spec @ (MemorySpec::Percent(_) | MemorySpec::Cgroup)) =
+            conf.memory_to_monitor.get(stage)
+        {
+            notes.push(spec.to_string());
+        }
+        if stages > 1 {
+            notes.push(format!("stage {} of {stages}", stage + 1));
+        }
+        let sized = !memory_to_monitor.is_empty() || conf.reference_file.is_some();
+        let detector = match (sized, notes.is_empty()) {
+            (true, true) => format!("{capacity_bytes} bytes"),
+            (true, false) => format!("{capacity_bytes} bytes ({})", notes.join(", ")),
+            (false, _) => match conf.use_all.expect("--use-all is required without a size") {
+                AllocationMode::Available => "as much memory as possible",
+                AllocationMode::Free => "all unused memory",
+            }
+            .to_owned(),
+        };
+        if stage == 0 {
+            output.allocating(&Settings {
+                detector,
+                delay: sleep_duration,
+                frequency: conf.frequency.is_some(),
+                pattern,
+                reference_file: conf.reference_file.clone(),
+                threads,
+                lock_memory: conf.lock_memory,
+                rolling_slices: conf.rolling.map(NonZeroUsize::get),
+                duty_cycle: conf.duty_cycle,
+            })?;
+        }
+
+        let StageDetector {
+            mut detector,
+            mut backing,
+            mut canaries,
+            resumed,
+            downtime_flips,
+            mut scan,
+        } = allocate_stage(&setup, capacity_bytes, reference.take(), throttle.as_ref())?;
+
+        if let Some(finished) = finished_stage.take() {
+            output.stage(&Stage {
+                next_bytes: Some(detector.capacity()),
+                ..finished
+            })?;
+        } else {
+            output.startup(&Startup {
+                allocated_bytes: detector.capacity(),
+                allocation_mode: conf.use_all,
+                pattern,
+                reference_file: conf.reference_file.clone(),
+                snapshot: conf.snapshot,
+                delay: sleep_duration,
+                threads,
+                lock_memory: conf.lock_memory,
+                huge_pages: detector.uses_huge_pages(),
+                checksum_block_size: detector.uses_checksums().then_some(checksum::BLOCK_SIZE),
+                ecc_data_bytes: detector
+                    .uses_ecc_emulation()
+                    .then(|| detector.data_capacity()),
+                coverage: scan
+                    .selector
+                    .as_ref()
+                    .map(|_| (conf.coverage.unwrap_or(100.0), coverage_seed)),
+                resumed,
+                downtime_flips: downtime_flips.len(),
+            })?;
+        }
+
+        if physical_addresses && pagemap.is_none() {
+            // Fail early rather than on the first flip if the kernel won't tell.
+            match Pagemap::open().and_then(|pagemap| {
+                let first = detector.byte(0).expect("the detector memory is not empty");
+                pagemap.physical_address(first).map(|_| pagemap)
+            }) {
+                Ok(opened) => pagemap = Some(opened),
+                Err(e) => {
+                    warn!("could not look up physical addresses, flips are reported by their index in the detector memory only: {e}");
+                    physical_addresses = false;
+                }
+            }
+        }
+        let mut mce = if conf.correlate_mce {
+            match MceCounters::open() {
+                Ok(counters) => Some(counters),
+                Err(e) => {
+                    warn!("could not read the error counters of the memory controllers, flips are not correlated with machine check exceptions: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Whatever needed privileges has been set up by now, except the memory of the later stages.
+        if conf.drop_privs && stage == 0 {
+            let checks = if conf.lock_memory && stages > 1 {
+                vec![Capability::IpcLock]
+            } else {
+                Vec::new()
+            };
+            privileges::restrict(&checks).map_err(|e| format!("could not drop privileges: {e}"))?;
+            match checks.first() {
+                Some(cap) => info!("Dropped all capabilities except {cap}"),
+                None => info!("Dropped all capabilities"),
+            }
+        }
+
+        // The statistics carry on over the stages, which only change the size of the detector.
+        let summary = match &mut summary {
+            Some(summary) => {
+                summary.begin_stage(detector.capacity());
+                summary
+            }
+            None => {
+                let mut new = Summary::new(detector.capacity());
+                new.verification = conf.until_clean.map(|_| Verification::default());
+                summary.insert(new)
+            }
+        };
+        #[cfg(feature = "numa")]
+        if conf.numa_node.is_some() {
+            for node in detector.numa_nodes() {
+                summary.flips_per_node.entry(node).or_default();
+            }
+        }
+        #[cfg(any(feature = "metrics", feature = "statsd"))]
+        metrics.set_monitored_bytes(detector.capacity());
+        for flip in &downtime_flips {
+            warn!(
+                "flip during downtime at offset {}: {:#010b} instead of {:#010b}",
+                flip.offset, flip.found, flip.expected
+            );
+        }
+        summary.downtime_flips += downtime_flips.len() as u64;
+        // They happened before this run, so the memory is brought back to what the committed parity describes.
+        detector.scrub(&downtime_flips);
+        let deadline = conf.duration.map(|d| Instant::now() + d);
+
+        // Reset detector! Unless it carries on from an earlier run, then whatever changed since is reported by the first check.
+        if !resumed && !conf.snapshot {
+            if stage == 0 {
+                output.resetting()?;
+            }
+            // The reset writes a zero pattern twice.
+            let passes = if pattern.is_zero() && conf.reference_file.is_none() {
+                2
+            } else {
+                1
+            };
+            let bar = progress_bar(
+                show_progress,
+                &detector,
+                "Resetting the detector memory",
+                passes,
+            );
+            detector.reset();
+            drop(bar);
+            if stage == 0 {
+                output.reset_done()?;
+            }
+            if let Some(backing) = &mut backing {
+                backing
+                    .commit()
+                    .map_err(|e| format!("could not write the header of the backing file: {e}"))?;
+            }
+        }
+
+        // A byte that does not hold the pattern right after the fill was never written correctly, rather than flipped later.
+        if !resumed && !conf.snapshot && !conf.skip_self_test {
+            let self_test_start = Instant::now();
+            let bar = progress_bar(show_progress, &detector, "Running the self-test", 1);
+            let mismatches = detector.check();
+            drop(bar);
+            // The self-test reads the whole detector, so it tells the watchdog how long a check takes before there is one.
+            if let Some(watchdog) = &mut watchdog {
+                watchdog.record_check(self_test_start.elapsed());
+            }
+            if let Some(first) = mismatches.first() {
+                return Err(format!(
+                    "the self-test found {} bytes that did not hold the pattern right after the detector was filled, the first at offset {} with {:#010b} instead of {:#010b}. Either this program has a bug or the memory is already failing. Pass --skip-self-test to monitor it anyway",
+                    mismatches.len(),
+                    first.offset,
+                    first.found,
+                    first.expected
+                )
+                .into());
+            }
+        }
+
+        if let Some(period) = conf.frequency {
+            // Checks only read the detector, so a flip that this pass runs into is still reported by the first check.
+            let full_pass = Benchmark::run(&detector, threads, 1).median;
+            let pass = match &scan.selector {
+                Some(_) => full_pass.mul_f64(conf.coverage.unwrap_or(100.0) / 100.0),
+                None => full_pass,
+            };
+            let pass = Duration::from_micros(pass.as_micros() as u64);
+            if pass >= period {
+                warn!(
+                    "a pass over the detector takes {}, which is longer than the period of {} that the frequency asks for, running the checks back to back instead",
+                    format_duration(pass),
+                    format_duration(period)
+                );
+            } else {
+                info!(
+                    "A pass over the detector takes {}, waiting {} between integrity checks",
+                    format_duration(pass),
+                    format_duration(period - pass)
+                );
+            }
+            check_interval = period.saturating_sub(pass) / slices as u32;
+        }
+
+        // The pages of suspected stuck bits are still read, but whatever flips in them is ignored.
+        let mut pass_start = Instant::now();
+        // Whether a check of the current pass found something, which spoils it for --until-clean.
+        let mut pass_dirty = false;
+        let mut last_refill = Instant::now();
+        let mut check_time = Duration::ZERO;
+        let mut bursts = conf
+            .duty_cycle
+            .map(|cycle| Bursts::start(cycle, Instant::now()));
+        loop {
+            // We're not gonna miss any events by being too slow
+            let interval = match &mut jitter {
+                Some(jitter) => {
+                    let interval = jitter.apply(check_interval);
+                    info!(
+                        "Waiting {} before integrity check number {}",
+                        format_duration(Duration::from_micros(interval.as_micros() as u64)),
+                        summary.checks + 1
+                    );
+                    interval
+                }
+                None => check_interval,
+            };
+            let interval = match &throttle {
+                Some(throttle) => throttle.delay(interval, check_time),
+                None => interval,
+            };
+            // Once a burst is over the next check waits for the next one.
+            let idle_until = bursts
+                .as_mut()
+                .and_then(|bursts| bursts.idle_until(Instant::now()));
+            let interval = match idle_until {
+                Some(next) => {
+                    let idle = next.saturating_duration_since(Instant::now());
+                    info!(
+                        "The burst is over, idling for {}",
+                        format_duration(Duration::from_millis(idle.as_millis() as u64))
+                    );
+                    summary.start_idle();
+                    idle
+                }
+                None => interval,
+            };
+            let delay = match deadline {
+                Some(deadline) => interval.min(deadline.saturating_duration_since(Instant::now())),
+                None => interval,
+            };
+            let check_at = Instant::now() + delay;
+            if delay.is_zero() {
+                // Scanning back to back, but let the rest of the system have a go in between.
+                std::thread::yield_now();
+            }
+            // Wake up for every heartbeat that is due before the next check,
+            // and keep waiting past it for as long as checking is paused, but not past the --duration.
+            loop {
+                if pause.load(Ordering::SeqCst) != paused {
+                    paused = !paused;
+                    if paused {
+                        summary.pause();
+                    } else {
+                        summary.resume();
+                    }
+                    output.paused(paused)?;
+                }
+                if let (Some(next), Some(interval)) = (&mut next_heartbeat, conf.heartbeat) {
+                    if Instant::now() >= *next {
+                        output.heartbeat(summary)?;
+                        *next = Instant::now() + interval;
+                    }
+                }
+                let now = Instant::now();
+                if let Some(watchdog) = &watchdog {
+                    watchdog.expect_check_within(check_at.saturating_duration_since(now));
+                }
+                if (now >= check_at && !paused)
+                    || (paused && deadline.is_some_and(|deadline| now >= deadline))
+                    || stop.load(Ordering::SeqCst)
+                {
+                    break;
+                }
+                // Wake up regularly to notice a pause or resume request.
+                sleep_unless_stopped(poll_time(now, check_at, next_heartbeat, paused), &stop);
+            }
+            if idle_until.is_some() {
+                summary.end_idle();
+                if !stop.load(Ordering::SeqCst) {
+                    info!("Starting the next burst");
+                }
+            }
+            // Still being paused means the --duration ran out during the pause, which ends the stage without another check.
+            if stop.load(Ordering::SeqCst) || paused {
+                break;
+            }
+
+            // Check if all the bytes still match the pattern
+            let check_start = Instant::now();
+            let mut found = check_detector(conf, &detector, &mut scan);
+            trace!(
+                "Integrity check number {} took {:?}",
+                summary.checks + 1,
+                check_start.elapsed()
+            );
+            if let Some(watchdog) = &mut watchdog {
+                watchdog.record_check(check_start.elapsed());
+            }
+            check_time = check_start.elapsed();
+            if let Some(current) = &mut throttle {
+                match load_monitor.load() {
+                    Ok(load) => {
+                        if current.update(load, Instant::now()) {
+                            detector.set_active_threads(current.threads(threads));
+                            log_throttle(current, load, threads);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("could not read the load of the machine, checking at full speed from now on: {e}");
+                        detector.set_active_threads(threads);
+                        throttle = None;
+                    }
+                }
+            }
+            locate_flips(
+                &detector,
+                &mut found.flips,
+                conf.virtual_addresses,
+                pagemap.as_ref(),
+                dimms.as_ref(),
+                mce.as_mut(),
+            );
+            #[cfg(any(feature = "metrics", feature = "statsd"))]
+            let excluded_pages = summary.excluded_pages;
+            report_check(
+                conf,
+                &found,
+                &mut scan,
+                detector.capacity(),
+                summary,
+                &mut output,
+            )?;
+            #[cfg(any(feature = "metrics", feature = "statsd"))]
+            if summary.excluded_pages != excluded_pages {
+                metrics.set_monitored_bytes(summary.monitored_bytes);
+            }
+            if scan.sliced && scan.cursor.finished_pass() {
+                summary.record_pass(pass_start.elapsed());
+                pass_start = Instant::now();
+            } else if conf.pass_timeout.is_some() {
+                info!(
+                    "Integrity check number {} ran out of time after {} of the {} slices of the detector, the next one carries on from there",
+                    summary.checks,
+                    scan.cursor.checked(),
+                    scan.cursor.slices()
+                );
+            }
+            #[cfg(feature = "numa")]
+            if conf.numa_node.is_some() {
+                for flip in &found.flips {
+                    if let Some(node) = detector.numa_node(flip.offset) {
+                        summary.record_node_flip(node);
+                    }
+                }
+            }
+            #[cfg(any(feature = "metrics", feature = "statsd"))]
+            metrics.record_check(found.flips.len());
+            #[cfg(feature = "metrics")]
+            if let Some(flip) = found.flips.last() {
+                metrics.record_exemplar(flip);
+            }
+            for violation in check_canaries(&mut canaries) {
+                summary.canary_violations += 1;
+                output.canary(&violation)?;
+            }
+
+            if conf.fail_on_flip && found.any() {
+                break;
+            }
+
+            // Only repair the memory once the flips have been reported.
+            if !conf.no_scrub {
+                scrub_check(&mut detector, &found);
+            }
+
+            if let Some(interval) = conf.refill_interval {
+                if last_refill.elapsed() >= interval {
+                    // Rewriting all of the detector is not a check the watchdog should wait for.
+                    if let Some(watchdog) = &watchdog {
+                        watchdog.disarm();
+                    }
+                    let refill_start = Instant::now();
+                    detector.fill();
+                    summary.record_refill(refill_start.elapsed());
+                    info!(
+                        "Refilled the detector memory with the pattern in {}",
+                        format_duration(Duration::from_millis(
+                            refill_start.elapsed().as_millis() as u64
+                        ))
+                    );
+                    last_refill = Instant::now();
+                }
+            }
+
+            if let (Some(attempts), Some(verification)) =
+                (conf.until_clean, &mut summary.verification)
+            {
+                pass_dirty |= found.any();
+                if !scan.sliced || scan.cursor.finished_pass() {
+                    verification.passes += 1;
+                    verification.clean = !pass_dirty;
+                    pass_dirty = false;
+                    if verification.clean || verification.passes >= attempts {
+                        break;
+                    }
+                }
+            }
+
+            if max_checks.is_some_and(|max| summary.stage_checks() >= max)
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
+        }
+
+        // Allocating and filling the detector of the next stage is not a check.
+        if let Some(watchdog) = &watchdog {
+            watchdog.disarm();
+        }
+        let last = stage + 1 == stages
+            || stop.load(Ordering::SeqCst)
+            || (conf.fail_on_flip && summary.exit_status() != 0);
+        if stages > 1 {
+            let finished = summary.stage(stage + 1, stages);
+            if last {
+                output.stage(&finished)?;
+            } else {
+                finished_stage = Some(finished);
+            }
+        }
+        if last {
+            break;
+        }
+    }
+
+    let summary = summary.expect("the first stage always begins");
+    output.shutdown(&summary)?;
+    if let Some(path) = &conf.report {
+        Report::new(&summary, &conf.arguments, &conf.environment, tag.as_deref())
+            .write(path)
+            .map_err(|e| format!("could not write the report {}: {e}", path.display()))?;
+    }
+
+    Ok(ExitCode::from(summary.exit_status()))
+}
+
+/// What every stage of a run is set up with, worked out from the command line once.
+struct Setup<'a> {
+    conf: &'a Cli,
+    pattern: Pattern,
+    threads: usize,
+    /// The CPUs to pin the detector threads to.
+    cpus: Option<Vec<usize>>,
+    region_options: RegionOptions,
+    coverage_seed: u64,
+    show_progress: bool,
+}
+
+/// The detector of a stage, with what was allocated along with it.
+struct StageDetector {
+    detector: Detector,
+    backing: Option<BackingFile>,
+    canaries: Vec<Canary>,
+    /// Whether the detector carries on from an earlier run in the backing file.
+    resumed: bool,
+    /// The flips that happened while no detector was running, according to the parity of the backing file.
+    downtime_flips: Vec<FlipEvent>,
+    scan: Scan,
+}
+
+/// How the checks of a stage go through the detector.
+struct Scan {
+    cursor: SliceCursor,
+    /// Whether the detector is checked a few slices at a time, with --rolling or --pass-timeout.
+    sliced: bool,
+    selector: Option<PageSelector>,
+    /// The pages of suspected stuck bits, which are still read, but whatever flips in them is ignored.
+    excluded_pages: HashSet<usize>,
+}
+
+/// Allocates and fills the detector of a stage of the given size, or maps it from the backing file,
+/// and sets it up the way the command line asks for. The detector of a run with --reference-file holds it.
+fn allocate_stage(
+    setup: &Setup,
+    capacity_bytes: usize,
+    reference: Option<Reference>,
+    throttle: Option<&Throttle>,
+) -> Result<StageDetector, Box<dyn Error>> {
+    let conf = setup.conf;
+    // Instead of building a detector out of scintillators and photo multiplier tubes,
+    // we just allocate some memory on this here computer.
+    let mut backing = match &conf.backing_file {
+        Some(path) => {
+            let header = Header {
+                pattern: setup.pattern,
+                size: capacity_bytes as u64,
+                ecc: conf.ecc_emulate,
+            };
+            let backing = BackingFile::open(path, header)
+                .map_err(|e| format!("could not open the backing file {}: {e}", path.display()))?;
+            if backing.found().is_some() && !backing.resumed() {
+                warn!(
+                    "the backing file {} holds a detector with a different size, pattern or ECC emulation, filling it anew",
+                    path.display()
+                );
+            }
+            Some(backing)
+        }
+        None => None,
+    };
+    let resumed = backing.as_ref().is_some_and(BackingFile::resumed);
+    // The canaries are allocated right before and after the detector, so that they most likely end up next to it.
+    let mut canaries = Vec::new();
+    if conf.canary {
+        allocate_canary(&mut canaries);
+    }
+    let mut detector = match &backing {
+        Some(backing) => {
+            let region = backing
+                .map()
+                .map_err(|e| format!("could not map the backing file: {e}"))?;
+            Detector::resume(
+                setup.threads,
+                setup.pattern,
+                vec![region],
+                resumed && conf.ecc_emulate,
+            )
+        }
+        None => Detector::unfilled(
+            setup.threads,
+            setup.pattern,
+            capacity_bytes,
+            setup.region_options,
+        ),
+    };
+    // Whatever the memory holds is what a snapshot watches, so it is not filled.
+    if !resumed && !conf.snapshot {
+        let _bar = progress_bar(
+            setup.show_progress,
+            &detector,
+            "Filling the detector memory",
+            1,
+        );
+        detector.fill();
+    }
+    if conf.canary {
+        allocate_canary(&mut canaries);
+    }
+    if detector.capacity() == 0 {
+        return Err("could not allocate any memory for the detector".into());
+    }
+    // Without the parts of pages the detector can only be as large as the whole pages it was asked for.
+    let expected_bytes = if conf.align_pages {
+        capacity_bytes / PAGE_SIZE * PAGE_SIZE
+    } else {
+        capacity_bytes
+    };
+    if conf.align_pages {
+        info!(
+            "Trimmed {} bytes off the allocated memory, so that the detector memory starts and ends on page boundaries",
+            detector.trimmed_bytes()
+        );
+    }
+    if detector.capacity() < expected_bytes {
+        warn!(
+            "only {} of the requested {capacity_bytes} bytes could be allocated, continuing with those",
+            detector.capacity()
+        );
+    }
+    if let Some(reference) = reference {
+        detector
+            .use_reference(reference)
+            .map_err(|e| format!("could not fill the detector with the reference file: {e}"))?;
+    }
+    if conf.checksum {
+        detector.use_checksums();
+    }
+    if conf.ecc_emulate && !resumed {
+        detector.use_ecc_emulation();
+    }
+    if conf.anti_dedup {
+        detector.use_page_markers();
+    }
+    detector.use_scan_order(conf.scan_order);
+    detector.use_word_size(conf.word_size);
+    if let Some(cpus) = &setup.cpus {
+        pin_threads(&mut detector, cpus);
+    }
+    // A stage that starts while the machine is busy starts out as slow as the last one ended.
+    if let Some(throttle) = throttle {
+        detector.set_active_threads(throttle.threads(setup.threads));
+    }
+    // The checks of a pass that can time out go through the detector in slices as well, as many at a time as fit.
+    let pass_slices = match (conf.rolling, conf.pass_timeout) {
+        (Some(slices), _) => slices,
+        (None, Some(_)) => NonZeroUsize::new(detector.capacity().div_ceil(PASS_SLICE_BYTES))
+            .unwrap_or(NonZeroUsize::MIN),
+        (None, None) => NonZeroUsize::MIN,
+    };
+    let sliced = conf.rolling.is_some() || conf.pass_timeout.is_some();
+    if sliced {
+        detector.use_slices(pass_slices);
+    }
+    let cursor = SliceCursor::new(pass_slices.get());
+    if let Some(copies) = conf
+        .mirror
+        .and_then(|copies| NonZeroUsize::new(copies.into()))
+    {
+        detector.use_mirrors(copies);
+        info!(
+            "Comparing {copies} copies of {} bytes of the detector memory with each other",
+            detector.mirror_len()
+        );
+    }
+    let selector = conf
+        .coverage
+        .filter(|&percent| percent < 100.0)
+        .map(|percent| {
+            PageSelector::new(
+                detector.capacity().div_ceil(PAGE_SIZE),
+                percent,
+                setup.coverage_seed,
+            )
+        });
+    // Less exciting, much less accurate and sensitive, but much cheaper
+
+    // Avoid the pitfalls of virtual memory by writing nonzero values to the allocated memory.
+    if !resumed && !conf.snapshot {
+        let _bar = progress_bar(
+            setup.show_progress,
+            &detector,
+            "Writing the detector memory",
+            1,
+        );
+        detector.write(42);
+    }
+
+    if conf.lock_memory {
+        detector.lock().map_err(|e| e.to_string())?;
+    }
+
+    // The blocks whose parity changed since the earlier run committed it hold the flips that happened while
+    // no detector was running, which are told apart from the ones the checks find from here on.
+    let mut downtime_flips = Vec::new();
+    if let Some(backing) = backing.as_mut().filter(|_| resumed) {
+        let damaged = backing
+            .verify()
+            .map_err(|e| format!("could not verify the parity of the backing file: {e}"))?;
+        if !damaged.is_empty() {
+            downtime_flips = detector.flips();
+            downtime_flips.retain(|flip| {
+                let block = damaged.partition_point(|block| block.end <= flip.offset);
+                damaged
+                    .get(block)
+                    .is_some_and(|block| block.contains(&flip.offset))
+            });
+        }
+    }
+
+    if conf.snapshot {
+        detector.use_snapshot();
+    }
+
+    Ok(StageDetector {
+        detector,
+        backing,
+        canaries,
+        resumed,
+        downtime_flips,
+        scan: Scan {
+            cursor,
+            sliced,
+            selector,
+            excluded_pages: HashSet::new(),
+        },
+    })
+}
+
+/// What an integrity check found in the detector memory.
+struct Found {
+    flips: Vec<FlipEvent>,
+    word_errors: Vec<WordError>,
+    changed_blocks: Vec<ChangedBlock>,
+    reclaimed: Vec<ReclaimedPage>,
+}
+
+impl Found {
+    /// Whether the check found a flip or a changed block, which spoils a pass of --until-clean.
+    fn any(&self) -> bool {
+        !self.flips.is_empty() || !self.changed_blocks.is_empty()
+    }
+}
+
+/// Runs an integrity check over the detector, or the part of it that is due, leaves out whatever is in an excluded
+/// page or in a page the operating system reclaimed, and confirms the remaining flips with rereads and the mirrors.
+fn check_detector(conf: &Cli, detector: &Detector, scan: &mut Scan) -> Found {
+    let (mut flips, mut word_errors) = if detector.uses_ecc_emulation() {
+        // With ECC emulation the flips are found by decoding every word.
+        let errors = if scan.sliced {
+            scan.cursor
+                .check(conf.pass_timeout, |slice| detector.slice_word_errors(slice))
+        } else {
+            detector.word_errors()
+        };
+        (
+            errors.iter().flat_map(|e| e.flips.clone()).collect(),
+            errors,
+        )
+    } else if conf.snapshot {
+        // Without a pattern there is nothing to compare single bytes to, only the changed blocks are found.
+        (Vec::new(), Vec::new())
+    } else {
+        let flips = match (scan.sliced, &mut scan.selector) {
+            (true, _) => scan
+                .cursor
+                .check(conf.pass_timeout, |slice| detector.slice_flips(slice)),
+            (false, Some(selector)) => detector.page_flips(&selector.next_check()),
+            (false, None) => detector.flips(),
+        };
+        (flips, Vec::new())
+    };
+    let changed_blocks = if conf.snapshot {
+        detector.changed_blocks()
+    } else {
+        Vec::new()
+    };
+    if !scan.excluded_pages.is_empty() {
+        let excluded = |flip: &FlipEvent| scan.excluded_pages.contains(&(flip.offset / PAGE_SIZE));
+        flips.retain(|flip| !excluded(flip));
+        word_errors.retain(|error| !error.flips.iter().all(excluded));
+    }
+    // A page the operating system reclaimed reads as zeros, which is one event rather than a flip of every byte.
+    let reclaimed = detector.take_reclaimed_pages(&mut flips);
+    if !reclaimed.is_empty() {
+        let in_reclaimed =
+            |flip: &FlipEvent| reclaimed.iter().any(|page| page.contains(flip.offset));
+        word_errors.retain(|error| !error.flips.iter().all(in_reclaimed));
+    }
+    detector.reread(&mut flips, conf.rereads);
+    detector.vote_mirrors(&mut flips);
+    Found {
+        flips,
+        word_errors,
+        changed_blocks,
+        reclaimed,
+    }
+}
+
+/// Fills in where the flips are, as far as the command line asks for it and the system tells,
+/// and marks them if the memory controllers reported an error since the previous check.
+fn locate_flips(
+    detector: &Detector,
+    flips: &mut [FlipEvent],
+    virtual_addresses: bool,
+    pagemap: Option<&Pagemap>,
+    dimms: Option<&dmi::Inventory>,
+    mce: Option<&mut MceCounters>,
+) {
+    if virtual_addresses {
+        for flip in flips.iter_mut() {
+            let byte = detector
+                .byte(flip.offset)
+                .expect("flips are in the detector memory");
+            flip.virtual_address = Some(byte as *const u8 as usize);
+        }
+    }
+    if let Some(pagemap) = pagemap {
+        for flip in flips.iter_mut() {
+            let byte = detector
+                .byte(flip.offset)
+                .expect("flips are in the detector memory");
+            flip.physical_address = pagemap.physical_address(byte).ok().flatten();
+            flip.dimm = dimms
+                .zip(flip.physical_address)
+                .and_then(|(dimms, address)| dimms.locate(address));
+        }
+    }
+    // The counters are read at every check, so that an error the hardware reported counts for the flips
+    // of the interval it showed up in.
+    if mce.is_some_and(MceCounters::poll) {
+        for flip in flips.iter_mut() {
+            flip.correlated_mce = true;
+        }
+    }
+}
+
+/// Records what a check found in the statistics and reports it to the output, along with the bytes
+/// that flipped often enough to count as stuck. With --exclude-stuck their pages are excluded from the next checks.
+fn report_check(
+    conf: &Cli,
+    found: &Found,
+    scan: &mut Scan,
+    capacity: usize,
+    summary: &mut Summary,
+    output: &mut dyn Output,
+) -> io::Result<()> {
+    let flips = &found.flips;
+    summary.record_check(flips);
+    let coverage = match &scan.selector {
+        Some(selector) => Some(selector.coverage()),
+        None if conf.pass_timeout.is_some() => {
+            Some(scan.cursor.coverage(capacity.div_ceil(PAGE_SIZE)))
+        }
+        None => None,
+    };
+    if let Some(coverage) = coverage {
+        summary.coverage = Some(coverage);
+        output.coverage(&coverage)?;
+    }
+    output.check(summary.checks, flips.len())?;
+    let shown = conf
+        .max_events_per_check
+        .map_or(flips.len(), |max| max.get().min(flips.len()));
+    for flip in &flips[..shown] {
+        output.flip(flip)?;
+    }
+    if shown < flips.len() {
+        output.omitted_flips(flips.len() - shown)?;
+    }
+    for error in &found.word_errors {
+        output.word_error(error)?;
+    }
+    for page in &found.reclaimed {
+        summary.reclaimed_pages += 1;
+        output.reclaimed(page)?;
+    }
+    for block in &found.changed_blocks {
+        summary.changed_blocks += 1;
+        output.changed_block(block)?;
+    }
+    if let Some(threshold) = conf.stuck_threshold {
+        for flip in flips {
+            if summary.flip_counts.get(flip.offset) != threshold + 1 {
+                continue;
+            }
+            summary.stuck_bits += 1;
+            let page = flip.offset / PAGE_SIZE;
+            if conf.exclude_stuck && scan.excluded_pages.insert(page) {
+                summary.exclude_page(PAGE_SIZE.min(capacity - page * PAGE_SIZE));
+            }
+            output.stuck(&StuckBit {
+                offset: flip.offset,
+                flips: threshold + 1,
+                physical_address: flip.physical_address,
+                dimm: flip.dimm.clone(),
+                excluded: conf.exclude_stuck,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the pattern back over what a check found, once it has been reported.
+fn scrub_check(detector: &mut Detector, found: &Found) {
+    detector.scrub(&found.flips);
+    for page in &found.reclaimed {
+        detector.scrub(&page.flips);
+    }
+    if !found.changed_blocks.is_empty() {
+        detector.update_snapshot(&found.changed_blocks);
+    }
+}
+
+/// Opens the output to the terminal, or the dashboard that replaces it, and every other output the command line
+/// asks for. The events of the JSON outputs carry the given tag.
+#[cfg_attr(not(feature = "tui"), allow(unused_variables))]
+fn open_outputs(
+    conf: &Cli,
+    log_level: LevelFilter,
+    terminal: bool,
+    tag: &Option<String>,
+    stop: &Arc<AtomicBool>,
+) -> Result<MultiOutput, Box<dyn Error>> {
+    let mut outputs: Vec<Box<dyn Output>> = vec![match conf.format {
+        OutputFormat::Text => {
+            Box::new(TextOutput::new(stdout(), log_level).with_color(conf.color.enabled(terminal)))
+        }
+        OutputFormat::Json => Box::new(JsonOutput::new(stdout()).with_tag(tag.clone())),
+    }];
+    // The dashboard replaces the output to the terminal, the other outputs carry on as usual.
+    #[cfg(feature = "tui")]
+    if conf.tui {
+        outputs[0] = Box::new(
+            tui::TuiOutput::start(Arc::clone(stop), log_level)
+                .map_err(|e| format!("could not start the dashboard: {e}"))?,
+        );
+    }
+    let rotate_size = conf.log_rotate_size.map(|s| s.get() as u64);
+    for spec in &conf.log_file {
+        let path = &spec.path;
+        #[cfg(feature = "compression")]
+        let log_file = if conf.log_compress {
+            LogFile::open_compressed(path, rotate_size)
+        } else {
+            LogFile::open(path, rotate_size)
+        };
+        #[cfg(not(feature = "compression"))]
+        let log_file = LogFile::open(path, rotate_size);
+        let log_file =
+            log_file.map_err(|e| format!("could not open the log file {}: {e}", path.display()))?;
+        outputs.push(match spec.format.unwrap_or(conf.format) {
+            OutputFormat::Text => Box::new(LogOutput::new(log_file)),
+            OutputFormat::Json => Box::new(JsonOutput::new(log_file).with_tag(tag.clone())),
+        });
+    }
+    if let Some(path) = &conf.csv {
+        let csv_file = LogFile::open(path, None)
+            .map_err(|e| format!("could not open the CSV file {}: {e}", path.display()))?;
+        let header = csv_file.is_empty();
+        outputs.push(Box::new(CsvOutput::new(csv_file, header)));
+    }
+    #[cfg(feature = "notify")]
+    if conf.notify {
+        outputs.push(Box::new(notify::NotifyOutput::new()));
+    }
+    #[cfg(all(unix, feature = "syslog"))]
+    if conf.syslog {
+        outputs.push(Box::new(syslog::SyslogOutput::new().with_tag(tag.clone())));
+    }
+    #[cfg(feature = "webhook")]
+    if let Some(url) = &conf.webhook_url {
+        outputs.push(Box::new(
+            webhook::WebhookOutput::new(url.clone()).with_tag(tag.clone()),
+        ));
+    }
+    Ok(MultiOutput(outputs))
+}
+
+/// Shows a progress bar for a pass over the detector memory, or the given number of them, for as long as
+/// the returned bar is kept, if the progress is shown at all and the detector is large enough to need it.
+fn progress_bar(
+    show: bool,
+    detector: &Detector,
+    label: &'static str,
+    passes: usize,
+) -> Option<ProgressBar> {
+    (show && detector.capacity() >= progress::THRESHOLD).then(|| {
+        ProgressBar::start(
+            stdout(),
+            label,
+            passes * detector.data_capacity(),
+            detector.progress(),
+        )
+    })
+}
+
+/// Logs the level the throttle moved to because of the given load.
+fn log_throttle(throttle: &Throttle, load: f64, threads: usize) {
+    let threshold = throttle.threshold();
+    match throttle.level() {
+        0 => info!("The load is down to {load:.2}, checking at full speed again"),
+        _ if load > threshold => info!(
+            "The load of {load:.2} is above {threshold}, slowing the checks down {} times, running them on {} of {threads} threads",
+            throttle.slowdown(),
+            throttle.threads(threads)
+        ),
+        _ => info!(
+            "The load is down to {load:.2}, speeding the checks up to {} times slower than full speed, running them on {} of {threads} threads",
+            throttle.slowdown(),
+            throttle.threads(threads)
+        ),
+    }
+}
+
+/// Pins the threads of the detector to the given cores and reports which core every thread landed on.
+/// Where that fails the threads are left to the scheduler.
+fn pin_threads(detector: &mut Detector, cpus: &[usize]) {
+    match detector.pin_threads(cpus.to_vec()) {
+        Ok(landed) => {
+            for (thread, cpu) in landed.into_iter().enumerate() {
+                match cpu {
+                    Some(cpu) => info!("Pinned detector thread {thread} to CPU {cpu}"),
+                    None => info!("Pinned detector thread {thread}, the CPU it runs on is unknown"),
+                }
+            }
+        }
+        Err(e) => {
+            warn!("could not pin the detector threads to CPUs, leaving them to the scheduler: {e}")
+        }
+    }
+}
+
+/// Allocates another canary page, or warns and carries on without it.
+fn allocate_canary(canaries: &mut Vec<Canary>) {
+    match Canary::allocate() {
+        Ok(canary) => canaries.push(canary),
+        Err(e) => warn!("could not allocate a canary page, continuing without it: {e}"),
+    }
+}
+
+/// Checks the detector every `delay` until `cancel` is cancelled, sends every flip it finds to `flips`
+/// and restores the flipped bytes to the pattern. A check that is running when the token is cancelled is
+/// finished first, so that nothing it finds is lost. Returns the summary of the whole run, like the one
+/// the command line program prints when it is stopped, after which the detector memory is freed.
+/// Flips are still counted in the summary if nobody receives them anymore.
+#[cfg(feature = "tokio")]
+pub async fn monitor(
+    mut detector: Detector,
+    delay: Duration,
+    flips: Sender<FlipEvent>,
+    cancel: CancellationToken,
+) -> Summary {
+    let mut summary = Summary::new(detector.capacity());
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        let (checked, found) = task::spawn_blocking(move || {
+            let found = detector.check();
+            detector.scrub(&found);
+            (detector, found)
+        })
+        .await
+        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()));
+        detector = checked;
+
+        summary.record_check(&found);
+        for flip in found {
+            let _ = flips.send(flip).await;
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::read_environment;
+    use clap::Parser;
+    #[cfg(feature = "tokio")]
+    use tokio::sync::mpsc;
+
+    /// Keeps the events of the checks that a test cares about.
+    #[derive(Default)]
+    struct Recorder {
+        checks: Vec<(u64, usize)>,
+        flips: usize,
+        omitted: usize,
+        /// The offsets of the stuck bytes, and whether their pages were excluded.
+        stuck: Vec<(usize, bool)>,
+    }
+
+    impl Output for Recorder {
+        fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+            self.checks.push((check, flips));
+            Ok(())
+        }
+
+        fn flip(&mut self, _flip: &FlipEvent) -> io::Result<()> {
+            self.flips += 1;
+            Ok(())
+        }
+
+        fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+            self.omitted += omitted;
+            Ok(())
+        }
+
+        fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+            self.stuck.push((stuck.offset, stuck.excluded));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_check_step() {
+        let _env = read_environment();
+        let conf = Cli::try_parse_from([
+            "cosmic_ray_detection",
+            "-m",
+            "64",
+            "--max-events-per-check",
+            "4",
+            "--stuck-threshold",
+            "1",
+            "--exclude-stuck",
+        ])
+        .unwrap();
+        let mut detector = Detector::builder(64).build();
+        let mut scan = Scan {
+            cursor: SliceCursor::new(1),
+            sliced: false,
+            selector: None,
+            excluded_pages: HashSet::new(),
+        };
+        let mut summary = Summary::new(detector.capacity());
+        let mut output = Recorder::default();
+        let mut check = |detector: &mut Detector, scan: &mut Scan, summary: &mut Summary| {
+            let found = check_detector(&conf, detector, scan);
+            report_check(&conf, &found, scan, 64, summary, &mut output).unwrap();
+            scrub_check(detector, &found);
+            found.flips.len()
+        };
+
+        detector.write(0x10);
+        assert_eq!(check(&mut detector, &mut scan, &mut summary), 64);
+        // The flips were scrubbed.
+        assert_eq!(check(&mut detector, &mut scan, &mut summary), 0);
+        // The second flip of every byte makes it stuck, which excludes the page from the next checks.
+        detector.write(0x10);
+        assert_eq!(check(&mut detector, &mut scan, &mut summary), 64);
+        detector.write(0x10);
+        assert_eq!(check(&mut detector, &mut scan, &mut summary), 0);
+
+        assert_eq!(output.checks, [(1, 64), (2, 0), (3, 64), (4, 0)]);
+        assert_eq!((output.flips, output.omitted), (8, 120));
+        assert_eq!(
+            output.stuck,
+            (0..64).map(|offset| (offset, true)).collect::<Vec<_>>()
+        );
+        assert_eq!(scan.excluded_pages, HashSet::from([0]));
+        assert_eq!((summary.flips, summary.stuck_bits), (128, 64));
+        assert_eq!((summary.excluded_pages, summary.monitored_bytes), (1, 0));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn check_async_monitoring() {
+        let mut detector = Detector::builder(64).build();
+        detector.write(0x10);
+        let (sender, mut receiver) = mpsc::channel(16);
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(monitor(
+            detector,
+            Duration::from_millis(10),
+            sender,
+            cancel.clone(),
+        ));
+
+        let mut offsets = Vec::new();
+        while offsets.len() < 64 {
+            let flip = receiver.recv().await.unwrap();
+            assert_eq!((flip.expected, flip.found), (0x00, 0x10));
+            offsets.push(flip.offset);
+        }
+        assert_eq!(offsets, (0..64).collect::<Vec<_>>());
+
+        // The flips were scrubbed, so later checks find nothing.
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cancel.cancel();
+        let summary = task.await.unwrap();
+        assert_eq!(summary.flips, 64);
+        assert!(summary.checks >= 2);
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,250 @@
+//! Desktop notifications for detected flips, through the freedesktop notification service on Linux
+//! and the native notification centers on macOS and Windows.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use notify_rust::Notification;
+
+use crate::canary::CanaryViolation;
+use crate::detector::{ChangedBlock, FlipEvent, ReclaimedPage};
+use crate::output::{Output, StuckBit};
+use crate::summary::Summary;
+
+/// At most one notification is shown per window, the flips in between are coalesced into one.
+const WINDOW: Duration = Duration::from_secs(60);
+/// How long we wait for the notifications that are still queued to be shown when the detector shuts down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decides when to show a notification, so that a storm of flips from failing hardware
+/// does not turn into a storm of popups.
+struct RateLimiter {
+    last_shown: Option<Instant>,
+    /// The flips that have not been shown since the last notification.
+    pending: u64,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        RateLimiter {
+            last_shown: None,
+            pending: 0,
+        }
+    }
+
+    fn window_open(&self, now: Instant) -> bool {
+        self.last_shown
+            .is_none_or(|last| now.duration_since(last) >= WINDOW)
+    }
+
+    /// Records a flip and returns whether it should be shown right away.
+    fn flip(&mut self, now: Instant) -> bool {
+        if self.pending == 0 && self.window_open(now) {
+            self.last_shown = Some(now);
+            true
+        } else {
+            self.pending += 1;
+            false
+        }
+    }
+
+    /// Returns the number of coalesced flips if it is time to show them.
+    fn coalesced(&mut self, now: Instant) -> Option<u64> {
+        if self.pending == 0 || !self.window_open(now) {
+            return None;
+        }
+        self.last_shown = Some(now);
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// Returns the number of coalesced flips, if any, whether or not it is time to show them.
+    fn flush(&mut self) -> Option<u64> {
+        Some(std::mem::take(&mut self.pending)).filter(|&flips| flips > 0)
+    }
+}
+
+/// Describes which bits of the byte flipped in which direction, e.g. "bit 3 0 -> 1, bit 5 1 -> 0".
+fn describe_bits(flip: &FlipEvent) -> String {
+    (0..8)
+        .filter_map(|bit| {
+            if flip.set_bits() & (1 << bit) != 0 {
+                Some(format!("bit {bit} 0 -> 1"))
+            } else if flip.cleared_bits() & (1 << bit) != 0 {
+                Some(format!("bit {bit} 1 -> 0"))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Shows a desktop notification for detected flips, at most one a minute.
+/// The notifications are shown from a background thread, so that a slow notification service never stalls the detector.
+pub struct NotifyOutput {
+    limiter: RateLimiter,
+    sender: Option<Sender<String>>,
+    done: Receiver<()>,
+}
+
+impl NotifyOutput {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let (done_sender, done) = mpsc::channel();
+        thread::spawn(move || {
+            let mut warned = false;
+            for body in receiver {
+                let shown = Notification::new()
+                    .summary("Cosmic ray detector")
+                    .body(&body)
+                    .show();
+                // Without a desktop session every notification fails the same way, so only say so once.
+                if let Err(e) = shown {
+                    if !warned {
+                        warn!("could not show a desktop notification: {e}");
+                        warned = true;
+                    }
+                }
+            }
+            let _ = done_sender.send(());
+        });
+        NotifyOutput {
+            limiter: RateLimiter::new(),
+            sender: Some(sender),
+            done,
+        }
+    }
+
+    fn show(&self, body: String) {
+        if let Some(sender) = &self.sender {
+            // The background thread only stops once the sender is dropped, so this can not fail.
+            let _ = sender.send(body);
+        }
+    }
+
+    fn show_coalesced(&mut self) {
+        if let Some(flips) = self.limiter.coalesced(Instant::now()) {
+            self.show(format!("{flips} more bit flips in the last minute"));
+        }
+    }
+}
+
+impl Default for NotifyOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Output for NotifyOutput {
+    fn check(&mut self, _check: u64, _flips: usize) -> io::Result<()> {
+        self.show_coalesced();
+        Ok(())
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        if self.limiter.flip(Instant::now()) {
+            self.show(format!(
+                "Bit flip in byte at index {}: {}",
+                flip.offset,
+                describe_bits(flip)
+            ));
+        }
+        Ok(())
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        // At least one flip of the check was just recorded, so these can only be shown with the coalesced ones.
+        self.limiter.pending += omitted as u64;
+        Ok(())
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.show(format!(
+            "The page at index {} of the detector memory reads as all zeros, it was most likely reclaimed by the operating system",
+            page.offset
+        ));
+        Ok(())
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.show(format!(
+            "The {} byte block at index {} of the detector memory changed since the snapshot",
+            block.len, block.offset
+        ));
+        Ok(())
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        // This happens once per byte, so it is never rate limited.
+        self.show(format!(
+            "Suspected stuck bit in byte at index {}, which flipped {} times",
+            stuck.offset, stuck.flips
+        ));
+        Ok(())
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        // A canary is restored after it is reported, so after a single stray write this happens once as well.
+        self.show(format!(
+            "Something wrote {} bytes to canary page {}",
+            violation.changed_bytes, violation.canary
+        ));
+        Ok(())
+    }
+
+    fn heartbeat(&mut self, _summary: &Summary) -> io::Result<()> {
+        self.show_coalesced();
+        Ok(())
+    }
+
+    fn shutdown(&mut self, _summary: &Summary) -> io::Result<()> {
+        // The flips since the last notification would otherwise never be shown.
+        if let Some(flips) = self.limiter.flush() {
+            self.show(format!(
+                "{flips} more bit flips since the last notification"
+            ));
+        }
+        // Give the notifications that are still queued a chance to be shown, but don't hang around forever.
+        self.sender = None;
+        let _ = self.done.recv_timeout(SHUTDOWN_TIMEOUT);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_rate_limiting() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        assert!(limiter.flip(start));
+        assert!(!limiter.flip(start + Duration::from_secs(1)));
+        assert!(!limiter.flip(start + Duration::from_secs(2)));
+        assert_eq!(limiter.coalesced(start + Duration::from_secs(59)), None);
+        assert_eq!(limiter.coalesced(start + WINDOW), Some(2));
+        assert_eq!(limiter.coalesced(start + 3 * WINDOW), None);
+
+        // A flip right after the coalesced notification waits for the next window.
+        assert!(!limiter.flip(start + WINDOW + Duration::from_secs(1)));
+        assert!(!limiter.flip(start + 3 * WINDOW));
+        assert_eq!(limiter.coalesced(start + 3 * WINDOW), Some(2));
+
+        // Shutting down shows the coalesced flips before their window is over.
+        assert!(!limiter.flip(start + 3 * WINDOW + Duration::from_secs(1)));
+        assert_eq!(limiter.flush(), Some(1));
+        assert_eq!(limiter.flush(), None);
+    }
+
+    #[test]
+    fn check_bit_description() {
+        assert_eq!(
+            describe_bits(&FlipEvent::new(0, 0b0010_0000, 0b0000_1000)),
+            "bit 3 0 -> 1, bit 5 1 -> 0"
+        );
+    }
+}
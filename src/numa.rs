@@ -0,0 +1,199 @@
+//! Binding the detector memory and threads to NUMA nodes, so that every set of DIMMs can be monitored on its own.
+//! This talks to the kernel with the same system calls that libnuma wraps, so that the program does not need
+//! the library to be installed. NUMA is only supported on Linux, elsewhere every function fails.
+
+use std::io;
+use std::str::FromStr;
+
+/// The NUMA nodes to put the detector memory on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumaNodes {
+    /// All of it on the given node.
+    Node(u32),
+    /// An equal share on every node.
+    All,
+}
+
+impl FromStr for NumaNodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(NumaNodes::All);
+        }
+        s.parse()
+            .map(NumaNodes::Node)
+            .map_err(|_| format!("'{s}' is neither a NUMA node number nor \"all\""))
+    }
+}
+
+/// Parses a list of numbers and ranges like "0-3,8,10-11", as used by the kernel for sets of nodes and CPUs.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_list(list: &str) -> Option<Vec<u32>> {
+    let mut numbers = Vec::new();
+    for item in list.trim().split(',').filter(|item| !item.is_empty()) {
+        match item.split_once('-') {
+            Some((first, last)) => numbers.extend(first.parse::<u32>().ok()?..=last.parse().ok()?),
+            None => numbers.push(item.parse().ok()?),
+        }
+    }
+    Some(numbers)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::parse_list;
+    use std::fs;
+    use std::io;
+
+    // Flags of mbind and get_mempolicy that libc does not define.
+    const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+    const MPOL_F_NODE: libc::c_ulong = 1 << 0;
+    const MPOL_F_ADDR: libc::c_ulong = 1 << 1;
+
+    fn read_list(path: &str) -> io::Result<Vec<u32>> {
+        let list = fs::read_to_string(path)?;
+        parse_list(&list).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("could not parse {path}: {list:?}"),
+            )
+        })
+    }
+
+    pub fn nodes() -> io::Result<Vec<u32>> {
+        read_list("/sys/devices/system/node/online")
+    }
+
+    pub fn bind_memory(buf: &mut [u8], node: u32) -> io::Result<()> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        // The kernel only binds whole pages, so the partial pages at the edges are left to the default policy.
+        let start = (buf.as_ptr() as usize).next_multiple_of(page_size);
+        let end = (buf.as_ptr() as usize + buf.len()) / page_size * page_size;
+        if end <= start {
+            return Ok(());
+        }
+
+        let bits = libc::c_ulong::BITS as usize;
+        let mut mask = vec![0; node as usize / bits + 1];
+        mask[node as usize / bits] |= 1 << (node as usize % bits);
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                start,
+                end - start,
+                libc::MPOL_BIND,
+                mask.as_ptr(),
+                // The kernel ignores the last bit of the mask.
+                mask.len() * bits + 1,
+                MPOL_MF_MOVE,
+            )
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn node_of(byte: &u8) -> Option<u32> {
+        let mut node: libc::c_int = 0;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_get_mempolicy,
+                &mut node,
+                std::ptr::null_mut::<libc::c_ulong>(),
+                0,
+                byte as *const u8,
+                MPOL_F_NODE | MPOL_F_ADDR,
+            )
+        };
+        (result == 0).then(|| u32::try_from(node).ok()).flatten()
+    }
+
+    pub fn bind_thread(node: u32) -> io::Result<()> {
+        let cpus = read_list(&format!("/sys/devices/system/node/node{node}/cpulist"))?;
+        if cpus.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("NUMA node {node} has no CPUs"),
+            ));
+        }
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for cpu in cpus {
+            unsafe { libc::CPU_SET(cpu as usize, &mut set) };
+        }
+        if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "NUMA nodes are only supported on Linux",
+        )
+    }
+
+    pub fn nodes() -> io::Result<Vec<u32>> {
+        Err(unsupported())
+    }
+
+    pub fn bind_memory(_buf: &mut [u8], _node: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn node_of(_byte: &u8) -> Option<u32> {
+        None
+    }
+
+    pub fn bind_thread(_node: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+/// Returns the NUMA nodes of the machine that are online.
+pub fn nodes() -> io::Result<Vec<u32>> {
+    imp::nodes()
+}
+
+/// Makes the kernel place the memory of the buffer on the given node, like `numa_alloc_onnode` does.
+/// This is meant for memory that has not been touched yet, pages that are already in use are moved if possible.
+pub fn bind_memory(buf: &mut [u8], node: u32) -> io::Result<()> {
+    imp::bind_memory(buf, node)
+}
+
+/// Returns the node that the page of the given byte is on, or `None` if the kernel can not tell.
+pub fn node_of(byte: &u8) -> Option<u32> {
+    imp::node_of(byte)
+}
+
+/// Restricts the calling thread to the CPUs of the given node.
+/// Threads that it spawns afterwards inherit this, so the detector threads then run on the node as well.
+pub fn bind_thread(node: u32) -> io::Result<()> {
+    imp::bind_thread(node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_list_parsing() {
+        assert_eq!(parse_list("0\n"), Some(vec![0]));
+        assert_eq!(parse_list("0-3,8,10-11"), Some(vec![0, 1, 2, 3, 8, 10, 11]));
+        assert_eq!(parse_list(""), Some(vec![]));
+        assert_eq!(parse_list("0-x"), None);
+
+        assert_eq!("all".parse(), Ok(NumaNodes::All));
+        assert_eq!("1".parse(), Ok(NumaNodes::Node(1)));
+        assert!("first".parse::<NumaNodes>().is_err());
+    }
+}
@@ -0,0 +1,1230 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use humantime::{format_duration, format_rfc3339_seconds};
+use log::{Level, LevelFilter};
+use serde::Serialize;
+
+use crate::canary::CanaryViolation;
+use crate::config::AllocationMode;
+use crate::coverage::Coverage;
+use crate::detector::{ChangedBlock, FlipEvent, Persistence, ReclaimedPage, WordError};
+use crate::duty_cycle::DutyCycle;
+use crate::events::{Event, TaggedEvent};
+use crate::hamming::WordStatus;
+use crate::pattern::Pattern;
+use crate::summary::{Stage, Summary, Verification};
+
+/// The ANSI escape sequences the text output highlights things with on a terminal.
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// How many of the bytes that flipped repeatedly the summary lists.
+pub const REPEATED_FLIPS_LISTED: usize = 5;
+
+/// A byte that flipped more often than the stuck threshold, which points to failing hardware rather than cosmic rays.
+pub struct StuckBit {
+    pub offset: usize,
+    /// How many times the byte has flipped so far.
+    pub flips: u64,
+    pub physical_address: Option<u64>,
+    pub dimm: Option<String>,
+    /// Whether the page of the byte is no longer monitored from now on.
+    pub excluded: bool,
+}
+
+/// The settings of a run, as they are known before the detector memory is allocated.
+pub struct Settings {
+    /// A description of how much memory the detector will use.
+    pub detector: String,
+    pub delay: Duration,
+    /// With --frequency the delay is the period of the passes over the detector, the actual delay is chosen later.
+    pub frequency: bool,
+    pub pattern: Pattern,
+    /// The file the detector is filled with instead of the pattern, if it is.
+    pub reference_file: Option<PathBuf>,
+    pub threads: usize,
+    pub lock_memory: bool,
+    /// The number of slices the detector memory is checked in one after the other, if it is.
+    pub rolling_slices: Option<usize>,
+    /// The bursts the detector is checked in, if it is.
+    pub duty_cycle: Option<DutyCycle>,
+}
+
+/// What the detector ended up with once its memory has been allocated.
+pub struct Startup {
+    pub allocated_bytes: usize,
+    /// How the size was chosen with --use-all, if it was.
+    pub allocation_mode: Option<AllocationMode>,
+    pub pattern: Pattern,
+    /// The file the detector is filled with instead of the pattern, if it is.
+    pub reference_file: Option<PathBuf>,
+    /// Whether the detector holds a snapshot of what was in its memory instead of the pattern.
+    pub snapshot: bool,
+    pub delay: Duration,
+    pub threads: usize,
+    pub lock_memory: bool,
+    pub huge_pages: bool,
+    /// The size of the blocks that are checked by checksum, if checksums are used.
+    pub checksum_block_size: Option<usize>,
+    /// The number of bytes that hold data protected by emulated ECC, if it is emulated.
+    pub ecc_data_bytes: Option<usize>,
+    /// The percentage of the pages that every check verifies and the seed that picks them, with sparse checks.
+    pub coverage: Option<(f64, u64)>,
+    /// Whether the detector memory was filled by an earlier run and is checked as it is.
+    pub resumed: bool,
+    /// How many flips the parity of a resumed backing file showed to have happened while no detector was running.
+    pub downtime_flips: usize,
+}
+
+impl Startup {
+    /// Describes the settings the detector runs with in one line, e.g. "1000000 bytes filled with the pattern zeros,
+    /// checked every 30s on 1 thread", so that the settings of a run can be found in its output later on.
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} bytes", self.allocated_bytes);
+        match self.allocation_mode {
+            Some(AllocationMode::Available) => description.push_str(" of available memory"),
+            Some(AllocationMode::Free) => description.push_str(" of free memory"),
+            None => {}
+        }
+        match &self.reference_file {
+            Some(path) => description.push_str(&format!(" filled with {}", path.display())),
+            None if self.snapshot => description.push_str(" holding a snapshot of their contents"),
+            None => description.push_str(&format!(" filled with the pattern {}", self.pattern)),
+        }
+        description.push_str(&format!(
+            ", checked every {} on {} thread{}",
+            format_duration(self.delay),
+            self.threads,
+            if self.threads == 1 { "" } else { "s" }
+        ));
+        if self.lock_memory {
+            description.push_str(", locked into RAM");
+        }
+        if self.huge_pages {
+            description.push_str(", backed by huge pages");
+        }
+        if let Some((percent, seed)) = self.coverage {
+            description.push_str(&format!(
+                ", {percent}% of the pages in every check (seed {seed})"
+            ));
+        }
+        if self.resumed {
+            description.push_str(&format!(
+                ", resumed from the backing file with {} flips detected during downtime",
+                self.downtime_flips
+            ));
+        }
+        description
+    }
+}
+
+/// What the detector would do, as reported by a dry run.
+#[derive(Serialize)]
+#[serde(tag = "event", rename = "dry_run")]
+pub struct Plan {
+    pub bytes: usize,
+    /// The sizes of the later stages of a staircase test.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub later_bytes: Vec<usize>,
+    #[serde(serialize_with = "serialize_display")]
+    pub pattern: Pattern,
+    pub threads: usize,
+    #[serde(rename = "delay_seconds", serialize_with = "serialize_seconds")]
+    pub delay: Duration,
+    /// Whether the delay is the period of the passes over the detector given with --frequency.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub frequency: bool,
+}
+
+fn serialize_display<T: std::fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Serializes a duration as a number of seconds, for the fields named `*_seconds`.
+pub(crate) fn serialize_seconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Plan {
+    /// Writes the plan as text, or as a single JSON object.
+    pub fn write(&self, mut w: impl Write, json: bool) -> io::Result<()> {
+        if json {
+            writeln!(w, "{}", serde_json::to_string(self)?)
+        } else {
+            writeln!(w, "Would allocate {} bytes", self.bytes)?;
+            if !self.later_bytes.is_empty() {
+                let later: Vec<String> = self.later_bytes.iter().map(usize::to_string).collect();
+                writeln!(
+                    w,
+                    "Would then reallocate it with {} bytes in turn",
+                    later.join(", ")
+                )?;
+            }
+            writeln!(
+                w,
+                "Would fill the detector with the pattern {}",
+                self.pattern
+            )?;
+            writeln!(
+                w,
+                "Would check memory integrity on {} threads",
+                self.threads
+            )?;
+            if self.frequency {
+                writeln!(
+                    w,
+                    "Would check the whole detector every {}, waiting whatever a pass leaves of that in between",
+                    format_duration(self.delay)
+                )
+            } else {
+                writeln!(
+                    w,
+                    "Would wait {} between integrity checks",
+                    format_duration(self.delay)
+                )
+            }
+        }
+    }
+}
+
+/// Reports what the detector is doing. Every event is ignored unless the output implements it.
+pub trait Output {
+    /// Called right before the detector memory is allocated.
+    fn allocating(&mut self, _settings: &Settings) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the detector memory has been allocated and filled.
+    fn startup(&mut self, _startup: &Startup) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called right before the detector memory is reset to the pattern.
+    fn resetting(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the detector memory has been reset and the wait for the next check begins.
+    fn reset_done(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after every integrity check with the number of bytes that did not match the pattern.
+    fn check(&mut self, _check: u64, _flips: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called right before [`Output::check`] for every integrity check that only verified some of the pages.
+    fn coverage(&mut self, _coverage: &Coverage) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every byte that did not match the pattern in an integrity check, after [`Output::check`].
+    fn flip(&mut self, _flip: &FlipEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after [`Output::flip`] with the number of flips of an integrity check beyond --max-events-per-check,
+    /// which are not reported one by one. They are still part of the count passed to [`Output::check`].
+    fn omitted_flips(&mut self, _omitted: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every word whose emulated ECC check bits did not match in an integrity check, after [`Output::flip`].
+    fn word_error(&mut self, _error: &WordError) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every page of the detector memory that read as all zeros in an integrity check, after [`Output::word_error`].
+    /// Its bytes are not reported as flips.
+    fn reclaimed(&mut self, _page: &ReclaimedPage) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every block of the detector memory that changed since the snapshot of `--snapshot`
+    /// in an integrity check, after [`Output::reclaimed`].
+    fn changed_block(&mut self, _block: &ChangedBlock) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once for every byte that flipped more often than the stuck threshold, after [`Output::changed_block`].
+    fn stuck(&mut self, _stuck: &StuckBit) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every canary page that was written to since the previous integrity check, after [`Output::stuck`].
+    fn canary(&mut self, _violation: &CanaryViolation) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when checking is paused or resumed.
+    fn paused(&mut self, _paused: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called at every heartbeat interval with the statistics so far.
+    fn heartbeat(&mut self, _summary: &Summary) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called at the end of every stage of a staircase run. Between two stages this happens once the detector
+    /// of the next one has been allocated, which is not announced by [`Output::allocating`] and [`Output::startup`] again.
+    fn stage(&mut self, _stage: &Stage) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the detector stops.
+    fn shutdown(&mut self, _summary: &Summary) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Forwards every event to all of the contained outputs, in order.
+pub struct MultiOutput(pub Vec<Box<dyn Output>>);
+
+impl Output for MultiOutput {
+    fn allocating(&mut self, settings: &Settings) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.allocating(settings))
+    }
+
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.startup(startup))
+    }
+
+    fn resetting(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.resetting())
+    }
+
+    fn reset_done(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.reset_done())
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.check(check, flips))
+    }
+
+    fn coverage(&mut self, coverage: &Coverage) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.coverage(coverage))
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.flip(flip))
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.omitted_flips(omitted))
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.word_error(error))
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.reclaimed(page))
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.changed_block(block))
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.stuck(stuck))
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.canary(violation))
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.paused(paused))
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.heartbeat(summary))
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.stage(stage))
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|o| o.shutdown(summary))
+    }
+}
+
+/// Human readable output. Flips are printed at the warn level, the settings, startup, pauses and heartbeats
+/// at the info level and the progress of every check at the debug level. The summary is always printed.
+pub struct TextOutput<W: Write> {
+    writer: W,
+    level: LevelFilter,
+    start: Instant,
+    checksum_block_size: Option<usize>,
+    /// The coverage of the last check, if it only verified some of the pages.
+    coverage: Option<Coverage>,
+    color: bool,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(writer: W, level: LevelFilter) -> Self {
+        TextOutput {
+            writer,
+            level,
+            start: Instant::now(),
+            checksum_block_size: None,
+            coverage: None,
+            color: false,
+        }
+    }
+
+    /// Highlights headers and bit flips with ANSI colors, for a writer that is a terminal.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    /// Returns the escape sequences that start and end text in the given style, which are empty without color.
+    fn style(&self, style: &'static str) -> (&'static str, &'static str) {
+        if self.color {
+            (style, RESET)
+        } else {
+            ("", "")
+        }
+    }
+}
+
+impl<W: Write> Output for TextOutput<W> {
+    fn allocating(&mut self, settings: &Settings) -> io::Result<()> {
+        if self.enabled(Level::Info) {
+            let (bold, reset) = self.style(BOLD);
+            let w = &mut self.writer;
+            writeln!(
+                w,
+                "\n{bold}------------ Runtime settings ------------{reset}"
+            )?;
+            writeln!(w, "Using {} as detector", settings.detector)?;
+            match (settings.duty_cycle, settings.rolling_slices) {
+                (Some(cycle), _) => writeln!(
+                    w,
+                    "Checking back to back in bursts of {}, idling for {} after every one",
+                    format_duration(cycle.on),
+                    format_duration(cycle.off)
+                )?,
+                (None, Some(slices)) => writeln!(
+                    w,
+                    "Checking one of {slices} slices of the detector every {}, so that every byte is checked every {}",
+                    format_duration(settings.delay / slices as u32),
+                    format_duration(settings.delay)
+                )?,
+                (None, None) if settings.frequency => writeln!(
+                    w,
+                    "Checking the whole detector every {}, waiting whatever a pass leaves of that in between",
+                    format_duration(settings.delay)
+                )?,
+                (None, None) => writeln!(
+                    w,
+                    "Waiting {} between integrity checks",
+                    format_duration(settings.delay)
+                )?,
+            }
+            match &settings.reference_file {
+                Some(path) => writeln!(
+                    w,
+                    "Filling the detector with the contents of {}",
+                    path.display()
+                )?,
+                None => writeln!(
+                    w,
+                    "Filling the detector with the pattern {}",
+                    settings.pattern
+                )?,
+            }
+            if settings.threads > 1 {
+                writeln!(
+                    w,
+                    "Checking memory integrity in parallel on {} threads",
+                    settings.threads
+                )?;
+            }
+            if settings.lock_memory {
+                writeln!(w, "Locking the detector memory into RAM")?;
+            }
+            writeln!(
+                w,
+                "{bold}------------------------------------------{reset}\n"
+            )?;
+            write!(w, "Allocating detector memory...")?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.checksum_block_size = startup.checksum_block_size;
+        if self.enabled(Level::Info) {
+            write!(
+                self.writer,
+                " done with allocation of {} bytes",
+                startup.allocated_bytes
+            )?;
+            if startup.huge_pages {
+                write!(self.writer, " backed by huge pages")?;
+            }
+            if startup.resumed {
+                write!(self.writer, ", resumed from the backing file")?;
+                if startup.downtime_flips > 0 {
+                    let (red, reset) = self.style(RED);
+                    write!(
+                        self.writer,
+                        " with {red}{} flips detected during downtime{reset}",
+                        startup.downtime_flips
+                    )?;
+                }
+            }
+            if let Some(block_size) = startup.checksum_block_size {
+                write!(
+                    self.writer,
+                    ", checked by the CRC32 of every {block_size} byte block"
+                )?;
+            }
+            if let Some(data_bytes) = startup.ecc_data_bytes {
+                write!(
+                    self.writer,
+                    ", {data_bytes} of which ({:.1}%) hold data protected by emulated ECC",
+                    100.0 * data_bytes as f64 / startup.allocated_bytes as f64
+                )?;
+            }
+            if let Some((percent, seed)) = startup.coverage {
+                write!(
+                    self.writer,
+                    ", verifying {percent}% of its pages in every check, picked with the seed {seed}"
+                )?;
+            }
+            writeln!(self.writer, "\nBeginning detection loop")?;
+        } else if self.enabled(Level::Warn) {
+            // This includes the seed, without which a run with the random pattern can not be reproduced.
+            writeln!(self.writer, "Monitoring {}", startup.describe())?;
+        }
+        Ok(())
+    }
+
+    fn resetting(&mut self) -> io::Result<()> {
+        if self.enabled(Level::Debug) {
+            write!(self.writer, "Resetting detector memory... ")?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn reset_done(&mut self) -> io::Result<()> {
+        // Some feedback for the user that the program is still running
+        if self.enabled(Level::Debug) {
+            write!(self.writer, "done\nWaiting for first check")?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        if flips > 0 && self.enabled(Level::Warn) {
+            let (red, reset) = self.style(RED);
+            writeln!(
+                self.writer,
+                "\n{red}Detected a bitflip after {:?} on integrity check number {check}{reset}",
+                self.start.elapsed(),
+            )?;
+        } else if flips == 0 && self.enabled(Level::Debug) {
+            write!(self.writer, "\rIntegrity checks passed: {check}")?;
+            if let Some(coverage) = &self.coverage {
+                write!(
+                    self.writer,
+                    " ({:.1}% of the pages this time, {:.1}% so far)",
+                    coverage.check_percent(),
+                    coverage.cumulative_percent()
+                )?;
+            }
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn coverage(&mut self, coverage: &Coverage) -> io::Result<()> {
+        self.coverage = Some(*coverage);
+        Ok(())
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let verbose = self.enabled(Level::Info);
+        let (red, reset) = self.style(RED);
+        let w = &mut self.writer;
+        write!(w, "{red}Bit flip in byte at index {}", flip.offset)?;
+        if let Some(address) = flip.virtual_address {
+            write!(w, " (address {address:#x})")?;
+        }
+        match (flip.physical_address, &flip.dimm) {
+            (Some(address), Some(dimm)) => write!(w, " (physical address {address:#x} in {dimm})")?,
+            (Some(address), None) => write!(w, " (physical address {address:#x})")?,
+            _ => {}
+        }
+        if let Some(block_size) = self.checksum_block_size {
+            write!(w, " (checksum block {})", flip.offset / block_size)?;
+        }
+        if let Some(mirror) = &flip.mirror {
+            write!(
+                w,
+                " (copy {} of {}, {} of the other copies hold the pattern",
+                mirror.copy + 1,
+                mirror.copies,
+                mirror.agreeing
+            )?;
+            if let Some(majority) = mirror.majority {
+                write!(w, ", the copies vote for {majority:#010b}")?;
+            }
+            write!(w, ")")?;
+        }
+        if flip.correlated_mce {
+            write!(w, " (correlated MCE)")?;
+        }
+        write!(
+            w,
+            ", it became {} ({} error in its word",
+            flip.found, flip.class
+        )?;
+        match flip.persistence {
+            Persistence::Persistent => writeln!(w, "){reset}")?,
+            Persistence::Transient => writeln!(w, ", gone when read again){reset}")?,
+        }
+        if !flip.cluster.is_empty() {
+            let bits: Vec<String> = flip
+                .cluster
+                .iter()
+                .map(|bit| format!("byte {} bit {} ({})", bit.offset, bit.bit, bit.direction))
+                .collect();
+            writeln!(
+                w,
+                "    the word flipped {} bits: {}",
+                bits.len(),
+                bits.join(", ")
+            )?;
+        }
+        if verbose {
+            writeln!(
+                w,
+                "    expected {:#010b}, found {:#010b}, changed bits {:#010b}",
+                flip.expected,
+                flip.found,
+                flip.changed_bits(),
+            )?;
+            for bit in 0..8 {
+                if flip.set_bits() & (1 << bit) != 0 {
+                    writeln!(w, "    bit {bit} was set (0 -> 1)")?;
+                } else if flip.cleared_bits() & (1 << bit) != 0 {
+                    writeln!(w, "    bit {bit} was cleared (1 -> 0)")?;
+                }
+            }
+        }
+        w.flush()
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let (red, reset) = self.style(RED);
+        writeln!(
+            self.writer,
+            "{red}...and {omitted} more flips this check{reset}"
+        )?;
+        self.writer.flush()
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        match error.status {
+            WordStatus::Clean => Ok(()),
+            WordStatus::Corrected => {
+                let (yellow, reset) = self.style(YELLOW);
+                writeln!(
+                    self.writer,
+                    "{yellow}Corrected a single bit flip in word {} with the emulated ECC{reset}",
+                    error.word
+                )
+            }
+            WordStatus::Uncorrectable => {
+                let (red, reset) = self.style(RED);
+                writeln!(
+                    self.writer,
+                    "{red}Detected an uncorrectable multi bit flip in word {}{reset}",
+                    error.word
+                )
+            }
+        }
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let (yellow, reset) = self.style(YELLOW);
+        writeln!(
+            self.writer,
+            "{yellow}The page at index {} of the detector memory reads as all zeros, {} of its {} bytes no longer hold the pattern. The operating system most likely reclaimed it under memory pressure, this is not a bit flip. Locking the detector memory with --lock-memory prevents this{reset}",
+            page.offset, page.flips.len(), page.len
+        )?;
+        self.writer.flush()
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let (red, reset) = self.style(RED);
+        writeln!(
+            self.writer,
+            "{red}The {} byte block at index {} of the detector memory changed since the snapshot{reset}",
+            block.len, block.offset
+        )?;
+        self.writer.flush()
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let (red, reset) = self.style(RED);
+        let w = &mut self.writer;
+        write!(
+            w,
+            "{red}Suspected a stuck bit in the byte at index {}",
+            stuck.offset
+        )?;
+        match (stuck.physical_address, &stuck.dimm) {
+            (Some(address), Some(dimm)) => write!(w, " (physical address {address:#x} in {dimm})")?,
+            (Some(address), None) => write!(w, " (physical address {address:#x})")?,
+            _ => {}
+        }
+        write!(
+            w,
+            ", which flipped {} times. This points to failing memory rather than cosmic rays",
+            stuck.flips
+        )?;
+        if stuck.excluded {
+            write!(w, ", its page is no longer monitored")?;
+        }
+        writeln!(w, "{reset}")?;
+        w.flush()
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        if !self.enabled(Level::Warn) {
+            return Ok(());
+        }
+        let (red, reset) = self.style(RED);
+        writeln!(
+            self.writer,
+            "{red}Something wrote to canary page {}: {} bytes changed, the first at index {} from {:#010b} to {:#010b}. This is a stray write rather than a bit flip{reset}",
+            violation.canary, violation.changed_bytes, violation.first, violation.expected, violation.found
+        )?;
+        self.writer.flush()
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        if !self.enabled(Level::Info) {
+            return Ok(());
+        }
+        // The progress line is not terminated.
+        if self.enabled(Level::Debug) {
+            writeln!(self.writer)?;
+        }
+        let w = &mut self.writer;
+        let action = if paused { "Paused" } else { "Resumed" };
+        writeln!(
+            w,
+            "{action} checking at {}",
+            format_rfc3339_seconds(SystemTime::now())
+        )?;
+        w.flush()
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        if !self.enabled(Level::Info) {
+            return Ok(());
+        }
+        // The progress line is not terminated.
+        if self.enabled(Level::Debug) {
+            writeln!(self.writer)?;
+        }
+        let w = &mut self.writer;
+        let uptime = Duration::from_millis(summary.runtime().as_millis() as u64);
+        writeln!(
+            w,
+            "Still running after {}: {} integrity checks, {} bit flips, {:.6} GB-hours monitored",
+            format_duration(uptime),
+            summary.checks,
+            summary.flips,
+            summary.gb_hours()
+        )?;
+        w.flush()
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        // The progress line is not terminated.
+        if self.enabled(Level::Debug) {
+            writeln!(self.writer)?;
+        }
+        let w = &mut self.writer;
+        let time = Duration::from_millis(stage.monitored_time.as_millis() as u64);
+        write!(
+            w,
+            "Stage {} of {} with {} bytes: {} integrity checks and {} bit flips in {}",
+            stage.number,
+            stage.stages,
+            stage.monitored_bytes,
+            stage.checks,
+            stage.flips,
+            format_duration(time)
+        )?;
+        if let Some(rate) = stage.flips_per_gb_hour() {
+            write!(w, ", {rate:.4} flips per GB-hour")?;
+        }
+        match stage.next_bytes {
+            Some(bytes) => writeln!(w, ", moving on to {bytes} bytes")?,
+            None => writeln!(w)?,
+        }
+        w.flush()
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        let (bold, reset) = self.style(BOLD);
+        let (red, _) = self.style(RED);
+        let w = &mut self.writer;
+        // The runtime is rounded to whole milliseconds to keep it readable.
+        let runtime = Duration::from_millis(summary.runtime().as_millis() as u64);
+        writeln!(
+            w,
+            "\n{bold}------------------ Summary ------------------{reset}"
+        )?;
+        writeln!(w, "Ran for {}", format_duration(runtime))?;
+        let paused = Duration::from_millis(summary.paused_time().as_millis() as u64);
+        if !paused.is_zero() {
+            writeln!(w, "Paused for {}", format_duration(paused))?;
+        }
+        let idle = Duration::from_millis(summary.idle_time().as_millis() as u64);
+        if !idle.is_zero() {
+            writeln!(w, "Idled for {} between the bursts", format_duration(idle))?;
+        }
+        writeln!(
+            w,
+            "Monitored {} bytes, {:.6} GB-hours in total",
+            summary.monitored_bytes,
+            summary.gb_hours()
+        )?;
+        writeln!(w, "Performed {} integrity checks", summary.checks)?;
+        if let Some(interval) = summary.revisit_interval() {
+            let interval = Duration::from_millis(interval.as_millis() as u64);
+            writeln!(
+                w,
+                "Checked every byte {} times, once every {} on average",
+                summary.passes,
+                format_duration(interval)
+            )?;
+        }
+        if let Some(coverage) = &summary.coverage {
+            writeln!(
+                w,
+                "Verified {} of {} pages ({:.1}%) at least once",
+                coverage.covered,
+                coverage.pages,
+                coverage.cumulative_percent()
+            )?;
+        }
+        if summary.flips > 0 {
+            writeln!(
+                w,
+                "{red}Detected {} bit flips ({} single-bit, {} double-bit, {} multi-bit; {} persistent, {} transient){reset}",
+                summary.flips,
+                summary.single_bit_flips,
+                summary.double_bit_flips,
+                summary.multi_bit_flips,
+                summary.persistent_flips,
+                summary.transient_flips
+            )?;
+        } else {
+            writeln!(w, "Detected 0 bit flips")?;
+        }
+        for (node, flips) in &summary.flips_per_node {
+            writeln!(w, "    {flips} on NUMA node {node}")?;
+        }
+        let repeated = summary.flip_counts.repeated(REPEATED_FLIPS_LISTED);
+        if !repeated.is_empty() {
+            writeln!(w, "Bytes that flipped repeatedly:")?;
+            for (offset, flips) in repeated {
+                writeln!(w, "    index {offset}: {flips} flips")?;
+            }
+        }
+        if summary.excluded_pages > 0 {
+            writeln!(
+                w,
+                "Stopped monitoring {} pages with suspected stuck bits",
+                summary.excluded_pages
+            )?;
+        }
+        if summary.canary_violations > 0 {
+            writeln!(w, "Canary pages written to: {}", summary.canary_violations)?;
+        }
+        if summary.reclaimed_pages > 0 {
+            writeln!(
+                w,
+                "Pages reclaimed by the operating system: {}",
+                summary.reclaimed_pages
+            )?;
+        }
+        if summary.changed_blocks > 0 {
+            writeln!(
+                w,
+                "Blocks changed since the snapshot: {}",
+                summary.changed_blocks
+            )?;
+        }
+        if summary.downtime_flips > 0 {
+            writeln!(
+                w,
+                "Flips detected during downtime: {}",
+                summary.downtime_flips
+            )?;
+        }
+        match summary.verification {
+            Some(Verification {
+                passes,
+                clean: true,
+            }) => writeln!(
+                w,
+                "Pass {passes} found no flips, the memory is verified clean"
+            )?,
+            Some(Verification { passes: 0, .. }) => writeln!(
+                w,
+                "{red}No whole pass was checked, the memory is not verified clean{reset}"
+            )?,
+            Some(Verification { passes, .. }) => writeln!(
+                w,
+                "{red}All {passes} passes found flips, the memory is not verified clean{reset}"
+            )?,
+            None => {}
+        }
+        match summary.flips_per_gb_hour() {
+            Some(rate) => writeln!(w, "Flip rate: {rate:.4} flips per GB-hour")?,
+            None => writeln!(w, "Flip rate: n/a")?,
+        }
+        writeln!(w, "---------------------------------------------")?;
+        w.flush()
+    }
+}
+
+/// Newline delimited JSON output, one object per event.
+pub struct JsonOutput<W: Write> {
+    writer: W,
+    tag: Option<String>,
+}
+
+impl<W: Write> JsonOutput<W> {
+    pub fn new(writer: W) -> Self {
+        JsonOutput { writer, tag: None }
+    }
+
+    /// Adds the given tag to every event, see `--tag`.
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    fn emit(&mut self, event: &Event) -> io::Result<()> {
+        let mut line = serde_json::to_string(&TaggedEvent {
+            event,
+            tag: self.tag.as_deref(),
+        })?;
+        line.push('\n');
+        // Write every line in one go and flush it immediately,
+        // so that the stream only ever ends in the middle of a line if the write itself is interrupted.
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Output for JsonOutput<W> {
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.emit(&Event::startup(startup))
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        self.emit(&Event::check(check, flips))
+    }
+
+    fn coverage(&mut self, coverage: &Coverage) -> io::Result<()> {
+        self.emit(&Event::coverage(coverage))
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        self.emit(&Event::flip(flip))
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        self.emit(&Event::omitted_flips(omitted))
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        self.emit(&Event::word_error(error))
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.emit(&Event::reclaimed(page))
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.emit(&Event::changed_block(block))
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        self.emit(&Event::stuck(stuck))
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        self.emit(&Event::canary(violation))
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        self.emit(&Event::paused(paused))
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        self.emit(&Event::heartbeat(summary))
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        self.emit(&Event::stage(stage))
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        self.emit(&Event::shutdown(summary))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_text_levels() {
+        let text = |level| {
+            let mut output = TextOutput::new(Vec::new(), level);
+            output.check(1, 0).unwrap();
+            output.check(2, 1).unwrap();
+            output.flip(&FlipEvent::new(7, 0xFF, 0xFB)).unwrap();
+            output.heartbeat(&Summary::new(1000)).unwrap();
+            String::from_utf8(output.writer).unwrap()
+        };
+
+        let warn = text(LevelFilter::Warn);
+        assert!(warn.contains("Bit flip in byte at index 7"));
+        assert!(!warn.contains("bit 2 was cleared"));
+        assert!(!warn.contains("Still running"));
+        assert!(!warn.contains("Integrity checks passed"));
+
+        let info = text(LevelFilter::Info);
+        assert!(info.contains("bit 2 was cleared"));
+        assert!(info.contains("Still running"));
+        assert!(!info.contains("Integrity checks passed"));
+
+        assert!(text(LevelFilter::Debug).contains("Integrity checks passed: 1"));
+        assert!(text(LevelFilter::Error).is_empty());
+    }
+
+    #[test]
+    fn check_text_colors() {
+        let text = |color| {
+            let mut output = TextOutput::new(Vec::new(), LevelFilter::Warn).with_color(color);
+            output.flip(&FlipEvent::new(7, 0xFF, 0xFB)).unwrap();
+            output.shutdown(&Summary::new(1000)).unwrap();
+            String::from_utf8(output.writer).unwrap()
+        };
+
+        let plain = text(false);
+        assert!(!plain.contains('\x1b'));
+        let colored = text(true);
+        assert!(colored.contains(&format!("{RED}Bit flip in byte at index 7")));
+        assert!(colored.contains(&format!("{BOLD}------------------ Summary")));
+        assert_eq!(colored.matches(RESET).count(), 2);
+        assert_eq!(
+            colored
+                .replace(RED, "")
+                .replace(BOLD, "")
+                .replace(RESET, ""),
+            plain
+        );
+    }
+
+    #[test]
+    fn check_startup_description() {
+        let mut startup = Startup {
+            allocated_bytes: 1000,
+            allocation_mode: None,
+            pattern: Pattern::Random(7),
+            reference_file: None,
+            snapshot: false,
+            delay: Duration::from_secs(30),
+            threads: 1,
+            lock_memory: false,
+            huge_pages: false,
+            checksum_block_size: None,
+            ecc_data_bytes: None,
+            coverage: None,
+            resumed: false,
+            downtime_flips: 0,
+        };
+        assert_eq!(
+            startup.describe(),
+            "1000 bytes filled with the pattern random (seed 7), checked every 30s on 1 thread"
+        );
+        startup.allocation_mode = Some(AllocationMode::Free);
+        startup.threads = 4;
+        startup.lock_memory = true;
+        startup.huge_pages = true;
+        assert_eq!(
+            startup.describe(),
+            "1000 bytes of free memory filled with the pattern random (seed 7), checked every 30s on 4 threads, locked into RAM, backed by huge pages"
+        );
+        startup.allocation_mode = None;
+        startup.reference_file = Some(PathBuf::from("dataset.bin"));
+        assert_eq!(
+            startup.describe(),
+            "1000 bytes filled with dataset.bin, checked every 30s on 4 threads, locked into RAM, backed by huge pages"
+        );
+        startup.reference_file = None;
+        startup.lock_memory = false;
+        startup.huge_pages = false;
+        startup.resumed = true;
+        startup.downtime_flips = 2;
+        assert_eq!(
+            startup.describe(),
+            "1000 bytes filled with the pattern random (seed 7), checked every 30s on 4 threads, resumed from the backing file with 2 flips detected during downtime"
+        );
+        startup.resumed = false;
+        startup.snapshot = true;
+        assert_eq!(
+            startup.describe(),
+            "1000 bytes holding a snapshot of their contents, checked every 30s on 4 threads"
+        );
+    }
+
+    #[test]
+    fn check_json_output() {
+        let mut output = JsonOutput::new(Vec::new()).with_tag(Some("rack-3".to_owned()));
+        output
+            .startup(&Startup {
+                allocated_bytes: 1000,
+                allocation_mode: None,
+                pattern: Pattern::Ones,
+                reference_file: None,
+                snapshot: false,
+                delay: Duration::from_secs(30),
+                threads: 2,
+                lock_memory: false,
+                huge_pages: false,
+                checksum_block_size: None,
+                ecc_data_bytes: None,
+                coverage: None,
+                resumed: false,
+                downtime_flips: 0,
+            })
+            .unwrap();
+        output.check(1, 0).unwrap();
+        output.check(2, 1).unwrap();
+        output.flip(&FlipEvent::new(7, 0xFF, 0xFB)).unwrap();
+        output.omitted_flips(3).unwrap();
+        let mut summary = Summary::new(1000);
+        summary.record_check(&[]);
+        summary.record_check(&[FlipEvent::new(7, 0xFF, 0xFB)]);
+        output.heartbeat(&summary).unwrap();
+        summary.record_check(&[FlipEvent::new(7, 0xFF, 0xFB)]);
+        output
+            .stuck(&StuckBit {
+                offset: 7,
+                flips: 2,
+                physical_address: None,
+                dimm: None,
+                excluded: true,
+            })
+            .unwrap();
+        output
+            .canary(&CanaryViolation {
+                canary: 1,
+                changed_bytes: 8,
+                first: 0,
+                expected: 0x5A,
+                found: 0,
+            })
+            .unwrap();
+        summary.canary_violations += 1;
+        output.shutdown(&summary).unwrap();
+
+        let text = String::from_utf8(output.writer).unwrap();
+        let events: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(events.len(), 9);
+        assert!(events.iter().all(|event| event["tag"] == "rack-3"));
+        assert_eq!(events[0]["event"], "startup");
+        assert_eq!(events[0]["allocated_bytes"], 1000);
+        assert_eq!(events[0]["pattern"], "ones");
+        assert!(events[0].get("seed").is_none());
+        assert_eq!(events[0]["delay_seconds"], 30.0);
+        assert_eq!(events[0]["threads"], 2);
+        assert_eq!(events[2]["event"], "check");
+        assert_eq!(events[2]["check"], 2);
+        assert_eq!(events[2]["flips"], 1);
+        assert_eq!(events[3]["event"], "flip");
+        assert_eq!(events[3]["offset"], 7);
+        assert_eq!(events[3]["found"], 0xFB);
+        assert_eq!(events[3]["class"], "single-bit");
+        assert!(events[3].get("cluster").is_none());
+        assert!(humantime::parse_rfc3339(events[3]["timestamp"].as_str().unwrap()).is_ok());
+        assert_eq!(events[4]["event"], "omitted_flips");
+        assert_eq!(events[4]["flips"], 3);
+        assert_eq!(events[5]["event"], "heartbeat");
+        assert_eq!(events[5]["checks"], 2);
+        assert_eq!(events[5]["flips"], 1);
+        assert!(events[5]["byte_seconds"].as_f64().unwrap() >= 0.0);
+        assert_eq!(events[6]["event"], "stuck");
+        assert_eq!(events[6]["flips"], 2);
+        assert_eq!(events[6]["excluded"], true);
+        assert_eq!(events[7]["event"], "canary");
+        assert_eq!(events[7]["canary"], 1);
+        assert_eq!(events[7]["changed_bytes"], 8);
+        assert_eq!(events[8]["event"], "shutdown");
+        assert_eq!(
+            events[8]["repeated_flips"],
+            serde_json::json!([{ "offset": 7, "flips": 2 }])
+        );
+        assert!(events[8].get("excluded_pages").is_none());
+        assert_eq!(events[8]["canary_violations"], 1);
+    }
+}
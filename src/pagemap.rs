@@ -0,0 +1,108 @@
+//! Translating addresses of the detector memory to physical addresses with `/proc/self/pagemap`,
+//! so that a flip can be traced back to a DIMM. The kernel only reveals the page frame numbers
+//! to processes with CAP_SYS_ADMIN, and physical addresses are only supported on Linux.
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::fs::FileExt;
+
+    /// Set in an entry of the pagemap if the page is in RAM.
+    const PRESENT: u64 = 1 << 63;
+    /// The bits of an entry that hold the page frame number.
+    const FRAME_MASK: u64 = (1 << 55) - 1;
+
+    pub struct Pagemap {
+        file: File,
+        page_size: usize,
+    }
+
+    impl Pagemap {
+        pub fn open() -> io::Result<Self> {
+            Ok(Pagemap {
+                file: File::open("/proc/self/pagemap")?,
+                page_size: unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize,
+            })
+        }
+
+        pub fn physical_address(&self, byte: &u8) -> io::Result<Option<u64>> {
+            let address = byte as *const u8 as usize;
+            let mut entry = [0; 8];
+            self.file
+                .read_exact_at(&mut entry, (address / self.page_size * 8) as u64)?;
+            let entry = u64::from_le_bytes(entry);
+            if entry & PRESENT == 0 {
+                return Ok(None);
+            }
+            match entry & FRAME_MASK {
+                // The frame numbers read as zero without the privilege to see them.
+                0 => Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "reading physical addresses requires CAP_SYS_ADMIN",
+                )),
+                frame => Ok(Some(
+                    frame * self.page_size as u64 + (address % self.page_size) as u64,
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    pub struct Pagemap;
+
+    impl Pagemap {
+        pub fn open() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "physical addresses are only supported on Linux",
+            ))
+        }
+
+        pub fn physical_address(&self, _byte: &u8) -> io::Result<Option<u64>> {
+            Ok(None)
+        }
+    }
+}
+
+/// The page table of this process, as far as the kernel lets us see it.
+pub struct Pagemap(imp::Pagemap);
+
+impl Pagemap {
+    /// Opens the page table of this process.
+    pub fn open() -> io::Result<Self> {
+        imp::Pagemap::open().map(Pagemap)
+    }
+
+    /// Returns the physical address of the given byte, or `None` if its page is not in RAM.
+    /// Fails with [`io::ErrorKind::PermissionDenied`] if the process is not allowed to see physical addresses.
+    pub fn physical_address(&self, byte: &u8) -> io::Result<Option<u64>> {
+        self.0.physical_address(byte)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_physical_address() {
+        let pagemap = Pagemap::open().unwrap();
+        let buf = vec![1u8; 2 * 4096];
+        // Either the address is revealed, with the offset into the page intact, or we are told why not.
+        match pagemap.physical_address(&buf[4097]) {
+            Ok(Some(address)) => {
+                let start = pagemap.physical_address(&buf[4096]).unwrap().unwrap();
+                assert_eq!(address, start + 1);
+            }
+            Ok(None) => panic!("the buffer has been written to, so it is in RAM"),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+        }
+    }
+}
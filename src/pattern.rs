@@ -0,0 +1,421 @@
+use std::fmt;
+use std::ptr::{read_volatile, write_volatile};
+
+use crate::config::WordSize;
+use crate::region::PAGE_SIZE;
+use crate::simd;
+
+/// An unsigned integer that the detector memory can be read and compared in.
+trait Word: Copy + Eq {
+    /// Returns the word with the given little endian bytes, of which there must be as many as it is long.
+    fn from_le_slice(bytes: &[u8]) -> Self;
+}
+
+impl Word for u16 {
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        u16::from_le_bytes(bytes.try_into().expect("a u16 has two bytes"))
+    }
+}
+
+impl Word for u32 {
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        u32::from_le_bytes(bytes.try_into().expect("a u32 has four bytes"))
+    }
+}
+
+impl Word for u64 {
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes.try_into().expect("a u64 has eight bytes"))
+    }
+}
+
+/// The contents the detector memory is filled with.
+/// Different patterns are sensitive to different kinds of faults,
+/// e.g. all zeros can only detect bits that flip from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Every byte is 0x00.
+    Zeros,
+    /// Every byte is 0xFF.
+    Ones,
+    /// Bytes alternate between 0xAA and 0x55.
+    Alternating,
+    /// A single set bit that walks from the lowest to the highest bit over every eight bytes.
+    WalkingOnes,
+    /// A single cleared bit that walks from the lowest to the highest bit over every eight bytes.
+    WalkingZeros,
+    /// Pseudo-random bytes generated from the given seed, which can be regenerated for any offset
+    /// so that no copy of the detector memory is needed to verify it.
+    Random(u64),
+    /// Every byte is the given value.
+    Byte(u8),
+}
+
+/// The increment of the splitmix64 generator.
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Returns the `index`th output of the splitmix64 generator started from `seed`.
+/// Unlike most generators any output can be computed directly, without computing the ones before it.
+pub fn splitmix64(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(GOLDEN_GAMMA));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Returns a seed for the random pattern that is different for every run.
+pub fn random_seed() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    splitmix64(nanos, u64::from(std::process::id()))
+}
+
+impl Pattern {
+    /// Returns the value the byte at the given offset into the detector memory should have.
+    pub fn expected(&self, offset: usize) -> u8 {
+        match self {
+            Pattern::Zeros => 0x00,
+            Pattern::Ones => 0xFF,
+            Pattern::Alternating => {
+                if offset.is_multiple_of(2) {
+                    0xAA
+                } else {
+                    0x55
+                }
+            }
+            Pattern::WalkingOnes => 1 << (offset % 8),
+            Pattern::WalkingZeros => !(1 << (offset % 8)),
+            // Every output of the generator provides eight bytes.
+            Pattern::Random(seed) => {
+                (splitmix64(*seed, (offset / 8) as u64) >> (8 * (offset % 8))) as u8
+            }
+            Pattern::Byte(b) => *b,
+        }
+    }
+
+    /// Returns the seed of the random pattern.
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            Pattern::Random(seed) => Some(*seed),
+            _ => None,
+        }
+    }
+
+    /// Returns whether every byte of the pattern is zero.
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Pattern::Zeros | Pattern::Byte(0))
+    }
+
+    /// Returns the value of every byte if the pattern is a constant.
+    fn constant(&self) -> Option<u8> {
+        match self {
+            Pattern::Zeros => Some(0x00),
+            Pattern::Ones => Some(0xFF),
+            Pattern::Byte(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Writes the pattern to every element of the buffer,
+    /// where `offset` is the offset of the first element of the buffer into the detector memory.
+    pub fn fill(&self, buf: &mut [u8], offset: usize) {
+        buf.iter_mut()
+            .enumerate()
+            .for_each(|(i, n)| unsafe { write_volatile(n, self.expected(offset + i)) });
+    }
+
+    /// If an element in the buffer does not match the pattern, return its index in the buffer.
+    /// `offset` is the offset of the first element of the buffer into the detector memory.
+    pub fn verify(&self, buf: &[u8], offset: usize) -> Option<usize> {
+        if let Some(value) = self.constant() {
+            return simd::find_mismatch(buf, value);
+        }
+        buf.iter()
+            .enumerate()
+            .position(|(i, r)| unsafe { read_volatile(r) != self.expected(offset + i) })
+    }
+
+    /// Like [`Pattern::verify`], but reads and compares the buffer in words of the given size.
+    pub fn verify_words(&self, buf: &[u8], offset: usize, word_size: WordSize) -> Option<usize> {
+        if let Some(value) = self.constant() {
+            return simd::find_mismatch(buf, value);
+        }
+        match word_size {
+            WordSize::U8 => self.verify(buf, offset),
+            WordSize::U16 => self.verify_in::<u16>(buf, offset),
+            WordSize::U32 => self.verify_in::<u32>(buf, offset),
+            WordSize::U64 => self.verify_in::<u64>(buf, offset),
+        }
+    }
+
+    fn verify_in<W: Word>(&self, buf: &[u8], offset: usize) -> Option<usize> {
+        let size = std::mem::size_of::<W>();
+        // Any integer is valid for any bytes. The ends of the buffer that are not whole aligned words are compared byte by byte.
+        let (head, words, tail) = unsafe { buf.align_to::<W>() };
+        if let Some(i) = self.verify(head, offset) {
+            return Some(i);
+        }
+        let mut expected = [0; 8];
+        for (i, word) in words.iter().enumerate() {
+            let start = head.len() + i * size;
+            for (b, e) in expected[..size].iter_mut().enumerate() {
+                *e = self.expected(offset + start + b);
+            }
+            if unsafe { read_volatile(word) } != W::from_le_slice(&expected[..size]) {
+                // The word may have been changed back in the meantime, then there is nothing to report.
+                if let Some(b) =
+                    (0..size).find(|&b| unsafe { read_volatile(&buf[start + b]) } != expected[b])
+                {
+                    return Some(start + b);
+                }
+            }
+        }
+        let tail_start = buf.len() - tail.len();
+        self.verify(tail, offset + tail_start)
+            .map(|i| tail_start + i)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Zeros => write!(f, "zeros"),
+            Pattern::Ones => write!(f, "ones"),
+            Pattern::Alternating => write!(f, "alternating"),
+            Pattern::WalkingOnes => write!(f, "walking-ones"),
+            Pattern::WalkingZeros => write!(f, "walking-zeros"),
+            Pattern::Random(seed) => write!(f, "random (seed {seed})"),
+            Pattern::Byte(b) => write!(f, "{b:#04X}"),
+        }
+    }
+}
+
+/// Parses the name of a pattern, or a literal hex byte like '0x3C'.
+/// The seed of the random pattern is zero, it is meant to be replaced with the one given with `--seed` or [`random_seed`].
+pub fn parse_pattern(s: &str) -> Result<Pattern, String> {
+    match s {
+        "zeros" => Ok(Pattern::Zeros),
+        "ones" => Ok(Pattern::Ones),
+        "alternating" => Ok(Pattern::Alternating),
+        "walking-ones" => Ok(Pattern::WalkingOnes),
+        "walking-zeros" => Ok(Pattern::WalkingZeros),
+        "random" => Ok(Pattern::Random(0)),
+        _ => match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u8::from_str_radix(hex, 16)
+                .map(Pattern::Byte)
+                .map_err(|_| format!("'{hex}' is not a hexadecimal byte")),
+            None => Err(format!(
+                "'{s}' is not a pattern, expected 'zeros', 'ones', 'alternating', 'walking-ones', 'walking-zeros', 'random' or a hex byte like '0x3C'"
+            )),
+        },
+    }
+}
+
+/// The number of bytes at the start of every page that hold its index with page markers.
+const MARKER_LEN: usize = 8;
+
+/// What the detector memory is filled with: the pattern, and possibly markers that make every page different,
+/// see [`crate::detector::Detector::use_page_markers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contents {
+    pub pattern: Pattern,
+    /// Whether the first [`MARKER_LEN`] bytes of every page hold the index of the page, as a little endian number.
+    pub page_markers: bool,
+}
+
+impl Contents {
+    /// Returns whether the byte at the given offset into the detector memory is part of a page marker.
+    fn in_marker(&self, offset: usize) -> bool {
+        self.page_markers && offset % PAGE_SIZE < MARKER_LEN
+    }
+
+    /// Returns the value the byte at the given offset into the detector memory should have.
+    pub fn expected(&self, offset: usize) -> u8 {
+        if self.in_marker(offset) {
+            ((offset / PAGE_SIZE) as u64).to_le_bytes()[offset % PAGE_SIZE]
+        } else {
+            self.pattern.expected(offset)
+        }
+    }
+
+    /// Like [`Pattern::fill`], with the page markers.
+    pub fn fill(&self, buf: &mut [u8], offset: usize) {
+        self.pattern.fill(buf, offset);
+        if self.page_markers {
+            for i in marker_ranges(buf.len(), offset).flatten() {
+                unsafe { write_volatile(&mut buf[i], self.expected(offset + i)) };
+            }
+        }
+    }
+
+    /// Like [`Pattern::verify_words`], with the page markers.
+    pub fn verify(&self, buf: &[u8], offset: usize, word_size: WordSize) -> Option<usize> {
+        if !self.page_markers {
+            return self.pattern.verify_words(buf, offset, word_size);
+        }
+        // The pattern between the markers can still be verified in one go.
+        let mut start = 0;
+        for marker in marker_ranges(buf.len(), offset).chain(std::iter::once(buf.len()..buf.len()))
+        {
+            if let Some(i) =
+                self.pattern
+                    .verify_words(&buf[start..marker.start], offset + start, word_size)
+            {
+                return Some(start + i);
+            }
+            if let Some(i) = marker
+                .clone()
+                .find(|&i| unsafe { read_volatile(&buf[i]) } != self.expected(offset + i))
+            {
+                return Some(i);
+            }
+            start = marker.end;
+        }
+        None
+    }
+}
+
+/// Returns the ranges of a buffer of the given length that hold page markers,
+/// where `offset` is the offset of the first element of the buffer into the detector memory.
+fn marker_ranges(len: usize, offset: usize) -> impl Iterator<Item = std::ops::Range<usize>> {
+    let first_page = offset / PAGE_SIZE;
+    (first_page..)
+        .map(|page| page * PAGE_SIZE)
+        .take_while(move |&page_start| page_start < offset + len)
+        .map(move |page_start| {
+            let start = page_start.max(offset);
+            let end = (page_start + MARKER_LEN).min(offset + len).max(start);
+            start - offset..end - offset
+        })
+        .filter(|range| !range.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_pattern_parsing() {
+        assert_eq!(parse_pattern("zeros").unwrap(), Pattern::Zeros);
+        assert_eq!(parse_pattern("ones").unwrap(), Pattern::Ones);
+        assert_eq!(parse_pattern("alternating").unwrap(), Pattern::Alternating);
+        assert_eq!(parse_pattern("walking-ones").unwrap(), Pattern::WalkingOnes);
+        assert_eq!(
+            parse_pattern("walking-zeros").unwrap(),
+            Pattern::WalkingZeros
+        );
+        assert_eq!(parse_pattern("random").unwrap(), Pattern::Random(0));
+        assert_eq!(parse_pattern("0x3C").unwrap(), Pattern::Byte(0x3C));
+        assert_eq!(parse_pattern("0Xff").unwrap(), Pattern::Byte(0xFF));
+        assert!(parse_pattern("0x100").is_err());
+        assert!(parse_pattern("0x").is_err());
+        assert!(parse_pattern("twos").is_err());
+    }
+
+    #[test]
+    fn check_fill_and_verify() {
+        for pattern in [
+            Pattern::Zeros,
+            Pattern::Ones,
+            Pattern::Alternating,
+            Pattern::WalkingOnes,
+            Pattern::WalkingZeros,
+            Pattern::Random(1234),
+            Pattern::Byte(0x3C),
+        ] {
+            let mut buf = vec![0x42; 100];
+            assert_eq!(pattern.verify(&buf, 0), Some(0));
+            pattern.fill(&mut buf, 0);
+            assert_eq!(pattern.verify(&buf, 0), None);
+            buf[37] ^= 0b0001_0000;
+            assert_eq!(pattern.verify(&buf, 0), Some(37));
+            for word_size in [WordSize::U8, WordSize::U16, WordSize::U32, WordSize::U64] {
+                assert_eq!(pattern.verify_words(&buf, 0, word_size), Some(37));
+                // Whatever the alignment of the words.
+                assert_eq!(pattern.verify_words(&buf[3..], 3, word_size), Some(34));
+                assert_eq!(pattern.verify_words(&buf[..37], 0, word_size), None);
+            }
+
+            // A buffer that starts in the middle of the detector memory.
+            pattern.fill(&mut buf, 13);
+            assert_eq!(pattern.verify(&buf, 13), None);
+            assert_eq!(pattern.verify(&buf[1..], 14), None);
+        }
+    }
+
+    #[test]
+    fn check_walking_patterns() {
+        let mut buf = vec![0; 10];
+        Pattern::WalkingOnes.fill(&mut buf, 0);
+        assert_eq!(
+            buf,
+            [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x01, 0x02]
+        );
+        Pattern::WalkingZeros.fill(&mut buf, 5);
+        assert_eq!(
+            buf,
+            [0xDF, 0xBF, 0x7F, 0xFE, 0xFD, 0xFB, 0xF7, 0xEF, 0xDF, 0xBF]
+        );
+        // The pattern depends on the offset into the detector memory, not just the position in the buffer.
+        assert_eq!(Pattern::WalkingZeros.verify(&buf, 0), Some(0));
+    }
+
+    #[test]
+    fn check_random_pattern() {
+        let mut buf = vec![0; 4096];
+        Pattern::Random(7).fill(&mut buf, 0);
+        // The same seed gives the same bytes, also when filled in pieces.
+        let mut pieces = vec![0; 4096];
+        let (first, second) = pieces.split_at_mut(1001);
+        Pattern::Random(7).fill(first, 0);
+        Pattern::Random(7).fill(second, 1001);
+        assert_eq!(buf, pieces);
+
+        assert_eq!(Pattern::Random(8).verify(&buf, 0), Some(0));
+        // Roughly uniformly distributed bytes.
+        for value in 0..=255 {
+            let count = buf.iter().filter(|&&b| b == value).count();
+            assert!(count > 0 && count < 48, "{value} appears {count} times");
+        }
+    }
+
+    #[test]
+    fn check_page_markers() {
+        let contents = Contents {
+            pattern: Pattern::Zeros,
+            page_markers: true,
+        };
+        let mut buf = vec![0; 3 * PAGE_SIZE];
+        contents.fill(&mut buf, 0);
+        assert_eq!(buf[PAGE_SIZE..PAGE_SIZE + MARKER_LEN], 1u64.to_le_bytes());
+        assert_eq!(
+            buf[2 * PAGE_SIZE..2 * PAGE_SIZE + MARKER_LEN],
+            2u64.to_le_bytes()
+        );
+        assert!(buf[..PAGE_SIZE] != buf[PAGE_SIZE..2 * PAGE_SIZE]);
+        assert_eq!(contents.verify(&buf, 0, WordSize::U64), None);
+
+        // Flips in and outside of the markers.
+        buf[PAGE_SIZE + 2] ^= 1;
+        assert_eq!(contents.verify(&buf, 0, WordSize::U64), Some(PAGE_SIZE + 2));
+        buf[PAGE_SIZE + 2] ^= 1;
+        buf[PAGE_SIZE + 100] ^= 1;
+        assert_eq!(
+            contents.verify(&buf, 0, WordSize::U64),
+            Some(PAGE_SIZE + 100)
+        );
+
+        // A buffer that starts and ends in the middle of markers.
+        let mut part = vec![0xFF; PAGE_SIZE];
+        contents.fill(&mut part, PAGE_SIZE + 4);
+        assert_eq!(&part[..4], &buf[PAGE_SIZE + 4..PAGE_SIZE + MARKER_LEN]);
+        assert_eq!(part[PAGE_SIZE - 4..], 2u64.to_le_bytes()[..4]);
+        assert_eq!(contents.verify(&part, PAGE_SIZE + 4, WordSize::U64), None);
+        part[PAGE_SIZE - 1] ^= 0x80;
+        assert_eq!(
+            contents.verify(&part, PAGE_SIZE + 4, WordSize::U64),
+            Some(PAGE_SIZE - 1)
+        );
+    }
+}
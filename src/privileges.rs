@@ -0,0 +1,169 @@
+//! Dropping the capabilities of the process with `--drop-privs` (Linux only), so that a detector that was started
+//! as root to set up its memory does not go on checking it with the privileges of root.
+//!
+//! Only the setup of the detector needs privileges, and only for these flags:
+//!
+//! - `--lock-memory` needs CAP_IPC_LOCK to lock more memory than RLIMIT_MEMLOCK allows.
+//! - `--physical-addresses` needs CAP_SYS_ADMIN when `/proc/self/pagemap` is opened.
+//!   The kernel decides whether the page frame numbers are revealed by who opened the file,
+//!   so it keeps working after the capability is gone.
+//! - `--dimm-slots` needs CAP_DAC_READ_SEARCH to read the SMBIOS tables, unless it runs as root,
+//!   which owns them.
+//!
+//! Nothing else needs a capability, including huge pages, NUMA nodes, pinning threads, the backing file
+//! and the outputs, as long as the files they use can be opened as the user the detector runs as.
+//! The one exception is a staircase run with `--lock-memory`, which locks the memory of every stage
+//! when it is allocated and therefore keeps CAP_IPC_LOCK.
+//!
+//! Capabilities belong to threads on Linux, and a thread can only drop its own. They are therefore dropped twice:
+//! before any thread is started down to the ones the setup needs, and after the setup down to the ones
+//! the checks need. The threads started in between for the outputs, the metrics and Ctrl-C
+//! are left with the capabilities of the setup, the threads that fill and check the memory are started after.
+//! Either way the process can not gain privileges again, not even by running a setuid program.
+
+use std::fmt;
+use std::io;
+
+/// The capabilities the setup of the detector may need, see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    DacReadSearch,
+    IpcLock,
+    SysAdmin,
+}
+
+impl Capability {
+    /// The number of the capability in the kernel headers.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn number(self) -> u32 {
+        match self {
+            Capability::DacReadSearch => 2,
+            Capability::IpcLock => 14,
+            Capability::SysAdmin => 21,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Capability::DacReadSearch => "CAP_DAC_READ_SEARCH",
+            Capability::IpcLock => "CAP_IPC_LOCK",
+            Capability::SysAdmin => "CAP_SYS_ADMIN",
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Drops every capability of the calling thread except the given ones, which it keeps if it has them,
+/// and stops it and the threads it starts from then on from gaining any again.
+pub fn restrict(keep: &[Capability]) -> io::Result<()> {
+    /// The version of the capability sets that holds 64 of them in two halves.
+    const VERSION_3: u32 = 0x2008_0522;
+    const CAP_SETPCAP: u32 = 8;
+
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: libc::c_int,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    let check = |result: libc::c_long| {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    let kept = keep.iter().fold(0u64, |mask, cap| mask | 1 << cap.number());
+
+    check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())?;
+    // The bounding set only matters for programs this one would run, which is ruled out above already,
+    // so it is cut down only as far as the thread is still allowed to.
+    let mut header = Header {
+        version: VERSION_3,
+        pid: 0,
+    };
+    let mut data = [Data::default(); 2];
+    check(unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) })?;
+    if data[0].effective & 1 << CAP_SETPCAP != 0 {
+        // The kernel rejects the first capability it does not know.
+        for cap in (0..64).filter(|cap| kept & 1 << cap == 0) {
+            if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } == -1 {
+                break;
+            }
+        }
+    }
+    check(
+        unsafe {
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            )
+        }
+        .into(),
+    )?;
+
+    for (half, data) in data.iter_mut().enumerate() {
+        let kept = (kept >> (32 * half)) as u32;
+        data.permitted &= kept;
+        data.effective &= kept;
+        data.inheritable = 0;
+    }
+    check(unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) })
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Dropping capabilities is only supported on Linux.
+pub fn restrict(_keep: &[Capability]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "dropping privileges is only supported on Linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_dropping_capabilities() {
+        let status = |field: &str| {
+            let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+            let value = status
+                .lines()
+                .find_map(|line| line.strip_prefix(field))
+                .unwrap()
+                .trim()
+                .to_owned();
+            u64::from_str_radix(&value, 16).unwrap()
+        };
+        // Only the thread that drops its capabilities loses them, not the ones the other tests run on.
+        std::thread::spawn(move || {
+            let permitted = status("CapPrm:");
+            restrict(&[Capability::IpcLock]).unwrap();
+            assert_eq!(status("NoNewPrivs:"), 1);
+            assert_eq!(status("CapPrm:"), permitted & 1 << 14);
+            assert_eq!(status("CapEff:"), permitted & 1 << 14);
+            assert_eq!(status("CapInh:"), 0);
+
+            // Dropping more later works without the capabilities that were dropped first.
+            restrict(&[]).unwrap();
+            assert_eq!(status("CapPrm:"), 0);
+            assert_eq!(status("CapEff:"), 0);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! A progress bar on the terminal for the passes over the detector memory before the first check,
+//! like filling it, which take minutes for a detector of hundreds of gigabytes and would otherwise look like a hang.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Detectors smaller than this are filled and checked too fast to need a progress bar.
+pub const THRESHOLD: usize = 1 << 30;
+
+/// How often the bar looks at the counter.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The bar is drawn again whenever it got this many percent further, or this long after it was last drawn.
+const STEP_PERCENT: usize = 2;
+const STEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of characters between the brackets of the bar.
+const WIDTH: usize = 30;
+
+/// Draws how far a pass over the detector memory got on a single line of a terminal, from a thread of its own
+/// since the threads of the detector are busy with the pass. The line is cleared again when the bar is dropped.
+pub struct ProgressBar {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProgressBar {
+    /// Starts drawing the progress of a pass over the given number of bytes, as far as the counter gets
+    /// beyond where it is now, see [`crate::Detector::progress`].
+    pub fn start(
+        mut writer: impl Write + Send + 'static,
+        label: &'static str,
+        total: usize,
+        counter: Arc<AtomicUsize>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let start = counter.load(Ordering::Relaxed);
+        let thread = thread::spawn(move || {
+            let mut drawn: Option<(usize, Instant)> = None;
+            let mut line_len = 0;
+            while !thread_stop.load(Ordering::SeqCst) {
+                let done = counter.load(Ordering::Relaxed).saturating_sub(start);
+                let percent = percent(done, total);
+                if drawn.is_none_or(|(last, at)| {
+                    percent >= last + STEP_PERCENT || at.elapsed() >= STEP_INTERVAL
+                }) {
+                    let line = render(label, percent);
+                    line_len = line.len();
+                    // The bar is only for show, a terminal that went away does not stop the pass.
+                    let _ = write!(writer, "\r{line}").and_then(|_| writer.flush());
+                    drawn = Some((percent, Instant::now()));
+                }
+                thread::park_timeout(POLL_INTERVAL);
+            }
+            let _ = write!(writer, "\r{:line_len$}\r", "").and_then(|_| writer.flush());
+        });
+        ProgressBar {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns how many percent of the total are done, which some passes go past.
+fn percent(done: usize, total: usize) -> usize {
+    (done as u128 * 100 / total.max(1) as u128).min(100) as usize
+}
+
+/// Returns the line of a bar that got the given percentage of the way.
+fn render(label: &str, percent: usize) -> String {
+    let filled = percent * WIDTH / 100;
+    format!(
+        "{label} [{}{}] {percent:>3}%",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_progress_bar() {
+        assert_eq!(
+            render("Filling", 50),
+            "Filling [###############---------------]  50%"
+        );
+        assert_eq!(percent(3, 4), 75);
+        assert_eq!(percent(10, 4), 100);
+        assert_eq!(percent(0, 0), 0);
+
+        let counter = Arc::new(AtomicUsize::new(1000));
+        let writer = Shared::default();
+        let bar = ProgressBar::start(writer.clone(), "Filling", 200, Arc::clone(&counter));
+        counter.fetch_add(100, Ordering::Relaxed);
+        thread::sleep(POLL_INTERVAL * 3);
+        drop(bar);
+        let text = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        assert!(text.starts_with("\rFilling ["), "{text:?}");
+        assert!(text.contains("  50%"), "{text:?}");
+        // The bar clears its line when it is done.
+        assert!(text.ends_with(&format!("\r{}\r", " ".repeat(render("Filling", 0).len()))));
+    }
+}
@@ -0,0 +1,72 @@
+//! A file whose contents the detector memory is filled with instead of a pattern, to watch over a particular dataset.
+//! Only the checksums of the blocks of the file are kept in memory, the file itself is read again
+//! to find the flipped bytes of a block whose checksum no longer matches, and to put them right.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// An open reference file, which must not change while the detector runs.
+pub struct Reference {
+    file: Mutex<File>,
+    len: usize,
+}
+
+impl Reference {
+    /// Opens the file, which must not be empty.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the file is empty",
+            ));
+        }
+        let len = usize::try_from(len).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the file is larger than the address space",
+            )
+        })?;
+        Ok(Reference {
+            file: Mutex::new(file),
+            len,
+        })
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Reads the bytes of the file from the given offset on into the buffer, which has to be filled completely.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = self.file.lock().expect("a detector thread panicked");
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_reference_file() {
+        let dir = TempDir::new("reference");
+        let path = dir.join("reference");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let reference = Reference::open(&path).unwrap();
+        assert_eq!(reference.size(), 10);
+        let mut buf = [0; 4];
+        reference.read(3, &mut buf).unwrap();
+        assert_eq!(&buf, b"3456");
+        assert!(reference.read(8, &mut buf).is_err());
+
+        std::fs::write(&path, b"").unwrap();
+        assert!(Reference::open(&path).is_err());
+    }
+}
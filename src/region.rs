@@ -0,0 +1,558 @@
+use std::alloc::{alloc_zeroed, Layout};
+use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut};
+use std::ptr::write_volatile;
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use std::{io, ptr::NonNull};
+
+use log::warn;
+
+use crate::backend::MemoryBackend;
+use crate::config::AllocationMode;
+use crate::detector::maximum_size_in_mode;
+#[cfg(feature = "numa")]
+use crate::numa;
+#[cfg(feature = "numa")]
+pub use crate::numa::NumaNodes;
+
+/// The size of the regions the detector memory is allocated in, unless told otherwise.
+pub const DEFAULT_REGION_SIZE: usize = 256 * 1024 * 1024;
+
+/// The smallest page size of the platforms we run on. Touching one byte every this many bytes faults in every page.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Returns the size of the pages the operating system maps memory in, or `None` if it can not tell.
+pub fn system_page_size() -> Option<usize> {
+    #[cfg(unix)]
+    return usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) })
+        .ok()
+        .filter(|&size| size > 0);
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+        let mut info: SYSTEM_INFO = unsafe { std::mem::zeroed() };
+        unsafe { GetSystemInfo(&mut info) };
+        usize::try_from(info.dwPageSize)
+            .ok()
+            .filter(|&size| size > 0)
+    }
+    #[cfg(not(any(unix, windows)))]
+    return None;
+}
+
+/// Every retry of an allocation that came up short asks for this many tenths of the previous attempt.
+const RETRY_TENTHS: usize = 9;
+/// Allocations are not retried with less than this many tenths of the requested size,
+/// the last attempt keeps whatever it could allocate.
+const RETRY_FLOOR_TENTHS: usize = 5;
+/// How long to wait before the first retry, doubled for every retry after it,
+/// which gives whatever grabbed the memory in the meantime a chance to settle down.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How the memory of the detector should be allocated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegionOptions {
+    /// Back the regions with huge pages of this size, if possible.
+    pub huge_page_size: Option<NonZeroUsize>,
+    /// Stop allocating before less than this many bytes of memory would remain available.
+    pub reserve: Option<usize>,
+    /// The size of every region but the last, [`DEFAULT_REGION_SIZE`] if not given.
+    /// It is rounded up to a whole number of pages, and of huge pages if they are used.
+    pub region_size: Option<NonZeroUsize>,
+    #[cfg(feature = "numa")]
+    /// Put the regions on these NUMA nodes.
+    pub numa: Option<NumaNodes>,
+    /// Probe for as much of the size as can be allocated with [`allocate_regions_probing`],
+    /// instead of retrying with less of it, for a size that is only an upper bound.
+    pub probe: bool,
+    /// Only use the whole pages of memory from the heap, which the allocator does not necessarily align to pages.
+    /// Every region is then cut down to whole pages, see [`Region::trimmed_bytes`].
+    pub align_pages: bool,
+}
+
+impl RegionOptions {
+    fn region_size(&self) -> usize {
+        let size = self
+            .region_size
+            .map_or(DEFAULT_REGION_SIZE, NonZeroUsize::get)
+            .next_multiple_of(PAGE_SIZE);
+        match self.huge_page_size {
+            Some(page_size) => size.next_multiple_of(page_size.get()),
+            None => size,
+        }
+    }
+
+    /// Returns how much of the given size the regions can cover, which is only its whole pages with [`RegionOptions::align_pages`].
+    fn usable_size(&self, size: usize) -> usize {
+        if self.align_pages {
+            size / PAGE_SIZE * PAGE_SIZE
+        } else {
+            size
+        }
+    }
+}
+
+/// A part of the memory that makes up the detector.
+pub enum Region {
+    /// Memory from the heap, backed by normal pages.
+    Heap(Vec<u8>),
+    /// The whole pages in the middle of memory from the heap, from `start` on for `len` bytes.
+    Aligned {
+        buffer: Vec<u8>,
+        start: usize,
+        len: usize,
+    },
+    #[cfg(target_os = "linux")]
+    /// An anonymous memory mapping backed by huge pages.
+    HugePages {
+        ptr: NonNull<u8>,
+        len: usize,
+        mapped_len: usize,
+    },
+    #[cfg(unix)]
+    /// A shared mapping of a file, so that the contents of the region end up in the file and outlive the detector.
+    File { ptr: NonNull<u8>, len: usize },
+}
+
+// SAFETY: a region owns its memory just like a Vec does, the pointer is never shared.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+/// Allocates the detector memory one region at a time, touching every page so that it is really backed by RAM
+/// before going on to the next one. This stops early, keeping the regions allocated so far, as soon as the next
+/// region can not be allocated or would leave less than the reserve of memory available.
+/// So the regions may add up to less than `size` bytes, or even none at all.
+/// Regions are at most [`RegionOptions::region_size`] bytes long, and all of them but the last are
+/// made up of whole pages, so every region starts at a page boundary of the detector memory.
+pub fn allocate_regions(size: usize, mut options: RegionOptions) -> Vec<Region> {
+    #[cfg(feature = "numa")]
+    if let Some(NumaNodes::All) = options.numa {
+        match numa::nodes() {
+            Ok(nodes) if !nodes.is_empty() => {
+                // Every node gets the same number of whole pages, and the last one the rest.
+                let share = size / nodes.len() / PAGE_SIZE * PAGE_SIZE;
+                let mut regions = Vec::new();
+                for (i, &node) in nodes.iter().enumerate() {
+                    let len = if i + 1 == nodes.len() {
+                        size - share * i
+                    } else {
+                        share
+                    };
+                    let node_options = RegionOptions {
+                        numa: Some(NumaNodes::Node(node)),
+                        ..options
+                    };
+                    regions.extend(allocate_regions(len, node_options));
+                }
+                return regions;
+            }
+            Ok(_) => warn!("no NUMA nodes are online, allocating the detector without binding it to any"),
+            Err(e) => warn!("could not find the NUMA nodes, allocating the detector without binding it to any: {e}"),
+        }
+        options.numa = None;
+    }
+
+    let size = options.usable_size(size);
+    let region_size = options.region_size();
+    let mut regions = Vec::new();
+    let mut allocated = 0;
+    while allocated < size {
+        let len = region_size.min(size - allocated);
+        if let Some(reserve) = options.reserve {
+            if maximum_size_in_mode(AllocationMode::Available) < len.saturating_add(reserve) {
+                break;
+            }
+        }
+        let Ok(mut region) = Region::allocate(len, options) else {
+            break;
+        };
+        // The pages are only placed on the node once they are touched below.
+        #[cfg(feature = "numa")]
+        if let Some(NumaNodes::Node(node)) = options.numa {
+            if let Err(e) = numa::bind_memory(&mut region, node) {
+                warn!("could not bind the detector memory to NUMA node {node}: {e}");
+                options.numa = None;
+            }
+        }
+        for i in (0..region.len()).step_by(PAGE_SIZE) {
+            unsafe { write_volatile(&mut region[i], 0) };
+        }
+        // Only warn once if huge pages can not be used.
+        if !region.is_huge_pages() {
+            options.huge_page_size = None;
+        }
+        allocated += len;
+        regions.push(region);
+    }
+    regions
+}
+
+/// Like [`allocate_regions`], but if not all of the memory could be allocated it is released again
+/// and the allocation retried with 90% of the size, until it succeeds or the size would drop below half of `size`.
+pub fn allocate_regions_retrying(size: usize, options: RegionOptions) -> Vec<Region> {
+    let floor = size / 10 * RETRY_FLOOR_TENTHS;
+    let mut target = size;
+    let mut delay = INITIAL_RETRY_DELAY;
+    loop {
+        let regions = allocate_regions(target, options);
+        let allocated: usize = regions.iter().map(|region| region.len()).sum();
+        let next = target / 10 * RETRY_TENTHS;
+        if allocated >= options.usable_size(target) || next < floor.max(1) {
+            return regions;
+        }
+        warn!("could only allocate {allocated} of {target} bytes, trying again with {next} bytes");
+        drop(regions);
+        thread::sleep(delay);
+        delay *= 2;
+        target = next;
+    }
+}
+
+/// Like [`allocate_regions`], but if not all of the memory could be allocated the rest is probed for in chunks
+/// of half the size of the region that failed, halving the chunks again whenever one fails, down to a single page.
+/// This is a binary search for the largest size that can be committed, which keeps every chunk that could
+/// be allocated, so it never has to release memory, nor hold more of it than it ends up with.
+pub fn allocate_regions_probing(size: usize, options: RegionOptions) -> Vec<Region> {
+    let granularity = options
+        .huge_page_size
+        .map_or(PAGE_SIZE, |page_size| page_size.get().max(PAGE_SIZE));
+    probe(
+        options.usable_size(size),
+        options.region_size(),
+        granularity,
+        |len| allocate_regions(len, options),
+    )
+}
+
+fn probe(
+    size: usize,
+    region_size: usize,
+    granularity: usize,
+    mut allocate: impl FnMut(usize) -> Vec<Region>,
+) -> Vec<Region> {
+    let length = |regions: &[Region]| regions.iter().map(|region| region.len()).sum::<usize>();
+    let mut regions = allocate(size);
+    let mut allocated = length(&regions);
+    // The allocation stopped at the first region that could not be allocated, so less than one region is left to find.
+    let halve = |chunk: usize| chunk / 2 / granularity * granularity;
+    let mut chunk = halve(region_size.min(size - allocated));
+    while allocated < size && chunk > 0 {
+        let len = chunk.min(size - allocated);
+        let more = allocate(len);
+        let found = length(&more);
+        if found < len {
+            chunk = halve(chunk);
+        }
+        allocated += found;
+        regions.extend(more);
+    }
+    regions
+}
+
+/// Allocates a zeroed region of the given size, or returns `None` if there is not enough memory for it.
+/// If huge pages are requested but can not be used the region falls back to normal pages with a warning.
+pub fn allocate_region(size: usize, options: RegionOptions) -> Option<Region> {
+    if let Some(page_size) = options.huge_page_size {
+        #[cfg(target_os = "linux")]
+        match Region::huge_pages(size, page_size) {
+            Ok(region) => return Some(region),
+            Err(e) => warn!(
+                "could not allocate the detector with huge pages of {page_size} bytes, falling back to normal pages: {e}"
+            ),
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        warn!(
+            "huge pages of {page_size} bytes are only supported on Linux, falling back to normal pages"
+        );
+    }
+
+    if size == 0 {
+        return Some(Region::Heap(Vec::new()));
+    }
+    if options.align_pages {
+        // With a page more than asked for, the whole pages of the size fit in between the page boundaries.
+        let buffer = allocate_heap(size.checked_add(PAGE_SIZE)?)?;
+        let start = buffer.as_ptr().align_offset(PAGE_SIZE);
+        return Some(Region::Aligned {
+            buffer,
+            start,
+            len: size / PAGE_SIZE * PAGE_SIZE,
+        });
+    }
+    allocate_heap(size).map(Region::Heap)
+}
+
+/// Allocates `size` zeroed bytes on the heap, or returns `None` if there is not enough memory for them.
+fn allocate_heap(size: usize) -> Option<Vec<u8>> {
+    // Unlike `vec![0; size]` this reports a failed allocation instead of aborting.
+    let layout = Layout::array::<u8>(size).ok()?;
+    let ptr = unsafe { alloc_zeroed(layout) };
+    // SAFETY: the memory was allocated by the global allocator with the layout of `size` bytes, all of them zeroed.
+    (!ptr.is_null()).then(|| unsafe { Vec::from_raw_parts(ptr, size, size) })
+}
+
+impl Region {
+    #[cfg(target_os = "linux")]
+    /// Maps an anonymous region backed by huge pages of the given size.
+    /// The mapping is rounded up to a whole number of huge pages.
+    fn huge_pages(size: usize, page_size: NonZeroUsize) -> io::Result<Self> {
+        if !page_size.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the huge page size must be a power of two",
+            ));
+        }
+
+        let mapped_len = size.div_ceil(page_size.get()).max(1) * page_size.get();
+        // The size of the huge pages is encoded as its base 2 logarithm in the upper bits of the flags.
+        let page_size_flag = (page_size.trailing_zeros() as libc::c_int) << libc::MAP_HUGE_SHIFT;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mapped_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB | page_size_flag,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Region::HugePages {
+            ptr: NonNull::new(ptr.cast()).expect("mmap does not return null on success"),
+            len: size,
+            mapped_len,
+        })
+    }
+
+    #[cfg(unix)]
+    /// Maps `len` bytes of the file starting at `offset`, which must be a multiple of the page size.
+    /// The file must be at least `offset + len` bytes long.
+    pub fn map_file(file: &std::fs::File, offset: u64, len: usize) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can not map an empty region",
+            ));
+        }
+        let offset = libc::off_t::try_from(offset).map_err(io::Error::other)?;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Region::File {
+            ptr: NonNull::new(ptr.cast()).expect("mmap does not return null on success"),
+            len,
+        })
+    }
+
+    /// Returns whether the region is backed by huge pages.
+    pub fn is_huge_pages(&self) -> bool {
+        match self {
+            Region::Heap(_) | Region::Aligned { .. } => false,
+            #[cfg(target_os = "linux")]
+            Region::HugePages { .. } => true,
+            #[cfg(unix)]
+            Region::File { .. } => false,
+        }
+    }
+
+    /// Returns how many bytes of the memory that was allocated for the region are not part of it,
+    /// because they are not in a whole page of it.
+    pub fn trimmed_bytes(&self) -> usize {
+        match self {
+            Region::Aligned { buffer, len, .. } => buffer.len() - len,
+            _ => 0,
+        }
+    }
+}
+
+impl Deref for Region {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Region::Heap(v) => v,
+            Region::Aligned { buffer, start, len } => &buffer[*start..*start + *len],
+            #[cfg(target_os = "linux")]
+            Region::HugePages { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr(), *len)
+            },
+            #[cfg(unix)]
+            Region::File { ptr, len } => unsafe { std::slice::from_raw_parts(ptr.as_ptr(), *len) },
+        }
+    }
+}
+
+impl DerefMut for Region {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Region::Heap(v) => v,
+            Region::Aligned { buffer, start, len } => &mut buffer[*start..*start + *len],
+            #[cfg(target_os = "linux")]
+            Region::HugePages { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts_mut(ptr.as_ptr(), *len)
+            },
+            #[cfg(unix)]
+            Region::File { ptr, len } => unsafe {
+                std::slice::from_raw_parts_mut(ptr.as_ptr(), *len)
+            },
+        }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Region::HugePages {
+            ptr, mapped_len, ..
+        } = self
+        {
+            unsafe { libc::munmap(ptr.as_ptr().cast(), *mapped_len) };
+        }
+        #[cfg(unix)]
+        if let Region::File { ptr, len } = self {
+            // Write the contents back to the file before letting go of them, so that the next run finds them there.
+            unsafe {
+                libc::msync(ptr.as_ptr().cast(), *len, libc::MS_SYNC);
+                libc::munmap(ptr.as_ptr().cast(), *len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_huge_page_fallback() {
+        // Whether or not huge pages are available we should get a zeroed region of the requested size.
+        let mut region = allocate_region(
+            3 * 1024 * 1024,
+            RegionOptions {
+                huge_page_size: NonZeroUsize::new(2 * 1024 * 1024),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(region.len(), 3 * 1024 * 1024);
+        assert!(region.iter().all(|&b| b == 0));
+        region[3 * 1024 * 1024 - 1] = 42;
+        assert_eq!(region[3 * 1024 * 1024 - 1], 42);
+
+        let region = allocate_region(100, RegionOptions::default()).unwrap();
+        assert!(!region.is_huge_pages());
+        assert_eq!(region.len(), 100);
+    }
+
+    #[test]
+    fn check_aligned_regions() {
+        let options = RegionOptions {
+            align_pages: true,
+            ..Default::default()
+        };
+        let region = allocate_region(3 * PAGE_SIZE + 100, options).unwrap();
+        assert_eq!(region.len(), 3 * PAGE_SIZE);
+        assert_eq!(region.as_ptr() as usize % PAGE_SIZE, 0);
+        assert_eq!(region.trimmed_bytes(), PAGE_SIZE + 100);
+        assert!(region.iter().all(|&b| b == 0));
+
+        // Only the whole pages are asked for, so there is nothing to retry or probe for.
+        for allocate in [allocate_regions_retrying, allocate_regions_probing] {
+            let regions = allocate(5 * PAGE_SIZE + 100, options);
+            assert_eq!(
+                regions.iter().map(|r| r.len()).collect::<Vec<_>>(),
+                [5 * PAGE_SIZE]
+            );
+        }
+        assert_eq!(
+            allocate_region(100, RegionOptions::default())
+                .unwrap()
+                .trimmed_bytes(),
+            0
+        );
+    }
+
+    #[test]
+    fn check_region_sizes() {
+        let options = RegionOptions {
+            region_size: NonZeroUsize::new(5000),
+            ..Default::default()
+        };
+        // The region size is rounded up to two pages.
+        let regions = allocate_regions(5 * PAGE_SIZE, options);
+        assert_eq!(
+            regions.iter().map(|r| r.len()).collect::<Vec<_>>(),
+            [2 * PAGE_SIZE, 2 * PAGE_SIZE, PAGE_SIZE]
+        );
+        assert!(regions.iter().flat_map(|r| r.iter()).all(|&b| b == 0));
+
+        // No machine has this much memory to spare.
+        let regions = allocate_regions(
+            PAGE_SIZE,
+            RegionOptions {
+                reserve: Some(usize::MAX / 2),
+                ..options
+            },
+        );
+        assert!(regions.is_empty());
+    }
+
+    #[test]
+    fn check_probing() {
+        // Pretend that there is only room for 10 pages and 100 bytes, in regions of 4 pages.
+        let probe_with_room = |size: usize, room: usize| {
+            let mut left = room;
+            let regions = probe(size, 4 * PAGE_SIZE, PAGE_SIZE, |len| {
+                let mut regions = Vec::new();
+                let mut allocated = 0;
+                while allocated < len {
+                    let region_len = (4 * PAGE_SIZE).min(len - allocated);
+                    if region_len > left {
+                        break;
+                    }
+                    left -= region_len;
+                    allocated += region_len;
+                    regions.push(allocate_region(region_len, RegionOptions::default()).unwrap());
+                }
+                regions
+            });
+            regions.iter().map(|r| r.len()).collect::<Vec<_>>()
+        };
+        let room = 10 * PAGE_SIZE + 100;
+        // The two regions that fit are kept, and the two pages after them are found in halves of the next region.
+        assert_eq!(
+            probe_with_room(100 * PAGE_SIZE, room),
+            [4 * PAGE_SIZE, 4 * PAGE_SIZE, 2 * PAGE_SIZE]
+        );
+        // Whatever fits is not probed for.
+        assert_eq!(
+            probe_with_room(6 * PAGE_SIZE, room),
+            [4 * PAGE_SIZE, 2 * PAGE_SIZE]
+        );
+        assert!(probe_with_room(100 * PAGE_SIZE, 100).is_empty());
+        assert_eq!(
+            probe_with_room(100 * PAGE_SIZE, 3 * PAGE_SIZE),
+            [2 * PAGE_SIZE, PAGE_SIZE]
+        );
+    }
+}
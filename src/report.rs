@@ -0,0 +1,109 @@
+//! The JSON report of a whole run that `--report` writes when the detector stops, the counterpart of the summary
+//! for a database of test runs rather than a person.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use humantime::format_rfc3339;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::events::Totals;
+use crate::summary::Summary;
+
+/// The statistics of a run as they are written to the report file, the same as those of the shutdown event
+/// together with when and how the run was started.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Report {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    started: String,
+    stopped: String,
+    arguments: Vec<String>,
+    /// The environment variables that set an option, which the arguments do not show.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, String>,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+impl Report {
+    /// Puts together the report of a run with the given summary, that was started with the given arguments
+    /// and environment variables and tagged with the given tag, see `--tag`.
+    pub fn new(
+        summary: &Summary,
+        arguments: &[String],
+        environment: &[(String, String)],
+        tag: Option<&str>,
+    ) -> Self {
+        Report {
+            tag: tag.map(str::to_owned),
+            started: format_rfc3339(summary.started()).to_string(),
+            stopped: format_rfc3339(SystemTime::now()).to_string(),
+            arguments: arguments.to_vec(),
+            environment: environment.iter().cloned().collect(),
+            totals: Totals::new(summary),
+        }
+    }
+
+    /// Writes the report to a temporary file next to the given path and renames it to the path once it is complete,
+    /// so that the path holds either the whole report or whatever it held before.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = Path::new(&temporary);
+        let result = (|| {
+            let mut file = File::create(temporary)?;
+            serde_json::to_writer_pretty(&mut file, self)?;
+            writeln!(file)?;
+            file.sync_all()?;
+            fs::rename(temporary, path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(temporary);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn check_report_file() {
+        let dir = TempDir::new("report");
+        let path = dir.join("report.json");
+        let mut summary = Summary::new(1000);
+        summary.record_check(&[]);
+        summary.stuck_bits = 2;
+        Report::new(
+            &summary,
+            &["-m".to_owned(), "1kB".to_owned()],
+            &[("COSMIC_PATTERN".to_owned(), "ones".to_owned())],
+            Some("rack-3"),
+        )
+        .write(&path)
+        .unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["tag"], "rack-3");
+        assert_eq!(report["arguments"], serde_json::json!(["-m", "1kB"]));
+        assert_eq!(report["environment"]["COSMIC_PATTERN"], "ones");
+        assert_eq!(report["monitored_bytes"], 1000);
+        assert_eq!(report["checks"], 1);
+        assert_eq!(report["stuck_bits"], 2);
+        assert!(humantime::parse_rfc3339(report["started"].as_str().unwrap()).is_ok());
+        // Nothing is left behind next to the report.
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+    }
+}
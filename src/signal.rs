@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How long we sleep at a time while waiting, so that a stop or pause request is noticed promptly.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Installs a handler for SIGINT and SIGTERM (Ctrl-C and Ctrl-Break on Windows)
+/// and returns a flag that is set when one of them is received.
+///
+/// The handler only sets the flag, it is up to the detection loop to notice it.
+/// This way an integrity check that is in progress, parallel or not, always runs to completion.
+pub fn stop_flag() -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))?;
+    Ok(stop)
+}
+
+/// Set and cleared by the SIGUSR1 handler.
+static PAUSE: AtomicBool = AtomicBool::new(false);
+
+/// Installs a handler for SIGUSR1 that toggles the returned flag, so that checking can be paused and resumed
+/// with `kill -USR1 <pid>`. There is no equivalent on Windows yet, where the flag is never set.
+pub fn pause_flag() -> std::io::Result<&'static AtomicBool> {
+    #[cfg(unix)]
+    {
+        extern "C" fn toggle(_signal: libc::c_int) {
+            // Atomic operations are safe to use in a signal handler, unlike nearly everything else.
+            PAUSE.fetch_xor(true, Ordering::SeqCst);
+        }
+
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = toggle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        if unsafe { libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(&PAUSE)
+}
+
+/// Returns how long the wait for the check that is due at `check_at` sleeps before it looks at the flags again,
+/// waking up for the heartbeat that is due next as well. While checking is paused that is the poll interval,
+/// however long ago the check was due, so that a pause does not keep the CPU busy.
+pub fn poll_time(
+    now: Instant,
+    check_at: Instant,
+    heartbeat: Option<Instant>,
+    paused: bool,
+) -> Duration {
+    let wake = if paused {
+        now + POLL_INTERVAL
+    } else {
+        check_at
+    };
+    let wake = heartbeat.map_or(wake, |heartbeat| heartbeat.min(wake));
+    wake.saturating_duration_since(now).min(POLL_INTERVAL)
+}
+
+/// Sleeps for the given duration, or until the stop flag is set.
+pub fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let start = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let remaining = duration.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_poll_time() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(poll_time(now, now + ms(30), None, false), ms(30));
+        assert_eq!(
+            poll_time(now, now + ms(30), Some(now + ms(10)), false),
+            ms(10)
+        );
+        assert_eq!(poll_time(now, now + ms(1000), None, false), POLL_INTERVAL);
+        assert_eq!(poll_time(now, now, None, false), Duration::ZERO);
+        assert_eq!(poll_time(now, now, None, true), POLL_INTERVAL);
+        assert_eq!(poll_time(now, now, Some(now + ms(10)), true), ms(10));
+    }
+
+    #[test]
+    fn check_paused_wait_does_not_spin() {
+        // The wait while a check is overdue and checking is paused, which has to sleep rather than spin.
+        let stop = AtomicBool::new(false);
+        let start = Instant::now();
+        let mut polls = 0;
+        while start.elapsed() < Duration::from_millis(350) {
+            sleep_unless_stopped(poll_time(Instant::now(), start, None, true), &stop);
+            polls += 1;
+        }
+        assert!(polls <= 5, "polled {polls} times in 350ms");
+
+        // The stop flag ends the sleep right away.
+        stop.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        sleep_unless_stopped(Duration::from_secs(10), &stop);
+        assert!(start.elapsed() < POLL_INTERVAL);
+    }
+}
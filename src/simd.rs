@@ -0,0 +1,103 @@
+//! Comparing memory with a constant byte many bytes at a time, which is what limits how often a large detector
+//! filled with a constant pattern can be checked. On x86_64 this compares 32 bytes at a time with AVX2 if the CPU
+//! supports it and 16 bytes at a time with SSE2 otherwise. Elsewhere it falls back to comparing byte by byte.
+
+use std::ptr::read_volatile;
+
+/// Returns the index of the first byte of the buffer that is not `value`, comparing one byte at a time.
+pub fn find_mismatch_scalar(buf: &[u8], value: u8) -> Option<usize> {
+    buf.iter()
+        .position(|r| unsafe { read_volatile(r) } != value)
+}
+
+/// Returns the index of the first byte of the buffer that is not `value`.
+#[cfg(target_arch = "x86_64")]
+pub fn find_mismatch(buf: &[u8], value: u8) -> Option<usize> {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { x86::find_mismatch_avx2(buf, value) }
+    } else {
+        // SSE2 is part of x86_64, so every CPU that runs this has it.
+        unsafe { x86::find_mismatch_sse2(buf, value) }
+    }
+}
+
+/// Returns the index of the first byte of the buffer that is not `value`.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn find_mismatch(buf: &[u8], value: u8) -> Option<usize> {
+    find_mismatch_scalar(buf, value)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::find_mismatch_scalar;
+    use std::arch::x86_64::*;
+    use std::ptr::read_volatile;
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_mismatch_avx2(buf: &[u8], value: u8) -> Option<usize> {
+        // Volatile reads of whole vectors have to be aligned, so the unaligned edges are compared byte by byte.
+        let (head, body, tail) = buf.align_to::<__m256i>();
+        if let Some(i) = find_mismatch_scalar(head, value) {
+            return Some(i);
+        }
+        let expected = _mm256_set1_epi8(value as i8);
+        for (v, vector) in body.iter().enumerate() {
+            // Every bit of the mask tells whether the corresponding byte of the vector matched.
+            let matched =
+                _mm256_movemask_epi8(_mm256_cmpeq_epi8(read_volatile(vector), expected)) as u32;
+            if matched != u32::MAX {
+                return Some(head.len() + 32 * v + (!matched).trailing_zeros() as usize);
+            }
+        }
+        find_mismatch_scalar(tail, value).map(|i| head.len() + 32 * body.len() + i)
+    }
+
+    /// # Safety
+    /// The CPU must support SSE2.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_mismatch_sse2(buf: &[u8], value: u8) -> Option<usize> {
+        let (head, body, tail) = buf.align_to::<__m128i>();
+        if let Some(i) = find_mismatch_scalar(head, value) {
+            return Some(i);
+        }
+        let expected = _mm_set1_epi8(value as i8);
+        for (v, vector) in body.iter().enumerate() {
+            let matched = _mm_movemask_epi8(_mm_cmpeq_epi8(read_volatile(vector), expected)) as u32;
+            if matched != 0xFFFF {
+                return Some(head.len() + 16 * v + (!matched).trailing_zeros() as usize);
+            }
+        }
+        find_mismatch_scalar(tail, value).map(|i| head.len() + 16 * body.len() + i)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_mismatch_detection() {
+        let mut buf = [0x3C; 200];
+        // Every alignment of the start and every position of the mismatch, including the unaligned edges.
+        for start in 0..40 {
+            assert_eq!(find_mismatch(&buf[start..], 0x3C), None);
+            for i in start..buf.len() {
+                buf[i] = 0x3D;
+                assert_eq!(find_mismatch(&buf[start..], 0x3C), Some(i - start));
+                assert_eq!(find_mismatch_scalar(&buf[start..], 0x3C), Some(i - start));
+                buf[i] = 0x3C;
+            }
+        }
+        assert_eq!(find_mismatch(&[], 0), None);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn check_sse2_fallback() {
+        let mut buf = [0; 100];
+        buf[77] = 0x80;
+        assert_eq!(unsafe { x86::find_mismatch_sse2(&buf[3..], 0) }, Some(74));
+    }
+}
@@ -0,0 +1,147 @@
+//! Checking the detector memory a few slices at a time, for rolling checks with `--rolling` and for passes
+//! that can be interrupted with `--pass-timeout`, which carry on where the previous check stopped.
+
+use std::time::{Duration, Instant};
+
+use crate::coverage::Coverage;
+
+/// Keeps track of which slice of the detector the next check starts at, and how far the current pass got.
+#[derive(Debug)]
+pub struct SliceCursor {
+    slices: usize,
+    next: usize,
+    /// How many slices the last check went through.
+    checked: usize,
+    /// How many slices of the current pass have been checked, including the ones of the last check.
+    covered: usize,
+}
+
+impl SliceCursor {
+    /// Starts at the first of the given number of slices.
+    /// # Panic
+    /// Panics if there are no slices.
+    pub fn new(slices: usize) -> Self {
+        assert!(slices > 0, "there are no slices to check");
+        SliceCursor {
+            slices,
+            next: 0,
+            checked: 0,
+            covered: 0,
+        }
+    }
+
+    /// Checks the slices from the cursor on and moves the cursor past them. Without a pass timeout that is
+    /// a single slice of a rolling check, with one as many as fit into the timeout, up to the end of the pass.
+    pub fn check<T>(
+        &mut self,
+        pass_timeout: Option<Duration>,
+        mut check: impl FnMut(usize) -> Vec<T>,
+    ) -> Vec<T> {
+        let start = Instant::now();
+        // The check after the end of a pass begins the next one.
+        if self.next == 0 {
+            self.covered = 0;
+        }
+        self.checked = 0;
+        let mut found = Vec::new();
+        loop {
+            found.extend(check(self.next));
+            self.next = (self.next + 1) % self.slices;
+            self.checked += 1;
+            if self.next == 0 || pass_timeout.is_none_or(|timeout| start.elapsed() >= timeout) {
+                break;
+            }
+        }
+        self.covered += self.checked;
+        found
+    }
+
+    /// Returns the number of slices.
+    pub fn slices(&self) -> usize {
+        self.slices
+    }
+
+    /// Returns how many slices the last check went through.
+    pub fn checked(&self) -> usize {
+        self.checked
+    }
+
+    /// Returns whether the last check finished a pass over the whole detector.
+    pub fn finished_pass(&self) -> bool {
+        self.checked > 0 && self.next == 0
+    }
+
+    /// Returns how much of the detector of the given number of pages the last check
+    /// and the current pass so far went through.
+    pub fn coverage(&self, pages: usize) -> Coverage {
+        Coverage {
+            pages,
+            checked: self.checked * pages / self.slices,
+            covered: self.covered * pages / self.slices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_rolling_slices() {
+        let mut cursor = SliceCursor::new(3);
+        assert!(!cursor.finished_pass());
+        // Without a timeout every check is one slice.
+        for (slice, finished) in [(0, false), (1, false), (2, true), (0, false)] {
+            assert_eq!(cursor.check(None, |s| vec![s]), [slice]);
+            assert_eq!(cursor.checked(), 1);
+            assert_eq!(cursor.finished_pass(), finished);
+        }
+    }
+
+    #[test]
+    fn check_pass_timeout() {
+        let mut cursor = SliceCursor::new(4);
+        // A timeout that is over right away still checks one slice, so that the detector is not stuck.
+        assert_eq!(cursor.check(Some(Duration::ZERO), |s| vec![s]), [0]);
+        // One that is long enough goes up to the end of the pass, but not past it.
+        assert_eq!(
+            cursor.check(Some(Duration::from_secs(3600)), |s| vec![s]),
+            [1, 2, 3]
+        );
+        assert!(cursor.finished_pass());
+        assert_eq!(cursor.checked(), 3);
+        // The timeout counts from the start of the check.
+        let found = cursor.check(Some(Duration::from_millis(50)), |s| {
+            std::thread::sleep(Duration::from_millis(30));
+            vec![s]
+        });
+        assert_eq!(found, [0, 1]);
+    }
+
+    #[test]
+    fn check_slice_coverage() {
+        let mut cursor = SliceCursor::new(4);
+        let pages = 100;
+        cursor.check(Some(Duration::ZERO), |_| Vec::<()>::new());
+        assert_eq!(
+            cursor.coverage(pages),
+            Coverage {
+                pages,
+                checked: 25,
+                covered: 25
+            }
+        );
+        cursor.check(Some(Duration::from_secs(3600)), |_| Vec::<()>::new());
+        assert_eq!(cursor.coverage(pages).covered, pages);
+        // The next pass starts from nothing again, rather than staying at the whole detector.
+        cursor.check(Some(Duration::ZERO), |_| Vec::<()>::new());
+        assert_eq!(
+            cursor.coverage(pages),
+            Coverage {
+                pages,
+                checked: 25,
+                covered: 25
+            }
+        );
+    }
+}
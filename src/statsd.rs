@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::metrics::{Kind, Metrics};
+
+/// How often the metrics are pushed without a heartbeat interval.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Starts pushing the metrics to the StatsD server at the given address on a background thread,
+/// once right away and then at the given interval. The address is resolved before this function returns,
+/// so that a bad address is reported immediately, while failures to send are only logged, as UDP may drop packets anyway.
+pub fn push(addr: &str, interval: Duration, metrics: Arc<Metrics>) -> io::Result<()> {
+    let server = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{addr} does not resolve to any address"),
+        )
+    })?;
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+
+    thread::spawn(move || {
+        let mut pushed = HashMap::new();
+        loop {
+            if let Err(e) = socket.send(packet(&metrics, &mut pushed).as_bytes()) {
+                debug!("could not send the metrics to the StatsD server {server}: {e}");
+            }
+            thread::sleep(interval);
+        }
+    });
+    Ok(())
+}
+
+/// Renders the metrics as StatsD lines, one per metric. A counter is sent as the amount it grew
+/// since the values in `pushed`, which are updated, and a gauge as its value.
+/// The tag of the metrics is added to every line the way DogStatsD and Telegraf understand it.
+fn packet(metrics: &Metrics, pushed: &mut HashMap<&'static str, f64>) -> String {
+    let tags = metrics
+        .tag()
+        .map_or(String::new(), |tag| format!("|#tag:{tag}"));
+    let mut lines = Vec::new();
+    for sample in metrics.samples() {
+        match sample.kind {
+            Kind::Counter => {
+                let previous = pushed.insert(sample.name, sample.value).unwrap_or(0.0);
+                lines.push(format!(
+                    "{}:{}|c{tags}",
+                    sample.name,
+                    sample.value - previous
+                ));
+            }
+            Kind::Gauge => lines.push(format!("{}:{}|g{tags}", sample.name, sample.value)),
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_statsd_push() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let metrics = Arc::new(Metrics::new(1000));
+        metrics.record_check(2);
+        push(
+            &server.local_addr().unwrap().to_string(),
+            Duration::from_millis(50),
+            Arc::clone(&metrics),
+        )
+        .unwrap();
+
+        let mut buf = [0; 1024];
+        let mut receive = || {
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+        let first = receive();
+        let lines: Vec<&str> = first.lines().collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "cosmic_ray_flips_total:2|c",
+                "cosmic_ray_checks_total:1|c",
+                "cosmic_ray_monitored_bytes:1000|g"
+            ]
+        );
+        assert!(lines[3].starts_with("cosmic_ray_uptime_seconds:"));
+        assert!(lines[3].ends_with("|g"));
+
+        // Counters only send what was added since the last push.
+        metrics.record_check(1);
+        let (mut flips, mut checks) = (0.0, 0.0);
+        while (flips, checks) != (1.0, 1.0) {
+            for line in receive().lines() {
+                let (name, value) = line.split_once(':').unwrap();
+                let value: f64 = value.strip_suffix("|c").map_or(0.0, |v| v.parse().unwrap());
+                match name {
+                    "cosmic_ray_flips_total" => flips += value,
+                    "cosmic_ray_checks_total" => checks += value,
+                    _ => {}
+                }
+            }
+            assert!(flips <= 1.0 && checks <= 1.0);
+        }
+
+        assert!(push("not a host name", Duration::from_secs(1), metrics).is_err());
+
+        let metrics = Metrics::new(1000).with_tag(Some("rack-3".to_owned()));
+        let packet = packet(&metrics, &mut HashMap::new());
+        assert!(packet.lines().all(|line| line.ends_with("|#tag:rack-3")));
+    }
+}
@@ -0,0 +1,649 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::coverage::Coverage;
+use crate::detector::{FlipClass, FlipEvent, Persistence};
+
+/// The exit status of a run that detected at least one flip.
+pub const FLIPS_DETECTED_EXIT_STATUS: u8 = 10;
+
+/// The exit status of a run with `--until-clean` that stopped before it checked a whole pass,
+/// and detected nothing until then.
+pub const NOT_VERIFIED_EXIT_STATUS: u8 = 12;
+
+/// How many bytes [`FlipCounts`] keeps track of at most, which takes a few MiB however large the detector is.
+const MAX_TRACKED_OFFSETS: usize = 1 << 16;
+
+/// How many times the bytes of the detector flipped, to find the ones that flip again and again.
+/// At most [`MAX_TRACKED_OFFSETS`] bytes are tracked: once that many are, the ones that flipped only once are
+/// forgotten to make room, and if all of them flipped repeatedly, bytes that flip for the first time are not tracked.
+#[derive(Debug, Default)]
+pub struct FlipCounts {
+    counts: HashMap<usize, u64>,
+}
+
+impl FlipCounts {
+    /// Counts a flip of the byte at the given offset.
+    fn record(&mut self, offset: usize) {
+        if self.counts.len() >= MAX_TRACKED_OFFSETS && !self.counts.contains_key(&offset) {
+            self.counts.retain(|_, &mut count| count > 1);
+            if self.counts.len() >= MAX_TRACKED_OFFSETS {
+                return;
+            }
+        }
+        *self.counts.entry(offset).or_default() += 1;
+    }
+
+    /// Returns how many times the byte at the given offset flipped, as far as it is tracked.
+    pub fn get(&self, offset: usize) -> u64 {
+        self.counts.get(&offset).copied().unwrap_or(0)
+    }
+
+    /// Returns up to `n` of the bytes that flipped more than once as their offsets and numbers of flips,
+    /// the ones that flipped most often first.
+    pub fn repeated(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut repeated: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .filter(|(_, &count)| count > 1)
+            .map(|(&offset, &count)| (offset, count))
+            .collect();
+        repeated.sort_unstable_by_key(|&(offset, count)| (std::cmp::Reverse(count), offset));
+        repeated.truncate(n);
+        repeated
+    }
+}
+
+/// Statistics that are accumulated over the whole run.
+pub struct Summary {
+    start: Instant,
+    /// How long checking was paused, not counting a pause that is still going on.
+    paused: Duration,
+    paused_since: Option<Instant>,
+    /// How long the detector idled between the bursts of `--duty-cycle`, not counting an idle window
+    /// that is still going on or the time it was paused during one.
+    idle: Duration,
+    idle_since: Option<Instant>,
+    /// Whether an idle window is going on, which only counts while checking is not paused.
+    idling: bool,
+    /// How long refilling the detector memory took, during which it was not monitored.
+    refilling: Duration,
+    /// The number of bytes the detector monitors.
+    pub monitored_bytes: usize,
+    /// The number of integrity checks performed so far.
+    pub checks: u64,
+    /// The number of flipped bytes detected so far.
+    pub flips: u64,
+    /// The number of times every slice of the detector has been checked, when checking it one slice at a time.
+    pub passes: u64,
+    /// How long all of those passes took together.
+    pass_time: Duration,
+    /// The number of flipped bytes of words with a single flipped bit.
+    pub single_bit_flips: u64,
+    /// The number of flipped bytes of words with two flipped bits.
+    pub double_bit_flips: u64,
+    /// The number of flipped bytes of words with more than two flipped bits.
+    pub multi_bit_flips: u64,
+    /// The number of flipped bytes that were still wrong when they were read again.
+    pub persistent_flips: u64,
+    /// The number of flipped bytes that read as expected when they were read again.
+    pub transient_flips: u64,
+    /// The number of flipped bytes on every NUMA node of the detector, if it is bound to nodes.
+    pub flips_per_node: BTreeMap<u32, u64>,
+    /// How much of the current detector the checks verified, if they only verify some of its pages.
+    pub(crate) coverage: Option<Coverage>,
+    /// How many times the bytes of the current detector flipped.
+    pub flip_counts: FlipCounts,
+    /// The number of bytes that were suspected to have a stuck bit.
+    pub stuck_bits: u64,
+    /// The number of pages that are no longer monitored because of a suspected stuck bit.
+    pub excluded_pages: u64,
+    /// The number of times a canary page was found written to.
+    pub canary_violations: u64,
+    /// The number of times a page of the detector memory was found reclaimed by the operating system.
+    pub reclaimed_pages: u64,
+    /// The number of flips that a resumed backing file showed to have happened while no detector was running.
+    /// They are not part of [`Summary::flips`], which only counts what was found while monitoring.
+    pub downtime_flips: u64,
+    /// The number of times a block of the detector memory was found changed since the snapshot, see `--snapshot`.
+    pub changed_blocks: u64,
+    /// How the passes of `--until-clean` went, if the run is one.
+    pub verification: Option<Verification>,
+    /// The monitored bytes times the monitored seconds up to [`Summary::integrated_until`], which is what the chance
+    /// of a flip scales with. Every interval counts with the size of the detector during it.
+    byte_seconds: f64,
+    /// The monitored time up to which [`Summary::byte_seconds`] is accumulated.
+    integrated_until: Duration,
+    /// Where the current stage began.
+    stage_start: StageStart,
+}
+
+/// The passes of a run with `--until-clean`, which stops at the first one that found the memory free of flips.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// How many whole passes over the detector memory were checked.
+    pub passes: u64,
+    /// Whether the last of them found no flips, which ends the run.
+    pub clean: bool,
+}
+
+/// The monitored time and the counters of a [`Summary`] at the beginning of a stage.
+#[derive(Default)]
+struct StageStart {
+    monitored: Duration,
+    checks: u64,
+    flips: u64,
+}
+
+/// The statistics of one stage of a staircase run, which runs the detector at several sizes one after the other.
+pub struct Stage {
+    /// Which stage this is, counting from 1.
+    pub number: usize,
+    /// How many stages there are.
+    pub stages: usize,
+    pub monitored_bytes: usize,
+    /// How long the detector was checking in this stage, without the pauses.
+    pub monitored_time: Duration,
+    pub checks: u64,
+    pub flips: u64,
+    /// The size the detector of the next stage was allocated with, or `None` after the last stage.
+    pub next_bytes: Option<usize>,
+}
+
+impl Stage {
+    /// Returns the number of flips per gigabyte-hour in this stage, see [`Summary::flips_per_gb_hour`].
+    pub fn flips_per_gb_hour(&self) -> Option<f64> {
+        flip_rate(self.flips, self.monitored_bytes, self.monitored_time)
+    }
+}
+
+impl Summary {
+    /// Starts accumulating statistics for a detector of the given size.
+    pub fn new(monitored_bytes: usize) -> Self {
+        Summary {
+            start: Instant::now(),
+            paused: Duration::ZERO,
+            paused_since: None,
+            idle: Duration::ZERO,
+            idle_since: None,
+            idling: false,
+            refilling: Duration::ZERO,
+            monitored_bytes,
+            checks: 0,
+            flips: 0,
+            passes: 0,
+            pass_time: Duration::ZERO,
+            single_bit_flips: 0,
+            double_bit_flips: 0,
+            multi_bit_flips: 0,
+            persistent_flips: 0,
+            transient_flips: 0,
+            flips_per_node: BTreeMap::new(),
+            coverage: None,
+            flip_counts: FlipCounts::default(),
+            stuck_bits: 0,
+            excluded_pages: 0,
+            canary_violations: 0,
+            reclaimed_pages: 0,
+            downtime_flips: 0,
+            changed_blocks: 0,
+            verification: None,
+            byte_seconds: 0.0,
+            integrated_until: Duration::ZERO,
+            stage_start: StageStart::default(),
+        }
+    }
+
+    /// Records that a page of the given size is no longer monitored, see `--exclude-stuck`. It is still read,
+    /// but the flips in it are ignored, so it does not count towards the monitored size from now on.
+    pub fn exclude_page(&mut self, bytes: usize) {
+        self.integrate();
+        self.monitored_bytes = self.monitored_bytes.saturating_sub(bytes);
+        self.excluded_pages += 1;
+    }
+
+    /// Ends the current stage of a staircase run and begins the next one with a detector of the given size.
+    pub fn begin_stage(&mut self, monitored_bytes: usize) {
+        self.integrate();
+        let monitored = self.integrated_until;
+        self.monitored_bytes = monitored_bytes;
+        // The offsets of the next detector are different bytes.
+        self.flip_counts = FlipCounts::default();
+        self.stage_start = StageStart {
+            monitored,
+            checks: self.checks,
+            flips: self.flips,
+        };
+    }
+
+    /// Returns the statistics of the current stage, which is the given one of the given number of stages.
+    pub fn stage(&self, number: usize, stages: usize) -> Stage {
+        Stage {
+            number,
+            stages,
+            monitored_bytes: self.monitored_bytes,
+            monitored_time: self
+                .monitored_time()
+                .saturating_sub(self.stage_start.monitored),
+            checks: self.checks - self.stage_start.checks,
+            flips: self.flips - self.stage_start.flips,
+            next_bytes: None,
+        }
+    }
+
+    /// Returns the number of integrity checks performed in the current stage.
+    pub fn stage_checks(&self) -> u64 {
+        self.checks - self.stage_start.checks
+    }
+
+    /// Returns the average size of the detector over the monitored time, which differs from
+    /// [`Summary::monitored_bytes`] if the stages of a staircase run had different sizes.
+    fn average_monitored_bytes(&self) -> usize {
+        let monitored = self.monitored_time();
+        if monitored.is_zero() {
+            return self.monitored_bytes;
+        }
+        (self.byte_seconds() / monitored.as_secs_f64()) as usize
+    }
+
+    /// Adds the monitored time since the last call at the current size of the detector to the byte-seconds.
+    fn integrate(&mut self) {
+        let monitored = self.monitored_time();
+        self.byte_seconds += self.monitored_bytes as f64
+            * monitored
+                .saturating_sub(self.integrated_until)
+                .as_secs_f64();
+        self.integrated_until = monitored.max(self.integrated_until);
+    }
+
+    /// Returns the bytes monitored so far times the seconds they were monitored for, without the pauses.
+    pub fn byte_seconds(&self) -> f64 {
+        self.byte_seconds
+            + self.monitored_bytes as f64
+                * self
+                    .monitored_time()
+                    .saturating_sub(self.integrated_until)
+                    .as_secs_f64()
+    }
+
+    /// Returns [`Summary::byte_seconds`] in gigabyte-hours.
+    pub fn gb_hours(&self) -> f64 {
+        self.byte_seconds() / 1e9 / 3600.0
+    }
+
+    /// Records the flips found by an integrity check.
+    pub fn record_check(&mut self, flips: &[FlipEvent]) {
+        self.integrate();
+        self.checks += 1;
+        self.flips += flips.len() as u64;
+        for flip in flips {
+            self.flip_counts.record(flip.offset);
+            *match flip.class {
+                FlipClass::Single => &mut self.single_bit_flips,
+                FlipClass::Double => &mut self.double_bit_flips,
+                FlipClass::Multi => &mut self.multi_bit_flips,
+            } += 1;
+            *match flip.persistence {
+                Persistence::Persistent => &mut self.persistent_flips,
+                Persistence::Transient => &mut self.transient_flips,
+            } += 1;
+        }
+    }
+
+    /// Records that the last slice of the detector has been checked, after [`Summary::record_check`],
+    /// in a pass over all of the slices that took the given time.
+    pub fn record_pass(&mut self, time: Duration) {
+        self.passes += 1;
+        self.pass_time += time;
+    }
+
+    /// Returns how long it took on average to check every slice of the detector once, which is how often
+    /// every byte is checked, or `None` if it is not checked one slice at a time or no pass is complete yet.
+    pub fn revisit_interval(&self) -> Option<Duration> {
+        (self.passes > 0).then(|| self.pass_time / self.passes as u32)
+    }
+
+    #[cfg(feature = "numa")]
+    /// Records a flip on the given NUMA node, in addition to [`Summary::record_check`].
+    pub fn record_node_flip(&mut self, node: u32) {
+        *self.flips_per_node.entry(node).or_default() += 1;
+    }
+
+    /// Returns how long the detector has been running.
+    pub fn runtime(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Returns when the detector started running, as far as the system clock can tell.
+    pub fn started(&self) -> SystemTime {
+        SystemTime::now() - self.runtime()
+    }
+
+    /// Records that checking was paused.
+    pub fn pause(&mut self) {
+        // A pause during an idle window counts as a pause only.
+        self.stop_idle_clock();
+        self.paused_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Records that checking was resumed after a pause.
+    pub fn resume(&mut self) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused += since.elapsed();
+        }
+        if self.idling {
+            self.idle_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Records that the detector stopped checking until the next burst of `--duty-cycle`.
+    pub fn start_idle(&mut self) {
+        self.idling = true;
+        if self.paused_since.is_none() {
+            self.idle_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Records that the next burst of `--duty-cycle` began.
+    pub fn end_idle(&mut self) {
+        self.idling = false;
+        self.stop_idle_clock();
+    }
+
+    fn stop_idle_clock(&mut self) {
+        if let Some(since) = self.idle_since.take() {
+            self.idle += since.elapsed();
+        }
+    }
+
+    /// Returns how long the detector idled between the bursts of `--duty-cycle` in total.
+    pub fn idle_time(&self) -> Duration {
+        self.idle
+            + self
+                .idle_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Returns how long checking has been paused in total.
+    pub fn paused_time(&self) -> Duration {
+        self.paused
+            + self
+                .paused_since
+                .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Records that the whole detector memory was refilled with the pattern, which took the given time.
+    /// Whatever was monitored before has been integrated by the check right before it.
+    pub fn record_refill(&mut self, took: Duration) {
+        self.refilling += took;
+    }
+
+    /// Returns how long the detector has been checking, which is the runtime without the pauses, the idle windows
+    /// and the refills.
+    pub fn monitored_time(&self) -> Duration {
+        self.runtime()
+            .saturating_sub(self.paused_time())
+            .saturating_sub(self.idle_time())
+            .saturating_sub(self.refilling)
+    }
+
+    /// Returns the exit status of the run so far: 0 if no flips or changed blocks were detected,
+    /// [`FLIPS_DETECTED_EXIT_STATUS`] otherwise, also if a later pass of `--until-clean` was clean.
+    /// If nothing was detected but `--until-clean` did not get to a clean pass it is [`NOT_VERIFIED_EXIT_STATUS`].
+    pub fn exit_status(&self) -> u8 {
+        match self.verification {
+            _ if self.flips > 0 || self.changed_blocks > 0 => FLIPS_DETECTED_EXIT_STATUS,
+            Some(Verification { clean: false, .. }) => NOT_VERIFIED_EXIT_STATUS,
+            _ => 0,
+        }
+    }
+
+    /// Returns the number of flips per gigabyte of monitored memory per hour of monitored time,
+    /// or `None` if the detector has been checking for less than a second.
+    /// Over the stages of a staircase run every stage counts with the size of its detector.
+    pub fn flips_per_gb_hour(&self) -> Option<f64> {
+        flip_rate(
+            self.flips,
+            self.average_monitored_bytes(),
+            self.monitored_time(),
+        )
+    }
+}
+
+/// The number of flips per gigabyte (10^9 bytes) per hour, which makes detectors of different sizes
+/// that ran for different amounts of time comparable. Runs shorter than a second give no meaningful rate.
+fn flip_rate(flips: u64, monitored_bytes: usize, runtime: Duration) -> Option<f64> {
+    if runtime < Duration::from_secs(1) || monitored_bytes == 0 {
+        return None;
+    }
+    let gigabytes = monitored_bytes as f64 / 1e9;
+    let hours = runtime.as_secs_f64() / 3600.0;
+    Some(flips as f64 / (gigabytes * hours))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_exit_status() {
+        let mut summary = Summary::new(1000);
+        summary.record_check(&[]);
+        assert_eq!(summary.exit_status(), 0);
+        let mut double = FlipEvent::new(9, 0, 1);
+        double.class = FlipClass::Double;
+        summary.record_check(&[FlipEvent::new(3, 0, 1), double.clone(), double]);
+        summary.record_check(&[]);
+        assert_eq!(summary.exit_status(), FLIPS_DETECTED_EXIT_STATUS);
+        assert_eq!(summary.flips, 3);
+
+        // A clean pass of --until-clean does not hide the flips of the passes before it.
+        summary.verification = Some(Verification {
+            passes: 2,
+            clean: false,
+        });
+        assert_eq!(summary.exit_status(), FLIPS_DETECTED_EXIT_STATUS);
+        summary.verification = Some(Verification {
+            passes: 3,
+            clean: true,
+        });
+        assert_eq!(summary.exit_status(), FLIPS_DETECTED_EXIT_STATUS);
+        let mut unverified = Summary::new(1000);
+        unverified.verification = Some(Verification::default());
+        assert_eq!(unverified.exit_status(), NOT_VERIFIED_EXIT_STATUS);
+        unverified.verification = Some(Verification {
+            passes: 1,
+            clean: true,
+        });
+        assert_eq!(unverified.exit_status(), 0);
+        assert_eq!(
+            (
+                summary.single_bit_flips,
+                summary.double_bit_flips,
+                summary.multi_bit_flips
+            ),
+            (1, 2, 0)
+        );
+
+        let mut transient = FlipEvent::new(4, 0, 1);
+        transient.persistence = Persistence::Transient;
+        summary.record_check(&[transient]);
+        assert_eq!((summary.persistent_flips, summary.transient_flips), (3, 1));
+    }
+
+    #[test]
+    fn check_flip_counts() {
+        let mut summary = Summary::new(1000);
+        for _ in 0..3 {
+            summary.record_check(&[FlipEvent::new(5, 0, 1), FlipEvent::new(9, 0, 1)]);
+        }
+        summary.record_check(&[FlipEvent::new(9, 0, 1), FlipEvent::new(2, 0, 1)]);
+        assert_eq!(summary.flip_counts.get(9), 4);
+        assert_eq!(summary.flip_counts.get(7), 0);
+        assert_eq!(summary.flip_counts.repeated(5), [(9, 4), (5, 3)]);
+        assert_eq!(summary.flip_counts.repeated(1), [(9, 4)]);
+
+        // Once the map is full the bytes that flipped once make room, but the repeated ones stay.
+        let mut counts = FlipCounts::default();
+        counts.record(3);
+        counts.record(3);
+        for offset in 0..2 * MAX_TRACKED_OFFSETS {
+            counts.record(offset);
+        }
+        assert!(counts.counts.len() <= MAX_TRACKED_OFFSETS);
+        assert_eq!(counts.get(3), 3);
+        assert_eq!(counts.repeated(10), [(3, 3)]);
+
+        summary.begin_stage(2000);
+        assert!(summary.flip_counts.repeated(5).is_empty());
+    }
+
+    #[test]
+    fn check_paused_time() {
+        let mut summary = Summary::new(1000);
+        summary.pause();
+        std::thread::sleep(Duration::from_millis(20));
+        // Pausing again while paused changes nothing.
+        summary.pause();
+        assert!(summary.paused_time() >= Duration::from_millis(20));
+        summary.resume();
+        let paused = summary.paused_time();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(summary.paused_time(), paused);
+        assert!(summary.monitored_time() <= summary.runtime() - paused);
+    }
+
+    #[test]
+    fn check_idle_time() {
+        let mut summary = Summary::new(1000);
+        summary.start_idle();
+        std::thread::sleep(Duration::from_millis(10));
+        // A pause during the idle window stops the idle time, and it goes on once checking is resumed.
+        summary.pause();
+        let idle = summary.idle_time();
+        assert!(idle >= Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(summary.idle_time(), idle);
+        summary.resume();
+        std::thread::sleep(Duration::from_millis(10));
+        summary.end_idle();
+        let idle = summary.idle_time();
+        assert!(idle >= Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(summary.idle_time(), idle);
+        assert!(summary.monitored_time() <= summary.runtime() - idle - summary.paused_time());
+        assert!(summary.byte_seconds() <= 1000.0 * summary.monitored_time().as_secs_f64());
+    }
+
+    #[test]
+    fn check_excluded_pages() {
+        let mut summary = Summary::new(10_000);
+        std::thread::sleep(Duration::from_millis(20));
+        summary.exclude_page(4096);
+        assert_eq!((summary.monitored_bytes, summary.excluded_pages), (5904, 1));
+        // The time before the exclusion still counts with the whole detector.
+        let before = summary.byte_seconds();
+        assert!(before >= 10_000.0 * 0.02);
+        std::thread::sleep(Duration::from_millis(20));
+        let after = summary.byte_seconds() - before;
+        assert!(
+            after >= 5904.0 * 0.02 && after < 10_000.0 * summary.monitored_time().as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn check_revisit_interval() {
+        let mut summary = Summary::new(1000);
+        assert_eq!(summary.revisit_interval(), None);
+        summary.record_pass(Duration::from_secs(2));
+        summary.record_pass(Duration::from_secs(4));
+        assert_eq!(summary.revisit_interval(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn check_stages() {
+        let mut summary = Summary::new(1000);
+        summary.record_check(&[FlipEvent::new(3, 0, 1)]);
+        std::thread::sleep(Duration::from_millis(20));
+        let first = summary.stage(1, 2);
+        assert_eq!(
+            (first.monitored_bytes, first.checks, first.flips),
+            (1000, 1, 1)
+        );
+
+        summary.begin_stage(3000);
+        assert_eq!(summary.stage_checks(), 0);
+        summary.record_check(&[]);
+        summary.record_check(&[]);
+        std::thread::sleep(Duration::from_millis(20));
+        let second = summary.stage(2, 2);
+        assert_eq!((second.number, second.stages), (2, 2));
+        assert_eq!(
+            (second.monitored_bytes, second.checks, second.flips),
+            (3000, 2, 0)
+        );
+        assert!(second.monitored_time >= Duration::from_millis(20));
+        assert!(second.monitored_time < summary.monitored_time());
+        assert_eq!(summary.checks, 3);
+
+        // About as long at both sizes, so the average lies between them.
+        let average = summary.average_monitored_bytes();
+        assert!((1000..3000).contains(&average), "{average}");
+    }
+
+    #[test]
+    fn check_byte_seconds() {
+        let mut summary = Summary::new(1000);
+        std::thread::sleep(Duration::from_millis(20));
+        summary.record_check(&[]);
+        summary.begin_stage(3000);
+        std::thread::sleep(Duration::from_millis(20));
+        // At least 20ms at either size, and never more than the whole time at the larger one.
+        let byte_seconds = summary.byte_seconds();
+        assert!(
+            byte_seconds >= 1000.0 * 0.02 + 3000.0 * 0.02,
+            "{byte_seconds}"
+        );
+        assert!(byte_seconds <= 3000.0 * summary.monitored_time().as_secs_f64());
+
+        // Pauses are not monitored.
+        summary.pause();
+        let before = summary.byte_seconds();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(summary.byte_seconds() - before < 3000.0 * 0.005);
+        assert!((summary.gb_hours() - summary.byte_seconds() / 3.6e12).abs() < 1e-15);
+    }
+
+    #[test]
+    fn check_refills() {
+        let mut summary = Summary::new(1000);
+        summary.record_check(&[]);
+        let refill_start = Instant::now();
+        std::thread::sleep(Duration::from_millis(30));
+        summary.record_refill(refill_start.elapsed());
+        // The refill is not monitored time, so it adds no byte-seconds either.
+        assert!(summary.monitored_time() < Duration::from_millis(30));
+        assert!(summary.byte_seconds() < 1000.0 * 0.03);
+    }
+
+    #[test]
+    fn check_flip_rate() {
+        assert_eq!(
+            flip_rate(3, 1_000_000_000, Duration::from_millis(999)),
+            None
+        );
+        assert_eq!(flip_rate(3, 0, Duration::from_secs(3600)), None);
+        assert_eq!(
+            flip_rate(0, 1_000_000_000, Duration::from_secs(1)),
+            Some(0.0)
+        );
+        assert_eq!(
+            flip_rate(3, 1_000_000_000, Duration::from_secs(3600)),
+            Some(3.0)
+        );
+        assert_eq!(
+            flip_rate(1, 500_000_000, Duration::from_secs(1800)),
+            Some(4.0)
+        );
+    }
+}
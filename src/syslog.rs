@@ -0,0 +1,233 @@
+//! Sending events to the local syslog daemon, or journald, with `syslog(3)`.
+
+use std::ffi::CString;
+use std::io;
+
+use crate::canary::CanaryViolation;
+use crate::detector::{ChangedBlock, FlipClass, FlipEvent, ReclaimedPage, WordError};
+use crate::hamming::WordStatus;
+use crate::output::{Output, Startup, StuckBit};
+use crate::summary::{Stage, Summary};
+
+/// Sends startup, shutdown and heartbeats to syslog as informational messages, and every flip as a warning.
+/// Flips that are part of a multi-bit flip of their word, or of an uncorrectable word, are critical.
+pub struct SyslogOutput {
+    /// The tag every message starts with, see `--tag`.
+    tag: Option<String>,
+}
+
+impl SyslogOutput {
+    pub fn new() -> Self {
+        // syslog keeps the pointer to the identifier around, so it has to live for the rest of the program.
+        unsafe {
+            libc::openlog(
+                c"cosmic_ray_detection".as_ptr(),
+                libc::LOG_PID,
+                libc::LOG_DAEMON,
+            )
+        };
+        SyslogOutput { tag: None }
+    }
+
+    /// Starts every message with the given tag in brackets.
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    fn log(&mut self, priority: libc::c_int, message: String) -> io::Result<()> {
+        let message = match &self.tag {
+            Some(tag) => format!("[{tag}] {message}"),
+            None => message,
+        };
+        let message = CString::new(message).map_err(io::Error::other)?;
+        // The message is passed as an argument rather than as the format, so that it is never interpreted.
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+        Ok(())
+    }
+}
+
+impl Default for SyslogOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SyslogOutput {
+    fn drop(&mut self) {
+        unsafe { libc::closelog() };
+    }
+}
+
+/// Returns the syslog priority of a flip.
+fn flip_priority(flip: &FlipEvent) -> libc::c_int {
+    match flip.class {
+        FlipClass::Multi => libc::LOG_CRIT,
+        FlipClass::Single | FlipClass::Double => libc::LOG_WARNING,
+    }
+}
+
+impl Output for SyslogOutput {
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.log(
+            libc::LOG_INFO,
+            format!("startup: monitoring {}", startup.describe()),
+        )
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        let address = match (flip.physical_address, &flip.dimm) {
+            (Some(address), Some(dimm)) => format!(" (physical address {address:#x} in {dimm})"),
+            (Some(address), None) => format!(" (physical address {address:#x})"),
+            _ => String::new(),
+        };
+        let mce = if flip.correlated_mce {
+            " (correlated MCE)"
+        } else {
+            ""
+        };
+        self.log(
+            flip_priority(flip),
+            format!(
+                "{} {} flip at offset {}{address}{mce}: expected {:#04X}, found {:#04X}",
+                flip.persistence, flip.class, flip.offset, flip.expected, flip.found
+            ),
+        )
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        self.log(
+            libc::LOG_WARNING,
+            format!("...and {omitted} more flips this check"),
+        )
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        match error.status {
+            WordStatus::Clean => Ok(()),
+            WordStatus::Corrected => self.log(
+                libc::LOG_WARNING,
+                format!("corrected a single bit flip in word {}", error.word),
+            ),
+            WordStatus::Uncorrectable => self.log(
+                libc::LOG_CRIT,
+                format!("uncorrectable multi bit flip in word {}", error.word),
+            ),
+        }
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.log(
+            libc::LOG_WARNING,
+            format!(
+                "page at offset {} reads as all zeros, most likely reclaimed by the operating system: {} bytes lost the pattern",
+                page.offset,
+                page.flips.len()
+            ),
+        )
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.log(
+            libc::LOG_WARNING,
+            format!(
+                "block at offset {} changed since the snapshot: {} bytes",
+                block.offset, block.len
+            ),
+        )
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        let address = match (stuck.physical_address, &stuck.dimm) {
+            (Some(address), Some(dimm)) => format!(" (physical address {address:#x} in {dimm})"),
+            (Some(address), None) => format!(" (physical address {address:#x})"),
+            _ => String::new(),
+        };
+        self.log(
+            libc::LOG_CRIT,
+            format!(
+                "suspected stuck bit at offset {}{address}: flipped {} times{}",
+                stuck.offset,
+                stuck.flips,
+                if stuck.excluded {
+                    ", no longer monitoring its page"
+                } else {
+                    ""
+                }
+            ),
+        )
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        self.log(
+            libc::LOG_CRIT,
+            format!(
+                "canary page {} written to: {} bytes changed, the first at index {}",
+                violation.canary, violation.changed_bytes, violation.first
+            ),
+        )
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        let message = if paused { "paused" } else { "resumed" };
+        self.log(libc::LOG_INFO, message.to_owned())
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        self.log(
+            libc::LOG_INFO,
+            format!(
+                "heartbeat after {:.3}s: {} checks, {} flips",
+                summary.runtime().as_secs_f64(),
+                summary.checks,
+                summary.flips
+            ),
+        )
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        self.log(
+            libc::LOG_INFO,
+            format!(
+                "stage {} of {} with {} bytes after {:.3}s: {} checks, {} flips{}",
+                stage.number,
+                stage.stages,
+                stage.monitored_bytes,
+                stage.monitored_time.as_secs_f64(),
+                stage.checks,
+                stage.flips,
+                stage
+                    .next_bytes
+                    .map(|bytes| format!(", moving on to {bytes} bytes"))
+                    .unwrap_or_default()
+            ),
+        )
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        self.log(
+            libc::LOG_INFO,
+            format!(
+                "shutdown after {:.3}s: {} checks, {} flips",
+                summary.runtime().as_secs_f64(),
+                summary.checks,
+                summary.flips
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_flip_priority() {
+        let mut flip = FlipEvent::new(0, 0, 1);
+        assert_eq!(flip_priority(&flip), libc::LOG_WARNING);
+        flip.class = FlipClass::Double;
+        assert_eq!(flip_priority(&flip), libc::LOG_WARNING);
+        flip.class = FlipClass::Multi;
+        assert_eq!(flip_priority(&flip), libc::LOG_CRIT);
+    }
+}
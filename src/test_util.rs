@@ -0,0 +1,83 @@
+//! Fixtures that the tests of several modules share.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The environment variables are shared by all tests of the process, which run on threads of their own.
+/// The tests that parse the command line read them, so they must not run while another test changes them.
+static ENVIRONMENT: RwLock<()> = RwLock::new(());
+
+/// Keeps the environment variables from being changed by another test for as long as it is kept.
+pub(crate) fn read_environment() -> RwLockReadGuard<'static, ()> {
+    // A test that failed while it held the lock has restored the environment on the way out.
+    ENVIRONMENT.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Changes environment variables for as long as it is kept, without another test reading them in the meantime.
+/// The variables that were set are removed again when it is dropped.
+pub(crate) struct Environment {
+    set: Vec<&'static str>,
+    _lock: RwLockWriteGuard<'static, ()>,
+}
+
+impl Environment {
+    /// Waits until no other test reads or changes the environment.
+    pub(crate) fn lock() -> Self {
+        Environment {
+            set: Vec::new(),
+            _lock: ENVIRONMENT.write().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Sets the given environment variable.
+    pub(crate) fn set(&mut self, name: &'static str, value: &str) {
+        std::env::set_var(name, value);
+        self.set.push(name);
+    }
+
+    /// Removes the given environment variable.
+    pub(crate) fn remove(&mut self, name: &'static str) {
+        std::env::remove_var(name);
+        self.set.retain(|&set| set != name);
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        for name in &self.set {
+            std::env::remove_var(name);
+        }
+    }
+}
+
+/// A directory of its own for a test, which is removed with everything in it when it is dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory for the test of the given name, in the temporary directory of the system.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cosmic_ray_{name}_{}", std::process::id()));
+        // Whatever an earlier run of the same process ID left behind would spoil the test.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Returns the path of the directory.
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Returns the path of the given file or directory in it.
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // A test may have removed it itself already.
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
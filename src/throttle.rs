@@ -0,0 +1,208 @@
+//! Slowing the integrity checks down while the machine is busy with `--throttle`, so that a detector on a shared
+//! machine does not compete for memory bandwidth with the work the machine is there for.
+//!
+//! The throttle goes up one level at a time while the load is above the threshold, and down again once it has
+//! fallen clearly below it. Every level doubles the time from the start of one check to the start of the next
+//! and halves the threads the checks run on. The load is the 1-minute load average where the operating system
+//! keeps one, and on Windows the share of the time the CPUs were busy times their number, which is on the same scale.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+/// The highest level of the throttle, at which the checks run 16 times slower than at full speed.
+pub const MAX_LEVEL: u32 = 4;
+
+/// The throttle only goes down a level once the load is below this fraction of the threshold,
+/// so that it does not go back and forth with every check while the load is right at the threshold.
+const RESTORE_FRACTION: f64 = 0.8;
+
+/// How long the throttle stays at a level before it moves again. The 1-minute load average takes a while
+/// to follow a change in what the detector adds to it.
+const SETTLE_TIME: Duration = Duration::from_secs(10);
+
+/// The level the checks are slowed down to, depending on the load of the machine.
+#[derive(Debug)]
+pub struct Throttle {
+    threshold: f64,
+    level: u32,
+    changed: Option<Instant>,
+}
+
+impl Throttle {
+    /// Creates a throttle that slows the checks down while the load is above the given threshold.
+    pub fn new(threshold: f64) -> Self {
+        Throttle {
+            threshold,
+            level: 0,
+            changed: None,
+        }
+    }
+
+    /// Returns the load above which the checks are slowed down.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Returns the current level, 0 being full speed.
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Moves the throttle a level up if the given load is above the threshold, or a level down if it is
+    /// well below it, unless it moved less than a few seconds before the given time. Returns whether it moved.
+    pub fn update(&mut self, load: f64, now: Instant) -> bool {
+        if self
+            .changed
+            .is_some_and(|changed| now.saturating_duration_since(changed) < SETTLE_TIME)
+        {
+            return false;
+        }
+        let level = if load > self.threshold {
+            (self.level + 1).min(MAX_LEVEL)
+        } else if load < self.threshold * RESTORE_FRACTION {
+            self.level.saturating_sub(1)
+        } else {
+            self.level
+        };
+        if level == self.level {
+            return false;
+        }
+        self.level = level;
+        self.changed = Some(now);
+        true
+    }
+
+    /// Returns how many times slower than at full speed the checks run.
+    pub fn slowdown(&self) -> u32 {
+        1 << self.level
+    }
+
+    /// Returns the delay before the next check, given the delay at full speed and how long the last check took.
+    /// This spreads the starts of the checks out by the slowdown, also when they would run back to back.
+    pub fn delay(&self, interval: Duration, check_time: Duration) -> Duration {
+        (interval + check_time) * self.slowdown() - check_time
+    }
+
+    /// Returns how many of the given threads of the detector the checks run on.
+    pub fn threads(&self, threads: usize) -> usize {
+        (threads >> self.level).max(1)
+    }
+}
+
+/// Reads the load of the machine, see the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct LoadMonitor {
+    /// The idle and the total time of the CPUs when the load was last read.
+    #[cfg(windows)]
+    last: Option<(u64, u64)>,
+}
+
+impl LoadMonitor {
+    #[cfg(target_os = "linux")]
+    /// Returns the 1-minute load average.
+    pub fn load(&mut self) -> io::Result<f64> {
+        let contents = std::fs::read_to_string("/proc/loadavg")?;
+        parse_loadavg(&contents).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected contents of /proc/loadavg: {contents:?}"),
+            )
+        })
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    /// Returns the 1-minute load average.
+    pub fn load(&mut self) -> io::Result<f64> {
+        let mut load = 0.0;
+        if unsafe { libc::getloadavg(&mut load, 1) } != 1 {
+            return Err(io::Error::other("the load average is not available"));
+        }
+        Ok(load)
+    }
+
+    #[cfg(windows)]
+    /// Returns how busy the CPUs were since the load was last read, or since the machine started
+    /// the first time, times the number of CPUs.
+    pub fn load(&mut self) -> io::Result<f64> {
+        use windows_sys::Win32::Foundation::FILETIME;
+        use windows_sys::Win32::System::Threading::GetSystemTimes;
+
+        let mut times = [FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        }; 3];
+        let [idle, kernel, user] = &mut times;
+        if unsafe { GetSystemTimes(idle, kernel, user) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [idle, kernel, user] =
+            times.map(|t| (t.dwHighDateTime as u64) << 32 | t.dwLowDateTime as u64);
+        // The kernel time includes the idle time.
+        let now = (idle, kernel + user);
+        let (last_idle, last_total) = self.last.replace(now).unwrap_or((0, 0));
+        let total = now.1.saturating_sub(last_total);
+        let busy = total.saturating_sub(now.0.saturating_sub(last_idle));
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(busy as f64 / total.max(1) as f64 * cpus as f64)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    /// The load is not known on this operating system.
+    pub fn load(&mut self) -> io::Result<f64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the load of the machine is not known on this operating system",
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Parses the 1-minute load average from the contents of `/proc/loadavg`, like "0.52 0.58 0.59 1/1021 4242".
+fn parse_loadavg(contents: &str) -> Option<f64> {
+    contents.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_throttle() {
+        let mut throttle = Throttle::new(4.0);
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        assert!(!throttle.update(3.5, start));
+        assert_eq!(throttle.level(), 0);
+        assert_eq!(throttle.delay(second, second), second);
+
+        assert!(throttle.update(6.0, start));
+        assert_eq!(throttle.level(), 1);
+        // It does not move again right away, the load average needs time to follow.
+        assert!(!throttle.update(6.0, start + second));
+        for i in 2..=MAX_LEVEL + 1 {
+            throttle.update(6.0, start + SETTLE_TIME * i);
+        }
+        assert_eq!(throttle.level(), MAX_LEVEL);
+        assert_eq!(throttle.slowdown(), 16);
+        assert_eq!(throttle.threads(8), 1);
+        // Checks that would run back to back get 15 times as long as they took to themselves.
+        assert_eq!(throttle.delay(Duration::ZERO, second), second * 15);
+        assert_eq!(throttle.delay(second, second), second * 31);
+
+        // Right below the threshold it stays where it is, well below it speeds up again.
+        let later = start + SETTLE_TIME * 10;
+        assert!(!throttle.update(3.5, later));
+        assert!(throttle.update(3.0, later));
+        assert_eq!(throttle.level(), MAX_LEVEL - 1);
+        assert_eq!(throttle.threads(8), 1);
+        assert_eq!(throttle.threads(32), 4);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn check_load_average() {
+        assert_eq!(parse_loadavg("0.52 0.58 0.59 1/1021 4242\n"), Some(0.52));
+        assert_eq!(parse_loadavg(""), None);
+        assert!(LoadMonitor::default().load().unwrap() >= 0.0);
+    }
+}
@@ -0,0 +1,383 @@
+//! A live dashboard that takes over the terminal with `--tui`, for a machine that someone watches from across the room:
+//! the uptime, the size of the detector, the rate of the checks and the flips so far, a sparkline of the flips
+//! of the recent checks and a scrolling list of what happened.
+
+use std::collections::VecDeque;
+use std::io::{self, stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use humantime::{format_duration, format_rfc3339_seconds};
+use log::LevelFilter;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Sparkline};
+use ratatui::Frame;
+
+use crate::canary::CanaryViolation;
+use crate::detector::{ChangedBlock, FlipEvent, ReclaimedPage, WordError};
+use crate::hamming::WordStatus;
+use crate::output::{Output, Settings, Startup, StuckBit, TextOutput};
+use crate::summary::{Stage, Summary};
+
+/// How often the dashboard is redrawn when nothing happens, which is how often the uptime ticks.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many of the most recent checks the sparkline shows.
+const RECENT_CHECKS: usize = 120;
+
+/// How many of the most recent events are kept for the list.
+const RECENT_EVENTS: usize = 200;
+
+/// What the dashboard shows, updated by the events and drawn by the thread that owns the terminal.
+struct Dashboard {
+    start: Instant,
+    status: &'static str,
+    detector: String,
+    monitored_bytes: usize,
+    checks: u64,
+    flips: u64,
+    /// The number of flips of each of the most recent checks, the oldest first.
+    recent_checks: VecDeque<u64>,
+    /// The most recent events, the oldest first.
+    events: VecDeque<String>,
+}
+
+impl Dashboard {
+    fn new() -> Self {
+        Dashboard {
+            start: Instant::now(),
+            status: "Starting",
+            detector: String::new(),
+            monitored_bytes: 0,
+            checks: 0,
+            flips: 0,
+            recent_checks: VecDeque::with_capacity(RECENT_CHECKS),
+            events: VecDeque::with_capacity(RECENT_EVENTS),
+        }
+    }
+
+    fn event(&mut self, event: String) {
+        if self.events.len() == RECENT_EVENTS {
+            self.events.pop_front();
+        }
+        let time = format_rfc3339_seconds(SystemTime::now());
+        self.events.push_back(format!("{time} {event}"));
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [stats, sparkline, events, help] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let uptime = self.start.elapsed();
+        let checks_per_second = self.checks as f64 / uptime.as_secs_f64().max(1.0);
+        let flip_style = if self.flips > 0 {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new()
+        };
+        let lines = vec![
+            Line::from(format!(
+                "Uptime: {}    Detector: {}",
+                format_duration(Duration::from_secs(uptime.as_secs())),
+                self.detector
+            )),
+            Line::from(format!("Monitored: {} bytes", self.monitored_bytes)),
+            Line::from(format!(
+                "Integrity checks: {} ({checks_per_second:.2} per second)",
+                self.checks
+            )),
+            Line::styled(format!("Bit flips: {}", self.flips), flip_style),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::bordered().title(format!(" Cosmic ray detector: {} ", self.status))),
+            stats,
+        );
+
+        // The newest checks are at the right edge.
+        let width = usize::from(sparkline.width.saturating_sub(2));
+        let recent: Vec<u64> = self
+            .recent_checks
+            .iter()
+            .skip(self.recent_checks.len().saturating_sub(width))
+            .copied()
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" Bit flips per check "))
+                .style(Style::new().fg(Color::Yellow))
+                .data(&recent),
+            sparkline,
+        );
+
+        // The newest events are at the bottom, scrolled up as more come in.
+        let height = usize::from(events.height.saturating_sub(2));
+        let items: Vec<ListItem> = self
+            .events
+            .iter()
+            .skip(self.events.len().saturating_sub(height))
+            .map(|event| ListItem::new(event.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" Events ")),
+            events,
+        );
+
+        frame.render_widget(Paragraph::new("Press q to stop"), help);
+    }
+}
+
+/// Shows the dashboard, taking over the terminal from when it is created until the detector stops.
+/// The terminal is restored when it is dropped, also when the detector stops with an error, and by the panic hook
+/// of ratatui when it panics. The summary is printed as text afterwards, so that it is still there to read.
+pub struct TuiOutput {
+    dashboard: Arc<Mutex<Dashboard>>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+    level: LevelFilter,
+}
+
+impl TuiOutput {
+    /// Takes over the terminal, which fails if standard output is not one. The terminal is in raw mode,
+    /// so Ctrl-C doesn't raise a signal, instead it and the q key set the given stop flag.
+    pub fn start(stop: Arc<AtomicBool>, level: LevelFilter) -> io::Result<Self> {
+        let mut terminal = ratatui::try_init()?;
+        let dashboard = Arc::new(Mutex::new(Dashboard::new()));
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (dashboard, done) = (Arc::clone(&dashboard), Arc::clone(&done));
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    terminal.draw(|frame| lock(&dashboard).draw(frame))?;
+                    if !event::poll(REDRAW_INTERVAL)? {
+                        continue;
+                    }
+                    if let Event::Key(key) = event::read()? {
+                        let ctrl_c = key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL);
+                        if key.kind == KeyEventKind::Press
+                            && (ctrl_c || key.code == KeyCode::Char('q'))
+                        {
+                            stop.store(true, Ordering::SeqCst);
+                            lock(&dashboard).status = "Stopping";
+                        }
+                    }
+                }
+                Ok(())
+            })
+        };
+        Ok(TuiOutput {
+            dashboard,
+            done,
+            thread: Some(thread),
+            level,
+        })
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Dashboard)) -> io::Result<()> {
+        f(&mut lock(&self.dashboard));
+        Ok(())
+    }
+
+    /// Stops drawing and gives the terminal back.
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        self.done.store(true, Ordering::SeqCst);
+        let result = thread.join();
+        ratatui::restore();
+        result.unwrap_or_else(|_| Err(io::Error::other("the dashboard panicked")))
+    }
+}
+
+impl Drop for TuiOutput {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Gives the terminal back without waiting for the dashboard to stop drawing, for when the process
+/// is about to end without dropping the [`TuiOutput`].
+pub fn restore_terminal() {
+    ratatui::restore();
+}
+
+/// Locks the dashboard, which can not be left inconsistent by a panic, as every update is a plain assignment.
+fn lock(dashboard: &Mutex<Dashboard>) -> std::sync::MutexGuard<'_, Dashboard> {
+    dashboard
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+impl Output for TuiOutput {
+    fn allocating(&mut self, settings: &Settings) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.status = "Allocating";
+            dashboard.detector = settings.detector.clone();
+            dashboard.event(format!("Allocating {}", settings.detector));
+        })
+    }
+
+    fn startup(&mut self, startup: &Startup) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.status = "Monitoring";
+            dashboard.monitored_bytes = startup.allocated_bytes;
+            dashboard.event(format!("Monitoring {}", startup.describe()));
+        })
+    }
+
+    fn check(&mut self, check: u64, flips: usize) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.checks = check;
+            if dashboard.recent_checks.len() == RECENT_CHECKS {
+                dashboard.recent_checks.pop_front();
+            }
+            dashboard.recent_checks.push_back(flips as u64);
+        })
+    }
+
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.flips += 1;
+            dashboard.event(format!(
+                "Bit flip in byte at index {}: {:#010b} became {:#010b} ({}, {})",
+                flip.offset, flip.expected, flip.found, flip.class, flip.persistence
+            ));
+        })
+    }
+
+    fn omitted_flips(&mut self, omitted: usize) -> io::Result<()> {
+        self.update(|dashboard| dashboard.event(format!("...and {omitted} more flips this check")))
+    }
+
+    fn word_error(&mut self, error: &WordError) -> io::Result<()> {
+        let description = match error.status {
+            WordStatus::Clean => return Ok(()),
+            WordStatus::Corrected => "Corrected a single bit flip",
+            WordStatus::Uncorrectable => "Detected an uncorrectable multi bit flip",
+        };
+        self.update(|dashboard| dashboard.event(format!("{description} in word {}", error.word)))
+    }
+
+    fn reclaimed(&mut self, page: &ReclaimedPage) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.event(format!(
+                "The page at index {} reads as all zeros, {} bytes lost the pattern, most likely reclaimed by the OS",
+                page.offset,
+                page.flips.len()
+            ))
+        })
+    }
+
+    fn changed_block(&mut self, block: &ChangedBlock) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.event(format!(
+                "The {} byte block at index {} changed since the snapshot",
+                block.len, block.offset
+            ))
+        })
+    }
+
+    fn stuck(&mut self, stuck: &StuckBit) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.event(format!(
+                "Suspected a stuck bit in the byte at index {}, which flipped {} times",
+                stuck.offset, stuck.flips
+            ))
+        })
+    }
+
+    fn canary(&mut self, violation: &CanaryViolation) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.event(format!(
+                "Something wrote {} bytes to canary page {}",
+                violation.changed_bytes, violation.canary
+            ))
+        })
+    }
+
+    fn paused(&mut self, paused: bool) -> io::Result<()> {
+        self.update(|dashboard| {
+            let (status, event) = if paused {
+                ("Paused", "Paused checking")
+            } else {
+                ("Monitoring", "Resumed checking")
+            };
+            dashboard.status = status;
+            dashboard.event(event.to_owned());
+        })
+    }
+
+    fn heartbeat(&mut self, summary: &Summary) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.checks = summary.checks;
+            dashboard.flips = summary.flips;
+        })
+    }
+
+    fn stage(&mut self, stage: &Stage) -> io::Result<()> {
+        self.update(|dashboard| {
+            dashboard.event(format!(
+                "Stage {} of {} with {} bytes found {} bit flips in {} integrity checks",
+                stage.number, stage.stages, stage.monitored_bytes, stage.flips, stage.checks
+            ));
+            if let Some(bytes) = stage.next_bytes {
+                dashboard.monitored_bytes = bytes;
+            }
+        })
+    }
+
+    fn shutdown(&mut self, summary: &Summary) -> io::Result<()> {
+        self.finish()?;
+        TextOutput::new(stdout(), self.level).shutdown(summary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn check_dashboard() {
+        let mut dashboard = Dashboard::new();
+        dashboard.status = "Monitoring";
+        dashboard.monitored_bytes = 1000;
+        dashboard.checks = 3;
+        dashboard.flips = 1;
+        dashboard.recent_checks.extend([0, 1, 0]);
+        for i in 0..50 {
+            dashboard.event(format!("event number {i}"));
+        }
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(80)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        assert!(screen.contains("Cosmic ray detector: Monitoring"));
+        assert!(screen.contains("Monitored: 1000 bytes"));
+        assert!(screen.contains("Integrity checks: 3"));
+        assert!(screen.contains("Bit flips: 1"));
+        // Only the newest events fit.
+        assert!(screen.contains("event number 49"));
+        assert!(!screen.contains("event number 10 "));
+        assert!(screen.contains("Press q to stop"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Noticing that an integrity check hangs, e.g. because reading a failing page never returns,
+//! which would otherwise freeze the detector without any sign that it stopped checking.
+
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use humantime::format_duration;
+use log::{error, warn};
+
+/// The exit status with --watchdog-abort when a check stalled.
+pub const STALLED_EXIT_STATUS: u8 = 11;
+
+/// How long a check may take at the least before it counts as stalled, however fast the earlier ones were,
+/// as the system may just be busy.
+const MIN_MARGIN: Duration = Duration::from_secs(60);
+
+/// How many times as long as the slowest check so far a check may take before it counts as stalled.
+const MARGIN_FACTOR: u32 = 4;
+
+/// How often the watchdog thread looks at the deadline.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The deadline while no check is expected, e.g. while the detector of the next stage is filled.
+const DISARMED: u64 = u64::MAX;
+
+/// Whatever has to be undone before the process ends without running any destructors.
+type Cleanups = Mutex<Vec<Box<dyn FnOnce() + Send>>>;
+
+/// Watches the detection loop from a thread of its own. The loop tells it how long it is going to wait
+/// before the next check, and the check then has to finish within a margin after that.
+pub struct Watchdog {
+    start: Instant,
+    /// The milliseconds since `start` by which the loop has to tell the watchdog again that it is alive.
+    deadline: Arc<AtomicU64>,
+    slowest_check: Duration,
+    cleanups: Arc<Cleanups>,
+}
+
+impl Watchdog {
+    /// Starts the watchdog thread, disarmed until [`Watchdog::expect_check_within`] is first called.
+    /// A stall is logged once, or with `abort` ends the process right away with [`STALLED_EXIT_STATUS`],
+    /// after running what was passed to [`Watchdog::on_abort`].
+    pub fn start(abort: bool) -> Self {
+        let start = Instant::now();
+        let deadline = Arc::new(AtomicU64::new(DISARMED));
+        let watched = Arc::clone(&deadline);
+        let cleanups = Arc::new(Cleanups::default());
+        let abort_cleanups = Arc::clone(&cleanups);
+        thread::spawn(move || {
+            let mut warned = false;
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let now = start.elapsed().as_millis() as u64;
+                let Some(overdue) = overdue(watched.load(Ordering::SeqCst), now) else {
+                    warned = false;
+                    continue;
+                };
+                let message = format!(
+                    "the current integrity check should have finished {} ago, it may be stuck reading failing memory",
+                    format_duration(overdue)
+                );
+                if abort {
+                    error!("{message}, giving up so that a supervisor can restart the detector");
+                    // The stalled thread still holds everything else, so nothing of it is dropped on the way out.
+                    clean_up(&abort_cleanups);
+                    process::exit(STALLED_EXIT_STATUS.into());
+                } else if !warned {
+                    warn!("{message}");
+                    warned = true;
+                }
+            }
+        });
+        Watchdog {
+            start,
+            deadline,
+            slowest_check: Duration::ZERO,
+            cleanups,
+        }
+    }
+
+    /// Runs the given function from the watchdog thread if it ends the process, like removing the PID file
+    /// or giving the terminal back, which the destructors would otherwise have done.
+    pub fn on_abort(&self, cleanup: impl FnOnce() + Send + 'static) {
+        self.cleanups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(cleanup));
+    }
+
+    /// Records how long a check took, which makes the watchdog more patient with the next ones if it was slow.
+    pub fn record_check(&mut self, time: Duration) {
+        self.slowest_check = self.slowest_check.max(time);
+    }
+
+    /// Tells the watchdog that the next check starts after the given time and has to finish within the margin after it.
+    pub fn expect_check_within(&self, wait: Duration) {
+        let deadline = self.start.elapsed() + wait + margin(self.slowest_check);
+        self.deadline
+            .store(deadline.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Stops expecting checks until [`Watchdog::expect_check_within`] is called again.
+    pub fn disarm(&self) {
+        self.deadline.store(DISARMED, Ordering::SeqCst);
+    }
+}
+
+/// Runs the cleanups in the order they were added.
+fn clean_up(cleanups: &Cleanups) {
+    let cleanups = std::mem::take(&mut *cleanups.lock().unwrap_or_else(PoisonError::into_inner));
+    for cleanup in cleanups {
+        cleanup();
+    }
+}
+
+/// Returns how long a check may take whose predecessors took up to `slowest_check`.
+fn margin(slowest_check: Duration) -> Duration {
+    MIN_MARGIN.max(slowest_check * MARGIN_FACTOR)
+}
+
+/// Returns how long the deadline has passed at `now`, both in milliseconds since the start.
+fn overdue(deadline: u64, now: u64) -> Option<Duration> {
+    (deadline != DISARMED && now > deadline).then(|| Duration::from_millis(now - deadline))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_watchdog_deadline() {
+        assert_eq!(margin(Duration::ZERO), MIN_MARGIN);
+        assert_eq!(margin(Duration::from_secs(20)), Duration::from_secs(80));
+
+        assert_eq!(overdue(5000, 4000), None);
+        assert_eq!(overdue(5000, 5000), None);
+        assert_eq!(overdue(5000, 7500), Some(Duration::from_millis(2500)));
+        assert_eq!(overdue(DISARMED, 7500), None);
+
+        let mut watchdog = Watchdog::start(false);
+        let deadline = || watchdog.deadline.load(Ordering::SeqCst);
+        assert_eq!(deadline(), DISARMED);
+        watchdog.expect_check_within(Duration::from_secs(10));
+        let first = deadline();
+        assert!((70_000..71_000).contains(&first));
+
+        // The margin grows with slow checks, and never shrinks again.
+        watchdog.record_check(Duration::from_secs(30));
+        watchdog.record_check(Duration::from_secs(1));
+        watchdog.expect_check_within(Duration::from_secs(10));
+        assert!((130_000..131_000).contains(&watchdog.deadline.load(Ordering::SeqCst)));
+        watchdog.disarm();
+        assert_eq!(watchdog.deadline.load(Ordering::SeqCst), DISARMED);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for step in [1, 2] {
+            let order = Arc::clone(&order);
+            watchdog.on_abort(move || order.lock().unwrap().push(step));
+        }
+        clean_up(&watchdog.cleanups);
+        clean_up(&watchdog.cleanups);
+        assert_eq!(*order.lock().unwrap(), [1, 2]);
+    }
+}
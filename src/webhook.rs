@@ -0,0 +1,163 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use serde::Serialize;
+use sysinfo::{System, SystemExt};
+
+use crate::detector::FlipEvent;
+use crate::events::Event;
+use crate::output::Output;
+use crate::summary::Summary;
+
+/// How many times we try to deliver an event before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+/// How long we wait before the first retry, the wait is doubled for every retry after that.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// How long we wait for queued events to be delivered when the detector shuts down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The body of a request, which is the flip event of the JSON output together with the machine it is from.
+#[derive(Serialize)]
+struct FlipPayload {
+    hostname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    #[serde(flatten)]
+    event: Event,
+}
+
+/// Sends an HTTP POST with a JSON body to a URL for every detected flip.
+/// The requests are made on a background thread, so that a slow or unreachable endpoint never stalls the detector.
+pub struct WebhookOutput {
+    hostname: String,
+    tag: Option<String>,
+    sender: Option<Sender<FlipPayload>>,
+    done: Receiver<()>,
+}
+
+impl WebhookOutput {
+    pub fn new(url: String) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel();
+        thread::spawn(move || {
+            for payload in receiver {
+                deliver(&url, &payload);
+            }
+            let _ = done_sender.send(());
+        });
+
+        WebhookOutput {
+            hostname: System::new().host_name().unwrap_or_default(),
+            tag: None,
+            sender: Some(sender),
+            done,
+        }
+    }
+}
+
+impl WebhookOutput {
+    /// Adds the given tag to every request, see `--tag`.
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+}
+
+/// Posts the payload to the URL, retrying with exponential backoff on failures that might be transient.
+fn deliver(url: &str, payload: &FlipPayload) {
+    let body = serde_json::to_string(payload).expect("the payload can be serialized");
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let error = match ureq::post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(_) => return,
+            // Client errors other than rate limiting will not go away by trying again.
+            Err(ureq::Error::Status(code, _)) if (400..500).contains(&code) && code != 429 => {
+                warn!("the webhook at {url} rejected a flip event with status {code}");
+                return;
+            }
+            Err(e) => e,
+        };
+
+        if attempt == MAX_ATTEMPTS {
+            warn!(
+                "giving up on sending a flip event to the webhook at {url} after {MAX_ATTEMPTS} attempts: {error}"
+            );
+        } else {
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+impl Output for WebhookOutput {
+    fn flip(&mut self, flip: &FlipEvent) -> io::Result<()> {
+        if let Some(sender) = &self.sender {
+            // The background thread only stops once the sender is dropped, so this can not fail.
+            let _ = sender.send(FlipPayload {
+                hostname: self.hostname.clone(),
+                tag: self.tag.clone(),
+                event: Event::flip(flip),
+            });
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self, _summary: &Summary) -> io::Result<()> {
+        // Give the events that are still queued a chance to be delivered, but don't hang around forever.
+        self.sender = None;
+        let _ = self.done.recv_timeout(SHUTDOWN_TIMEOUT);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn check_webhook_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let mut output = WebhookOutput::new(url).with_tag(Some("rack-3".to_owned()));
+        output.flip(&FlipEvent::new(12, 0x00, 0x08)).unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                content_length = length.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        (&stream)
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload["offset"], 12);
+        assert_eq!(payload["expected"], 0);
+        assert_eq!(payload["found"], 8);
+        assert_eq!(payload["event"], "flip");
+        assert!(payload["hostname"].is_string());
+        assert_eq!(payload["tag"], "rack-3");
+        assert!(payload["timestamp"].is_string());
+
+        output.shutdown(&Summary::new(0)).unwrap();
+    }
+}